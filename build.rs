@@ -0,0 +1,15 @@
+// Generates the gRPC service (feature "grpc") from proto/quick_cli.proto, with a
+// vendored protoc so no system install is needed.
+fn main() {
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/quick_cli.proto");
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("no vendored protoc for this platform");
+        let mut config = prost_build::Config::new();
+        config.protoc_executable(protoc);
+        tonic_prost_build::configure()
+            .build_client(false)
+            .compile_with_config(config, &["proto/quick_cli.proto"], &["proto"])
+            .expect("failed to compile proto/quick_cli.proto");
+    }
+}
@@ -0,0 +1,530 @@
+///////////////////////////////////////////////////////////////////////////////
+// External VM Backends
+///////////////////////////////////////////////////////////////////////////////
+//
+// Besides quickemu configs, the list can show VMs managed by other tools,
+// enabled with `backends = [...]` in the config:
+//     "utm"     UTM on macOS, via utmctl
+//     "hyperv"  Hyper-V on Windows, via PowerShell (Get-VM, Start-VM, Stop-VM)
+//     "vbox"    VirtualBox, via VBoxManage
+//     "qemu"    plain qemu-system-* VMs from command files, see below
+// A background thread lists them every status poll; start, stop and connect
+// run the backend's own commands.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use serde::Deserialize;
+
+use crate::{
+    config_dir, describe_command, pid_alive, qmp::QmpClient, state_dir, viewer_args, viewer_command, Config,
+    RemoteProtocol,
+};
+
+#[derive(Clone)]
+pub struct ExternalVm {
+    pub backend: &'static str,
+    pub id: String, // What the backend's commands take (UUID, name, ...)
+    pub name: String,
+    pub running: bool,
+}
+
+pub trait Backend: Sync {
+    fn name(&self) -> &'static str;
+    fn list(&self) -> Result<Vec<ExternalVm>, String>;
+    /// Command that starts the VM, or why it cannot be started.
+    fn start_command(&self, vm: &ExternalVm) -> Result<Command, String>;
+    /// Stop the VM, honouring dry-run and verbose; false when it failed.
+    fn stop(&self, vm: &ExternalVm, config: &Config, logs: &Arc<Mutex<Vec<String>>>) -> bool;
+    /// Command that opens the VM's display, or why there is none.
+    fn connect_command(&self, vm: &ExternalVm, config: &Config) -> Result<Command, String>;
+}
+
+pub fn backend(name: &str) -> Option<&'static dyn Backend> {
+    match name {
+        "utm" => Some(&Utm),
+        "hyperv" => Some(&HyperV),
+        "vbox" => Some(&VirtualBox),
+        "qemu" => Some(&Qemu),
+        _ => None,
+    }
+}
+
+/// Stdout of a listing command, or its stderr as the error.
+fn command_output(command: &mut Command) -> Result<String, String> {
+    let output = command.output().map_err(|e| format!("failed to run {}: {}", describe_command(command), e))?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
+
+/// Poll the enabled backends in a background thread. Listing errors are logged
+/// when they first appear, not on every poll.
+pub fn spawn_poller(config: &Config, logs: &Arc<Mutex<Vec<String>>>) -> Arc<Mutex<Vec<ExternalVm>>> {
+    let vms = Arc::new(Mutex::new(Vec::new()));
+    let mut enabled = Vec::new();
+    for name in &config.backends {
+        match backend(name) {
+            Some(b) => enabled.push(b),
+            None => logs.lock().unwrap().push(format!("Unknown backend {:?} in config.", name)),
+        }
+    }
+    if enabled.is_empty() {
+        return vms;
+    }
+    let (shared, logs, interval) = (Arc::clone(&vms), Arc::clone(logs), config.status_poll_interval);
+    thread::spawn(move || {
+        let mut errors: Vec<Option<String>> = vec![None; enabled.len()];
+        loop {
+            let mut all = Vec::new();
+            for (b, last_error) in enabled.iter().zip(errors.iter_mut()) {
+                match b.list() {
+                    Ok(list) => {
+                        all.extend(list);
+                        *last_error = None;
+                    }
+                    Err(e) => {
+                        if last_error.as_ref() != Some(&e) {
+                            logs.lock().unwrap().push(format!("Listing {} VMs failed: {}", b.name(), e));
+                        }
+                        *last_error = Some(e);
+                    }
+                }
+            }
+            all.sort_by_key(|vm| vm.name.to_lowercase());
+            *shared.lock().unwrap() = all;
+            thread::sleep(interval.max(Duration::from_secs(2)));
+        }
+    });
+    vms
+}
+
+/// Run a start/stop command to completion, honouring dry-run and verbose.
+pub fn run_action(command: &mut Command, config: &Config, logs: &Arc<Mutex<Vec<String>>>) -> bool {
+    if config.dry_run {
+        logs.lock().unwrap().push(format!("[dry-run] {}", describe_command(command)));
+        return true;
+    }
+    let result = command_output(command);
+    let mut l = logs.lock().unwrap();
+    if config.verbose {
+        l.push(format!("$ {}", describe_command(command)));
+    }
+    match result {
+        Ok(_) => true,
+        Err(e) => {
+            l.push(format!("{} failed: {}", describe_command(command), e));
+            false
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// UTM (macOS)
+///////////////////////////////////////////////////////////////////////////////
+
+struct Utm;
+
+impl Backend for Utm {
+    fn name(&self) -> &'static str {
+        "utm"
+    }
+
+    /// `utmctl list` prints "UUID Status Name" rows after a header line.
+    fn list(&self) -> Result<Vec<ExternalVm>, String> {
+        let output = command_output(Command::new("utmctl").arg("list"))?;
+        Ok(output
+            .lines()
+            .skip(1)
+            .filter_map(|line| {
+                let mut words = line.split_whitespace();
+                let (id, status) = (words.next()?, words.next()?);
+                let name = words.collect::<Vec<_>>().join(" ");
+                Some(ExternalVm {
+                    backend: "utm",
+                    id: id.to_string(),
+                    name,
+                    running: matches!(status, "started" | "starting" | "resuming"),
+                })
+            })
+            .collect())
+    }
+
+    fn start_command(&self, vm: &ExternalVm) -> Result<Command, String> {
+        let mut command = Command::new("utmctl");
+        command.args(["start", &vm.id]);
+        Ok(command)
+    }
+
+    fn stop(&self, vm: &ExternalVm, config: &Config, logs: &Arc<Mutex<Vec<String>>>) -> bool {
+        run_action(Command::new("utmctl").args(["stop", &vm.id]), config, logs)
+    }
+
+    /// UTM shows the VM in its own window; the URL scheme brings it up.
+    fn connect_command(&self, vm: &ExternalVm, _config: &Config) -> Result<Command, String> {
+        let mut command = Command::new("open");
+        command.arg(format!("utm://start?name={}", vm.name.replace(' ', "%20")));
+        Ok(command)
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// Hyper-V (Windows)
+///////////////////////////////////////////////////////////////////////////////
+
+struct HyperV;
+
+fn powershell(script: &str) -> Command {
+    let mut command = Command::new("powershell");
+    command.args(["-NoProfile", "-NonInteractive", "-Command", script]);
+    command
+}
+
+/// Quote a value for a single-quoted PowerShell string.
+fn ps_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+impl Backend for HyperV {
+    fn name(&self) -> &'static str {
+        "hyperv"
+    }
+
+    fn list(&self) -> Result<Vec<ExternalVm>, String> {
+        // Strings rather than Guid/enum objects so both PowerShell 5 and 7 emit plain JSON values.
+        let script = "@(Get-VM | Select-Object @{n='Id';e={$_.Id.ToString()}},Name,@{n='State';e={$_.State.ToString()}}) \
+                      | ConvertTo-Json -Compress";
+        let output = command_output(&mut powershell(script))?;
+        let value: serde_json::Value = match output.trim() {
+            "" => return Ok(Vec::new()),
+            json => serde_json::from_str(json).map_err(|e| format!("unexpected Get-VM output: {}", e))?,
+        };
+        // ConvertTo-Json unwraps single-element arrays.
+        let entries = match value {
+            serde_json::Value::Array(entries) => entries,
+            single => vec![single],
+        };
+        Ok(entries
+            .iter()
+            .filter_map(|vm| {
+                Some(ExternalVm {
+                    backend: "hyperv",
+                    id: vm["Id"].as_str()?.to_string(),
+                    name: vm["Name"].as_str()?.to_string(),
+                    running: vm["State"] == "Running",
+                })
+            })
+            .collect())
+    }
+
+    fn start_command(&self, vm: &ExternalVm) -> Result<Command, String> {
+        Ok(powershell(&format!("Get-VM -Id {} | Start-VM", ps_quote(&vm.id))))
+    }
+
+    fn stop(&self, vm: &ExternalVm, config: &Config, logs: &Arc<Mutex<Vec<String>>>) -> bool {
+        // Graceful shutdown through the guest's integration services.
+        run_action(&mut powershell(&format!("Get-VM -Id {} | Stop-VM", ps_quote(&vm.id))), config, logs)
+    }
+
+    /// RDP to the guest's first IPv4 address as reported by Hyper-V, or the
+    /// Hyper-V console (vmconnect) when the guest reports none.
+    fn connect_command(&self, vm: &ExternalVm, _config: &Config) -> Result<Command, String> {
+        let script = format!(
+            "(Get-VM -Id {} | Get-VMNetworkAdapter).IPAddresses | Where-Object {{ $_ -match '^[0-9.]+$' }} \
+             | Select-Object -First 1",
+            ps_quote(&vm.id)
+        );
+        let ip = command_output(&mut powershell(&script)).unwrap_or_default().trim().to_string();
+        if ip.is_empty() {
+            let mut command = Command::new("vmconnect.exe");
+            command.args(["localhost", &vm.name]);
+            Ok(command)
+        } else {
+            let mut command = Command::new("mstsc.exe");
+            command.arg(format!("/v:{}", ip));
+            Ok(command)
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// VirtualBox
+///////////////////////////////////////////////////////////////////////////////
+
+struct VirtualBox;
+
+fn vboxmanage(args: &[&str]) -> Command {
+    let mut command = Command::new("VBoxManage");
+    command.args(args);
+    command
+}
+
+/// Parse `VBoxManage list vms` lines: "name" {uuid}.
+fn parse_vbox_list(output: &str) -> Vec<(String, String)> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (name, uuid) = line.rsplit_once(' ')?;
+            Some((name.trim().trim_matches('"').to_string(), uuid.trim_matches(|c| c == '{' || c == '}').to_string()))
+        })
+        .collect()
+}
+
+impl Backend for VirtualBox {
+    fn name(&self) -> &'static str {
+        "vbox"
+    }
+
+    fn list(&self) -> Result<Vec<ExternalVm>, String> {
+        let all = parse_vbox_list(&command_output(&mut vboxmanage(&["list", "vms"]))?);
+        let running: Vec<String> = parse_vbox_list(&command_output(&mut vboxmanage(&["list", "runningvms"]))?)
+            .into_iter()
+            .map(|(_, uuid)| uuid)
+            .collect();
+        Ok(all
+            .into_iter()
+            .map(|(name, uuid)| ExternalVm { backend: "vbox", running: running.contains(&uuid), id: uuid, name })
+            .collect())
+    }
+
+    fn start_command(&self, vm: &ExternalVm) -> Result<Command, String> {
+        Ok(vboxmanage(&["startvm", &vm.id, "--type", "headless"]))
+    }
+
+    fn stop(&self, vm: &ExternalVm, config: &Config, logs: &Arc<Mutex<Vec<String>>>) -> bool {
+        run_action(&mut vboxmanage(&["controlvm", &vm.id, "acpipowerbutton"]), config, logs)
+    }
+
+    /// RDP to the VirtualBox Remote Display (VRDE) port with the first RDP client.
+    fn connect_command(&self, vm: &ExternalVm, config: &Config) -> Result<Command, String> {
+        let info = command_output(&mut vboxmanage(&["showvminfo", &vm.id, "--machinereadable"]))?;
+        let value = |key: &str| {
+            info.lines().find_map(|line| line.strip_prefix(&format!("{}=", key)).map(|v| v.trim_matches('"').to_string()))
+        };
+        if value("vrde").as_deref() != Some("on") {
+            return Err(format!("remote display is off; enable it with VBoxManage modifyvm \"{}\" --vrde on", vm.name));
+        }
+        // vrdeport is the port in use while running; vrdeports the configured list or range.
+        let port = value("vrdeport")
+            .and_then(|p| p.parse::<u16>().ok())
+            .filter(|p| *p > 0)
+            .or_else(|| value("vrdeports").and_then(|p| p.split([',', '-']).next()?.parse().ok()))
+            .unwrap_or(3389);
+        let client = config.rdp_clients.first().ok_or("no RDP client configured")?;
+        let mut command = viewer_command(client, &vm.name, config);
+        command.args(viewer_args(client, &RemoteProtocol::Rdp(port), &vm.name, &config.connect_host));
+        Ok(command)
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// Plain QEMU
+///////////////////////////////////////////////////////////////////////////////
+//
+// VMs defined in $XDG_CONFIG_HOME/quick-cli/qemu/, for hosts without quickemu:
+//     <name>.qemu  a qemu command line (binary first), split like a shell does:
+//                  quotes, `#` comments and trailing backslashes, no expansions
+//     <name>.toml  binary = "qemu-system-x86_64", args = [...], and optionally
+//                  display = "vnc" | "spice" with port = 5901, or rdp_port for
+//                  an RDP server reached through the VM's own port forward
+// quick-cli adds -name, -daemonize, a pid file and a QMP socket (in
+// $XDG_STATE_HOME/quick-cli/qemu); stop asks for an ACPI powerdown over QMP
+// (see qmp.rs). The viewer port comes from the -vnc/-spice arguments.
+
+struct Qemu;
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct QemuDefinition {
+    binary: Option<String>,
+    args: Vec<String>,
+    display: Option<String>,
+    port: Option<u16>,
+    rdp_port: Option<u16>,
+}
+
+impl QemuDefinition {
+    fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        if path.extension().is_some_and(|e| e == "toml") {
+            let mut definition: Self = toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+            let display = match (definition.display.as_deref(), definition.port) {
+                (Some("vnc"), Some(port)) => ["-vnc".into(), format!(":{}", port.saturating_sub(5900))],
+                (Some("spice"), Some(port)) => ["-spice".into(), format!("port={},disable-ticketing=on", port)],
+                (Some(display), _) => return Err(format!("{}: display {:?} needs a port", path.display(), display)),
+                (None, _) => return Ok(definition),
+            };
+            definition.args.extend(display);
+            return Ok(definition);
+        }
+        let words = shell_words::split(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut words = words.into_iter();
+        let binary = words.next().ok_or_else(|| format!("{}: empty command file", path.display()))?;
+        Ok(Self { binary: Some(binary), args: words.collect(), ..Self::default() })
+    }
+
+    /// The viewer protocol and port, from -vnc :N, -spice port=N (or unix=on,addr=PATH) or rdp_port.
+    fn remote(&self) -> Option<RemoteProtocol> {
+        if let Some(port) = self.rdp_port {
+            return Some(RemoteProtocol::Rdp(port));
+        }
+        let value = |flag: &str| self.args.iter().position(|a| a == flag).and_then(|i| self.args.get(i + 1));
+        if let Some(display) = value("-vnc") {
+            let number: u16 = display.rsplit(':').next()?.split(',').next()?.parse().ok()?;
+            return Some(RemoteProtocol::Vnc(5900 + number));
+        }
+        let spice = value("-spice")?;
+        let option = |name: &str| spice.split(',').find_map(|o| o.strip_prefix(name));
+        if option("unix=").is_some_and(|v| v == "on")
+            && let Some(addr) = option("addr=")
+        {
+            return Some(RemoteProtocol::SpiceUnix(PathBuf::from(addr)));
+        }
+        option("port=")?.parse().ok().map(RemoteProtocol::Spice)
+    }
+}
+
+fn qemu_definitions_dir() -> PathBuf {
+    config_dir().join("qemu")
+}
+
+/// Pid file and QMP socket of a plain QEMU VM.
+fn qemu_runtime(name: &str) -> (PathBuf, PathBuf) {
+    let dir = state_dir().join("qemu");
+    (dir.join(format!("{}.pid", name)), dir.join(format!("{}-qmp.socket", name)))
+}
+
+fn qemu_pid(name: &str) -> Option<u32> {
+    fs::read_to_string(qemu_runtime(name).0).ok()?.trim().parse().ok().filter(|pid| pid_alive(*pid))
+}
+
+impl Backend for Qemu {
+    fn name(&self) -> &'static str {
+        "qemu"
+    }
+
+    fn list(&self) -> Result<Vec<ExternalVm>, String> {
+        let Ok(entries) = fs::read_dir(qemu_definitions_dir()) else { return Ok(Vec::new()) };
+        Ok(entries
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| p.extension().is_some_and(|e| e == "qemu" || e == "toml"))
+            .map(|path| {
+                let name = path.file_stem().unwrap().to_string_lossy().to_string();
+                let running = qemu_pid(&name).is_some();
+                ExternalVm { backend: "qemu", running, id: path.to_string_lossy().into(), name }
+            })
+            .collect())
+    }
+
+    fn start_command(&self, vm: &ExternalVm) -> Result<Command, String> {
+        let definition = QemuDefinition::load(Path::new(&vm.id))?;
+        let (pid_file, qmp_socket) = qemu_runtime(&vm.name);
+        if let Some(dir) = pid_file.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        }
+        let mut command = Command::new(definition.binary.as_deref().unwrap_or("qemu-system-x86_64"));
+        command.args(&definition.args).args(["-name", &vm.name, "-daemonize", "-pidfile"]).arg(&pid_file);
+        command.arg("-qmp").arg(format!("unix:{},server=on,wait=off", qmp_socket.display()));
+        if !definition.args.iter().any(|a| a == "-display" || a == "-nographic") {
+            command.args(["-display", "none"]);
+        }
+        Ok(command)
+    }
+
+    /// ACPI powerdown over QMP, like the first stage of stopping a quickemu VM.
+    fn stop(&self, vm: &ExternalVm, config: &Config, logs: &Arc<Mutex<Vec<String>>>) -> bool {
+        let (_, qmp_socket) = qemu_runtime(&vm.name);
+        let action = format!("QMP system_powerdown on {}", qmp_socket.display());
+        if config.dry_run {
+            logs.lock().unwrap().push(format!("[dry-run] {}", action));
+            return true;
+        }
+        let result = QmpClient::connect_socket(&qmp_socket).and_then(|mut qmp| qmp.execute("system_powerdown", None));
+        let mut l = logs.lock().unwrap();
+        if config.verbose {
+            l.push(format!("$ {}", action));
+        }
+        match result {
+            Ok(_) => true,
+            Err(e) => {
+                l.push(format!("{} failed: {}", action, e));
+                false
+            }
+        }
+    }
+
+    fn connect_command(&self, vm: &ExternalVm, config: &Config) -> Result<Command, String> {
+        let definition = QemuDefinition::load(Path::new(&vm.id))?;
+        let remote = definition.remote().ok_or("no -vnc or -spice display (or rdp_port) in the definition")?;
+        let client = remote.clients(config).first().ok_or("no viewer configured for the VM's display")?;
+        let mut command = viewer_command(client, &vm.name, config);
+        command.args(viewer_args(client, &remote, &vm.name, &config.connect_host));
+        Ok(command)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vbox_list_names_and_uuids() {
+        let output = concat!(
+            "\"Windows 11\" {0b9b2a4e-1c7d-4c1e-9a0e-3f2d5c6b7a81}\n",
+            "\"dev\" {11111111-2222-3333-4444-555555555555}\n",
+        );
+        assert_eq!(
+            parse_vbox_list(output),
+            [
+                ("Windows 11".to_string(), "0b9b2a4e-1c7d-4c1e-9a0e-3f2d5c6b7a81".to_string()),
+                ("dev".to_string(), "11111111-2222-3333-4444-555555555555".to_string()),
+            ]
+        );
+        assert!(parse_vbox_list("").is_empty());
+        assert!(parse_vbox_list("garbage\n").is_empty());
+    }
+
+    #[test]
+    fn qemu_definition_remote_from_display_args() {
+        let definition = |args: &[&str], rdp_port| QemuDefinition {
+            args: args.iter().map(|a| a.to_string()).collect(),
+            rdp_port,
+            ..QemuDefinition::default()
+        };
+        assert!(matches!(definition(&["-m", "4G", "-vnc", ":1"], None).remote(), Some(RemoteProtocol::Vnc(5901))));
+        let vnc = definition(&["-vnc", "0.0.0.0:2,password=on"], None);
+        assert!(matches!(vnc.remote(), Some(RemoteProtocol::Vnc(5902))));
+        let spice = definition(&["-spice", "port=5930,disable-ticketing=on"], None);
+        assert!(matches!(spice.remote(), Some(RemoteProtocol::Spice(5930))));
+        assert!(matches!(definition(&["-vnc", ":1"], Some(3390)).remote(), Some(RemoteProtocol::Rdp(3390))));
+        assert!(definition(&["-display", "none"], None).remote().is_none());
+    }
+
+    #[test]
+    fn qemu_command_file_words() {
+        let path = std::env::temp_dir().join(format!("quick-cli-test-{}.qemu", std::process::id()));
+        let text = concat!(
+            "# Debian test VM\n",
+            "qemu-system-x86_64 -m 4G \\\n",
+            "  -append \"console=ttyS0 root=/dev/vda1\" # serial\n",
+        );
+        fs::write(&path, text).unwrap();
+        let definition = QemuDefinition::load(&path);
+        fs::write(&path, "qemu-system-x86_64 -name 'unclosed\n").unwrap();
+        let unclosed = QemuDefinition::load(&path);
+        let _ = fs::remove_file(&path);
+        let definition = definition.unwrap();
+        assert_eq!(definition.binary.as_deref(), Some("qemu-system-x86_64"));
+        assert_eq!(definition.args, ["-m", "4G", "-append", "console=ttyS0 root=/dev/vda1"]);
+        assert!(unclosed.is_err());
+    }
+}
@@ -0,0 +1,489 @@
+///////////////////////////////////////////////////////////////////////////////
+// Backups
+///////////////////////////////////////////////////////////////////////////////
+//
+// `:backup <vm|all> [full]` and `[backup.<vm>]` schedules copy the VM's
+// disk_img into backup_dir/<vm>/ with `qemu-img convert`:
+//     <unix time>-full.qcow2   a standalone copy
+//     <unix time>-incr.qcow2   only the clusters that differ from the previous
+//                              backup, which it names as its backing file
+// A full backup and the incrementals after it form a chain; restoring any
+// file flattens its chain. Retention deletes whole chains, oldest first.
+//
+// A running VM is backed up from a temporary internal snapshot (see
+// snapshot.rs, which freezes the guest's filesystems when it can), read with
+// `qemu-img -U` while qemu keeps the image open, and deleted afterwards.
+//
+// The `B` browser restores a backup over the stopped VM's disk, or as a new
+// VM: a copy of the .conf pointing at <name>/<disk file>, without macaddr,
+// optionally with a cloud-init seed carrying the SSH keys (see cloudinit.rs).
+
+use std::{
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    describe_command, disk,
+    events::{now, Event},
+    is_vm_running,
+    jobs::{self, Jobs},
+    cloudinit, list_vms, snapshot, suspend, webhook, Config,
+};
+
+const SNAPSHOT_NAME: &str = "quick-cli-backup";
+const DEFAULT_KEEP: usize = 3;
+
+/// How often schedules are checked for due backups.
+pub const SCHEDULE_CHECK: Duration = Duration::from_secs(60);
+
+/// Per-VM backup job from a `[backup.<vm>]` table.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackupPolicy {
+    pub interval_hours: Option<u64>, // Back up automatically this often; without it only on demand
+    pub full_every: Option<u32>,     // Every Nth backup is full, the others incremental (default 1: all full)
+    pub keep: Option<usize>,         // Full backups kept, each with its incrementals (default 3)
+    pub max_age_days: Option<u64>,   // Also delete chains whose newest backup is older; the newest chain stays
+}
+
+/// One file in a VM's backup directory.
+#[derive(Clone)]
+pub struct Backup {
+    pub path: PathBuf,
+    pub time: u64, // Unix seconds, from the file name
+    pub full: bool,
+}
+
+fn vm_name(vm_conf: &Path) -> String {
+    vm_conf.file_stem().unwrap().to_string_lossy().to_string()
+}
+
+pub fn vm_backup_dir(vm_conf: &Path, config: &Config) -> PathBuf {
+    config.backup_dir.join(vm_name(vm_conf))
+}
+
+fn policy(vm_conf: &Path, config: &Config) -> BackupPolicy {
+    config.backups.get(&vm_name(vm_conf).to_lowercase()).cloned().unwrap_or_default()
+}
+
+/// The VM's backups, oldest first. Partial files of a running backup are skipped.
+pub fn list(vm_conf: &Path, config: &Config) -> Vec<Backup> {
+    let Ok(entries) = fs::read_dir(vm_backup_dir(vm_conf, config)) else { return Vec::new() };
+    let mut backups: Vec<Backup> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            let stem = path.file_name()?.to_str()?.strip_suffix(".qcow2")?.to_string();
+            let (time, kind) = stem.split_once('-')?;
+            let full = match kind {
+                "full" => true,
+                "incr" => false,
+                _ => return None,
+            };
+            Some(Backup { time: time.parse().ok()?, full, path })
+        })
+        .collect();
+    backups.sort_by_key(|b| b.time);
+    backups
+}
+
+/// Split backups (oldest first) into chains, each a full backup and its incrementals.
+/// Incrementals before the first full backup (whose base was deleted) form their own chain.
+fn chains(backups: &[Backup]) -> Vec<&[Backup]> {
+    let mut chains = Vec::new();
+    let mut start = 0;
+    for (i, backup) in backups.iter().enumerate() {
+        if backup.full && i > start {
+            chains.push(&backups[start..i]);
+            start = i;
+        }
+    }
+    if start < backups.len() {
+        chains.push(&backups[start..]);
+    }
+    chains
+}
+
+/// Delete chains beyond `keep` and those older than `max_age_days`, never the newest one.
+/// Returns the number of files removed.
+fn apply_retention(vm_conf: &Path, config: &Config) -> Result<usize, String> {
+    let policy = policy(vm_conf, config);
+    let keep = policy.keep.unwrap_or(DEFAULT_KEEP).max(1);
+    let backups = list(vm_conf, config);
+    let chains = chains(&backups);
+    let cutoff = policy.max_age_days.map(|days| now().saturating_sub(days * 86400));
+    let mut removed = 0;
+    for (age, chain) in chains.iter().rev().enumerate() {
+        let newest = chain.last().map_or(0, |b| b.time);
+        let expired = cutoff.is_some_and(|cutoff| newest < cutoff);
+        if age == 0 || (age < keep && !expired) {
+            continue;
+        }
+        for backup in chain.iter() {
+            fs::remove_file(&backup.path).map_err(|e| format!("cannot remove {}: {}", backup.path.display(), e))?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Whether the next backup is full: when forced, when there is none to build on,
+/// or when the current chain already holds `full_every` backups.
+fn next_is_full(backups: &[Backup], policy: &BackupPolicy, force_full: bool) -> bool {
+    let full_every = policy.full_every.unwrap_or(1).max(1) as usize;
+    let chain_len = backups.iter().rev().position(|b| b.full).map(|i| i + 1);
+    force_full || chain_len.is_none_or(|len| len >= full_every)
+}
+
+/// `(12.34/100%)` progress lines from `qemu-img convert -p`, separated by carriage returns.
+fn parse_progress(line: &str) -> Option<f32> {
+    let inner = line.trim().strip_prefix('(')?.strip_suffix("%)")?;
+    inner.split('/').next()?.trim().parse().ok()
+}
+
+/// Run qemu-img, feeding its progress into the job. Errors carry qemu-img's stderr.
+fn run_qemu_img(command: &mut Command, jobs: &Jobs, job: usize) -> Result<(), String> {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("cannot run qemu-img: {}", e))?;
+    let mut stdout = child.stdout.take().unwrap();
+    let mut buf = [0u8; 256];
+    let mut pending = String::new();
+    while let Ok(n) = stdout.read(&mut buf) {
+        if n == 0 {
+            break;
+        }
+        pending.push_str(&String::from_utf8_lossy(&buf[..n]));
+        while let Some(i) = pending.find(['\r', '\n']) {
+            if let Some(percent) = parse_progress(&pending[..i]) {
+                jobs::set_progress(jobs, job, percent);
+            }
+            pending.drain(..=i);
+        }
+    }
+    let mut stderr = String::new();
+    if let Some(mut err) = child.stderr.take() {
+        let _ = err.read_to_string(&mut stderr);
+    }
+    let status = child.wait().map_err(|e| e.to_string())?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(stderr.lines().last().unwrap_or("qemu-img failed").trim().to_string())
+    }
+}
+
+/// Copy the disk into `file` in the VM's backup directory, from a temporary snapshot if it runs.
+fn write_backup(
+    vm_conf: &Path,
+    source: &Path,
+    file: &str,
+    base: Option<&Backup>,
+    config: &Config,
+    (jobs, job): (&Jobs, usize),
+    logs: &Arc<Mutex<Vec<String>>>,
+) -> Result<(), String> {
+    let dir = vm_backup_dir(vm_conf, config);
+    let running = is_vm_running(vm_conf, config);
+    let partial = format!("{}.part", file);
+    let mut command = Command::new("qemu-img");
+    // Backing files are named relative to the backup directory, so it can be moved.
+    command.current_dir(&dir).args(["convert", "-p", "-O", "qcow2"]);
+    if let Some(base) = base {
+        let name = base.path.file_name().unwrap_or_default();
+        command.args(["-F", "qcow2", "-B"]).arg(name);
+    }
+    if running {
+        command.arg("-U").args(["-l", &format!("snapshot.name={}", SNAPSHOT_NAME)]);
+    }
+    command.arg(source).arg(&partial);
+    if config.dry_run {
+        logs.lock().unwrap().push(format!("[dry-run] {}", describe_command(&command)));
+        return Ok(());
+    }
+    fs::create_dir_all(&dir).map_err(|e| format!("cannot create {}: {}", dir.display(), e))?;
+    let devices = if running { Some(snapshot::create(vm_conf, SNAPSHOT_NAME, logs)?) } else { None };
+    let result = run_qemu_img(&mut command, jobs, job)
+        .and_then(|_| fs::rename(dir.join(&partial), dir.join(file)).map_err(|e| e.to_string()));
+    if let Some(devices) = devices
+        && let Err(e) = snapshot::delete(vm_conf, SNAPSHOT_NAME, &devices)
+    {
+        logs.lock().unwrap().push(format!("Failed to delete the backup snapshot of {}: {}", vm_conf.display(), e));
+    }
+    if result.is_err() {
+        let _ = fs::remove_file(dir.join(&partial));
+    }
+    result
+}
+
+/// Back up the VM in a background thread, tracked as a "backup" job. Incremental unless
+/// `force_full` or the VM's `full_every` calls for a full backup.
+pub fn start(vm_conf: &Path, force_full: bool, config: &Config, jobs: &Jobs, logs: &Arc<Mutex<Vec<String>>>) {
+    let name = vm_name(vm_conf);
+    if jobs::is_running(jobs, "backup", &name) {
+        logs.lock().unwrap().push(format!("A backup of {} is already running.", name));
+        return;
+    }
+    // Absolute, since qemu-img runs in the backup directory.
+    let Some(source) = disk::disk_image(vm_conf).and_then(|d| d.canonicalize().ok()) else {
+        logs.lock().unwrap().push(format!("Cannot back up {}: its disk_img does not exist.", name));
+        return;
+    };
+    if disk::disk_encryption(vm_conf).is_some() {
+        logs.lock().unwrap().push(format!("Cannot back up {}: encrypted disks are not supported.", name));
+        return;
+    }
+    let backups = list(vm_conf, config);
+    let full = next_is_full(&backups, &policy(vm_conf, config), force_full);
+    let base = if full { None } else { backups.last().cloned() };
+    let file = format!("{}-{}.qcow2", now(), if full { "full" } else { "incr" });
+    let detail = format!(
+        "{} to {}",
+        if full { "full" } else { "incremental" },
+        vm_backup_dir(vm_conf, config).join(&file).display()
+    );
+    let job = jobs::add(jobs, "backup", &name, detail.clone());
+    logs.lock().unwrap().push(format!("Backing up {}: {}.", name, detail));
+    let (vm_conf, config, jobs, logs) = (vm_conf.to_path_buf(), config.clone(), Arc::clone(jobs), Arc::clone(logs));
+    thread::spawn(move || {
+        let result = write_backup(&vm_conf, &source, &file, base.as_ref(), &config, (&jobs, job), &logs);
+        let message = match &result {
+            Ok(()) if config.dry_run => format!("[dry-run] Backup of {} not written.", name),
+            Ok(()) => match apply_retention(&vm_conf, &config) {
+                Ok(0) => format!("Backup of {} finished.", name),
+                Ok(removed) => format!("Backup of {} finished; removed {} old backup files.", name, removed),
+                Err(e) => format!("Backup of {} finished, but retention failed: {}", name, e),
+            },
+            Err(e) => format!("Backup of {} failed: {}", name, e),
+        };
+        logs.lock().unwrap().push(message);
+        jobs::finish(&jobs, job, &result);
+        webhook::post(&config.webhooks, &Event::job_finished("backup", &name, result.is_ok()), &logs);
+    });
+}
+
+/// Where `restore` writes a backup.
+pub enum RestoreTarget {
+    InPlace,                             // Over the VM's own disk_img; the VM must be stopped
+    Clone { name: String, seed: bool },  // As a new VM next to the original, with a cloud-init seed if `seed`
+}
+
+/// Flatten a backup and its chain into `disk`, through a partial file.
+fn write_restore(
+    backup: &Backup,
+    disk: &Path,
+    config: &Config,
+    (jobs, job): (&Jobs, usize),
+    logs: &Arc<Mutex<Vec<String>>>,
+) -> Result<(), String> {
+    let mut partial = disk.as_os_str().to_owned();
+    partial.push(".part");
+    let partial = PathBuf::from(partial);
+    let mut command = Command::new("qemu-img");
+    command.args(["convert", "-p", "-O", "qcow2"]).arg(&backup.path).arg(&partial);
+    if config.dry_run {
+        logs.lock().unwrap().push(format!("[dry-run] {}", describe_command(&command)));
+        return Ok(());
+    }
+    if let Some(dir) = disk.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("cannot create {}: {}", dir.display(), e))?;
+    }
+    let result =
+        run_qemu_img(&mut command, jobs, job).and_then(|_| fs::rename(&partial, disk).map_err(|e| e.to_string()));
+    if result.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+    result
+}
+
+/// The VM's .conf with disk_img moved to `<name>/<disk file>` and macaddr dropped,
+/// so the clone does not share the original's MAC address on a bridge. The
+/// original's cloud-init seed is not carried over either.
+fn clone_conf(vm_conf: &Path, name: &str, disk_file: &str) -> Result<String, String> {
+    let contents = fs::read_to_string(vm_conf).map_err(|e| format!("cannot read {}: {}", vm_conf.display(), e))?;
+    let seed = format!("fixed_iso=\"{}/seed.iso\"", vm_name(vm_conf));
+    let mut out = String::new();
+    for line in contents.lines() {
+        match line.split_once('=').map(|(key, _)| key.trim()) {
+            Some("disk_img") => out.push_str(&format!("disk_img=\"{}/{}\"", name, disk_file)),
+            Some("macaddr") => continue,
+            Some("fixed_iso") if line.trim() == seed => continue,
+            _ => out.push_str(line),
+        }
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// Restore a backup in a background thread, tracked as a "restore" job. In place the
+/// disk is replaced (internal snapshots and suspended state with it); a clone gets its
+/// .conf written once its disk is complete, so it only shows up when it can start.
+pub fn restore(
+    vm_conf: &Path,
+    backup: &Backup,
+    target: RestoreTarget,
+    config: &Config,
+    jobs: &Jobs,
+    logs: &Arc<Mutex<Vec<String>>>,
+) {
+    let name = vm_name(vm_conf);
+    let fail = |message: String| logs.lock().unwrap().push(format!("Cannot restore {}: {}", name, message));
+    if jobs::is_running(jobs, "backup", &name) || jobs::is_running(jobs, "restore", &name) {
+        return fail("a backup or restore of it is still running.".into());
+    }
+    let Some(disk) = disk::disk_image(vm_conf) else { return fail("it has no disk_img.".into()) };
+    let seed = matches!(target, RestoreTarget::Clone { seed: true, .. });
+    let (disk, new_conf) = match &target {
+        RestoreTarget::InPlace if is_vm_running(vm_conf, config) => return fail("stop the VM first.".into()),
+        RestoreTarget::InPlace => (disk, None),
+        RestoreTarget::Clone { name: new_name, .. } => {
+            let valid = |c: char| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.');
+            if new_name.is_empty() || new_name.starts_with('.') || !new_name.chars().all(valid) {
+                return fail(format!("\"{}\" is not a valid VM name.", new_name));
+            }
+            let new_conf = vm_conf.with_file_name(format!("{}.conf", new_name));
+            let new_dir = vm_conf.with_file_name(new_name);
+            if new_conf.exists() || new_dir.exists() {
+                return fail(format!("{} or its directory already exists.", new_conf.display()));
+            }
+            let disk_file = disk.file_name().unwrap_or_default().to_string_lossy().to_string();
+            match clone_conf(vm_conf, new_name, &disk_file) {
+                Ok(contents) => (new_dir.join(disk_file), Some((new_conf, contents))),
+                Err(e) => return fail(e),
+            }
+        }
+    };
+    let detail = format!(
+        "{} to {}",
+        backup.path.file_name().unwrap_or_default().to_string_lossy(),
+        disk.display()
+    );
+    let job = jobs::add(jobs, "restore", &name, detail.clone());
+    logs.lock().unwrap().push(format!("Restoring {}: {}.", name, detail));
+    let (vm_conf, backup, config) = (vm_conf.to_path_buf(), backup.clone(), config.clone());
+    let (jobs, logs) = (Arc::clone(jobs), Arc::clone(logs));
+    thread::spawn(move || {
+        let mut result = write_restore(&backup, &disk, &config, (&jobs, job), &logs);
+        if result.is_ok() && !config.dry_run {
+            result = match &new_conf {
+                Some((path, contents)) => {
+                    let written = fs::write(path, contents);
+                    if written.is_ok()
+                        && seed
+                        && let Err(e) = cloudinit::write_seed(path, &config, &logs)
+                    {
+                        logs.lock().unwrap().push(format!("No cloud-init seed for {}: {}", path.display(), e));
+                    }
+                    written.map_err(|e| format!("cannot write {}: {}", path.display(), e))
+                }
+                None => {
+                    suspend::clear(&vm_conf);
+                    Ok(())
+                }
+            };
+        }
+        let message = match (&result, &new_conf) {
+            (Ok(()), _) if config.dry_run => format!("[dry-run] Restore of {} not written.", name),
+            (Ok(()), Some((path, _))) => format!("Restored {} as {}.", name, path.display()),
+            (Ok(()), None) => format!("Restored {} from {}.", name, backup.path.display()),
+            (Err(e), _) => format!("Restore of {} failed: {}", name, e),
+        };
+        logs.lock().unwrap().push(message);
+        jobs::finish(&jobs, job, &result);
+        webhook::post(&config.webhooks, &Event::job_finished("restore", &name, result.is_ok()), &logs);
+    });
+}
+
+/// Start the scheduled backups that are due: VMs with `interval_hours` whose newest
+/// backup, and last attempt, are at least that old. Called periodically by the daemon,
+/// or by the TUI when no daemon runs.
+pub fn run_due(config: &Config, jobs: &Jobs, logs: &Arc<Mutex<Vec<String>>>) {
+    if config.dry_run || config.backups.values().all(|p| p.interval_hours.is_none()) {
+        return;
+    }
+    for vm_conf in list_vms(config) {
+        let Some(hours) = policy(&vm_conf, config).interval_hours else { continue };
+        let interval = hours.max(1) * 3600;
+        let newest = list(&vm_conf, config).last().map_or(0, |b| b.time);
+        let attempted = jobs::last_started(jobs, "backup", &vm_name(&vm_conf)).unwrap_or(0);
+        if now().saturating_sub(newest.max(attempted)) >= interval {
+            start(&vm_conf, false, config, jobs, logs);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backups(kinds: &str) -> Vec<Backup> {
+        kinds
+            .chars()
+            .enumerate()
+            .map(|(i, kind)| Backup { path: PathBuf::from(i.to_string()), time: i as u64, full: kind == 'F' })
+            .collect()
+    }
+
+    fn shapes(chains: &[&[Backup]]) -> Vec<String> {
+        chains.iter().map(|chain| chain.iter().map(|b| if b.full { 'F' } else { 'i' }).collect()).collect()
+    }
+
+    #[test]
+    fn chains_start_at_full_backups() {
+        assert_eq!(shapes(&chains(&backups("FiiFiF"))), ["Fii", "Fi", "F"]);
+        assert_eq!(shapes(&chains(&backups("iiFi"))), ["ii", "Fi"]);
+        assert!(chains(&backups("")).is_empty());
+    }
+
+    #[test]
+    fn next_backup_kind() {
+        let every = |n| BackupPolicy { full_every: Some(n), ..BackupPolicy::default() };
+        assert!(next_is_full(&backups(""), &every(3), false));
+        assert!(next_is_full(&backups("ii"), &every(3), false));
+        assert!(!next_is_full(&backups("Fi"), &every(3), false));
+        assert!(next_is_full(&backups("Fii"), &every(3), false));
+        assert!(next_is_full(&backups("Fi"), &every(3), true));
+        assert!(next_is_full(&backups("F"), &BackupPolicy::default(), false));
+    }
+
+    #[test]
+    fn qemu_img_progress() {
+        assert_eq!(parse_progress("    (12.34/100%)"), Some(12.34));
+        assert_eq!(parse_progress("(100.00/100%)\r"), Some(100.0));
+        assert_eq!(parse_progress("qemu-img: error"), None);
+        assert_eq!(parse_progress(""), None);
+    }
+
+    #[test]
+    fn retention_keeps_the_newest_chains() {
+        let dir = std::env::temp_dir().join(format!("quick-cli-backup-test-{}", std::process::id()));
+        let mut config = Config { backup_dir: dir.clone(), ..Config::default() };
+        config.backups.insert("vm".into(), BackupPolicy { keep: Some(2), ..BackupPolicy::default() });
+        let vm_conf = Path::new("/vms/vm.conf");
+        fs::create_dir_all(vm_backup_dir(vm_conf, &config)).unwrap();
+        let now = now();
+        for (age, kind) in [(50, "full"), (40, "incr"), (30, "full"), (20, "incr"), (10, "full"), (5, "partial")] {
+            let name = format!("{}-{}.qcow2", now - age, kind);
+            fs::write(vm_backup_dir(vm_conf, &config).join(name), "").unwrap();
+        }
+        assert_eq!(apply_retention(vm_conf, &config), Ok(2));
+        let left: Vec<u64> = list(vm_conf, &config).iter().map(|b| now - b.time).collect();
+        assert_eq!(left, [30, 20, 10]);
+
+        // Expired chains go too, but never the newest one.
+        config.backups.get_mut("vm").unwrap().max_age_days = Some(0);
+        assert_eq!(apply_retention(vm_conf, &config), Ok(2));
+        assert_eq!(list(vm_conf, &config).len(), 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,55 @@
+///////////////////////////////////////////////////////////////////////////////
+// Leader-Key Chords
+///////////////////////////////////////////////////////////////////////////////
+//
+// Single letters are running out, so less frequent actions sit behind the `g`
+// leader: `g` opens a hint popup listing the continuations and the next key
+// runs one (anything else cancels). Actions on a VM apply to the selected one
+// and go through the command line, e.g. `g s` runs `:snapshot <vm>`.
+
+#[derive(Clone, Copy)]
+pub enum ChordAction {
+    Snapshot,
+    Backup,
+    Jobs,
+    Isos,
+    Clean,
+    Firewall,
+    Export,
+    First,
+    Last,
+}
+
+pub const LEADER: char = 'g';
+
+/// Second key, hint label and action of every chord, in hint order.
+pub const CHORDS: [(char, &str, ChordAction); 9] = [
+    ('s', "Snapshot the VM", ChordAction::Snapshot),
+    ('b', "Back up the VM", ChordAction::Backup),
+    ('d', "Downloads and other jobs", ChordAction::Jobs),
+    ('i', "ISO images", ChordAction::Isos),
+    ('c', "Clean stale runtime files", ChordAction::Clean),
+    ('f', "Open the VM's ports in the firewall", ChordAction::Firewall),
+    ('x', "Export launchers", ChordAction::Export),
+    ('g', "First VM", ChordAction::First),
+    ('e', "Last VM", ChordAction::Last),
+];
+
+pub fn find(key: char) -> Option<ChordAction> {
+    CHORDS.iter().find(|(c, ..)| *c == key).map(|(_, _, action)| *action)
+}
+
+impl ChordAction {
+    /// The command line this runs for a VM, for the actions that are commands.
+    pub fn command(self, vm: &str) -> Option<String> {
+        let command = match self {
+            ChordAction::Snapshot => "snapshot",
+            ChordAction::Backup => "backup",
+            ChordAction::Clean => "clean",
+            ChordAction::Firewall => "firewall",
+            ChordAction::Export => "export",
+            ChordAction::Jobs | ChordAction::Isos | ChordAction::First | ChordAction::Last => return None,
+        };
+        Some(format!("{} {}", command, vm))
+    }
+}
@@ -0,0 +1,113 @@
+///////////////////////////////////////////////////////////////////////////////
+// Command-Line Arguments
+///////////////////////////////////////////////////////////////////////////////
+//
+// Usage: quick-cli [--config <path>] [--read-only] [--dry-run] [--demo] [-v] [--connect <vm>]
+//        [--remote <addr>] [daemon [--events] | events | apply <manifest> |
+//        watch [--changes | --line] [--once] | migrate-config [--from <path>] [--force]]
+
+use std::path::PathBuf;
+
+use quick_cli::watch::WatchMode;
+
+pub const USAGE: &str = "\
+Usage: quick-cli [OPTIONS] [COMMAND]
+
+Commands:
+  daemon            Run the status poller, watchdog and control socket
+                    without a UI (--events also prints VM events to stdout
+                    as NDJSON)
+  events            Print VM state changes to stdout as NDJSON
+  apply <manifest>  Create, configure, start and stop VMs to match a
+                    manifest TOML file (see the README)
+  watch             Print a table of VM states, kept up to date
+                    (--changes: a line per state change, --line: a one-line
+                    summary, --once: print once and exit)
+  migrate-config    Convert ~/.quick-cli.conf (or --from <path>) to the
+                    TOML config file and list the lines it could not map
+                    (--force replaces an existing config file)
+
+Options:
+  --config <path>   Use this config file instead of $QUICK_CLI_CONFIG or
+                    $XDG_CONFIG_HOME/quick-cli/config.toml
+  --connect <vm>    Start the VM if needed, open its viewer and exit
+                    (used by launchers from :export)
+  --remote <addr>   Drive the daemon at host:port (its daemon_listen) or at a
+                    forwarded control socket path instead of local VMs
+  --read-only       Only view and connect; refuse start, stop and other changes
+  --dry-run         Log the commands that would be run (quickemu, viewers)
+                    instead of running them
+  --demo            Show made-up VMs whose starts and stops are simulated,
+                    without quickemu (implies --dry-run)
+  -v, --verbose     Log every spawned command line and environment override
+  -h, --help        Show this help";
+
+#[derive(Default)]
+pub struct Cli {
+    pub command: Option<String>,
+    pub config_path: Option<PathBuf>,
+    pub connect: Option<String>,
+    pub remote: Option<String>,
+    pub read_only: bool,
+    pub dry_run: bool,
+    pub demo: bool,
+    pub verbose: bool,
+    pub events: bool,
+    pub manifest: Option<PathBuf>, // Path given to `apply`
+    pub from: Option<PathBuf>,     // Legacy config `migrate-config` converts
+    pub force: bool,
+    pub watch_mode: WatchMode,
+    pub once: bool,
+    pub help: bool,
+}
+
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Cli, String> {
+    let mut cli = Cli::default();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => {
+                let path = args.next().ok_or("--config needs a path")?;
+                cli.config_path = Some(PathBuf::from(path));
+            }
+            "--connect" => cli.connect = Some(args.next().ok_or("--connect needs a VM name")?),
+            "--remote" => cli.remote = Some(args.next().ok_or("--remote needs host:port or a socket path")?),
+            "--read-only" => cli.read_only = true,
+            "--dry-run" => cli.dry_run = true,
+            "--demo" => cli.demo = true,
+            "-v" | "--verbose" => cli.verbose = true,
+            "-h" | "--help" => cli.help = true,
+            "daemon" | "events" | "watch" | "migrate-config" if cli.command.is_none() => cli.command = Some(arg),
+            "apply" if cli.command.is_none() => {
+                cli.manifest = Some(PathBuf::from(args.next().ok_or("apply needs a manifest path")?));
+                cli.command = Some(arg);
+            }
+            "--events" => cli.events = true,
+            "--changes" => cli.watch_mode = WatchMode::Changes,
+            "--line" => cli.watch_mode = WatchMode::Line,
+            "--once" => cli.once = true,
+            "--from" => cli.from = Some(PathBuf::from(args.next().ok_or("--from needs a path")?)),
+            "--force" => cli.force = true,
+            other => {
+                if let Some(path) = other.strip_prefix("--config=") {
+                    cli.config_path = Some(PathBuf::from(path));
+                } else {
+                    return Err(format!("unexpected argument: {}", other));
+                }
+            }
+        }
+    }
+    if cli.demo && cli.remote.is_some() {
+        return Err("--demo cannot be combined with --remote".into());
+    }
+    if cli.events && cli.command.as_deref() != Some("daemon") {
+        return Err("--events is only valid with the daemon command".into());
+    }
+    if (cli.once || cli.watch_mode != WatchMode::Table) && cli.command.as_deref() != Some("watch") {
+        return Err("--changes, --line and --once are only valid with the watch command".into());
+    }
+    if (cli.from.is_some() || cli.force) && cli.command.as_deref() != Some("migrate-config") {
+        return Err("--from and --force are only valid with the migrate-config command".into());
+    }
+    Ok(cli)
+}
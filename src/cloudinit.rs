@@ -0,0 +1,295 @@
+///////////////////////////////////////////////////////////////////////////////
+// Cloud-init Seeds
+///////////////////////////////////////////////////////////////////////////////
+//
+// `:create <os> <release> [edition] --cloud-init` runs quickget and then gives
+// the new Linux VM a NoCloud seed: user-data and meta-data rendered from the
+// `[cloud_init]` templates into {vm dir}/cloud-init/, packed into a `cidata`
+// ISO at {vm dir}/seed.iso and attached with quickemu's `fixed_iso`. Guests
+// whose image runs cloud-init (cloud images, Ubuntu's autoinstall) come up
+// with the hostname, a user and the SSH keys, ready for `S`.
+//
+// Templates may use {hostname}, {user}, {ssh_keys} (a YAML flow list) and
+// {instance_id}. A VM restored as a clone can get a seed too: its fresh
+// instance-id makes cloud-init in the guest add the keys again.
+//
+// Guests without cloud-init get the same keys from `:sshkey <vm> [user]`
+// through the QEMU guest agent (guest-ssh-add-authorized-keys, QEMU 5.2+).
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    describe_command,
+    events::{now, Event},
+    expand_path, find_in_path, guest_os,
+    download,
+    jobs::{self, Jobs},
+    quickget_command, snapshot, vm_conf_value, vm_dir, webhook, Config, GuestOs,
+};
+
+const USER_DATA: &str = "#cloud-config
+hostname: {hostname}
+users:
+  - name: {user}
+    sudo: ALL=(ALL) NOPASSWD:ALL
+    shell: /bin/bash
+    lock_passwd: true
+    ssh_authorized_keys: {ssh_keys}
+ssh_pwauth: false
+";
+
+const META_DATA: &str = "instance-id: {instance_id}
+local-hostname: {hostname}
+";
+
+/// The `[cloud_init]` table.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CloudInit {
+    pub user: Option<String>,       // Guest user to create (default: ssh_user, then the host user)
+    pub ssh_keys: Vec<String>,      // Public key files or literal keys (default: ~/.ssh/id_*.pub)
+    pub user_data: Option<PathBuf>, // user-data template replacing the built-in one
+    pub meta_data: Option<PathBuf>, // meta-data template replacing the built-in one
+}
+
+/// Lowercase letters, digits and dashes, as hostnames allow.
+fn hostname(vm_conf: &Path) -> String {
+    let stem = vm_conf.file_stem().unwrap().to_string_lossy().to_lowercase();
+    let name: String = stem.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '-' }).collect();
+    name.trim_matches('-').to_string()
+}
+
+pub fn user(config: &Config) -> String {
+    config
+        .cloud_init
+        .user
+        .clone()
+        .or_else(|| config.ssh_user.clone())
+        .or_else(|| std::env::var("USER").ok())
+        .or_else(|| std::env::var("USERNAME").ok())
+        .unwrap_or_else(|| "user".into())
+}
+
+fn is_key(text: &str) -> bool {
+    ["ssh-", "ecdsa-", "sk-"].iter().any(|prefix| text.starts_with(prefix))
+}
+
+/// Public keys from `ssh_keys`, or every ~/.ssh/id_*.pub when it is empty.
+pub fn ssh_keys(config: &Config) -> Result<Vec<String>, String> {
+    let mut entries = config.cloud_init.ssh_keys.clone();
+    if entries.is_empty() {
+        let ssh = dirs::home_dir().unwrap_or_default().join(".ssh");
+        let mut files: Vec<String> = fs::read_dir(&ssh)
+            .map(|dir| dir.flatten().map(|e| e.path().to_string_lossy().to_string()).collect())
+            .unwrap_or_default();
+        files.retain(|f| {
+            let name = Path::new(f).file_name().unwrap_or_default().to_string_lossy();
+            name.starts_with("id_") && name.ends_with(".pub")
+        });
+        files.sort();
+        entries = files;
+    }
+    let mut keys = Vec::new();
+    for entry in &entries {
+        if is_key(entry) {
+            keys.push(entry.trim().to_string());
+            continue;
+        }
+        let path = expand_path(Path::new(entry));
+        let contents = fs::read_to_string(&path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        keys.extend(contents.lines().map(str::trim).filter(|l| is_key(l)).map(String::from));
+    }
+    if keys.is_empty() {
+        return Err("no SSH public key found; set ssh_keys in [cloud_init]".into());
+    }
+    Ok(keys)
+}
+
+fn render(template: &str, vm_conf: &Path, config: &Config, keys: &[String]) -> String {
+    let host = hostname(vm_conf);
+    template
+        .replace("{hostname}", &host)
+        .replace("{user}", &user(config))
+        .replace("{ssh_keys}", &serde_json::to_string(keys).unwrap_or_default())
+        .replace("{instance_id}", &format!("{}-{}", host, now()))
+}
+
+fn template(path: &Option<PathBuf>, built_in: &str) -> Result<String, String> {
+    match path {
+        Some(path) => {
+            let path = expand_path(path);
+            fs::read_to_string(&path).map_err(|e| format!("cannot read {}: {}", path.display(), e))
+        }
+        None => Ok(built_in.to_string()),
+    }
+}
+
+/// genisoimage, mkisofs or xorriso, or hdiutil on macOS, packing `dir` into a `cidata` ISO.
+fn iso_command(dir: &Path, iso: &Path) -> Option<Command> {
+    for tool in ["genisoimage", "mkisofs", "xorriso"] {
+        if find_in_path(tool).is_none() {
+            continue;
+        }
+        let mut command = Command::new(tool);
+        if tool == "xorriso" {
+            command.args(["-as", "mkisofs"]);
+        }
+        command.arg("-output").arg(iso).args(["-volid", "cidata", "-joliet", "-rock"]).arg(dir);
+        return Some(command);
+    }
+    find_in_path("hdiutil").map(|tool| {
+        let mut command = Command::new(tool);
+        command.args(["makehybrid", "-o"]).arg(iso).args(["-iso", "-joliet", "-default-volume-name", "cidata"]);
+        command.arg(dir);
+        command
+    })
+}
+
+/// Point the .conf's fixed_iso at the seed, unless it already names another image.
+fn attach(vm_conf: &Path, iso_name: &str) -> Result<(), String> {
+    match vm_conf_value(vm_conf, "fixed_iso") {
+        Some(current) if current == iso_name => Ok(()),
+        Some(current) => Err(format!("fixed_iso is already set to {}; attach the seed by hand", current)),
+        None => {
+            let mut contents = fs::read_to_string(vm_conf).map_err(|e| e.to_string())?;
+            if !contents.is_empty() && !contents.ends_with('\n') {
+                contents.push('\n');
+            }
+            contents.push_str(&format!("fixed_iso=\"{}\"\n", iso_name));
+            fs::write(vm_conf, contents).map_err(|e| format!("cannot write {}: {}", vm_conf.display(), e))
+        }
+    }
+}
+
+/// Render the seed of a Linux VM, build its ISO and attach it. Returns the ISO path.
+pub fn write_seed(vm_conf: &Path, config: &Config, logs: &Arc<Mutex<Vec<String>>>) -> Result<PathBuf, String> {
+    if guest_os(vm_conf) != GuestOs::Linux {
+        return Err("cloud-init seeds are only made for Linux guests".into());
+    }
+    let keys = ssh_keys(config)?;
+    let user_data = render(&template(&config.cloud_init.user_data, USER_DATA)?, vm_conf, config, &keys);
+    let meta_data = render(&template(&config.cloud_init.meta_data, META_DATA)?, vm_conf, config, &keys);
+    let dir = vm_dir(vm_conf);
+    let sources = dir.join("cloud-init");
+    let iso = dir.join("seed.iso");
+    let Some(mut command) = iso_command(&sources, &iso) else {
+        return Err("building the seed ISO needs genisoimage, mkisofs, xorriso or hdiutil".into());
+    };
+    if config.dry_run {
+        logs.lock().unwrap().push(format!("[dry-run] {}", describe_command(&command)));
+        return Ok(iso);
+    }
+    fs::create_dir_all(&sources).map_err(|e| format!("cannot create {}: {}", sources.display(), e))?;
+    fs::write(sources.join("user-data"), user_data).map_err(|e| e.to_string())?;
+    fs::write(sources.join("meta-data"), meta_data).map_err(|e| e.to_string())?;
+    let _ = fs::remove_file(&iso); // hdiutil refuses to overwrite
+    let output = command.stdin(Stdio::null()).output().map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(stderr.lines().last().unwrap_or("building the seed ISO failed").trim().to_string());
+    }
+    let stem = vm_conf.file_stem().unwrap().to_string_lossy();
+    attach(vm_conf, &format!("{}/seed.iso", stem))?;
+    Ok(iso)
+}
+
+/// Add the SSH keys to `user`'s authorized_keys through the guest agent, in a
+/// background thread, logging the outcome.
+pub fn inject_keys(vm_conf: &Path, user: &str, config: &Config, logs: &Arc<Mutex<Vec<String>>>) {
+    let keys = match ssh_keys(config) {
+        Ok(keys) => keys,
+        Err(e) => return logs.lock().unwrap().push(format!("Cannot add SSH keys: {}", e)),
+    };
+    let (vm_conf, user, logs) = (vm_conf.to_path_buf(), user.to_string(), Arc::clone(logs));
+    thread::spawn(move || {
+        let name = vm_conf.file_stem().unwrap().to_string_lossy().to_string();
+        let arguments = json!({ "username": user, "keys": keys, "reset": false });
+        let timeout = Duration::from_secs(10);
+        let message = match snapshot::guest_agent(&vm_conf, "guest-ssh-add-authorized-keys", Some(arguments), timeout) {
+            Ok(_) => format!("Added {} SSH keys for {} on {}.", keys.len(), user, name),
+            Err(e) => format!("Adding SSH keys on {} failed: {}", name, e),
+        };
+        logs.lock().unwrap().push(message);
+    });
+}
+
+fn conf_files(dir: &Path) -> Vec<PathBuf> {
+    fs::read_dir(dir)
+        .map(|entries| {
+            entries.flatten().map(|e| e.path()).filter(|p| p.extension().is_some_and(|e| e == "conf")).collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Run quickget in `dir` in a background thread, tracked as a "create" job, and give
+/// the VM it writes a cloud-init seed when `cloud_init` is set.
+pub fn create_vm(
+    dir: &Path,
+    args: &[String],
+    cloud_init: bool,
+    config: &Config,
+    jobs: &Jobs,
+    logs: &Arc<Mutex<Vec<String>>>,
+) {
+    let target = args.join("-");
+    if jobs::is_running(jobs, "create", &target) {
+        logs.lock().unwrap().push(format!("{} is already being created.", target));
+        return;
+    }
+    let words: Vec<&str> = args.iter().map(String::as_str).collect();
+    let mut command = quickget_command(config, &words);
+    command.current_dir(dir).stdin(Stdio::null());
+    if config.dry_run {
+        let _ = download::prefetch(dir, &words, config, &|message| logs.lock().unwrap().push(message), &|_| {});
+        logs.lock().unwrap().push(format!("[dry-run] {}", describe_command(&command)));
+        return;
+    }
+    let detail = format!("quickget {} in {}", words.join(" "), dir.display());
+    let job = jobs::add(jobs, "create", &target, detail.clone());
+    logs.lock().unwrap().push(format!("Creating {}: {} (downloads can take a while).", target, detail));
+    let (dir, config, jobs, logs) = (dir.to_path_buf(), config.clone(), Arc::clone(jobs), Arc::clone(logs));
+    let args = args.to_vec();
+    thread::spawn(move || {
+        let before = conf_files(&dir);
+        let words: Vec<&str> = args.iter().map(String::as_str).collect();
+        let log = |message: String| logs.lock().unwrap().push(message);
+        let progress = |percent: f32| jobs::set_progress(&jobs, job, percent);
+        if let Err(e) = download::prefetch(&dir, &words, &config, &log, &progress) {
+            log(format!("{}; quickget downloads the image instead.", e));
+        }
+        let result = match command.output() {
+            Ok(output) if output.status.success() => Ok(()),
+            Ok(output) => {
+                let text = String::from_utf8_lossy(&output.stdout).to_string();
+                let text = text + &String::from_utf8_lossy(&output.stderr);
+                Err(text.lines().rfind(|l| !l.trim().is_empty()).unwrap_or("quickget failed").trim().to_string())
+            }
+            Err(e) => Err(format!("cannot run quickget: {}", e)),
+        };
+        let created: Vec<PathBuf> = conf_files(&dir).into_iter().filter(|c| !before.contains(c)).collect();
+        let mut message = match (&result, created.first()) {
+            (Ok(()), Some(vm_conf)) => format!("Created {}.", vm_conf.display()),
+            (Ok(()), None) => format!("quickget finished but wrote no new .conf in {}.", dir.display()),
+            (Err(e), _) => format!("Creating {} failed: {}", target, e),
+        };
+        if let (Ok(()), Some(vm_conf), true) = (&result, created.first(), cloud_init) {
+            message = match write_seed(vm_conf, &config, &logs) {
+                Ok(iso) => format!("{} Attached cloud-init seed {}.", message, iso.display()),
+                Err(e) => format!("{} No cloud-init seed: {}", message, e),
+            };
+        }
+        logs.lock().unwrap().push(message);
+        jobs::finish(&jobs, job, &result);
+        webhook::post(&config.webhooks, &Event::job_finished("create", &target, result.is_ok()), &logs);
+    });
+}
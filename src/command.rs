@@ -0,0 +1,320 @@
+///////////////////////////////////////////////////////////////////////////////
+// Command Mode Parsing and Completion
+///////////////////////////////////////////////////////////////////////////////
+//
+// The `:` command line accepts:
+//     start <vm|all>
+//     stop <vm|all>
+//     connect <vm> [--spice]
+//     hmp <vm> <monitor command>
+//     snapshot <vm> [name]
+//     backup <vm|all> [full]
+//     create <os> <release> [edition] [--cloud-init]
+//     sshkey <vm> [user]
+//     firewall <vm> [close]
+//     export <vm|all>
+//     clean [vm|all]
+//     metrics <csv|json> [path]
+//     quit
+// VM names are matched case-insensitively against the config file stem.
+
+pub const COMMANDS: [&str; 13] = [
+    "start", "stop", "connect", "hmp", "snapshot", "backup", "create", "sshkey", "firewall", "export", "clean",
+    "metrics", "quit",
+];
+
+#[derive(Debug, PartialEq)]
+pub enum Target {
+    All,
+    Vm(String),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Start(Target),
+    Stop(Target),
+    Connect { vm: String, spice: bool },
+    Hmp { vm: String, command: String },
+    Snapshot { vm: String, name: Option<String> },
+    Backup { target: Target, full: bool },
+    Create { args: Vec<String>, cloud_init: bool }, // quickget arguments
+    SshKey { vm: String, user: Option<String> },
+    Firewall { vm: String, close: bool },
+    Export(Target),
+    Clean(Target),
+    Metrics { json: bool, path: Option<String> },
+    Quit,
+}
+
+fn parse_target(arg: Option<&str>, command: &str) -> Result<Target, String> {
+    match arg {
+        Some("all") => Ok(Target::All),
+        Some(vm) => Ok(Target::Vm(vm.to_string())),
+        None => Err(format!("{} needs a VM name or 'all'", command)),
+    }
+}
+
+pub fn parse_command(line: &str) -> Result<Command, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.first().copied() {
+        Some("start") if words.len() <= 2 => Ok(Command::Start(parse_target(words.get(1).copied(), "start")?)),
+        Some("stop") if words.len() <= 2 => Ok(Command::Stop(parse_target(words.get(1).copied(), "stop")?)),
+        Some("connect") => {
+            let mut vm = None;
+            let mut spice = false;
+            for word in &words[1..] {
+                match *word {
+                    "--spice" => spice = true,
+                    w if w.starts_with("--") => return Err(format!("unknown option {}", w)),
+                    w if vm.is_none() => vm = Some(w.to_string()),
+                    _ => return Err("connect takes a single VM name".into()),
+                }
+            }
+            let vm = vm.ok_or("connect needs a VM name")?;
+            Ok(Command::Connect { vm, spice })
+        }
+        Some("export") if words.len() <= 2 => Ok(Command::Export(parse_target(words.get(1).copied(), "export")?)),
+        Some("clean") if words.len() <= 2 => match words.get(1) {
+            Some(_) => Ok(Command::Clean(parse_target(words.get(1).copied(), "clean")?)),
+            None => Ok(Command::Clean(Target::All)),
+        },
+        Some("metrics") if words.len() <= 3 => {
+            let json = match words.get(1).copied() {
+                Some("csv") => false,
+                Some("json") => true,
+                _ => return Err("metrics needs a format: csv or json".into()),
+            };
+            Ok(Command::Metrics { json, path: words.get(2).map(|p| p.to_string()) })
+        }
+        Some("hmp") => {
+            // Keep the monitor command verbatim; only the VM name is split off.
+            let mut parts = line.trim().splitn(3, char::is_whitespace);
+            parts.next();
+            let vm = parts.next().ok_or("hmp needs a VM name and a monitor command")?;
+            let command = parts.next().map(str::trim).unwrap_or_default();
+            if command.is_empty() {
+                return Err("hmp needs a monitor command, e.g. hmp win11 info block".into());
+            }
+            Ok(Command::Hmp { vm: vm.to_string(), command: command.to_string() })
+        }
+        Some("snapshot") if (2..=3).contains(&words.len()) => {
+            Ok(Command::Snapshot { vm: words[1].to_string(), name: words.get(2).map(|n| n.to_string()) })
+        }
+        Some("snapshot") => Err("snapshot needs a VM name and optionally a snapshot name".into()),
+        Some("backup") if words.len() <= 3 => {
+            let full = match words.get(2).copied() {
+                Some("full") => true,
+                Some(other) => return Err(format!("unknown backup option {} (only 'full')", other)),
+                None => false,
+            };
+            Ok(Command::Backup { target: parse_target(words.get(1).copied(), "backup")?, full })
+        }
+        Some("create") => {
+            let mut args = Vec::new();
+            let mut cloud_init = false;
+            for word in &words[1..] {
+                match *word {
+                    "--cloud-init" => cloud_init = true,
+                    w if w.starts_with("--") => return Err(format!("unknown option {}", w)),
+                    w => args.push(w.to_string()),
+                }
+            }
+            if !(2..=3).contains(&args.len()) {
+                return Err("create needs an OS and a release for quickget, e.g. create ubuntu-server 24.04".into());
+            }
+            Ok(Command::Create { args, cloud_init })
+        }
+        Some("sshkey") if (2..=3).contains(&words.len()) => {
+            Ok(Command::SshKey { vm: words[1].to_string(), user: words.get(2).map(|u| u.to_string()) })
+        }
+        Some("sshkey") => Err("sshkey needs a VM name and optionally the guest user".into()),
+        Some("firewall") if (2..=3).contains(&words.len()) => {
+            let close = match words.get(2).copied() {
+                Some("close") => true,
+                Some(other) => return Err(format!("unknown firewall option {} (only 'close')", other)),
+                None => false,
+            };
+            Ok(Command::Firewall { vm: words[1].to_string(), close })
+        }
+        Some("firewall") => Err("firewall needs a VM name and optionally 'close'".into()),
+        Some("quit") | Some("q") if words.len() == 1 => Ok(Command::Quit),
+        Some(other) => Err(format!("unknown command: {}", other)),
+        None => Err("empty command".into()),
+    }
+}
+
+fn common_prefix<'a>(candidates: &[&'a str]) -> &'a str {
+    let first = candidates[0];
+    let mut len = first.len();
+    for c in &candidates[1..] {
+        len = first
+            .char_indices()
+            .zip(c.chars())
+            .take_while(|((_, a), b)| a == b)
+            .map(|((i, a), _)| i + a.len_utf8())
+            .last()
+            .unwrap_or(0)
+            .min(len);
+    }
+    &first[..len]
+}
+
+/// Complete the last word of the command line against commands, VM names and options.
+/// A unique match is completed and followed by a space; several matches are
+/// extended to their longest common prefix.
+pub fn complete(line: &str, vm_names: &[String]) -> Option<String> {
+    let (head, word) = match line.rfind(' ') {
+        Some(i) => (&line[..=i], &line[i + 1..]),
+        None => ("", line),
+    };
+    let command = head.split_whitespace().next();
+    let mut candidates: Vec<&str> = match command {
+        None => COMMANDS.to_vec(),
+        Some("start") | Some("stop") | Some("export") | Some("clean") => {
+            let mut c: Vec<&str> = vm_names.iter().map(String::as_str).collect();
+            c.push("all");
+            c
+        }
+        Some("connect") => {
+            let mut c: Vec<&str> = vm_names.iter().map(String::as_str).collect();
+            c.push("--spice");
+            c
+        }
+        Some("hmp") | Some("snapshot") | Some("sshkey") | Some("firewall") if head.split_whitespace().count() == 1 => {
+            vm_names.iter().map(String::as_str).collect()
+        }
+        Some("metrics") if head.split_whitespace().count() == 1 => vec!["csv", "json"],
+        Some("backup") if head.split_whitespace().count() == 1 => {
+            let mut c: Vec<&str> = vm_names.iter().map(String::as_str).collect();
+            c.push("all");
+            c
+        }
+        Some("backup") if head.split_whitespace().count() == 2 => vec!["full"],
+        Some("firewall") if head.split_whitespace().count() == 2 => vec!["close"],
+        Some("create") if head.split_whitespace().count() >= 3 => vec!["--cloud-init"],
+        Some(_) => return None,
+    };
+    let lower = word.to_lowercase();
+    candidates.retain(|c| c.to_lowercase().starts_with(&lower));
+    match candidates.len() {
+        0 => None,
+        1 => Some(format!("{}{} ", head, candidates[0])),
+        _ => Some(format!("{}{}", head, common_prefix(&candidates))).filter(|l| l.len() > line.len()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vm(name: &str) -> Target {
+        Target::Vm(name.to_string())
+    }
+
+    fn strings(words: &[&str]) -> Vec<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_commands_and_arguments() {
+        let cases = [
+            ("start win11", Command::Start(vm("win11"))),
+            ("  stop   all  ", Command::Stop(Target::All)),
+            ("connect debian-12 --spice", Command::Connect { vm: "debian-12".into(), spice: true }),
+            ("connect --spice debian-12", Command::Connect { vm: "debian-12".into(), spice: true }),
+            ("connect debian-12", Command::Connect { vm: "debian-12".into(), spice: false }),
+            ("hmp win11 info block", Command::Hmp { vm: "win11".into(), command: "info block".into() }),
+            // The monitor command is passed through verbatim, quotes and inner spacing included.
+            (
+                "hmp win11 savevm \"before update\"  now",
+                Command::Hmp { vm: "win11".into(), command: "savevm \"before update\"  now".into() },
+            ),
+            ("export all", Command::Export(Target::All)),
+            ("clean", Command::Clean(Target::All)),
+            ("clean win11", Command::Clean(vm("win11"))),
+            ("metrics csv", Command::Metrics { json: false, path: None }),
+            ("metrics json /tmp/m.json", Command::Metrics { json: true, path: Some("/tmp/m.json".into()) }),
+            ("snapshot win11", Command::Snapshot { vm: "win11".into(), name: None }),
+            ("snapshot win11 clean", Command::Snapshot { vm: "win11".into(), name: Some("clean".into()) }),
+            ("backup all full", Command::Backup { target: Target::All, full: true }),
+            ("backup win11", Command::Backup { target: vm("win11"), full: false }),
+            (
+                "create ubuntu-server 24.04 --cloud-init",
+                Command::Create { args: strings(&["ubuntu-server", "24.04"]), cloud_init: true },
+            ),
+            (
+                "create windows 11 English",
+                Command::Create { args: strings(&["windows", "11", "English"]), cloud_init: false },
+            ),
+            ("sshkey debian-12 admin", Command::SshKey { vm: "debian-12".into(), user: Some("admin".into()) }),
+            ("firewall win11 close", Command::Firewall { vm: "win11".into(), close: true }),
+            ("firewall win11", Command::Firewall { vm: "win11".into(), close: false }),
+            ("quit", Command::Quit),
+            ("q", Command::Quit),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(parse_command(line), Ok(expected), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn parse_rejects_unknown_and_malformed_commands() {
+        let cases = [
+            ("", "empty command"),
+            ("   ", "empty command"),
+            ("reboot win11", "unknown command: reboot"),
+            ("Start win11", "unknown command: Start"),
+            ("start win11 extra", "unknown command: start"),
+            ("quit now", "unknown command: quit"),
+            ("start", "start needs a VM name or 'all'"),
+            ("connect", "connect needs a VM name"),
+            ("connect win11 debian-12", "connect takes a single VM name"),
+            ("connect win11 --vnc", "unknown option --vnc"),
+            ("hmp win11", "hmp needs a monitor command, e.g. hmp win11 info block"),
+            ("hmp", "hmp needs a VM name and a monitor command"),
+            ("export", "export needs a VM name or 'all'"),
+            ("metrics xml", "metrics needs a format: csv or json"),
+            ("snapshot", "snapshot needs a VM name and optionally a snapshot name"),
+            ("backup", "backup needs a VM name or 'all'"),
+            ("backup win11 incremental", "unknown backup option incremental (only 'full')"),
+            ("create ubuntu", "create needs an OS and a release for quickget, e.g. create ubuntu-server 24.04"),
+            ("create ubuntu 24.04 --iso", "unknown option --iso"),
+            ("sshkey", "sshkey needs a VM name and optionally the guest user"),
+            ("firewall win11 open", "unknown firewall option open (only 'close')"),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(parse_command(line), Err(expected.to_string()), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn complete_prefixes() {
+        let vms = strings(&["win11", "win10", "debian-12"]);
+        let cases = [
+            // Command names.
+            ("sta", Some("start ")),
+            ("Con", Some("connect ")),
+            ("st", None),
+            ("xyz", None),
+            // VM names and `all`, matched case-insensitively.
+            ("start d", Some("start debian-12 ")),
+            ("start W", Some("start win1")),
+            ("stop a", Some("stop all ")),
+            ("connect win11 --", Some("connect win11 --spice ")),
+            ("hmp deb", Some("hmp debian-12 ")),
+            ("hmp debian-12 in", None),
+            ("ex", Some("export ")),
+            ("metrics j", Some("metrics json ")),
+            ("backup win11 f", Some("backup win11 full ")),
+            ("create ubuntu 24.04 --c", Some("create ubuntu 24.04 --cloud-init ")),
+            ("create ubu", None),
+            ("firewall win11 c", Some("firewall win11 close ")),
+            ("quit x", None),
+            // Nothing longer than what was typed.
+            ("start win1", None),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(complete(line, &vms), expected.map(str::to_string), "{:?}", line);
+        }
+    }
+}
@@ -0,0 +1,129 @@
+///////////////////////////////////////////////////////////////////////////////
+// VM Config Snapshots and Diff
+///////////////////////////////////////////////////////////////////////////////
+//
+// Each time quick-cli starts or stops a VM, its .conf is copied to
+// $XDG_STATE_HOME/quick-cli/snapshots. The `D` view diffs the current file
+// against that copy, showing edits made since and settings rewritten by
+// quickemu upgrades.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::state_dir;
+
+pub enum DiffLine {
+    Same(String),
+    Added(String),
+    Removed(String),
+}
+
+/// Snapshot file for a VM config. The full path is encoded in the name so VMs
+/// with the same name in different directories do not collide.
+fn snapshot_path(vm_conf: &Path) -> PathBuf {
+    let encoded: String = vm_conf
+        .to_string_lossy()
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+        .collect();
+    state_dir().join("snapshots").join(encoded)
+}
+
+/// Record the VM config as last known state.
+pub fn snapshot(vm_conf: &Path) -> std::io::Result<()> {
+    let path = snapshot_path(vm_conf);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::copy(vm_conf, path).map(|_| ())
+}
+
+/// Line diff of the last snapshot against the current config, or None without a snapshot.
+pub fn diff_with_snapshot(vm_conf: &Path) -> Option<Vec<DiffLine>> {
+    let old = fs::read_to_string(snapshot_path(vm_conf)).ok()?;
+    let new = fs::read_to_string(vm_conf).unwrap_or_default();
+    Some(diff(&old, &new))
+}
+
+/// Minimal line diff based on the longest common subsequence. VM configs are a
+/// few dozen lines at most, so the quadratic table is fine.
+fn diff(old: &str, new: &str) -> Vec<DiffLine> {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut lines = Vec::new();
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            lines.push(DiffLine::Same(a[i].to_string()));
+            i += 1;
+            j += 1;
+        } else if j < b.len() && (i == a.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            lines.push(DiffLine::Added(b[j].to_string()));
+            j += 1;
+        } else {
+            lines.push(DiffLine::Removed(a[i].to_string()));
+            i += 1;
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use DiffLine::{Added, Removed, Same};
+
+    fn marked(lines: &[DiffLine]) -> Vec<(char, &str)> {
+        lines
+            .iter()
+            .map(|line| match line {
+                Same(l) => (' ', l.as_str()),
+                Added(l) => ('+', l.as_str()),
+                Removed(l) => ('-', l.as_str()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn diff_marks_edited_and_appended_lines() {
+        let old = "guest_os=\"linux\"\nram=\"4G\"\ndisk_img=\"d/disk.qcow2\"\n";
+        let new = "guest_os=\"linux\"\nram=\"8G\"\ndisk_img=\"d/disk.qcow2\"\ncpu_cores=\"4\"\n";
+        assert_eq!(
+            marked(&diff(old, new)),
+            [
+                (' ', "guest_os=\"linux\""),
+                ('+', "ram=\"8G\""),
+                ('-', "ram=\"4G\""),
+                (' ', "disk_img=\"d/disk.qcow2\""),
+                ('+', "cpu_cores=\"4\""),
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_edge_cases() {
+        assert!(diff("", "").is_empty());
+        assert_eq!(marked(&diff("a\nb\n", "a\nb")), [(' ', "a"), (' ', "b")]);
+        assert_eq!(marked(&diff("", "a\nb\n")), [('+', "a"), ('+', "b")]);
+        assert_eq!(marked(&diff("a\nb\n", "")), [('-', "a"), ('-', "b")]);
+        assert_eq!(
+            marked(&diff("a\nb\nc\n", "c\nb\na\n")),
+            [('+', "c"), ('+', "b"), (' ', "a"), ('-', "b"), ('-', "c")]
+        );
+    }
+
+    #[test]
+    fn snapshot_names_encode_the_full_path() {
+        let name = |p: &str| snapshot_path(Path::new(p)).file_name().unwrap().to_string_lossy().to_string();
+        assert_eq!(name("/vms/win 11.conf"), "_vms_win_11.conf");
+        assert_ne!(name("/vms/a/lab.conf"), name("/vms/b/lab.conf"));
+    }
+}
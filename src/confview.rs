@@ -0,0 +1,68 @@
+///////////////////////////////////////////////////////////////////////////////
+// Config Viewer
+///////////////////////////////////////////////////////////////////////////////
+//
+// `C` shows the selected VM's .conf read-only with simple highlighting; Tab
+// switches to quick-cli's own config.toml. Both formats are `key = value`
+// lines with `#` comments, plus `[table]` headers in TOML.
+
+use std::{fs, path::PathBuf};
+
+use ratatui::{
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+};
+
+pub struct ConfigView {
+    pub path: PathBuf,
+    pub text: Result<String, String>, // Error message when the file cannot be read
+    pub scroll: u16,
+    pub showing_vm: bool, // Which of the two files is shown, for Tab
+}
+
+impl ConfigView {
+    pub fn open(path: PathBuf, showing_vm: bool) -> Self {
+        let text = fs::read_to_string(&path).map_err(|e| format!("cannot read {}: {}", path.display(), e));
+        Self { path, text, scroll: 0, showing_vm }
+    }
+
+    pub fn lines(&self) -> Vec<Line<'static>> {
+        match &self.text {
+            Ok(text) => text.lines().map(highlight).collect(),
+            Err(e) => vec![Line::from(Span::styled(e.clone(), Style::default().fg(Color::Red)))],
+        }
+    }
+}
+
+fn value_style(value: &str) -> Style {
+    let v = value.trim();
+    if v.starts_with('"') || v.starts_with('\'') {
+        Style::default().fg(Color::Green)
+    } else if v == "true" || v == "false" || v.parse::<f64>().is_ok() {
+        Style::default().fg(Color::Yellow)
+    } else {
+        Style::default().fg(Color::White)
+    }
+}
+
+/// Highlight one line: comments, table headers, and key/value pairs.
+fn highlight(line: &str) -> Line<'static> {
+    let trimmed = line.trim_start();
+    if trimmed.starts_with('#') {
+        return Line::from(Span::styled(line.to_string(), Style::default().fg(Color::DarkGray)));
+    }
+    if trimmed.starts_with('[') {
+        return Line::from(Span::styled(
+            line.to_string(),
+            Style::default().fg(Color::Magenta).add_modifier(Modifier::BOLD),
+        ));
+    }
+    match line.split_once('=') {
+        Some((key, value)) => Line::from(vec![
+            Span::styled(key.to_string(), Style::default().fg(Color::Cyan)),
+            Span::raw("="),
+            Span::styled(value.to_string(), value_style(value)),
+        ]),
+        None => Line::from(line.to_string()),
+    }
+}
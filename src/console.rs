@@ -0,0 +1,113 @@
+///////////////////////////////////////////////////////////////////////////////
+// SSH and Serial Consoles
+///////////////////////////////////////////////////////////////////////////////
+//
+// `S` opens SSH to the port quickemu forwards (the "ssh" entry of {vm}.ports);
+// `O` attaches to the serial console socket ({vm}-serial.socket) with socat.
+// Inside tmux ($TMUX), zellij ($ZELLIJ) or Windows Terminal ($WT_SESSION) the
+// console can open in a new pane or window/tab (`multiplexer = "pane" | "window"`);
+// otherwise the TUI is suspended until the console exits.
+
+use std::{path::Path, process::Command};
+
+use crate::{read_runtime, vm_runtime_file};
+
+#[derive(Clone, Copy)]
+pub enum ConsoleKind {
+    Ssh,
+    Serial,
+}
+
+#[derive(Clone, Copy)]
+enum Multiplexer {
+    Tmux,
+    Zellij,
+    WindowsTerminal,
+}
+
+fn detect_multiplexer() -> Option<Multiplexer> {
+    if std::env::var_os("TMUX").is_some() {
+        Some(Multiplexer::Tmux)
+    } else if std::env::var_os("ZELLIJ").is_some() {
+        Some(Multiplexer::Zellij)
+    } else if std::env::var_os("WT_SESSION").is_some() {
+        Some(Multiplexer::WindowsTerminal)
+    } else {
+        None
+    }
+}
+
+/// Host port quickemu forwards to the guest's SSH server.
+pub fn ssh_port(vm_conf: &Path) -> Result<u16, String> {
+    read_runtime(vm_conf).ports.get("ssh").copied().ok_or_else(|| "no ssh port in the .ports file".into())
+}
+
+/// Command line for the console, or why it is not available.
+pub fn console_command(
+    kind: ConsoleKind,
+    vm_conf: &Path,
+    host: &str,
+    ssh_user: Option<&str>,
+) -> Result<Vec<String>, String> {
+    match kind {
+        ConsoleKind::Ssh => {
+            let port = ssh_port(vm_conf)?;
+            // ssh takes IPv6 literals without brackets.
+            let target = match ssh_user {
+                Some(user) => format!("{}@{}", user, host),
+                None => host.to_string(),
+            };
+            Ok(vec!["ssh".into(), "-p".into(), port.to_string(), target])
+        }
+        ConsoleKind::Serial => {
+            let socket = vm_runtime_file(vm_conf, "-serial.socket");
+            if !socket.exists() {
+                return Err(format!("{} does not exist", socket.display()));
+            }
+            Ok(vec!["socat".into(), "-,raw,echo=0".into(), format!("UNIX-CONNECT:{}", socket.display())])
+        }
+    }
+}
+
+/// Build the command that opens `words` in a new multiplexer pane or window, if
+/// quick-cli runs inside one and `mode` asks for it.
+pub fn multiplexer_command(mode: &str, title: &str, words: &[String]) -> Option<Command> {
+    let mux = detect_multiplexer()?;
+    let mut command = match (mux, mode) {
+        (Multiplexer::Tmux, "window") => {
+            let mut c = Command::new("tmux");
+            c.args(["new-window", "-n", title, "--"]);
+            c
+        }
+        (Multiplexer::Tmux, "pane") => {
+            let mut c = Command::new("tmux");
+            c.args(["split-window", "-h", "--"]);
+            c
+        }
+        // zellij has no "run in a new tab"; a floating pane is the closest to a window.
+        (Multiplexer::Zellij, "window") => {
+            let mut c = Command::new("zellij");
+            c.args(["run", "--floating", "--name", title, "--"]);
+            c
+        }
+        (Multiplexer::Zellij, "pane") => {
+            let mut c = Command::new("zellij");
+            c.args(["run", "--name", title, "--"]);
+            c
+        }
+        // `-w 0` targets the window quick-cli runs in rather than opening a new one.
+        (Multiplexer::WindowsTerminal, "window") => {
+            let mut c = Command::new("wt.exe");
+            c.args(["-w", "0", "new-tab", "--title", title, "--"]);
+            c
+        }
+        (Multiplexer::WindowsTerminal, "pane") => {
+            let mut c = Command::new("wt.exe");
+            c.args(["-w", "0", "split-pane", "-V", "--title", title, "--"]);
+            c
+        }
+        _ => return None,
+    };
+    command.args(words);
+    Some(command)
+}
@@ -0,0 +1,133 @@
+///////////////////////////////////////////////////////////////////////////////
+// Terminal Restoration
+///////////////////////////////////////////////////////////////////////////////
+//
+// The TUI runs the terminal in raw mode on the alternate screen. Whichever way
+// it ends (quit, an error, a panic, SIGTERM or SIGINT) the terminal is put
+// back first, so the shell stays usable and any message is readable. A panic
+// also leaves a crash report in $XDG_STATE_HOME/quick-cli/crash-<time>.log
+// with the message, a backtrace and the last log lines. A panic on a background
+// thread only writes the report and logs where it is; the TUI keeps running.
+
+use std::{
+    backtrace::Backtrace,
+    fs,
+    io,
+    panic::{self, PanicHookInfo},
+    path::PathBuf,
+    sync::{atomic::AtomicBool, Arc, Mutex, TryLockError},
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crossterm::{
+    cursor::Show,
+    execute,
+    terminal::{disable_raw_mode, LeaveAlternateScreen},
+};
+
+use quick_cli::state_dir;
+
+const REPORT_LOG_LINES: usize = 200;
+
+/// Restores the terminal when dropped, including on early returns from the TUI loop.
+pub struct TerminalGuard;
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore();
+    }
+}
+
+fn restore() {
+    let _ = disable_raw_mode();
+    let _ = execute!(io::stdout(), LeaveAlternateScreen, Show);
+}
+
+/// Install the panic hook. Call before entering raw mode.
+pub fn install(logs: &Arc<Mutex<Vec<String>>>) {
+    let logs = Arc::clone(logs);
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let on_main = thread::current().name() == Some("main");
+        if on_main {
+            restore();
+        }
+        let report = write_report(info, &logs);
+        if on_main {
+            previous(info);
+            match report {
+                Ok(path) => eprintln!("quick-cli crashed; report written to {}", path.display()),
+                Err(e) => eprintln!("quick-cli crashed; cannot write a crash report: {}", e),
+            }
+        } else if let Some(mut l) = lock(&logs) {
+            match report {
+                Ok(path) => l.push(format!("A background task crashed; report written to {}", path.display())),
+                Err(e) => l.push(format!("A background task crashed: {} (no report: {})", message(info), e)),
+            }
+        }
+    }));
+}
+
+/// Set when SIGTERM or SIGINT arrives, so the TUI loop can end normally. SIGHUP keeps
+/// its default: the terminal is gone, and crossterm would spin polling it.
+#[cfg(unix)]
+pub fn termination_flag() -> Arc<AtomicBool> {
+    use signal_hook::consts::{SIGINT, SIGTERM};
+    let flag = Arc::new(AtomicBool::new(false));
+    for signal in [SIGTERM, SIGINT] {
+        let _ = signal_hook::flag::register(signal, Arc::clone(&flag));
+    }
+    flag
+}
+
+#[cfg(not(unix))]
+pub fn termination_flag() -> Arc<AtomicBool> {
+    Arc::new(AtomicBool::new(false))
+}
+
+/// The log buffer, unless the panicking thread holds it.
+fn lock(logs: &Mutex<Vec<String>>) -> Option<std::sync::MutexGuard<'_, Vec<String>>> {
+    match logs.try_lock() {
+        Ok(guard) => Some(guard),
+        Err(TryLockError::Poisoned(poisoned)) => Some(poisoned.into_inner()),
+        Err(TryLockError::WouldBlock) => None,
+    }
+}
+
+fn message(info: &PanicHookInfo) -> String {
+    let payload = info.payload();
+    payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+fn write_report(info: &PanicHookInfo, logs: &Mutex<Vec<String>>) -> io::Result<PathBuf> {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let dir = state_dir();
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("crash-{}.log", time));
+    let mut report = format!(
+        "quick-cli {} crashed at unix time {}\nThread: {}\nPanic: {}\nLocation: {}\n\nBacktrace:\n{}\n",
+        env!("CARGO_PKG_VERSION"),
+        time,
+        thread::current().name().unwrap_or("unnamed"),
+        message(info),
+        info.location().map(|l| l.to_string()).unwrap_or_else(|| "unknown".to_string()),
+        Backtrace::force_capture()
+    );
+    match lock(logs) {
+        Some(l) => {
+            report.push_str(&format!("Last {} log lines:\n", REPORT_LOG_LINES.min(l.len())));
+            for line in &l[l.len().saturating_sub(REPORT_LOG_LINES)..] {
+                report.push_str(line);
+                report.push('\n');
+            }
+        }
+        None => report.push_str("Log buffer unavailable (held by the crashing thread).\n"),
+    }
+    fs::write(&path, report)?;
+    Ok(path)
+}
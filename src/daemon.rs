@@ -0,0 +1,507 @@
+///////////////////////////////////////////////////////////////////////////////
+// Background Daemon and Control Socket
+///////////////////////////////////////////////////////////////////////////////
+//
+// `quick-cli daemon` runs the status poller, the watchdog (watchdog.rs) and a
+// line-based control socket without a terminal UI. The TUI connects to the
+// socket when it exists and forwards start/stop requests and status queries to
+// the daemon. With `--events` the daemon also prints VM events to stdout as
+// NDJSON (events.rs).
+// Scheduled backups (backup.rs) run here instead of in the TUI.
+//
+// With `daemon_listen` the same protocol is served over TCP, for a TUI on
+// another machine (`--remote host:port`); a forwarded unix socket works too
+// (`--remote /path/to.sock`). The remote TUI mirrors the VM definitions and
+// ports files into $XDG_STATE_HOME/quick-cli/remote/<addr> and launches
+// viewers locally against the forwarded ports.
+//
+// Protocol (one request per connection, newline terminated):
+//     status          -> "<vm>\t<running|stopped>" lines
+//     start <vm>      -> nothing
+//     stop <vm>       -> nothing
+//     vms             -> one JSON object per VM: name, .conf and .ports contents
+// Every response ends with a final "ok" or "err <message>" line. TCP clients
+// first send "auth <control_token>". Lines are at most 4096 bytes, and a TCP
+// client that sends nothing for 10 seconds is disconnected.
+//
+// The same requests are also available over gRPC with `grpc_listen` (grpc.rs).
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+#[cfg(unix)]
+use std::{
+    io,
+    net::TcpListener,
+    os::unix::net::{UnixListener, UnixStream},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    backup,
+    disk::disk_encryption,
+    firewall,
+    events::{self, Event, VmState, Watcher},
+    host::read_disk_free,
+    jobs::Jobs,
+    logsink::LogSink,
+    mdns::Advertiser,
+    notify::LowDiskAlarm,
+    watchdog::Watchdog,
+    is_vm_running, list_vms, metrics, mqtt, start_vm, state_dir, stop_vm, vm_dir, vm_runtime_file, webhook, Config,
+};
+
+/// Longest auth or request line a client may send.
+#[cfg(unix)]
+const MAX_LINE: u64 = 4096;
+
+/// How long a TCP client may take to send each line before it is dropped.
+#[cfg(unix)]
+const TCP_READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Lines of the log buffer kept once written to the sink, which has the full log.
+const KEPT_LOG_LINES: usize = 1000;
+
+/// Called with every VM event; returns false once it wants no more (e.g. a
+/// gRPC stream was closed) and is then dropped.
+pub(crate) type Subscriber = Box<dyn Fn(&Event) -> bool + Send>;
+
+/// Shared daemon state: latest state per VM stem, the log buffer, and the
+/// subscribers to VM events.
+pub(crate) struct DaemonState {
+    pub(crate) status: Mutex<HashMap<String, VmState>>,
+    pub(crate) logs: Arc<Mutex<Vec<String>>>,
+    pub(crate) subscribers: Mutex<Vec<Subscriber>>,
+}
+
+fn vm_stem(vm_conf: &Path) -> String {
+    vm_conf.file_stem().unwrap().to_string_lossy().to_string()
+}
+
+fn find_vm(config: &Config, name: &str) -> Option<PathBuf> {
+    list_vms(config).into_iter().find(|vm| vm_stem(vm) == name)
+}
+
+/// A VM as sent to remote TUIs by the `vms` request.
+#[derive(Serialize, Deserialize)]
+struct RemoteVm {
+    name: String,
+    conf: String,
+    ports: Option<String>,
+}
+
+/// Poll every VM, record status changes in the log, and write new log lines and
+/// events to the log sink (stderr, journald or syslog, see logsink.rs). With
+/// `--events` the changes are also printed to stdout as NDJSON. Changes are
+/// posted to the configured webhooks and states published over MQTT and mDNS; low
+/// disk space and the metrics alert thresholds trigger notifications. The watchdog
+/// starts `autostart` VMs and reports and restarts crashed ones. Firewall rules
+/// opened for VMs that stopped are removed.
+fn poll_loop(config: Arc<Config>, state: Arc<DaemonState>, print_events: bool) {
+    let (sink, fallback) = LogSink::open(&config);
+    state.logs.lock().unwrap().extend(fallback);
+    let mut printed = 0;
+    let mut watcher = Watcher::default();
+    let mut low_disk = LowDiskAlarm::default();
+    let mut advertiser = Advertiser::default();
+    let mut watchdog = Watchdog::default();
+    let _metrics = metrics::spawn_poller(&config, &state.logs, true);
+    let jobs = Jobs::default();
+    let mut last_backup_check: Option<Instant> = None;
+    loop {
+        let events = watcher.poll(&config);
+        {
+            let mut l = state.logs.lock().unwrap();
+            // Earlier lines go out first; events are written with their fields as they are added.
+            l[printed..].iter().for_each(|line| sink.line(line));
+            for event in &events {
+                l.push(event.describe());
+                sink.event(event);
+                if print_events {
+                    events::print_event(event);
+                }
+                if event.is_change() {
+                    webhook::post(&config.webhooks, event, &state.logs);
+                }
+                mqtt::publish(&config, event, &state.logs);
+            }
+            printed = trim_logs(&mut l);
+            // Update the states before notifying, so subscribers never miss a change.
+            *state.status.lock().unwrap() = watcher.states().clone();
+            state.subscribers.lock().unwrap().retain(|subscriber| events.iter().all(subscriber));
+        }
+        advertiser.sync(watcher.states(), &config, &state.logs);
+        watchdog.handle(&events, &config, &state.logs);
+        firewall::close_stopped(&watcher.running(), &config, &state.logs);
+        for dir in &config.quickemu_dirs {
+            low_disk.check(dir, read_disk_free(dir), &config, &state.logs);
+        }
+        if last_backup_check.is_none_or(|t| t.elapsed() >= backup::SCHEDULE_CHECK) {
+            backup::run_due(&config, &jobs, &state.logs);
+            last_backup_check = Some(Instant::now());
+        }
+        {
+            let mut l = state.logs.lock().unwrap();
+            l[printed..].iter().for_each(|line| sink.line(line));
+            printed = trim_logs(&mut l);
+        }
+        thread::sleep(config.status_poll_interval);
+    }
+}
+
+/// Drop the oldest lines past KEPT_LOG_LINES once all have been written; returns the new length.
+fn trim_logs(logs: &mut Vec<String>) -> usize {
+    let excess = logs.len().saturating_sub(KEPT_LOG_LINES);
+    logs.drain(..excess);
+    logs.len()
+}
+
+/// Execute a single control request and return the response body lines.
+pub(crate) fn handle_request(line: &str, config: &Config, state: &DaemonState) -> Result<Vec<String>, String> {
+    let line = line.trim();
+    let (command, arg) = match line.split_once(' ') {
+        Some((command, arg)) => (command, Some(arg.trim())),
+        None => (line, None),
+    };
+    match (command, arg) {
+        ("start" | "stop", Some(_)) if config.read_only => Err("daemon is in read-only mode".into()),
+        ("status", None) => {
+            let status = state.status.lock().unwrap();
+            let mut names: Vec<&String> = status.keys().collect();
+            names.sort();
+            Ok(names
+                .into_iter()
+                .map(|name| {
+                    let running = status[name] != VmState::Stopped;
+                    format!("{}\t{}", name, if running { "running" } else { "stopped" })
+                })
+                .collect())
+        }
+        ("start", Some(name)) => {
+            let vm_conf = find_vm(config, name).ok_or_else(|| format!("unknown VM {}", name))?;
+            let polled_running = state.status.lock().unwrap().get(name).is_some_and(|s| *s != VmState::Stopped);
+            if polled_running || is_vm_running(&vm_conf, config) {
+                return Err(format!("{} is already running", name));
+            }
+            if disk_encryption(&vm_conf).is_some() {
+                return Err(format!("{} has an encrypted disk; start it from the TUI to enter the passphrase", name));
+            }
+            start_vm(&vm_conf, config, &state.logs, None, false)?;
+            Ok(Vec::new())
+        }
+        ("stop", Some(name)) => {
+            let vm_conf = find_vm(config, name).ok_or_else(|| format!("unknown VM {}", name))?;
+            stop_vm(&vm_conf, config, &state.logs)?;
+            Ok(Vec::new())
+        }
+        ("vms", None) => Ok(list_vms(config)
+            .iter()
+            .filter_map(|vm| {
+                let remote = RemoteVm {
+                    name: vm_stem(vm),
+                    conf: fs::read_to_string(vm).ok()?,
+                    ports: fs::read_to_string(vm_runtime_file(vm, ".ports")).ok(),
+                };
+                serde_json::to_string(&remote).ok()
+            })
+            .collect()),
+        _ => Err(format!("unknown request: {}", line)),
+    }
+}
+
+/// Whether a presented control_token matches, taking the same time wherever they differ.
+pub(crate) fn token_matches(given: &[u8], expected: &[u8]) -> bool {
+    given.len() == expected.len() && given.iter().zip(expected).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Read one line of at most MAX_LINE bytes; false when it is longer.
+#[cfg(unix)]
+fn read_line_capped(reader: &mut impl BufRead, line: &mut String) -> io::Result<bool> {
+    line.clear();
+    let read = reader.take(MAX_LINE).read_line(line)?;
+    Ok(read < MAX_LINE as usize || line.ends_with('\n'))
+}
+
+/// Serve one connection. With a `token` the request must be preceded by a matching
+/// "auth" line; without one an "auth" line is accepted and ignored.
+#[cfg(unix)]
+fn handle_client(
+    reader: impl Read,
+    mut writer: impl Write,
+    token: Option<&str>,
+    config: &Config,
+    state: &DaemonState,
+) -> io::Result<()> {
+    let mut reader = BufReader::new(reader);
+    let mut line = String::new();
+    if !read_line_capped(&mut reader, &mut line)? {
+        return writeln!(writer, "err line too long");
+    }
+    if let Some(given) = line.trim().strip_prefix("auth ") {
+        if token.is_some_and(|token| !token_matches(given.as_bytes(), token.as_bytes())) {
+            return writeln!(writer, "err bad control_token");
+        }
+        if !read_line_capped(&mut reader, &mut line)? {
+            return writeln!(writer, "err line too long");
+        }
+    } else if token.is_some() {
+        return writeln!(writer, "err authentication required");
+    }
+    match handle_request(&line, config, state) {
+        Ok(body) => {
+            for l in body {
+                writeln!(writer, "{}", l)?;
+            }
+            writeln!(writer, "ok")
+        }
+        Err(e) => writeln!(writer, "err {}", e),
+    }
+}
+
+/// Run the daemon in the foreground until killed.
+#[cfg(unix)]
+pub fn run(config: Config, print_events: bool) -> Result<(), Box<dyn Error>> {
+    let socket_path = config.control_socket.clone();
+    if socket_path.exists() {
+        if UnixStream::connect(&socket_path).is_ok() {
+            return Err(format!("a daemon is already listening on {}", socket_path.display()).into());
+        }
+        // Left behind by a daemon that did not shut down cleanly.
+        std::fs::remove_file(&socket_path)?;
+    }
+    let listener = UnixListener::bind(&socket_path)?;
+    let tcp_listener = match &config.daemon_listen {
+        Some(_) if config.control_token.is_none() => return Err("daemon_listen needs a control_token".into()),
+        Some(addr) => Some((addr.clone(), TcpListener::bind(addr)?)),
+        None => None,
+    };
+    let config = Arc::new(config);
+    let state = Arc::new(DaemonState {
+        status: Mutex::new(HashMap::new()),
+        subscribers: Mutex::new(Vec::new()),
+        logs: Arc::new(Mutex::new(vec![format!("Daemon listening on {}.", socket_path.display())])),
+    });
+    {
+        let config = Arc::clone(&config);
+        let state = Arc::clone(&state);
+        thread::spawn(move || poll_loop(config, state, print_events));
+    }
+    {
+        let (config_c, state_c) = (Arc::clone(&config), Arc::clone(&state));
+        mqtt::subscribe(&config, &state.logs, move |vm, command| {
+            let result = match command {
+                "start" | "stop" => handle_request(&format!("{} {}", command, vm), &config_c, &state_c).map(|_| ()),
+                _ => Err(format!("unknown command {:?}", command)),
+            };
+            if let Err(e) = result {
+                state_c.logs.lock().unwrap().push(format!("MQTT {} {}: {}", command, vm, e));
+            }
+        });
+    }
+    if let Some((addr, tcp_listener)) = tcp_listener {
+        state.logs.lock().unwrap().push(format!("Daemon listening on {} (TCP).", addr));
+        let (config, state) = (Arc::clone(&config), Arc::clone(&state));
+        thread::spawn(move || {
+            for stream in tcp_listener.incoming().flatten() {
+                let (config, state) = (Arc::clone(&config), Arc::clone(&state));
+                thread::spawn(move || {
+                    let token = config.control_token.as_deref();
+                    // Unauthenticated peers must not hold a thread open forever.
+                    if stream.set_read_timeout(Some(TCP_READ_TIMEOUT)).is_ok()
+                        && let Ok(reader) = stream.try_clone()
+                    {
+                        let _ = handle_client(reader, stream, token, &config, &state);
+                    }
+                });
+            }
+        });
+    }
+    if let Some(addr) = &config.grpc_listen {
+        #[cfg(feature = "grpc")]
+        crate::grpc::serve(addr, Arc::clone(&config), Arc::clone(&state))?;
+        #[cfg(not(feature = "grpc"))]
+        state.logs.lock().unwrap().push(format!("Ignoring grpc_listen {}: built without the grpc feature.", addr));
+    }
+    for stream in listener.incoming().flatten() {
+        let config = Arc::clone(&config);
+        let state = Arc::clone(&state);
+        thread::spawn(move || {
+            if let Ok(reader) = stream.try_clone() {
+                let _ = handle_client(reader, stream, None, &config, &state);
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn run(_config: Config, _print_events: bool) -> Result<(), Box<dyn Error>> {
+    Err("daemon mode requires Unix domain sockets and is not supported on this platform".into())
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// Control Socket Client (used by the TUI)
+///////////////////////////////////////////////////////////////////////////////
+
+enum Endpoint {
+    #[cfg(unix)]
+    Unix(PathBuf),
+    Tcp(String),
+}
+
+pub struct DaemonClient {
+    endpoint: Endpoint,
+    token: Option<String>,
+}
+
+/// Where a remote TUI mirrors the VMs of the daemon at `addr`.
+pub fn remote_dir(addr: &str) -> PathBuf {
+    let slug: String = addr.chars().map(|c| if c.is_alphanumeric() || c == '.' { c } else { '_' }).collect();
+    state_dir().join("remote").join(slug.trim_matches('_'))
+}
+
+/// Host part of a TCP `addr` ("host:port", "[v6]:port"); None for a socket path.
+pub fn remote_host(addr: &str) -> Option<String> {
+    if Path::new(addr).is_absolute() {
+        return None;
+    }
+    let (host, _port) = addr.rsplit_once(':')?;
+    Some(host.trim_start_matches('[').trim_end_matches(']').to_string())
+}
+
+impl DaemonClient {
+    /// Returns a client if a daemon is listening on the configured socket.
+    #[cfg(unix)]
+    pub fn connect(socket_path: &Path) -> Option<Self> {
+        UnixStream::connect(socket_path).ok()?;
+        Some(Self { endpoint: Endpoint::Unix(socket_path.to_path_buf()), token: None })
+    }
+
+    #[cfg(not(unix))]
+    pub fn connect(_socket_path: &Path) -> Option<Self> {
+        None
+    }
+
+    /// Client for a daemon on another machine: "host:port" for its `daemon_listen`
+    /// address, or the path of a forwarded control socket (`ssh -L`).
+    pub fn remote(addr: &str, token: Option<&str>) -> Result<Self, String> {
+        let endpoint = if Path::new(addr).is_absolute() {
+            #[cfg(unix)]
+            {
+                Endpoint::Unix(PathBuf::from(addr))
+            }
+            #[cfg(not(unix))]
+            {
+                return Err("forwarded unix sockets are not supported on this platform; use host:port".into());
+            }
+        } else {
+            Endpoint::Tcp(addr.to_string())
+        };
+        let client = Self { endpoint, token: token.map(str::to_string) };
+        client.request("status")?;
+        Ok(client)
+    }
+
+    fn request(&self, line: &str) -> Result<Vec<String>, String> {
+        let timeout = Some(Duration::from_secs(5));
+        let (reader, mut writer): (Box<dyn Read>, Box<dyn Write>) = match &self.endpoint {
+            #[cfg(unix)]
+            Endpoint::Unix(path) => {
+                let stream = UnixStream::connect(path).map_err(|e| e.to_string())?;
+                stream.set_read_timeout(timeout).map_err(|e| e.to_string())?;
+                (Box::new(stream.try_clone().map_err(|e| e.to_string())?), Box::new(stream))
+            }
+            Endpoint::Tcp(addr) => {
+                let stream = TcpStream::connect(addr).map_err(|e| e.to_string())?;
+                stream.set_read_timeout(timeout).map_err(|e| e.to_string())?;
+                (Box::new(stream.try_clone().map_err(|e| e.to_string())?), Box::new(stream))
+            }
+        };
+        if let Some(token) = &self.token {
+            writeln!(writer, "auth {}", token).map_err(|e| e.to_string())?;
+        }
+        writeln!(writer, "{}", line).map_err(|e| e.to_string())?;
+        let mut body = Vec::new();
+        for l in BufReader::new(reader).lines() {
+            let l = l.map_err(|e| e.to_string())?;
+            if l == "ok" {
+                return Ok(body);
+            }
+            if let Some(msg) = l.strip_prefix("err ") {
+                return Err(msg.to_string());
+            }
+            body.push(l);
+        }
+        Err("daemon closed the connection".into())
+    }
+
+    /// Running status per VM stem, as last polled by the daemon.
+    pub fn status(&self) -> Result<HashMap<String, bool>, String> {
+        Ok(self
+            .request("status")?
+            .iter()
+            .filter_map(|l| l.split_once('\t'))
+            .map(|(name, state)| (name.to_string(), state == "running"))
+            .collect())
+    }
+
+    pub fn start(&self, vm_conf: &Path) -> Result<(), String> {
+        self.request(&format!("start {}", vm_stem(vm_conf))).map(|_| ())
+    }
+
+    pub fn stop(&self, vm_conf: &Path) -> Result<(), String> {
+        self.request(&format!("stop {}", vm_stem(vm_conf))).map(|_| ())
+    }
+
+    /// Copy the daemon's VM definitions and ports files into `dir`, laid out like a
+    /// quickemu directory, dropping VMs the daemon no longer lists. Returns whether
+    /// the set of VMs changed.
+    pub fn mirror(&self, dir: &Path) -> Result<bool, String> {
+        let vms: Vec<RemoteVm> = self
+            .request("vms")?
+            .iter()
+            .filter_map(|line| serde_json::from_str::<RemoteVm>(line).ok())
+            // Names become file names here; never let them leave the directory.
+            .filter(|vm| !vm.name.is_empty() && !vm.name.starts_with('.') && !vm.name.contains(['/', '\\']))
+            .collect();
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        let mut changed = false;
+        for vm in &vms {
+            let conf = dir.join(format!("{}.conf", vm.name));
+            changed |= !conf.exists();
+            if fs::read_to_string(&conf).ok().as_deref() != Some(vm.conf.as_str()) {
+                fs::write(&conf, &vm.conf).map_err(|e| e.to_string())?;
+            }
+            let ports = vm_runtime_file(&conf, ".ports");
+            match &vm.ports {
+                Some(contents) if fs::read_to_string(&ports).ok().as_ref() != Some(contents) => {
+                    fs::create_dir_all(vm_dir(&conf)).map_err(|e| e.to_string())?;
+                    fs::write(&ports, contents).map_err(|e| e.to_string())?;
+                }
+                Some(_) => {}
+                None => {
+                    let _ = fs::remove_file(&ports);
+                }
+            }
+        }
+        for entry in fs::read_dir(dir).map_err(|e| e.to_string())?.flatten() {
+            let path = entry.path();
+            if path.extension().is_some_and(|e| e == "conf") && !vms.iter().any(|vm| vm.name == vm_stem(&path)) {
+                let _ = fs::remove_file(&path);
+                let _ = fs::remove_dir_all(vm_dir(&path));
+                changed = true;
+            }
+        }
+        Ok(changed)
+    }
+}
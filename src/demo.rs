@@ -0,0 +1,173 @@
+///////////////////////////////////////////////////////////////////////////////
+// Demo Mode
+///////////////////////////////////////////////////////////////////////////////
+//
+// `--demo` fills the list with made-up VMs, for screencasts, trying themes and
+// exercising the UI on machines without quickemu. Their .conf files (and a
+// notes file) are written to a temporary directory that replaces
+// quickemu_dirs, and starting or stopping one plays a scripted sequence of log
+// lines and state changes instead of running quickemu. Everything else runs as
+// with --dry-run: viewers, snapshots and the like only log their commands.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use crate::{set_stop_stage, Config, StopStage};
+
+/// Name, .conf contents and whether the VM starts out running.
+const VMS: [(&str, &str, bool); 6] = [
+    ("debian-12", "guest_os=\"linux\"\nram=\"4G\"\ncpu_cores=\"2\"\nport_forwards=(\"22220:22\")\n", false),
+    ("ubuntu-24.04", "guest_os=\"linux\"\nram=\"8G\"\ncpu_cores=\"4\"\n", true),
+    ("fedora-40", "guest_os=\"linux\"\nram=\"4G\"\nport_forwards=(\"5901:5900\")\n", false),
+    ("windows-11", "guest_os=\"windows\"\nram=\"8G\"\ncpu_cores=\"4\"\nport_forwards=(\"3389:3389\")\n", true),
+    ("macos-sonoma", "guest_os=\"macos\"\nram=\"8G\"\nmacos_release=\"sonoma\"\n", false),
+    ("freebsd-14", "guest_os=\"freebsd\"\nram=\"2G\"\n", false),
+];
+
+const NOTES: (&str, &str) = ("windows-11", "Demo VM: RDP on port 3389.\nUser: demo / Password: demo");
+
+/// Pause between the steps of a scripted start or stop.
+#[cfg(not(test))]
+const STEP: Duration = Duration::from_millis(1500);
+#[cfg(test)]
+const STEP: Duration = Duration::from_millis(20);
+
+/// VMs the demo currently shows as running.
+static RUNNING: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// The demo's temporary directory, removed when dropped.
+pub struct DemoDir(PathBuf);
+
+impl Drop for DemoDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Write the demo VMs to a fresh temporary directory and point the config at it.
+pub fn setup(config: &mut Config) -> io::Result<DemoDir> {
+    let dir = std::env::temp_dir().join(format!("quick-cli-demo-{}", process::id()));
+    fs::create_dir_all(&dir)?;
+    let mut running = RUNNING.lock().unwrap();
+    for (name, contents, up) in VMS {
+        let conf = dir.join(format!("{}.conf", name));
+        fs::write(&conf, contents)?;
+        if up {
+            running.push(conf);
+        }
+    }
+    fs::write(dir.join(format!("{}.notes", NOTES.0)), NOTES.1)?;
+    config.demo = true;
+    config.dry_run = true;
+    config.quickemu_dirs = vec![dir.clone()];
+    config.scan_depth = 0;
+    config.ignore.clear();
+    config.backends.clear();
+    config.git_sync = false;
+    config.remote = None;
+    // The made-up VMs do not use the host's memory or count against real budgets.
+    config.ram_commit_percent = 0;
+    config.groups.clear();
+    Ok(DemoDir(dir))
+}
+
+pub fn is_running(vm_conf: &Path) -> bool {
+    RUNNING.lock().unwrap().iter().any(|vm| vm == vm_conf)
+}
+
+fn set_running(vm_conf: &Path, running: bool) {
+    let mut vms = RUNNING.lock().unwrap();
+    vms.retain(|vm| vm != vm_conf);
+    if running {
+        vms.push(vm_conf.to_path_buf());
+    }
+}
+
+/// Pretend to boot the VM: it shows as running after a few log lines.
+pub fn start(vm_conf: &Path, logs: &Arc<Mutex<Vec<String>>>) {
+    let name = vm_conf.file_stem().unwrap().to_string_lossy().to_string();
+    if is_running(vm_conf) {
+        logs.lock().unwrap().push(format!("{} is already running.", name));
+        return;
+    }
+    logs.lock().unwrap().push(format!("Launching VM {}...", vm_conf.display()));
+    let (vm_conf, logs) = (vm_conf.to_path_buf(), Arc::clone(logs));
+    thread::spawn(move || {
+        let log = |message: String| logs.lock().unwrap().push(message);
+        thread::sleep(STEP);
+        log(format!("[demo] {}: qemu started, booting from disk.", name));
+        thread::sleep(STEP);
+        set_running(&vm_conf, true);
+        log(format!("[demo] {}: guest is up.", name));
+    });
+}
+
+/// Pretend to shut the VM down through the first stop stage.
+pub fn stop(vm_conf: &Path, logs: &Arc<Mutex<Vec<String>>>) {
+    logs.lock().unwrap().push(format!("Stopping VM {}...", vm_conf.display()));
+    set_stop_stage(vm_conf, Some(StopStage::Powerdown));
+    let (vm_conf, logs) = (vm_conf.to_path_buf(), Arc::clone(logs));
+    thread::spawn(move || {
+        let log = |message: String| logs.lock().unwrap().push(message);
+        let name = vm_conf.file_stem().unwrap().to_string_lossy().to_string();
+        thread::sleep(STEP);
+        log(format!("Sent ACPI powerdown to {}.", name));
+        thread::sleep(STEP * 2);
+        set_running(&vm_conf, false);
+        set_stop_stage(&vm_conf, None);
+        log(format!("{} shut down.", name));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{is_vm_running, list_vms, start_vm, stop_stage, stop_vm};
+    use std::time::Instant;
+
+    /// Wait up to a few seconds for `done`.
+    fn wait_for(done: impl Fn() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !done() {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        true
+    }
+
+    #[test]
+    fn start_and_stop_a_demo_vm() {
+        let mut config = Config::default();
+        let dir = setup(&mut config).unwrap();
+        assert!(config.demo && config.dry_run);
+        let vms = list_vms(&config);
+        assert_eq!(vms.len(), VMS.len());
+        assert!(vms.iter().all(|vm| vm.starts_with(&dir.0)));
+        let mut running: Vec<_> = vms.iter().filter(|vm| is_vm_running(vm, &config)).collect();
+        running.sort();
+        assert_eq!(running, [&dir.0.join("ubuntu-24.04.conf"), &dir.0.join("windows-11.conf")]);
+
+        let logs = Arc::new(Mutex::new(Vec::new()));
+        let debian = dir.0.join("debian-12.conf");
+        assert!(!is_vm_running(&debian, &config));
+        start_vm(&debian, &config, &logs, None, false).unwrap();
+        assert!(wait_for(|| is_vm_running(&debian, &config)));
+
+        stop_vm(&debian, &config, &logs).unwrap();
+        assert!(matches!(stop_stage(&debian), Some(StopStage::Powerdown)));
+        assert!(wait_for(|| stop_stage(&debian).is_none()));
+        assert!(!is_vm_running(&debian, &config));
+        assert!(logs.lock().unwrap().iter().any(|l| l == "debian-12 shut down."));
+
+        drop(dir);
+        assert!(!debian.exists());
+    }
+}
@@ -0,0 +1,158 @@
+///////////////////////////////////////////////////////////////////////////////
+// Encrypted Disk Images
+///////////////////////////////////////////////////////////////////////////////
+//
+// quickemu has no passphrase handling of its own, so for an encrypted disk the
+// passphrase is written to a private file and handed to qemu through
+// `quickemu --extra_args`:
+//     -object secret,id=quickcli-disk,file=<state dir>/secrets/<vm>-<hash>.secret
+//     -set drive.SystemDisk.encrypt.key-secret=quickcli-disk   (qcow2)
+//     -set drive.SystemDisk.key-secret=quickcli-disk           (raw LUKS)
+// SystemDisk is the drive id quickemu gives the VM's disk_img. quickemu splits
+// --extra_args at whitespace, so the file is kept under the state dir with a
+// name made safe from the VM's, and a state dir path with whitespace in it is
+// refused; commas in it are doubled, qemu's escape inside an option value.
+
+use std::{
+    fs,
+    hash::{DefaultHasher, Hash, Hasher},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde_json::Value;
+
+use crate::{state_dir, vm_conf_value, vm_runtime_file};
+
+const SECRET_ID: &str = "quickcli-disk";
+
+// How long the secret file is kept around waiting for qemu to start and read it.
+const SECRET_TTL: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy)]
+pub enum DiskEncryption {
+    Qcow2, // qcow2 with encrypt.format=luks (or legacy aes)
+    Luks,  // raw LUKS container
+}
+
+/// The VM's disk_img, resolved relative to the directory holding its .conf file.
+pub fn disk_image(vm_conf: &Path) -> Option<PathBuf> {
+    let disk = PathBuf::from(vm_conf_value(vm_conf, "disk_img")?);
+    Some(if disk.is_relative() { vm_conf.with_file_name(disk) } else { disk })
+}
+
+/// Detect disk encryption from `qemu-img info`. Returns None for plain images, a
+/// missing disk, or when qemu-img is unavailable.
+pub fn disk_encryption(vm_conf: &Path) -> Option<DiskEncryption> {
+    let disk = disk_image(vm_conf).filter(|d| d.exists())?;
+    // -U: the image may be locked by a running qemu.
+    let output = Command::new("qemu-img")
+        .args(["info", "-U", "--output=json"])
+        .arg(&disk)
+        .output()
+        .ok()?;
+    let info: Value = serde_json::from_slice(&output.stdout).ok()?;
+    if info.get("format").and_then(Value::as_str) == Some("luks") {
+        Some(DiskEncryption::Luks)
+    } else if info.get("encrypted").and_then(Value::as_bool) == Some(true) {
+        Some(DiskEncryption::Qcow2)
+    } else {
+        None
+    }
+}
+
+/// Where the passphrase for the VM's disk is written: under the state dir, named after the
+/// VM with anything but [A-Za-z0-9_-] replaced, and a hash of its path for VMs of the same name.
+fn secret_file(vm_conf: &Path) -> PathBuf {
+    let stem = vm_conf.file_stem().unwrap_or_default().to_string_lossy();
+    let safe: String =
+        stem.chars().map(|c| if c.is_ascii_alphanumeric() || "-_".contains(c) { c } else { '_' }).collect();
+    let mut hasher = DefaultHasher::new();
+    vm_conf.hash(&mut hasher);
+    state_dir().join("secrets").join(format!("{}-{:08x}.secret", safe, hasher.finish() as u32))
+}
+
+/// A path as the value of a qemu option inside --extra_args: commas doubled, whitespace refused.
+fn qemu_path_value(path: &Path) -> Result<String, String> {
+    let path = path.to_string_lossy();
+    if path.contains(char::is_whitespace) {
+        return Err(format!("{} contains whitespace, which quickemu --extra_args cannot pass", path));
+    }
+    Ok(path.replace(',', ",,"))
+}
+
+/// qemu arguments that unlock the disk with the secret file written by secret_args.
+pub fn secret_qemu_args(vm_conf: &Path, encryption: DiskEncryption) -> Result<String, String> {
+    let key = match encryption {
+        DiskEncryption::Qcow2 => "encrypt.key-secret",
+        DiskEncryption::Luks => "key-secret",
+    };
+    Ok(format!(
+        "-object secret,id={id},file={file} -set drive.SystemDisk.{key}={id}",
+        id = SECRET_ID,
+        file = qemu_path_value(&secret_file(vm_conf))?,
+        key = key,
+    ))
+}
+
+/// Write the passphrase to a private file and return the qemu arguments that use it.
+/// The file is removed once qemu has started, or after SECRET_TTL.
+pub fn secret_args(
+    vm_conf: &Path,
+    encryption: DiskEncryption,
+    passphrase: &str,
+    logs: &Arc<Mutex<Vec<String>>>,
+) -> Result<String, String> {
+    let args = secret_qemu_args(vm_conf, encryption)?;
+    let secret_file = secret_file(vm_conf);
+    fs::create_dir_all(secret_file.parent().unwrap())
+        .and_then(|_| write_private(&secret_file, passphrase))
+        .map_err(|e| format!("cannot write {}: {}", secret_file.display(), e))?;
+    let pid_file = vm_runtime_file(vm_conf, ".pid");
+    let logs = Arc::clone(logs);
+    thread::spawn(move || {
+        let started = Instant::now();
+        while !pid_file.exists() && started.elapsed() < SECRET_TTL {
+            thread::sleep(Duration::from_millis(500));
+        }
+        // qemu writes its pid file after opening the disks.
+        thread::sleep(Duration::from_secs(1));
+        if let Err(e) = fs::remove_file(&secret_file) {
+            logs.lock().unwrap().push(format!("Failed to remove {}: {}", secret_file.display(), e));
+        }
+    });
+    Ok(args)
+}
+
+/// Write a file only its owner can read.
+#[cfg(unix)]
+pub fn write_private(path: &Path, contents: &str) -> std::io::Result<()> {
+    use std::{io::Write, os::unix::fs::OpenOptionsExt};
+    // Recreate rather than truncate so the 0600 mode always applies.
+    let _ = fs::remove_file(path);
+    let mut file = fs::OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path)?;
+    file.write_all(contents.as_bytes())
+}
+
+#[cfg(not(unix))]
+pub fn write_private(path: &Path, contents: &str) -> std::io::Result<()> {
+    fs::write(path, contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secret_file_paths_for_qemu() {
+        let file = secret_file(Path::new("/vms/My VM,1.conf"));
+        let name = file.file_name().unwrap().to_string_lossy();
+        assert!(name.starts_with("My_VM_1-") && name.ends_with(".secret"), "{}", name);
+        assert_ne!(file, secret_file(Path::new("/other/My VM,1.conf")));
+        assert_eq!(qemu_path_value(Path::new("/state/a,b/x.secret")).unwrap(), "/state/a,,b/x.secret");
+        assert!(qemu_path_value(Path::new("/home/a b/x.secret")).is_err());
+    }
+}
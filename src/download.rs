@@ -0,0 +1,126 @@
+///////////////////////////////////////////////////////////////////////////////
+// Accelerated ISO Downloads
+///////////////////////////////////////////////////////////////////////////////
+//
+// With `iso_downloader = "aria2c"`, `:create` and `apply` fetch the images
+// themselves before running quickget: `quickget --url <os> <release> [edition]`
+// names them, and aria2c downloads each over `aria2c_connections` connections
+// (segmented, `-x`/`-s`) into the `<os>-<release>[-<edition>]` directory quickget
+// uses, under the URL's file name. Interrupted downloads resume (`-c`) on the
+// next attempt, and quickget then finds the complete file in place. Images
+// quickget stores under another name (some Windows editions) are fetched again
+// by quickget itself. If aria2c fails, quickget downloads as usual.
+
+use std::{
+    io::Read,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use crate::{describe_command, quickget_command, Config};
+
+/// Image URLs quickget would download, from `quickget --url`.
+fn image_urls(args: &[&str], config: &Config) -> Result<Vec<String>, String> {
+    let mut command = quickget_command(config, &[&["--url"], args].concat());
+    let output = command.stdin(Stdio::null()).output().map_err(|e| format!("cannot run quickget: {}", e))?;
+    let text = String::from_utf8_lossy(&output.stdout);
+    let urls: Vec<String> = text
+        .split_whitespace()
+        .filter(|word| word.starts_with("https://") || word.starts_with("http://"))
+        .map(str::to_string)
+        .collect();
+    if !output.status.success() || urls.is_empty() {
+        return Err(format!("quickget --url {} printed no image URL", args.join(" ")));
+    }
+    Ok(urls)
+}
+
+/// The name quickget saves a URL under: its last path segment.
+fn file_name(url: &str) -> Option<&str> {
+    let path = url.split(['?', '#']).next()?;
+    path.rsplit('/').next().filter(|name| !name.is_empty())
+}
+
+/// `[#2089b0 400.0MiB/33.2GiB(1%) CN:16 DL:115.7MiB ETA:4m49s]` summary lines.
+fn parse_progress(line: &str) -> Option<f32> {
+    let start = line.find('(')? + 1;
+    let end = start + line[start..].find("%)")?;
+    line[start..end].parse().ok()
+}
+
+fn aria2c_command(url: &str, dir: &Path, name: &str, config: &Config) -> Command {
+    let connections = config.aria2c_connections.to_string();
+    let mut command = Command::new("aria2c");
+    command
+        .args(["-c", "-x", &connections, "-s", &connections, "--summary-interval=1", "--console-log-level=warn"])
+        .arg("--dir")
+        .arg(dir)
+        .args(["-o", name, url]);
+    command
+}
+
+/// Run aria2c, feeding its progress to `progress`. Errors carry aria2c's last message.
+fn run_aria2c(command: &mut Command, progress: &dyn Fn(f32)) -> Result<(), String> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("cannot run aria2c: {}", e))?;
+    let mut stdout = child.stdout.take().unwrap();
+    let mut buf = [0u8; 256];
+    let mut pending = String::new();
+    let mut last = String::new();
+    while let Ok(n) = stdout.read(&mut buf) {
+        if n == 0 {
+            break;
+        }
+        pending.push_str(&String::from_utf8_lossy(&buf[..n]));
+        while let Some(i) = pending.find(['\r', '\n']) {
+            let line = pending[..i].trim().to_string();
+            pending.drain(..=i);
+            if let Some(percent) = parse_progress(&line) {
+                progress(percent);
+            } else if !line.is_empty() {
+                last = line;
+            }
+        }
+    }
+    match child.wait() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(if last.is_empty() { format!("aria2c failed ({})", status) } else { last }),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Download the images of `quickget <args>` into `dir` with the configured downloader,
+/// reporting overall progress in percent. Ok(false) when quickget downloads them itself.
+pub fn prefetch(
+    dir: &Path,
+    args: &[&str],
+    config: &Config,
+    log: &dyn Fn(String),
+    progress: &dyn Fn(f32),
+) -> Result<bool, String> {
+    match config.iso_downloader.as_str() {
+        "quickget" => return Ok(false),
+        "aria2c" => {}
+        other => return Err(format!("unknown iso_downloader {:?}; use \"quickget\" or \"aria2c\"", other)),
+    }
+    let target = dir.join(args.join("-"));
+    if config.dry_run {
+        let example = aria2c_command("<url>", &target, "<file>", config);
+        log(format!("[dry-run] {} for each URL of quickget --url {}", describe_command(&example), args.join(" ")));
+        return Ok(true);
+    }
+    let urls = image_urls(args, config)?;
+    for (i, url) in urls.iter().enumerate() {
+        let name = file_name(url).ok_or_else(|| format!("no file name in {}", url))?;
+        log(format!("Downloading {} with aria2c ({} connections).", name, config.aria2c_connections));
+        let done = i as f32 * 100.0;
+        run_aria2c(&mut aria2c_command(url, &target, name, config), &|percent| {
+            progress((done + percent) / urls.len() as f32)
+        })?;
+    }
+    Ok(true)
+}
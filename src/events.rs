@@ -0,0 +1,184 @@
+///////////////////////////////////////////////////////////////////////////////
+// VM Events
+///////////////////////////////////////////////////////////////////////////////
+//
+// The watcher polls every VM and turns state transitions into events:
+//     vm_state    initial state of each VM when watching starts
+//     vm_started  qemu is running
+//     vm_ready    the RDP/VNC/SPICE port accepts connections
+//     vm_stopped  qemu is gone
+// Background jobs (ISO checks, file copies) report `job_finished` to webhooks.
+// `quick-cli events` (and `quick-cli daemon --events`) print them to stdout as
+// newline-delimited JSON, e.g.
+//     {"event":"vm_started","vm":"win11","time":1760000000}
+
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+use crate::{
+    is_port_open, is_vm_running, list_vms, parse_vm_config, socket_listening, vm_host, Config, RemoteProtocol,
+};
+
+#[derive(Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VmState {
+    Stopped,
+    Running,
+    Ready,
+}
+
+impl VmState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            VmState::Stopped => "stopped",
+            VmState::Running => "running",
+            VmState::Ready => "ready",
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+#[serde(tag = "event")]
+pub enum Event {
+    #[serde(rename = "vm_state")]
+    State { vm: String, state: VmState, time: u64 },
+    #[serde(rename = "vm_started")]
+    Started { vm: String, time: u64 },
+    #[serde(rename = "vm_ready")]
+    Ready { vm: String, time: u64 },
+    #[serde(rename = "vm_stopped")]
+    Stopped { vm: String, time: u64 },
+    #[serde(rename = "job_finished")]
+    JobFinished { job: String, target: String, ok: bool, time: u64 },
+}
+
+impl Event {
+    pub fn job_finished(job: &str, target: &str, ok: bool) -> Self {
+        Event::JobFinished { job: job.to_string(), target: target.to_string(), ok, time: now() }
+    }
+
+    /// The VM and the state it is in after this event, for VM events.
+    pub fn vm_state(&self) -> Option<(&str, VmState)> {
+        match self {
+            Event::State { vm, state, .. } => Some((vm, *state)),
+            Event::Started { vm, .. } => Some((vm, VmState::Running)),
+            Event::Ready { vm, .. } => Some((vm, VmState::Ready)),
+            Event::Stopped { vm, .. } => Some((vm, VmState::Stopped)),
+            Event::JobFinished { .. } => None,
+        }
+    }
+
+    /// Whether this reports a change rather than the initial state.
+    pub fn is_change(&self) -> bool {
+        !matches!(self, Event::State { .. })
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Current state of one VM: running qemu plus an open viewer port means ready.
+pub fn vm_state(vm_conf: &std::path::Path, config: &Config) -> VmState {
+    if !is_vm_running(vm_conf, config) {
+        return VmState::Stopped;
+    }
+    let ready = match parse_vm_config(vm_conf, config) {
+        RemoteProtocol::SpiceUnix(socket) => socket_listening(&socket),
+        protocol => is_port_open(vm_host(vm_conf, config), protocol.port().unwrap_or_default(), config.connect_timeout),
+    };
+    if ready { VmState::Ready } else { VmState::Running }
+}
+
+#[derive(Default)]
+pub struct Watcher {
+    states: HashMap<String, VmState>,
+}
+
+impl Watcher {
+    /// Poll all VMs and return the events since the previous poll.
+    pub fn poll(&mut self, config: &Config) -> Vec<Event> {
+        let time = now();
+        let mut events = Vec::new();
+        let mut seen = HashMap::new();
+        for vm_conf in list_vms(config) {
+            let vm = vm_conf.file_stem().unwrap().to_string_lossy().to_string();
+            let state = vm_state(&vm_conf, config);
+            match (self.states.get(&vm).copied(), state) {
+                (None, state) => events.push(Event::State { vm: vm.clone(), state, time }),
+                (Some(old), new) if old == new => {}
+                (Some(_), VmState::Stopped) => events.push(Event::Stopped { vm: vm.clone(), time }),
+                (Some(VmState::Stopped), new) => {
+                    events.push(Event::Started { vm: vm.clone(), time });
+                    if new == VmState::Ready {
+                        events.push(Event::Ready { vm: vm.clone(), time });
+                    }
+                }
+                (Some(VmState::Running), VmState::Ready) => events.push(Event::Ready { vm: vm.clone(), time }),
+                // Ready -> Running: the viewer port closed (e.g. guest rebooting); not an event.
+                (Some(_), _) => {}
+            }
+            seen.insert(vm, state);
+        }
+        self.states = seen;
+        events
+    }
+
+    /// State per VM stem as of the last poll.
+    pub fn states(&self) -> &HashMap<String, VmState> {
+        &self.states
+    }
+
+    /// Running (or ready) flag per VM stem as of the last poll.
+    pub fn running(&self) -> HashMap<String, bool> {
+        self.states.iter().map(|(vm, state)| (vm.clone(), *state != VmState::Stopped)).collect()
+    }
+}
+
+impl Event {
+    /// Human-readable form for log panes and the journal.
+    pub fn describe(&self) -> String {
+        match self {
+            Event::State { vm, state: VmState::Stopped, .. } | Event::Stopped { vm, .. } => {
+                format!("VM {} is stopped.", vm)
+            }
+            Event::State { vm, state: VmState::Running, .. } | Event::Started { vm, .. } => {
+                format!("VM {} is running.", vm)
+            }
+            Event::State { vm, state: VmState::Ready, .. } | Event::Ready { vm, .. } => {
+                format!("VM {} is ready for connections.", vm)
+            }
+            Event::JobFinished { job, target, ok, .. } => {
+                format!("{} {} {}.", job, target, if *ok { "finished" } else { "failed" })
+            }
+        }
+    }
+}
+
+/// Print one event as a JSON line, flushing so pipes see it immediately.
+pub fn print_event(event: &Event) {
+    let mut stdout = io::stdout().lock();
+    let _ = writeln!(stdout, "{}", event.to_json());
+    let _ = stdout.flush();
+}
+
+/// `quick-cli events`: poll forever and print events as NDJSON.
+pub fn run(config: Config) -> Result<(), Box<dyn std::error::Error>> {
+    let mut watcher = Watcher::default();
+    loop {
+        for event in watcher.poll(&config) {
+            print_event(&event);
+        }
+        thread::sleep(config.status_poll_interval);
+    }
+}
@@ -0,0 +1,303 @@
+///////////////////////////////////////////////////////////////////////////////
+// Guest File Browser (sftp)
+///////////////////////////////////////////////////////////////////////////////
+//
+// `F` opens a two-pane browser: the local directory on the left, the guest on
+// the right over the VM's forwarded SSH port. sftp runs in batch mode, so key
+// based authentication is required. Guest listings and copies run in the
+// background (see poll); copies report to the log and to the caller, which
+// posts them to the webhooks. `r` refreshes both panes.
+
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use crate::url_host;
+
+/// A finished guest listing: the resolved directory and its entries, or the sftp error.
+type Listing = Result<(String, Vec<Entry>), String>;
+
+pub struct Entry {
+    pub name: String,
+    pub is_dir: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Pane {
+    Local,
+    Remote,
+}
+
+pub struct FileBrowser {
+    pub vm_name: String,
+    target: String, // [user@]host, IPv6 in brackets
+    port: u16,
+    pub local_dir: PathBuf,
+    pub remote_dir: String,
+    pub local: Vec<Entry>,
+    pub remote: Vec<Entry>,
+    pub focus: Pane,
+    pub selected: [usize; 2], // Per pane, indexed by focus as usize
+    pub status: String,
+    listing: Arc<Mutex<Option<Listing>>>, // Filled by the guest listing in progress
+}
+
+/// Quote a path for an sftp batch file. Control characters are refused: a newline
+/// would end the command and start another.
+fn sftp_quote(path: &str) -> Result<String, String> {
+    if path.contains(char::is_control) {
+        return Err(format!("{:?} contains control characters", path));
+    }
+    Ok(format!("\"{}\"", path.replace('\\', "\\\\").replace('"', "\\\"")))
+}
+
+/// Run sftp commands in batch mode and return stdout, or stderr on failure.
+fn run_sftp(target: &str, port: u16, commands: &str) -> Result<String, String> {
+    let mut child = Command::new("sftp")
+        .args(["-q", "-o", "BatchMode=yes", "-P", &port.to_string(), "-b", "-", target])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to run sftp: {}", e))?;
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(commands.as_bytes())
+        .map_err(|e| e.to_string())?;
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
+
+/// Parse `ls -la` long format: the name is everything after the eighth field.
+fn parse_long_listing(line: &str) -> Option<Entry> {
+    let mut rest = line.trim_start();
+    let is_dir = rest.starts_with('d');
+    for _ in 0..8 {
+        let end = rest.find(char::is_whitespace)?;
+        rest = rest[end..].trim_start();
+    }
+    (!rest.is_empty() && rest != "." && rest != "..").then(|| Entry { name: rest.to_string(), is_dir })
+}
+
+fn sort_entries(entries: &mut [Entry]) {
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase())));
+}
+
+impl FileBrowser {
+    pub fn open(vm_name: &str, host: &str, port: u16, user: Option<&str>) -> Self {
+        // sftp needs brackets around IPv6 literals to tell them from a remote path.
+        let host = url_host(host);
+        let target = match user {
+            Some(user) => format!("{}@{}", user, host),
+            None => host,
+        };
+        let mut browser = Self {
+            vm_name: vm_name.to_string(),
+            target,
+            port,
+            local_dir: dirs::home_dir().unwrap_or_else(|| PathBuf::from(".")),
+            remote_dir: ".".to_string(),
+            local: Vec::new(),
+            remote: Vec::new(),
+            focus: Pane::Local,
+            selected: [0, 0],
+            status: String::new(),
+            listing: Arc::new(Mutex::new(None)),
+        };
+        browser.refresh();
+        browser
+    }
+
+    /// Re-read the local directory and start listing the guest one in the background.
+    pub fn refresh(&mut self) {
+        self.local = fs::read_dir(&self.local_dir)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .map(|e| Entry {
+                        name: e.file_name().to_string_lossy().to_string(),
+                        is_dir: e.path().is_dir(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        sort_entries(&mut self.local);
+        self.selected[0] = self.selected[0].min(self.local.len().saturating_sub(1));
+        self.remote.clear();
+        self.status = format!("Listing {}...", self.remote_dir);
+        // A fresh slot, so a listing still running for an earlier directory is ignored.
+        self.listing = Arc::new(Mutex::new(None));
+        let commands = match sftp_quote(&self.remote_dir) {
+            Ok(dir) => format!("cd {}\npwd\nls -la\n", dir),
+            Err(e) => {
+                self.status = format!("Cannot list: {}", e);
+                return;
+            }
+        };
+        let (target, port, listing) = (self.target.clone(), self.port, Arc::clone(&self.listing));
+        thread::spawn(move || {
+            let result = run_sftp(&target, port, &commands).map(|output| {
+                let dir = output.lines().find_map(|l| l.strip_prefix("Remote working directory: "));
+                let mut entries: Vec<Entry> = output.lines().filter_map(parse_long_listing).collect();
+                sort_entries(&mut entries);
+                (dir.map(|d| d.trim().to_string()).unwrap_or_default(), entries)
+            });
+            *listing.lock().unwrap() = Some(result);
+        });
+    }
+
+    /// Show the guest listing once it has finished; called before each draw.
+    pub fn poll(&mut self) {
+        let Some(result) = self.listing.lock().unwrap().take() else { return };
+        match result {
+            Ok((dir, entries)) => {
+                if !dir.is_empty() {
+                    self.remote_dir = dir;
+                }
+                self.remote = entries;
+                self.status = format!("sftp -P {} {}", self.port, self.target);
+            }
+            Err(e) => self.status = format!("sftp failed: {}", e),
+        }
+        self.selected[1] = self.selected[1].min(self.remote.len().saturating_sub(1));
+    }
+
+    fn entries(&self) -> &[Entry] {
+        match self.focus {
+            Pane::Local => &self.local,
+            Pane::Remote => &self.remote,
+        }
+    }
+
+    pub fn selected_entry(&self) -> Option<&Entry> {
+        self.entries().get(self.selected[self.focus as usize])
+    }
+
+    pub fn move_selection(&mut self, delta: isize) {
+        let count = self.entries().len();
+        if count > 0 {
+            let i = &mut self.selected[self.focus as usize];
+            *i = (*i as isize + delta).rem_euclid(count as isize) as usize;
+        }
+    }
+
+    pub fn toggle_focus(&mut self) {
+        self.focus = match self.focus {
+            Pane::Local => Pane::Remote,
+            Pane::Remote => Pane::Local,
+        };
+    }
+
+    /// Enter the selected directory, or go up with `name == ".."`.
+    pub fn change_dir(&mut self, name: &str) {
+        match self.focus {
+            Pane::Local if name == ".." => {
+                if let Some(parent) = self.local_dir.parent() {
+                    self.local_dir = parent.to_path_buf();
+                }
+            }
+            Pane::Local => self.local_dir.push(name),
+            Pane::Remote if name == ".." => {
+                let parent = Path::new(&self.remote_dir).parent().map(|p| p.to_string_lossy().to_string());
+                self.remote_dir = parent.filter(|p| !p.is_empty()).unwrap_or_else(|| "/".to_string());
+            }
+            Pane::Remote => self.remote_dir = format!("{}/{}", self.remote_dir.trim_end_matches('/'), name),
+        }
+        self.selected[self.focus as usize] = 0;
+        self.refresh();
+    }
+
+    /// Copy the selected entry to the other pane's directory: push from the local
+    /// pane, pull from the guest pane. Runs in the background and calls `on_done` with
+    /// the copy's description and whether it succeeded.
+    pub fn copy_selected<F>(&self, logs: &Arc<Mutex<Vec<String>>>, on_done: F)
+    where
+        F: FnOnce(&str, bool) + Send + 'static,
+    {
+        let Some(entry) = self.selected_entry() else { return };
+        let (description, commands) = match self.focus {
+            Pane::Local => {
+                let source = self.local_dir.join(&entry.name);
+                (
+                    format!("{} -> {}:{}", source.display(), self.vm_name, self.remote_dir),
+                    sftp_quote(&self.remote_dir).and_then(|dir| {
+                        Ok(format!("cd {}\nput -r {}\n", dir, sftp_quote(&source.to_string_lossy())?))
+                    }),
+                )
+            }
+            Pane::Remote => {
+                let source = format!("{}/{}", self.remote_dir.trim_end_matches('/'), entry.name);
+                (
+                    format!("{}:{} -> {}", self.vm_name, source, self.local_dir.display()),
+                    sftp_quote(&self.local_dir.to_string_lossy())
+                        .and_then(|dir| Ok(format!("lcd {}\nget -r {}\n", dir, sftp_quote(&source)?))),
+                )
+            }
+        };
+        let commands = match commands {
+            Ok(commands) => commands,
+            Err(e) => return logs.lock().unwrap().push(format!("Cannot copy {}: {}", description, e)),
+        };
+        logs.lock().unwrap().push(format!("Copying {}...", description));
+        let (target, port, logs) = (self.target.clone(), self.port, Arc::clone(logs));
+        thread::spawn(move || {
+            let result = run_sftp(&target, port, &commands);
+            let message = match &result {
+                Ok(_) => format!("Copied {}.", description),
+                Err(e) => format!("Copy failed ({}): {}", description, e),
+            };
+            logs.lock().unwrap().push(message);
+            on_done(&description, result.is_ok());
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_listing_lines() {
+        let cases = [
+            ("drwxr-xr-x    3 me  me      4096 Jan  5 10:12 projects", Some(("projects", true))),
+            ("-rw-r--r--    1 me  me   220 Jan  5  2024 notes for today.txt", Some(("notes for today.txt", false))),
+            ("lrwxrwxrwx    1 me  me         9 Jan  5 10:12 latest -> v2", Some(("latest -> v2", false))),
+            ("drwxr-xr-x    5 me  me      4096 Jan  5 10:12 .", None),
+            ("drwxr-xr-x    9 me  me      4096 Jan  5 10:12 ..", None),
+            ("total 48", None),
+            ("", None),
+        ];
+        for (line, expected) in cases {
+            let entry = parse_long_listing(line);
+            assert_eq!(entry.as_ref().map(|e| (e.name.as_str(), e.is_dir)), expected, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn entries_sort_directories_first_case_insensitively() {
+        let entry = |name: &str, is_dir| Entry { name: name.to_string(), is_dir };
+        let mut entries = vec![entry("b.txt", false), entry("Zeta", true), entry("A.txt", false), entry("alpha", true)];
+        sort_entries(&mut entries);
+        let names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["alpha", "Zeta", "A.txt", "b.txt"]);
+    }
+
+    #[test]
+    fn sftp_quote_escapes_quotes_and_refuses_control_characters() {
+        assert_eq!(sftp_quote("/home/me/my file").unwrap(), "\"/home/me/my file\"");
+        assert_eq!(sftp_quote(r#"say "hi"\now"#).unwrap(), r#""say \"hi\"\\now""#);
+        assert!(sftp_quote("evil\n!rm -rf ~").is_err());
+        assert!(sftp_quote("tab\there").is_err());
+    }
+}
@@ -0,0 +1,229 @@
+///////////////////////////////////////////////////////////////////////////////
+// Firewall Rules
+///////////////////////////////////////////////////////////////////////////////
+//
+// `:firewall <vm>` opens the host ports a running VM forwards (its
+// `port_forwards` and quickemu's SSH forward) to the LAN, after confirmation,
+// with ufw or else firewalld. The rules are recorded in
+// $XDG_STATE_HOME/quick-cli/firewall (`vm=port,port` lines) and removed again
+// once the VM is seen stopped, or with `:firewall <vm> close`. firewalld rules
+// are runtime-only, so a reboot drops them as well.
+//
+// Both tools need root: commands run through `firewall_sudo` (default
+// "sudo -n", which cannot prompt inside the TUI; use NOPASSWD rules for the
+// tool, "pkexec", or "" when quick-cli runs as root).
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use crate::{find_in_path, port_forwards, read_runtime, state_dir, Config};
+
+#[derive(Clone, Copy)]
+pub enum Tool {
+    Ufw,
+    Firewalld,
+}
+
+impl Tool {
+    /// ufw when installed, else firewalld.
+    pub fn detect() -> Option<Tool> {
+        if find_in_path("ufw").is_some() {
+            Some(Tool::Ufw)
+        } else if find_in_path("firewall-cmd").is_some() {
+            Some(Tool::Firewalld)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Tool::Ufw => "ufw",
+            Tool::Firewalld => "firewalld",
+        }
+    }
+
+    fn args(self, open: bool, port: u16, vm: &str) -> Vec<String> {
+        let rule = format!("{}/tcp", port);
+        match (self, open) {
+            (Tool::Ufw, true) => {
+                vec!["ufw".into(), "allow".into(), rule, "comment".into(), format!("quick-cli {}", vm)]
+            }
+            (Tool::Ufw, false) => vec!["ufw".into(), "delete".into(), "allow".into(), rule],
+            (Tool::Firewalld, true) => vec!["firewall-cmd".into(), format!("--add-port={}", rule)],
+            (Tool::Firewalld, false) => vec!["firewall-cmd".into(), format!("--remove-port={}", rule)],
+        }
+    }
+}
+
+/// Host ports the VM exposes: its TCP port forwards and quickemu's SSH forward.
+pub fn vm_ports(vm_conf: &Path) -> Vec<u16> {
+    let mut ports: Vec<u16> = port_forwards(vm_conf).into_iter().map(|(host, _)| host).collect();
+    ports.extend(read_runtime(vm_conf).ports.get("ssh"));
+    ports.sort_unstable();
+    ports.dedup();
+    ports
+}
+
+fn rules_path() -> PathBuf {
+    state_dir().join("firewall")
+}
+
+/// Opened ports by VM stem.
+fn opened() -> HashMap<String, Vec<u16>> {
+    parse_rules(&fs::read_to_string(rules_path()).unwrap_or_default())
+}
+
+fn save(rules: &HashMap<String, Vec<u16>>) -> io::Result<()> {
+    fs::create_dir_all(state_dir())?;
+    fs::write(rules_path(), format_rules(rules))
+}
+
+/// `vm=port,port` lines; ports that do not parse are skipped.
+fn parse_rules(contents: &str) -> HashMap<String, Vec<u16>> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (vm, ports) = line.split_once('=')?;
+            Some((vm.trim().to_string(), ports.split(',').filter_map(|p| p.trim().parse().ok()).collect()))
+        })
+        .collect()
+}
+
+/// The rules file for `rules`, sorted by VM, without VMs that have no ports left.
+fn format_rules(rules: &HashMap<String, Vec<u16>>) -> String {
+    let mut lines: Vec<String> = rules
+        .iter()
+        .filter(|(_, ports)| !ports.is_empty())
+        .map(|(vm, ports)| format!("{}={}\n", vm, ports.iter().map(u16::to_string).collect::<Vec<_>>().join(",")))
+        .collect();
+    lines.sort();
+    lines.concat()
+}
+
+/// Run one rule change; Err carries the tool's complaint.
+fn run(
+    tool: Tool,
+    open: bool,
+    port: u16,
+    vm: &str,
+    config: &Config,
+    logs: &Arc<Mutex<Vec<String>>>,
+) -> Result<(), String> {
+    let words: Vec<String> = config.firewall_sudo.iter().cloned().chain(tool.args(open, port, vm)).collect();
+    if config.dry_run {
+        logs.lock().unwrap().push(format!("[dry-run] {}", words.join(" ")));
+        return Ok(());
+    }
+    let output = Command::new(&words[0])
+        .args(&words[1..])
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("cannot run {}: {}", words[0], e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        Err(stderr.lines().last().map(str::to_string).unwrap_or_else(|| output.status.to_string()))
+    }
+}
+
+/// Open the ports in a background thread and record the ones that were opened.
+pub fn open(vm_conf: &Path, ports: Vec<u16>, tool: Tool, config: &Config, logs: &Arc<Mutex<Vec<String>>>) {
+    let vm = vm_conf.file_stem().unwrap().to_string_lossy().to_string();
+    let (config, logs) = (config.clone(), Arc::clone(logs));
+    thread::spawn(move || {
+        let mut done = Vec::new();
+        for port in ports {
+            match run(tool, true, port, &vm, &config, &logs) {
+                Ok(()) => done.push(port),
+                Err(e) => logs.lock().unwrap().push(format!("Firewall: opening port {} failed: {}", port, e)),
+            }
+        }
+        if done.is_empty() || config.dry_run {
+            return;
+        }
+        let mut rules = opened();
+        let entry = rules.entry(vm.clone()).or_default();
+        entry.extend(&done);
+        entry.sort_unstable();
+        entry.dedup();
+        if let Err(e) = save(&rules) {
+            logs.lock().unwrap().push(format!("Firewall: cannot record the rules: {}", e));
+        }
+        let list: Vec<String> = done.iter().map(u16::to_string).collect();
+        logs.lock().unwrap().push(format!("Firewall: opened TCP {} for {} ({}).", list.join(", "), vm, tool.name()));
+    });
+}
+
+/// Remove the rules recorded for a VM. A rule that cannot be removed is reported with
+/// the command to remove it by hand and forgotten, rather than retried on every poll.
+pub fn close(vm: &str, config: &Config, logs: &Arc<Mutex<Vec<String>>>) {
+    let mut rules = opened();
+    let Some(ports) = rules.remove(vm) else {
+        logs.lock().unwrap().push(format!("Firewall: no ports opened for {}.", vm));
+        return;
+    };
+    let Some(tool) = Tool::detect() else {
+        logs.lock().unwrap().push("Firewall: neither ufw nor firewall-cmd found.".into());
+        return;
+    };
+    let mut closed = true;
+    for port in ports {
+        if let Err(e) = run(tool, false, port, vm, config, logs) {
+            let by_hand = tool.args(false, port, vm).join(" ");
+            logs.lock().unwrap().push(format!("Firewall: closing port {} failed ({}); run: {}", port, e, by_hand));
+            closed = false;
+        }
+    }
+    if config.dry_run {
+        return;
+    }
+    if let Err(e) = save(&rules) {
+        logs.lock().unwrap().push(format!("Firewall: cannot record the rules: {}", e));
+    }
+    if closed {
+        logs.lock().unwrap().push(format!("Firewall: closed the ports of {}.", vm));
+    }
+}
+
+/// Close the ports of VMs that have stopped, given the running flag per VM stem.
+/// VMs missing from `running` (e.g. in another workspace) are left alone.
+pub fn close_stopped(running: &HashMap<String, bool>, config: &Config, logs: &Arc<Mutex<Vec<String>>>) {
+    for vm in opened().into_keys() {
+        if running.get(&vm) == Some(&false) {
+            close(&vm, config, logs);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rules_file_round_trip() {
+        let rules = parse_rules("win11=3389, 22220\nbad line\ndev=x,5900\n");
+        assert_eq!(rules.get("win11"), Some(&vec![3389, 22220]));
+        assert_eq!(rules.get("dev"), Some(&vec![5900]));
+        assert_eq!(rules.len(), 2);
+        let mut rules = rules;
+        rules.insert("empty".into(), Vec::new());
+        assert_eq!(format_rules(&rules), "dev=5900\nwin11=3389,22220\n");
+        assert_eq!(parse_rules(&format_rules(&rules)).len(), 2);
+    }
+
+    #[test]
+    fn tool_arguments() {
+        assert_eq!(Tool::Ufw.args(true, 3389, "win11"), ["ufw", "allow", "3389/tcp", "comment", "quick-cli win11"]);
+        assert_eq!(Tool::Ufw.args(false, 3389, "win11"), ["ufw", "delete", "allow", "3389/tcp"]);
+        assert_eq!(Tool::Firewalld.args(true, 22, "dev"), ["firewall-cmd", "--add-port=22/tcp"]);
+        assert_eq!(Tool::Firewalld.args(false, 22, "dev"), ["firewall-cmd", "--remove-port=22/tcp"]);
+    }
+}
@@ -0,0 +1,158 @@
+///////////////////////////////////////////////////////////////////////////////
+// gRPC API (feature "grpc")
+///////////////////////////////////////////////////////////////////////////////
+//
+// With `grpc_listen` the daemon also serves proto/quick_cli.proto. ListVms,
+// StartVm and StopVm go through the control socket's request handler, so they
+// obey read-only mode the same way; WatchStatus streams VM events as the
+// poller sees them. Every call must carry "authorization: Bearer <control_token>".
+// The server runs on a tokio runtime of its own; the rest of the daemon stays
+// thread based.
+
+use std::{error::Error, net::TcpListener, pin::Pin, sync::Arc, thread};
+
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio_stream::{
+    wrappers::{ReceiverStream, TcpListenerStream},
+    Stream,
+};
+use tonic::{metadata::MetadataValue, transport::Server, Request, Response, Status};
+
+use crate::{
+    daemon::{handle_request, token_matches, DaemonState},
+    events::{self, Event},
+    Config,
+};
+
+mod pb {
+    tonic::include_proto!("quick_cli.v1");
+}
+
+use pb::quick_cli_server::{QuickCli, QuickCliServer};
+
+/// Events queued per WatchStatus stream; a client that falls further behind misses some.
+const WATCH_BUFFER: usize = 256;
+
+struct Service {
+    config: Arc<Config>,
+    state: Arc<DaemonState>,
+}
+
+fn vm_state(state: events::VmState) -> pb::VmState {
+    match state {
+        events::VmState::Stopped => pb::VmState::Stopped,
+        events::VmState::Running => pb::VmState::Running,
+        events::VmState::Ready => pb::VmState::Ready,
+    }
+}
+
+/// The stream message for a VM event; job events are not streamed.
+fn vm_event(event: &Event) -> Option<pb::VmEvent> {
+    let (vm, state) = event.vm_state()?;
+    let (name, time) = match event {
+        Event::State { time, .. } => ("vm_state", *time),
+        Event::Started { time, .. } => ("vm_started", *time),
+        Event::Ready { time, .. } => ("vm_ready", *time),
+        Event::Stopped { time, .. } => ("vm_stopped", *time),
+        Event::JobFinished { .. } => return None,
+    };
+    Some(pb::VmEvent { vm: vm.to_string(), event: name.to_string(), state: vm_state(state) as i32, time })
+}
+
+impl Service {
+    /// Run a start/stop request off the async threads: starting waits for quickemu.
+    async fn control(&self, command: &str, name: String) -> Result<Response<pb::VmResponse>, Status> {
+        if name.trim().is_empty() {
+            return Err(Status::invalid_argument("missing VM name"));
+        }
+        let (config, state) = (Arc::clone(&self.config), Arc::clone(&self.state));
+        let line = format!("{} {}", command, name);
+        tokio::task::spawn_blocking(move || handle_request(&line, &config, &state))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .map(|_| Response::new(pb::VmResponse {}))
+            .map_err(Status::failed_precondition)
+    }
+}
+
+#[tonic::async_trait]
+impl QuickCli for Service {
+    async fn list_vms(&self, _request: Request<pb::ListVmsRequest>) -> Result<Response<pb::ListVmsResponse>, Status> {
+        let status = self.state.status.lock().unwrap();
+        let mut vms: Vec<pb::Vm> = status
+            .iter()
+            .map(|(name, state)| pb::Vm { name: name.clone(), state: vm_state(*state) as i32 })
+            .collect();
+        vms.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(Response::new(pb::ListVmsResponse { vms }))
+    }
+
+    async fn start_vm(&self, request: Request<pb::VmRequest>) -> Result<Response<pb::VmResponse>, Status> {
+        self.control("start", request.into_inner().name).await
+    }
+
+    async fn stop_vm(&self, request: Request<pb::VmRequest>) -> Result<Response<pb::VmResponse>, Status> {
+        self.control("stop", request.into_inner().name).await
+    }
+
+    type WatchStatusStream = Pin<Box<dyn Stream<Item = Result<pb::VmEvent, Status>> + Send>>;
+
+    async fn watch_status(
+        &self,
+        _request: Request<pb::WatchStatusRequest>,
+    ) -> Result<Response<Self::WatchStatusStream>, Status> {
+        let (tx, rx) = mpsc::channel(WATCH_BUFFER);
+        // Subscribe while holding the list, so no change slips in between the
+        // current states and the first event.
+        let mut subscribers = self.state.subscribers.lock().unwrap();
+        let time = events::now();
+        for (vm, state) in self.state.status.lock().unwrap().iter() {
+            let current = pb::VmEvent {
+                vm: vm.clone(),
+                event: "vm_state".to_string(),
+                state: vm_state(*state) as i32,
+                time,
+            };
+            let _ = tx.try_send(Ok(current));
+        }
+        subscribers.push(Box::new(move |event| match vm_event(event) {
+            Some(message) => !matches!(tx.try_send(Ok(message)), Err(TrySendError::Closed(_))),
+            None => !tx.is_closed(),
+        }));
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+}
+
+/// Start serving the gRPC API on `addr` in the background.
+pub(crate) fn serve(addr: &str, config: Arc<Config>, state: Arc<DaemonState>) -> Result<(), Box<dyn Error>> {
+    let token = config.control_token.as_deref().ok_or("grpc_listen needs a control_token")?;
+    let expected: MetadataValue<_> = format!("Bearer {}", token).parse()?;
+    // Bind here so a taken port fails the daemon's startup rather than a log line.
+    let listener = TcpListener::bind(addr)?;
+    listener.set_nonblocking(true)?;
+    let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build()?;
+    let logs = Arc::clone(&state.logs);
+    logs.lock().unwrap().push(format!("Daemon serving gRPC on {}.", addr));
+    let service = QuickCliServer::with_interceptor(Service { config, state }, move |request: Request<()>| {
+        match request.metadata().get("authorization") {
+            Some(value) if token_matches(value.as_bytes(), expected.as_bytes()) => Ok(request),
+            _ => Err(Status::unauthenticated("missing or wrong control_token")),
+        }
+    });
+    thread::spawn(move || {
+        runtime.block_on(async move {
+            let result = match tokio::net::TcpListener::from_std(listener) {
+                Ok(listener) => Server::builder()
+                    .add_service(service)
+                    .serve_with_incoming(TcpListenerStream::new(listener))
+                    .await
+                    .map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            if let Err(e) = result {
+                logs.lock().unwrap().push(format!("gRPC server stopped: {}", e));
+            }
+        });
+    });
+    Ok(())
+}
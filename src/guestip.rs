@@ -0,0 +1,121 @@
+///////////////////////////////////////////////////////////////////////////////
+// Guest IP Discovery (bridged networking)
+///////////////////////////////////////////////////////////////////////////////
+//
+// VMs with `network="br0"` (any value other than nat, restrict or none) sit on
+// the host's LAN with no localhost port forwards, so RDP/VNC must target the
+// guest's own address. It is looked up, in order, from:
+//   1. the QEMU guest agent on {vm}-qga.socket, when one is configured
+//   2. the host's ARP/neighbour table, matching the VM's `macaddr`
+//   3. dnsmasq leases, matching `macaddr` or the VM name as hostname
+
+use std::{fs, path::Path, process::Command};
+
+use crate::{vm_conf_value, vm_runtime_file};
+
+const LEASE_FILES: &[&str] = &["/var/lib/misc/dnsmasq.leases", "/var/lib/dnsmasq/dnsmasq.leases"];
+
+/// Whether the VM is attached to a bridge rather than QEMU's user networking.
+pub fn is_bridged(vm_conf: &Path) -> bool {
+    vm_conf_value(vm_conf, "network").is_some_and(|n| !matches!(n.as_str(), "" | "nat" | "restrict" | "none"))
+}
+
+/// Parse a MAC address, tolerating the dropped leading zeros of BSD `arp` output.
+fn parse_mac(s: &str) -> Option<[u8; 6]> {
+    let parts: Vec<&str> = s.split([':', '-']).collect();
+    if parts.len() != 6 {
+        return None;
+    }
+    let mut mac = [0u8; 6];
+    for (byte, part) in mac.iter_mut().zip(parts) {
+        *byte = u8::from_str_radix(part, 16).ok()?;
+    }
+    Some(mac)
+}
+
+/// The guest agent's `guest-network-get-interfaces` reply: addresses and, with
+/// recent agents, per-interface statistics.
+#[cfg(unix)]
+pub(crate) fn guest_agent_interfaces(vm_conf: &Path) -> Option<Vec<serde_json::Value>> {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        os::unix::net::UnixStream,
+        time::Duration,
+    };
+    let mut stream = UnixStream::connect(vm_runtime_file(vm_conf, "-qga.socket")).ok()?;
+    stream.set_read_timeout(Some(Duration::from_secs(2))).ok()?;
+    stream.write_all(b"{\"execute\":\"guest-network-get-interfaces\"}\n").ok()?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line).ok()?;
+    let reply: serde_json::Value = serde_json::from_str(&line).ok()?;
+    reply["return"].as_array().cloned()
+}
+
+#[cfg(not(unix))]
+pub(crate) fn guest_agent_interfaces(_vm_conf: &Path) -> Option<Vec<serde_json::Value>> {
+    None
+}
+
+fn from_guest_agent(vm_conf: &Path, mac: Option<[u8; 6]>) -> Option<String> {
+    let interfaces = guest_agent_interfaces(vm_conf)?;
+    // Prefer the interface with the VM's MAC; otherwise the first non-loopback IPv4.
+    let matches_mac = |iface: &serde_json::Value| {
+        mac.is_some() && iface["hardware-address"].as_str().and_then(parse_mac) == mac
+    };
+    let ordered = interfaces.iter().filter(|i| matches_mac(i)).chain(interfaces.iter().filter(|i| !matches_mac(i)));
+    ordered
+        .flat_map(|iface| iface["ip-addresses"].as_array().into_iter().flatten())
+        .filter(|addr| addr["ip-address-type"] == "ipv4")
+        .filter_map(|addr| addr["ip-address"].as_str())
+        .find(|ip| !ip.starts_with("127."))
+        .map(String::from)
+}
+
+/// Look the MAC up in the neighbour table: `ip neigh` on Linux, `arp -an` elsewhere.
+fn from_arp(mac: [u8; 6]) -> Option<String> {
+    let output = if cfg!(target_os = "linux") {
+        Command::new("ip").arg("neigh").output().ok()?
+    } else {
+        Command::new("arp").arg("-an").output().ok()?
+    };
+    // "192.168.1.50 dev br0 lladdr 52:54:00:aa:bb:cc REACHABLE"
+    // "? (192.168.1.50) at 52:54:0:aa:bb:cc on en0 ifscope [ethernet]"
+    String::from_utf8_lossy(&output.stdout).lines().find_map(|line| {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        words.iter().any(|w| parse_mac(w) == Some(mac)).then(|| {
+            let ip = if words.first() == Some(&"?") { words.get(1)? } else { words.first()? };
+            Some(ip.trim_matches(|c| c == '(' || c == ')').to_string())
+        })?
+    })
+}
+
+/// dnsmasq lease lines: "<expiry> <mac> <ip> <hostname> <client-id>".
+fn from_leases(mac: Option<[u8; 6]>, hostname: &str) -> Option<String> {
+    LEASE_FILES.iter().filter_map(|path| fs::read_to_string(path).ok()).find_map(|contents| {
+        contents.lines().find_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (lease_mac, ip, host) = (fields.get(1)?, fields.get(2)?, fields.get(3)?);
+            let hit = (mac.is_some() && parse_mac(lease_mac) == mac) || host.eq_ignore_ascii_case(hostname);
+            hit.then(|| ip.to_string())
+        })
+    })
+}
+
+/// The guest's IP address and where it was found.
+pub fn discover(vm_conf: &Path) -> Result<(String, &'static str), String> {
+    let mac = vm_conf_value(vm_conf, "macaddr").and_then(|m| parse_mac(&m));
+    let name = vm_conf.file_stem().unwrap().to_string_lossy();
+    if let Some(ip) = from_guest_agent(vm_conf, mac) {
+        return Ok((ip, "guest agent"));
+    }
+    if let Some(ip) = mac.and_then(from_arp) {
+        return Ok((ip, "ARP table"));
+    }
+    if let Some(ip) = from_leases(mac, &name) {
+        return Ok((ip, "dnsmasq leases"));
+    }
+    Err(match mac {
+        Some(_) => "not found via guest agent, ARP table or dnsmasq leases".into(),
+        None => "no guest agent answered and no macaddr is set in the .conf to look up".into(),
+    })
+}
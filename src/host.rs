@@ -0,0 +1,121 @@
+///////////////////////////////////////////////////////////////////////////////
+// Host Resources
+///////////////////////////////////////////////////////////////////////////////
+//
+// Load average and memory come from /proc on Linux (sysctl on macOS); free disk
+// space from `df`. Anything that cannot be read on the platform is left as None
+// and shown as "?" in the header.
+
+use std::path::Path;
+
+#[cfg(target_os = "linux")]
+use std::fs;
+#[cfg(unix)]
+use std::process::Command;
+
+use crate::vm_conf_value;
+
+#[derive(Default)]
+pub struct HostResources {
+    pub load: Option<f64>,          // 1-minute load average
+    pub mem_available: Option<u64>, // Bytes
+    pub mem_total: Option<u64>,     // Bytes
+    pub disk_free: Option<u64>,     // Bytes free on the filesystem holding the VMs
+}
+
+impl HostResources {
+    pub fn read(vm_dir: &Path) -> Self {
+        let (mem_available, mem_total) = read_memory();
+        Self { load: read_load(), mem_available, mem_total, disk_free: read_disk_free(vm_dir) }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn read_load() -> Option<f64> {
+    fs::read_to_string("/proc/loadavg").ok()?.split_whitespace().next()?.parse().ok()
+}
+
+#[cfg(target_os = "macos")]
+fn read_load() -> Option<f64> {
+    // Format: "{ 1.23 1.10 0.98 }"
+    let output = Command::new("sysctl").args(["-n", "vm.loadavg"]).output().ok()?;
+    String::from_utf8_lossy(&output.stdout).split_whitespace().nth(1)?.parse().ok()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn read_load() -> Option<f64> {
+    None
+}
+
+/// (available, total) memory in bytes.
+#[cfg(target_os = "linux")]
+pub fn read_memory() -> (Option<u64>, Option<u64>) {
+    let Ok(meminfo) = fs::read_to_string("/proc/meminfo") else { return (None, None) };
+    let field = |name: &str| {
+        meminfo.lines().find_map(|line| {
+            let kb = line.strip_prefix(name)?.trim_start_matches(':').split_whitespace().next()?;
+            kb.parse::<u64>().ok().map(|kb| kb * 1024)
+        })
+    };
+    (field("MemAvailable"), field("MemTotal"))
+}
+
+#[cfg(target_os = "macos")]
+pub fn read_memory() -> (Option<u64>, Option<u64>) {
+    let total = Command::new("sysctl")
+        .args(["-n", "hw.memsize"])
+        .output()
+        .ok()
+        .and_then(|o| String::from_utf8_lossy(&o.stdout).trim().parse().ok());
+    (None, total)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn read_memory() -> (Option<u64>, Option<u64>) {
+    (None, None)
+}
+
+#[cfg(unix)]
+pub fn read_disk_free(dir: &Path) -> Option<u64> {
+    // POSIX output: header line, then "fs 1024-blocks used available capacity mount".
+    let output = Command::new("df").arg("-Pk").arg(dir).output().ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let kb: u64 = stdout.lines().nth(1)?.split_whitespace().nth(3)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(not(unix))]
+pub fn read_disk_free(_dir: &Path) -> Option<u64> {
+    None
+}
+
+/// RAM configured for the VM (`ram="8G"`), in bytes. quickemu sizes RAM from the
+/// host when the key is missing, so None means "decided at start".
+pub fn vm_ram(vm_conf: &Path) -> Option<u64> {
+    parse_size(&vm_conf_value(vm_conf, "ram")?)
+}
+
+pub fn vm_cores(vm_conf: &Path) -> Option<u32> {
+    vm_conf_value(vm_conf, "cpu_cores")?.parse().ok()
+}
+
+/// Parse a qemu-style size such as "8G", "4096M" or "512" (MiB when unitless).
+pub fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse().ok()?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().trim_end_matches('B').trim_end_matches('I') {
+        "" | "M" => 1 << 20,
+        "K" => 1 << 10,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return None,
+    };
+    Some((number * multiplier as f64) as u64)
+}
+
+/// Human-readable size in GiB, e.g. "12.3 GiB".
+pub fn format_gib(bytes: u64) -> String {
+    format!("{:.1} GiB", bytes as f64 / (1u64 << 30) as f64)
+}
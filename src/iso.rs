@@ -0,0 +1,126 @@
+///////////////////////////////////////////////////////////////////////////////
+// ISO Images and Checksum Verification
+///////////////////////////////////////////////////////////////////////////////
+//
+// The ISO view lists the `iso` and `fixed_iso` images referenced by VM configs.
+// Expected SHA256 sums come from, in order:
+//   1. the `[iso_checksums]` config table (ISO file name -> sha256)
+//   2. a `<iso>.sha256` file next to the image
+//   3. a `SHA256SUMS` file in the image's directory, as left by quickget for
+//      distributions that publish one
+// Hashing runs `sha256sum` (`shasum -a 256` on macOS) in a background thread;
+// the result is posted to webhooks as a `verify_iso` job.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use crate::{events::Event, vm_conf_value, webhook, Config};
+
+#[derive(Clone)]
+pub enum IsoStatus {
+    NoChecksum,
+    Verifying,
+    Verified,
+    Mismatch,
+    Failed(String),
+}
+
+impl IsoStatus {
+    pub fn label(&self) -> String {
+        match self {
+            IsoStatus::NoChecksum => "no checksum".into(),
+            IsoStatus::Verifying => "verifying...".into(),
+            IsoStatus::Verified => "✔ verified".into(),
+            IsoStatus::Mismatch => "✘ checksum mismatch".into(),
+            IsoStatus::Failed(e) => format!("error: {}", e),
+        }
+    }
+}
+
+pub type IsoStatuses = Arc<Mutex<HashMap<PathBuf, IsoStatus>>>;
+
+/// ISO images referenced by the VM config, resolved relative to the .conf directory.
+pub fn vm_isos(vm_conf: &Path) -> Vec<PathBuf> {
+    ["iso", "fixed_iso"]
+        .iter()
+        .filter_map(|key| vm_conf_value(vm_conf, key))
+        .filter(|value| !value.is_empty())
+        .map(|value| {
+            let path = PathBuf::from(value);
+            if path.is_relative() { vm_conf.with_file_name(path) } else { path }
+        })
+        .collect()
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default()
+}
+
+/// Expected lowercase SHA256 of the image, if one is known.
+pub fn expected_checksum(iso: &Path, config: &Config) -> Option<String> {
+    let name = file_name(iso);
+    if let Some(sum) = config.iso_checksums.get(&name) {
+        return Some(sum.to_lowercase());
+    }
+    let mut sidecar = iso.as_os_str().to_owned();
+    sidecar.push(".sha256");
+    if let Ok(contents) = fs::read_to_string(PathBuf::from(sidecar)) {
+        return contents.split_whitespace().next().map(str::to_lowercase);
+    }
+    // "<sha256>  <file>" or "<sha256> *<file>" per line.
+    let sums = fs::read_to_string(iso.with_file_name("SHA256SUMS")).ok()?;
+    sums.lines().find_map(|line| {
+        let (sum, file) = line.split_once(char::is_whitespace)?;
+        (file.trim().trim_start_matches('*') == name).then(|| sum.to_lowercase())
+    })
+}
+
+fn sha256(path: &Path) -> Result<String, String> {
+    let mut command = if cfg!(target_os = "macos") {
+        let mut c = Command::new("shasum");
+        c.args(["-a", "256"]);
+        c
+    } else {
+        Command::new("sha256sum")
+    };
+    let output = command.arg(path).output().map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .next()
+        .map(str::to_lowercase)
+        .ok_or_else(|| "no output from sha256sum".into())
+}
+
+/// Verify the image against its expected checksum in a background thread,
+/// recording progress and the result in `statuses` and the log.
+pub fn verify(iso: &Path, config: &Config, statuses: &IsoStatuses, logs: &Arc<Mutex<Vec<String>>>) {
+    let Some(expected) = expected_checksum(iso, config) else {
+        statuses.lock().unwrap().insert(iso.to_path_buf(), IsoStatus::NoChecksum);
+        return;
+    };
+    statuses.lock().unwrap().insert(iso.to_path_buf(), IsoStatus::Verifying);
+    let iso = iso.to_path_buf();
+    let statuses = Arc::clone(statuses);
+    let logs = Arc::clone(logs);
+    let webhooks = config.webhooks.clone();
+    thread::spawn(move || {
+        let status = match sha256(&iso) {
+            Ok(actual) if actual == expected => IsoStatus::Verified,
+            Ok(_) => IsoStatus::Mismatch,
+            Err(e) => IsoStatus::Failed(e),
+        };
+        logs.lock().unwrap().push(format!("ISO {}: {}", iso.display(), status.label()));
+        let event = Event::job_finished("verify_iso", &iso.to_string_lossy(), matches!(status, IsoStatus::Verified));
+        webhook::post(&webhooks, &event, &logs);
+        statuses.lock().unwrap().insert(iso, status);
+    });
+}
@@ -0,0 +1,71 @@
+///////////////////////////////////////////////////////////////////////////////
+// Background Jobs
+///////////////////////////////////////////////////////////////////////////////
+//
+// Long-running work that reports progress (backups) is registered here so the
+// `J` view can show it. Finished jobs stay listed until quick-cli exits.
+
+use std::sync::{Arc, Mutex};
+
+use crate::events::now;
+
+#[derive(Clone, PartialEq)]
+pub enum JobState {
+    Running,
+    Done,
+    Failed(String),
+}
+
+#[derive(Clone)]
+pub struct Job {
+    pub kind: &'static str,    // e.g. "backup"
+    pub target: String,        // VM stem
+    pub detail: String,        // What is being done, e.g. "incremental to /backups/win11/1760000000-incr.qcow2"
+    pub progress: Option<f32>, // Percent done, when the tool reports it
+    pub state: JobState,
+    pub started: u64,          // Unix seconds
+    pub finished: Option<u64>,
+}
+
+pub type Jobs = Arc<Mutex<Vec<Job>>>;
+
+/// Register a running job and return its index for `update`.
+pub fn add(jobs: &Jobs, kind: &'static str, target: &str, detail: String) -> usize {
+    let mut jobs = jobs.lock().unwrap();
+    jobs.push(Job {
+        kind,
+        target: target.to_string(),
+        detail,
+        progress: None,
+        state: JobState::Running,
+        started: now(),
+        finished: None,
+    });
+    jobs.len() - 1
+}
+
+pub fn set_progress(jobs: &Jobs, index: usize, percent: f32) {
+    if let Some(job) = jobs.lock().unwrap().get_mut(index) {
+        job.progress = Some(percent);
+    }
+}
+
+pub fn finish(jobs: &Jobs, index: usize, result: &Result<(), String>) {
+    if let Some(job) = jobs.lock().unwrap().get_mut(index) {
+        job.state = match result {
+            Ok(()) => JobState::Done,
+            Err(e) => JobState::Failed(e.clone()),
+        };
+        job.finished = Some(now());
+    }
+}
+
+/// When the newest job of this kind for the target started.
+pub fn last_started(jobs: &Jobs, kind: &str, target: &str) -> Option<u64> {
+    jobs.lock().unwrap().iter().filter(|j| j.kind == kind && j.target == target).map(|j| j.started).max()
+}
+
+/// Whether a job of this kind is still running for the target.
+pub fn is_running(jobs: &Jobs, kind: &str, target: &str) -> bool {
+    jobs.lock().unwrap().iter().any(|j| j.kind == kind && j.target == target && j.state == JobState::Running)
+}
@@ -0,0 +1,133 @@
+///////////////////////////////////////////////////////////////////////////////
+// Launcher Export
+///////////////////////////////////////////////////////////////////////////////
+//
+// `:export <vm|all>` writes shortcuts that run `quick-cli --connect <vm>`:
+//   - shell aliases (`qc-<vm>`) in $XDG_CONFIG_HOME/quick-cli/aliases.sh
+//   - .desktop entries in ~/.local/share/applications on Linux
+//   - Start Menu shortcuts (Programs\Quick-CLI) on Windows
+
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use crate::config_dir;
+
+/// VM name reduced to characters safe in alias and file names.
+fn slug(vm_name: &str) -> String {
+    vm_name
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '-' })
+        .collect()
+}
+
+fn vm_name(vm_conf: &Path) -> String {
+    vm_conf.file_stem().unwrap().to_string_lossy().to_string()
+}
+
+pub fn aliases_path() -> PathBuf {
+    config_dir().join("aliases.sh")
+}
+
+/// Add or replace the aliases for the given VMs, keeping the others already in the file.
+fn write_aliases(exe: &Path, vms: &[PathBuf]) -> Result<PathBuf, Box<dyn Error>> {
+    let path = aliases_path();
+    let existing = fs::read_to_string(&path).unwrap_or_default();
+    let names: Vec<String> = vms.iter().map(|vm| slug(&vm_name(vm))).collect();
+    let mut lines: Vec<String> = existing
+        .lines()
+        .filter(|line| !names.iter().any(|n| line.starts_with(&format!("alias qc-{}=", n))))
+        .map(String::from)
+        .collect();
+    if lines.is_empty() {
+        lines.push("# Generated by quick-cli :export. Source this file from your shell rc.".into());
+    }
+    for vm in vms {
+        let name = vm_name(vm);
+        lines.push(format!(
+            "alias qc-{}='\"{}\" --connect \"{}\"'",
+            slug(&name),
+            exe.display(),
+            name.replace('\'', "")
+        ));
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, lines.join("\n") + "\n")?;
+    Ok(path)
+}
+
+#[cfg(target_os = "linux")]
+fn write_shortcut(exe: &Path, vm_conf: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    let name = vm_name(vm_conf);
+    let dir = dirs::data_dir().ok_or("no data directory")?.join("applications");
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("quick-cli-{}.desktop", slug(&name)));
+    let entry = format!(
+        "[Desktop Entry]\nType=Application\nName={name} (Quick-CLI)\nComment=Connect to the {name} VM\n\
+         Exec=\"{exe}\" --connect \"{name}\"\nIcon=computer\nTerminal=false\nCategories=System;Emulator;\n",
+        name = name,
+        exe = exe.display(),
+    );
+    fs::write(&path, entry)?;
+    Ok(path)
+}
+
+#[cfg(target_os = "windows")]
+fn write_shortcut(exe: &Path, vm_conf: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    let name = vm_name(vm_conf);
+    let dir = dirs::data_dir()
+        .ok_or("no AppData directory")?
+        .join(r"Microsoft\Windows\Start Menu\Programs\Quick-CLI");
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("{}.lnk", slug(&name)));
+    // .lnk files are binary; let the WScript.Shell COM object write them.
+    let quote = |s: &str| format!("'{}'", s.replace('\'', "''"));
+    let script = format!(
+        "$s = (New-Object -ComObject WScript.Shell).CreateShortcut({}); $s.TargetPath = {}; \
+         $s.Arguments = {}; $s.Save()",
+        quote(&path.to_string_lossy()),
+        quote(&exe.to_string_lossy()),
+        quote(&format!("--connect \"{}\"", name)),
+    );
+    let status = std::process::Command::new("powershell")
+        .args(["-NoProfile", "-NonInteractive", "-Command", &script])
+        .status()?;
+    if !status.success() {
+        return Err(format!("powershell exited with {}", status).into());
+    }
+    Ok(path)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn write_shortcut(_exe: &Path, _vm_conf: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    Err("desktop shortcuts are not supported on this platform; use the shell aliases".into())
+}
+
+/// Export aliases and desktop shortcuts for the given VMs, reporting to the log.
+pub fn export(vms: &[PathBuf], logs: &Arc<Mutex<Vec<String>>>) {
+    let mut l = logs.lock().unwrap();
+    let exe = match std::env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => {
+            l.push(format!("Export failed: cannot locate the quick-cli executable: {}", e));
+            return;
+        }
+    };
+    if !cfg!(windows) {
+        match write_aliases(&exe, vms) {
+            Ok(path) => l.push(format!("Wrote shell aliases to {} (source it from your shell rc).", path.display())),
+            Err(e) => l.push(format!("Failed to write shell aliases: {}", e)),
+        }
+    }
+    for vm in vms {
+        match write_shortcut(&exe, vm) {
+            Ok(path) => l.push(format!("Wrote launcher {}.", path.display())),
+            Err(e) => l.push(format!("No launcher for {}: {}", vm_name(vm), e)),
+        }
+    }
+}
@@ -0,0 +1,3022 @@
+//! Quick-CLI as a library: configuration loading, VM discovery, protocol
+//! detection, running-status checks, and starting, stopping and connecting to
+//! quickemu VMs. The `quick-cli` TUI and daemon are built on it; other tools can
+//! use it without the terminal UI.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    ffi::OsStr,
+    fs,
+    io,
+    net::{IpAddr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
+use serde::{Deserialize, Serialize};
+
+use backup::BackupPolicy;
+use cloudinit::CloudInit;
+use quota::Quota;
+
+pub mod backends;
+pub mod backup;
+pub mod cloudinit;
+pub mod confdiff;
+pub mod console;
+pub mod daemon;
+pub mod demo;
+pub mod disk;
+pub mod download;
+pub mod events;
+pub mod filebrowser;
+pub mod firewall;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod guestip;
+pub mod host;
+pub mod iso;
+pub mod jobs;
+pub mod launchers;
+pub mod logsink;
+pub mod manifest;
+pub mod mdns;
+pub mod metrics;
+pub mod mqtt;
+pub mod notify;
+pub mod procs;
+pub mod qmp;
+pub mod quota;
+pub mod remmina;
+pub mod screenshots;
+pub mod suspend;
+pub mod snapshot;
+pub mod sync;
+pub mod term;
+pub mod tunnel;
+pub mod viewer;
+pub mod watch;
+pub mod watchdog;
+pub mod webhook;
+
+///////////////////////////////////////////////////////////////////////////////
+// Configuration and VM Listing
+///////////////////////////////////////////////////////////////////////////////
+
+/// Effective settings: built-in defaults overlaid with config.toml (see `load_config`)
+/// and command-line flags.
+#[derive(Clone)]
+pub struct Config {
+    pub source: Option<PathBuf>, // config.toml this was loaded from, if any
+    pub remote_app: String,      // e.g. "remmina" (or native client on Windows/macOS)
+    pub quickemu_dirs: Vec<PathBuf>, // Directories with VM config files, one workspace each
+    pub default_spice_port: u16, // Default SPICE port if not specified in VM config
+    pub os_type: String,         // "windows", "macos", or "linux"
+    pub quickemu: Vec<String>,   // Command words that run quickemu: a path, wrapper or fork
+    pub quickget: Vec<String>,   // Command words that run quickget
+    pub iso_downloader: String,  // "quickget" (its own fetcher) or "aria2c", see download.rs
+    pub aria2c_connections: u8,  // Connections per aria2c download (1-16)
+    pub firewall_sudo: Vec<String>, // Command words ufw/firewall-cmd run through, e.g. ["sudo", "-n"] (empty: none)
+    // quickemu flag -> the flag this quickemu version or fork uses instead, e.g. "--display" -> "--viewer".
+    pub quickemu_flags: HashMap<String, String>,
+    // Override mapping: key = VM config file stem (lowercase), value = path to Remmina profile.
+    pub remmina_overrides: HashMap<String, String>,
+    pub remmina_dirs: Vec<PathBuf>, // Directories searched for .remmina profiles, in order
+    pub control_socket: PathBuf, // Unix socket used by `quick-cli daemon`
+    pub daemon_listen: Option<String>, // TCP address the daemon also accepts requests on, e.g. "0.0.0.0:7722"
+    pub control_token: Option<String>, // Shared secret for TCP control connections (required with daemon_listen)
+    pub grpc_listen: Option<String>, // Address of the daemon's gRPC API (needs the "grpc" build feature)
+    pub remote: Option<String>,  // Daemon the TUI drives instead of local VMs: "host:port" or a forwarded socket
+    pub autostart: Vec<String>,        // Glob patterns for VMs the daemon starts when it starts
+    pub restart_on_crash: Vec<String>, // Glob patterns for VMs the daemon restarts when their qemu dies
+    pub scan_depth: usize,       // Subdirectory levels searched for .conf files (0 = top level only)
+    pub scan_ignore: Vec<String>, // Glob patterns for directory names skipped while scanning
+    pub ignore: Vec<String>,     // Glob patterns for VM stems hidden from the list
+    pub tick_rate: Duration,            // UI refresh / spinner interval
+    pub status_poll_interval: Duration, // How often running status is re-checked
+    pub connect_timeout: Duration,      // TCP connect timeout for port probes
+    pub connect_host: String,           // Address of the forwarded ports: "127.0.0.1", "::1", ... (no brackets)
+    pub start_wait: Duration,           // Pause after spawning quickemu before connecting
+    pub stop_timeout: Duration,         // Wait after ACPI powerdown before escalating to quickemu --kill
+    pub viewer_timeout: Duration,       // Kill a viewer that has not connected by then and try the next (0 = off)
+    pub suspend_on_stop: Vec<String>,   // Glob patterns for VMs whose stop saves state and start resumes it
+    pub start_stagger: Duration,        // Delay between launches of `:start all`
+    pub start_concurrency: usize,       // VMs of `:start all` allowed to boot at once (0 = no limit)
+    pub paste_key_delay: Duration,      // Pause between keystrokes when pasting the clipboard into a guest
+    pub git_sync: bool,                 // Pull/commit/push .conf files in quickemu_dirs that are git repos
+    pub read_only: bool,                // Only view and connect; every mutating action is refused
+    pub dry_run: bool,                  // Log spawned commands instead of running them (--dry-run only)
+    pub demo: bool,                     // Made-up VMs with scripted starts and stops (--demo only)
+    pub verbose: bool,                  // Log every spawned command line with its environment overrides
+    pub ram_commit_percent: u64,        // Confirm starts that commit more host RAM than this (0 = off)
+    pub iso_checksums: HashMap<String, String>, // ISO file name -> expected SHA256
+    pub spice_display: HashMap<String, SpiceDisplay>, // Lowercase VM stem -> SPICE viewer options
+    pub rdp_gateways: HashMap<String, RdpGateway>,    // Lowercase VM stem -> RD Gateway to connect through
+    pub streaming: HashMap<String, Streaming>,        // Lowercase VM stem -> Sunshine port and Moonlight app
+    pub vm_hosts: HashMap<String, String>,            // Lowercase VM stem -> host instead of connect_host
+    pub ssh_tunnels: HashMap<String, String>,         // Lowercase VM stem -> ssh destination viewers tunnel through
+    pub protocols: HashMap<String, Vec<String>>,      // Lowercase VM stem -> protocols to try, e.g. ["rdp", "vnc"]
+    pub backups: HashMap<String, BackupPolicy>,       // Lowercase VM stem -> backup schedule and retention
+    pub groups: BTreeMap<String, Quota>,              // Group name -> member VMs and their RAM/CPU budget
+    pub backup_dir: PathBuf,            // Backups go to <backup_dir>/<vm>/, see backup.rs
+    pub cloud_init: CloudInit,          // User, SSH keys and templates for `:create --cloud-init` seeds
+    pub multiplexer: String,            // "off", "pane" or "window": where consoles open inside tmux/zellij
+    pub image_protocol: String,         // Screenshot previews: "auto", "kitty", "iterm2", "sixel" or "none"
+    pub layout: Option<String>,         // Startup layout preset; None keeps the one last used
+    pub macos_rdp_app: Option<String>,  // macOS app for rdp:// URLs (default: Windows App, then Microsoft RD)
+    pub macos_vnc_app: Option<String>,  // macOS app for vnc:// URLs (default: Screen Sharing, then VNC Viewer)
+    pub macos_spice_app: Option<String>, // macOS app for spice:// URLs when remote-viewer is not on $PATH
+    pub ssh_user: Option<String>,       // Guest user for SSH and the file browser (default: ssh's own choice)
+    pub webhooks: Vec<String>,          // URLs that receive a JSON POST for VM state changes and finished jobs
+    pub mqtt_host: Option<String>,      // MQTT broker ("host" or "host:port") the daemon publishes states to
+    pub mqtt_prefix: String,            // Topic prefix: <prefix>/<vm>/state and <prefix>/<vm>/command
+    pub mqtt_user: Option<String>,
+    pub mqtt_password: Option<String>,
+    pub mdns: bool,                     // Advertise running VMs' SSH/RDP/VNC forwards over mDNS, see mdns.rs
+    pub log_sink: String,               // Where the daemon logs: "stderr", "journald" or "syslog", see logsink.rs
+    pub notify: Vec<String>,            // ntfy/Slack/Discord URLs told about failed starts and low disk space
+    pub backends: Vec<String>,          // Other VM managers listed next to quickemu VMs, e.g. "utm"
+    pub low_disk_gib: u64,              // Notify when a VM directory has less free space than this (0 = off)
+    pub alert_cpu_percent: u32,         // Warn when a VM uses more CPU than this, in percent of one core (0 = off)
+    pub alert_cpu_duration: Duration,   // ...for at least this long
+    pub alert_free_ram_gib: u64,        // Warn when the host has less available memory than this (0 = off)
+    // Per-client command override, e.g. "remmina" -> ["flatpak", "run", "org.remmina.Remmina"].
+    pub client_commands: HashMap<String, Vec<String>>,
+    // Lowercase VM stem -> environment for its quickemu and viewer processes, e.g. "SDL_VIDEODRIVER" -> "wayland".
+    pub vm_env: HashMap<String, BTreeMap<String, String>>,
+    // Viewer fallback chains per protocol, most preferred first. Narrowed at startup
+    // to the clients that are actually installed (see detect_viewers).
+    pub rdp_clients: Vec<String>,
+    pub vnc_clients: Vec<String>,
+    pub spice_clients: Vec<String>,
+    pub moonlight_clients: Vec<String>, // Game streaming clients (Sunshine/Moonlight), see Streaming
+}
+
+/// Default location of the daemon control socket: $XDG_RUNTIME_DIR, falling back to
+/// %LOCALAPPDATA%\quick-cli on Windows and $HOME elsewhere.
+fn default_control_socket(home: &Path) -> PathBuf {
+    dirs::runtime_dir()
+        .map(|dir| dir.join("quick-cli.sock"))
+        .or_else(|| cfg!(windows).then(dirs::data_local_dir).flatten().map(|dir| dir.join(r"quick-cli\quick-cli.sock")))
+        .unwrap_or_else(|| home.join(".quick-cli.sock"))
+}
+
+/// quickemu and quickget are bash scripts; on a Windows host without native builds on
+/// PATH they usually live in WSL, so run them through wsl.exe.
+fn default_quick_command(name: &str, os_type: &str) -> Vec<String> {
+    if os_type != "windows" {
+        return vec![name.to_string()];
+    }
+    let exe = format!("{}.exe", name);
+    if find_in_path(&exe).is_none() && find_in_path("wsl.exe").is_some() {
+        return vec!["wsl.exe".to_string(), name.to_string()];
+    }
+    vec![exe]
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let home = dirs::home_dir().expect("Unable to get home directory");
+        let os_type = if cfg!(target_os = "windows") {
+            "windows".to_string()
+        } else if cfg!(target_os = "macos") {
+            "macos".to_string()
+        } else {
+            "linux".to_string()
+        };
+        let remote_app = match os_type.as_str() {
+            "windows" => "mstsc.exe".to_string(),
+            "macos" => "open".to_string(),
+            _ => "remmina".to_string(),
+        };
+        let [rdp_clients, vnc_clients, spice_clients] =
+            ["rdp", "vnc", "spice"].map(|scheme| default_clients(&remote_app, &os_type, scheme));
+        Self {
+            source: None,
+            quickemu_dirs: vec![home.join(".quickemu")],
+            remmina_dirs: vec![
+                home.join(".local/share/remmina"),
+                home.join(".var/app/org.remmina.Remmina/data/remmina"), // Flatpak
+            ],
+            default_spice_port: 5930,
+            quickemu: default_quick_command("quickemu", &os_type),
+            quickget: default_quick_command("quickget", &os_type),
+            iso_downloader: "quickget".to_string(),
+            aria2c_connections: 16,
+            firewall_sudo: vec!["sudo".to_string(), "-n".to_string()],
+            quickemu_flags: HashMap::new(),
+            os_type,
+            remmina_overrides: HashMap::new(),
+            control_socket: default_control_socket(&home),
+            daemon_listen: None,
+            control_token: None,
+            grpc_listen: None,
+            remote: None,
+            autostart: Vec::new(),
+            restart_on_crash: Vec::new(),
+            scan_depth: 0,
+            scan_ignore: Vec::new(),
+            ignore: Vec::new(),
+            tick_rate: Duration::from_millis(200),
+            status_poll_interval: Duration::from_secs(2),
+            connect_timeout: Duration::from_millis(200),
+            connect_host: "127.0.0.1".to_string(),
+            start_wait: Duration::from_secs(2),
+            stop_timeout: Duration::from_secs(60),
+            viewer_timeout: Duration::from_secs(15),
+            suspend_on_stop: Vec::new(),
+            start_stagger: Duration::from_secs(10),
+            start_concurrency: 2,
+            paste_key_delay: Duration::from_millis(30),
+            git_sync: false,
+            read_only: false,
+            dry_run: false,
+            demo: false,
+            verbose: false,
+            ram_commit_percent: 90,
+            iso_checksums: HashMap::new(),
+            spice_display: HashMap::new(),
+            rdp_gateways: HashMap::new(),
+            streaming: HashMap::new(),
+            groups: BTreeMap::new(),
+            vm_hosts: HashMap::new(),
+            ssh_tunnels: HashMap::new(),
+            protocols: HashMap::new(),
+            backups: HashMap::new(),
+            backup_dir: dirs::data_local_dir().unwrap_or_else(|| home.join(".local/share")).join("quick-cli/backups"),
+            cloud_init: CloudInit::default(),
+            multiplexer: "off".to_string(),
+            image_protocol: "auto".to_string(),
+            layout: None,
+            macos_rdp_app: None,
+            macos_vnc_app: None,
+            macos_spice_app: None,
+            ssh_user: None,
+            webhooks: Vec::new(),
+            mqtt_host: None,
+            mqtt_prefix: "quick-cli".to_string(),
+            mqtt_user: None,
+            mqtt_password: None,
+            mdns: false,
+            log_sink: "stderr".to_string(),
+            notify: Vec::new(),
+            backends: Vec::new(),
+            low_disk_gib: 10,
+            alert_cpu_percent: 0,
+            alert_cpu_duration: Duration::from_secs(300),
+            alert_free_ram_gib: 0,
+            client_commands: HashMap::new(),
+            vm_env: HashMap::new(),
+            rdp_clients,
+            vnc_clients,
+            spice_clients,
+            moonlight_clients: vec!["moonlight".to_string()],
+            remote_app,
+        }
+    }
+}
+
+/// Default fallback chain for `scheme` ("rdp", "vnc" or "spice"): the configured remote
+/// app first when it speaks the protocol, then the protocol-specific clients of the OS.
+fn default_clients(remote_app: &str, os_type: &str, scheme: &str) -> Vec<String> {
+    let rest: &[&str] = match (os_type, scheme) {
+        // TightVNC, then other VNC viewers; remote-viewer from the virt-viewer MSI.
+        ("windows", "rdp") => &["mstsc.exe"],
+        ("windows", "vnc") => &["tvnviewer", "vncviewer"],
+        ("windows", _) => &["remote-viewer"],
+        (_, "rdp") => &["xfreerdp"],
+        (_, "vnc") => &["vncviewer"],
+        _ => &["spicy", "remote-viewer"],
+    };
+    let lead = client_speaks(remote_app, scheme).then_some(remote_app);
+    let mut chain: Vec<String> = Vec::new();
+    for client in lead.into_iter().chain(rest.iter().copied()) {
+        if !chain.iter().any(|c| c == client) {
+            chain.push(client.to_string());
+        }
+    }
+    chain
+}
+
+/// Whether a known client speaks `scheme`; clients not known here are assumed to.
+fn client_speaks(client: &str, scheme: &str) -> bool {
+    let name = Path::new(client).file_stem().map(|s| s.to_string_lossy().to_lowercase()).unwrap_or_default();
+    match name.as_str() {
+        "mstsc" | "xfreerdp" | "xfreerdp3" | "wlfreerdp" | "sdl-freerdp" => scheme == "rdp",
+        "vncviewer" | "tvnviewer" => scheme == "vnc",
+        "spicy" | "remote-viewer" | "virt-viewer" => scheme == "spice",
+        _ => true, // remmina, macOS open, custom clients
+    }
+}
+
+const MAX_SCAN_DEPTH: usize = 8;
+
+/// Per-VM SPICE viewer options from a `[spice_display.<vm>]` table.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpiceDisplay {
+    pub fullscreen: bool,          // Open the viewer full screen
+    pub monitors: Option<u8>,      // Guest displays to spread over the client's monitors
+    pub auto_resize: Option<bool>, // Resize the guest display with the viewer window
+}
+
+/// Per-VM RD Gateway from a `[rdp_gateway.<vm>]` table, for VMs only reachable
+/// through a jump host.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RdpGateway {
+    pub host: String,             // Gateway host[:port]
+    pub user: Option<String>,     // Gateway credentials; the client prompts for the password
+    pub domain: Option<String>,
+}
+
+/// Default port of a Sunshine host's HTTP API, which Moonlight pairs and connects through.
+pub const SUNSHINE_PORT: u16 = 47989;
+
+/// Per-VM game streaming from a `[streaming.<vm>]` table, for GPU-passthrough VMs
+/// running Sunshine (or another GameStream host) that Moonlight connects to.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Streaming {
+    pub app: Option<String>, // App to launch (default "Desktop")
+    pub port: Option<u16>,   // Guest port of the host's HTTP API when not SUNSHINE_PORT
+}
+
+/// On-disk form of config.toml. Every key is optional; missing keys keep their defaults.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+struct ConfigFile {
+    remote_app: Option<String>,
+    quickemu_dirs: Option<Vec<PathBuf>>,
+    remmina_dirs: Option<Vec<PathBuf>>,
+    default_spice_port: Option<u16>,
+    os_type: Option<String>,
+    quickemu: Option<String>,
+    quickget: Option<String>,
+    iso_downloader: Option<String>,
+    aria2c_connections: Option<u8>,
+    firewall_sudo: Option<String>,
+    control_socket: Option<PathBuf>,
+    daemon_listen: Option<String>,
+    control_token: Option<String>,
+    grpc_listen: Option<String>,
+    remote: Option<String>,
+    autostart: Option<Vec<String>>,
+    restart_on_crash: Option<Vec<String>>,
+    scan_depth: Option<usize>,
+    scan_ignore: Option<Vec<String>>,
+    ignore: Option<Vec<String>>,
+    tick_rate_ms: Option<u64>,
+    status_poll_ms: Option<u64>,
+    connect_timeout_ms: Option<u64>,
+    connect_host: Option<String>,
+    start_wait_ms: Option<u64>,
+    stop_timeout_ms: Option<u64>,
+    viewer_timeout_ms: Option<u64>,
+    suspend_on_stop: Option<Vec<String>>,
+    start_stagger_ms: Option<u64>,
+    start_concurrency: Option<usize>,
+    paste_key_delay_ms: Option<u64>,
+    git_sync: Option<bool>,
+    read_only: Option<bool>,
+    verbose: Option<bool>,
+    ram_commit_percent: Option<u64>,
+    backup_dir: Option<PathBuf>,
+    multiplexer: Option<String>,
+    image_protocol: Option<String>,
+    layout: Option<String>,
+    macos_rdp_app: Option<String>,
+    macos_vnc_app: Option<String>,
+    macos_spice_app: Option<String>,
+    ssh_user: Option<String>,
+    webhooks: Option<Vec<String>>,
+    mqtt_host: Option<String>,
+    mqtt_prefix: Option<String>,
+    mqtt_user: Option<String>,
+    mqtt_password: Option<String>,
+    mdns: Option<bool>,
+    log_sink: Option<String>,
+    notify: Option<Vec<String>>,
+    backends: Option<Vec<String>>,
+    low_disk_gib: Option<u64>,
+    alert_cpu_percent: Option<u32>,
+    alert_cpu_duration_ms: Option<u64>,
+    alert_free_ram_gib: Option<u64>,
+    rdp_clients: Option<Vec<String>>,
+    vnc_clients: Option<Vec<String>>,
+    spice_clients: Option<Vec<String>>,
+    moonlight_clients: Option<Vec<String>>,
+    // Tables are kept last: TOML tables must follow plain keys.
+    // VM config file stem -> Remmina profile path.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    overrides: BTreeMap<String, String>,
+    // VM config file stem -> address of its ports, for VMs on another quickemu host.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    hosts: BTreeMap<String, String>,
+    // VM config file stem -> ssh destination ("user@host" or a ~/.ssh/config alias) for viewer tunnels.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    ssh_tunnel: BTreeMap<String, String>,
+    // VM config file stem -> protocols connect tries in order ("rdp", "vnc", "spice").
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    protocols: BTreeMap<String, Vec<String>>,
+    // Client name -> command line used to invoke it, e.g. remmina = "flatpak run org.remmina.Remmina".
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    clients: BTreeMap<String, String>,
+    // ISO file name -> expected SHA256, for images without a SHA256SUMS file.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    iso_checksums: BTreeMap<String, String>,
+    // VM config file stem -> SPICE viewer display options.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    spice_display: BTreeMap<String, SpiceDisplay>,
+    // VM config file stem -> RD Gateway for its RDP connections.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    rdp_gateway: BTreeMap<String, RdpGateway>,
+    // VM config file stem -> environment variables for its quickemu and viewer processes.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    env: BTreeMap<String, BTreeMap<String, String>>,
+    // VM config file stem -> Sunshine port and Moonlight app.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    streaming: BTreeMap<String, Streaming>,
+    // VM config file stem -> backup schedule and retention.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    backup: BTreeMap<String, BackupPolicy>,
+    // Group name -> member VM patterns and their shared RAM/CPU budget.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    group: BTreeMap<String, Quota>,
+    // Cloud-init seed settings for new VMs.
+    cloud_init: Option<CloudInit>,
+    // quickemu flag -> replacement flag, for quickemu versions or forks with a different CLI.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    quickemu_flags: BTreeMap<String, String>,
+}
+
+fn clamp_millis(ms: u64, min: u64, max: u64) -> Duration {
+    Duration::from_millis(ms.clamp(min, max))
+}
+
+impl Config {
+    /// Overlay the values present in a config file onto this config, clamping knobs to sane bounds.
+    fn apply_file(&mut self, file: ConfigFile) {
+        if let Some(v) = file.remote_app {
+            // Keep the default chains led by the configured remote app where it applies.
+            let os_type = file.os_type.as_deref().unwrap_or(&self.os_type);
+            self.rdp_clients = default_clients(&v, os_type, "rdp");
+            self.vnc_clients = default_clients(&v, os_type, "vnc");
+            self.spice_clients = default_clients(&v, os_type, "spice");
+            self.remote_app = v;
+        }
+        if let Some(v) = file.rdp_clients.filter(|c| !c.is_empty()) {
+            self.rdp_clients = v;
+        }
+        if let Some(v) = file.vnc_clients.filter(|c| !c.is_empty()) {
+            self.vnc_clients = v;
+        }
+        if let Some(v) = file.spice_clients.filter(|c| !c.is_empty()) {
+            self.spice_clients = v;
+        }
+        if let Some(v) = file.moonlight_clients.filter(|c| !c.is_empty()) {
+            self.moonlight_clients = v;
+        }
+        if let Some(v) = file.quickemu_dirs.filter(|d| !d.is_empty()) {
+            self.quickemu_dirs = v.iter().map(|d| expand_path(d)).collect();
+        }
+        if let Some(v) = file.remmina_dirs {
+            self.remmina_dirs = v.iter().map(|d| expand_path(d)).collect();
+        }
+        if let Some(v) = file.default_spice_port {
+            self.default_spice_port = v;
+        }
+        if let Some(v) = file.os_type {
+            self.os_type = v;
+        }
+        let words = |command: &str| -> Vec<String> {
+            expand_vars(command, None).split_whitespace().map(String::from).collect()
+        };
+        if let Some(v) = file.quickemu.map(|c| words(&c)).filter(|w| !w.is_empty()) {
+            self.quickemu = v;
+        }
+        if let Some(v) = file.quickget.map(|c| words(&c)).filter(|w| !w.is_empty()) {
+            self.quickget = v;
+        }
+        if let Some(v) = file.iso_downloader {
+            self.iso_downloader = v;
+        }
+        if let Some(v) = file.aria2c_connections {
+            self.aria2c_connections = v.clamp(1, 16);
+        }
+        if let Some(v) = file.firewall_sudo {
+            self.firewall_sudo = words(&v);
+        }
+        self.quickemu_flags.extend(file.quickemu_flags);
+        if let Some(v) = file.control_socket {
+            self.control_socket = expand_path(&v);
+        }
+        if let Some(v) = file.daemon_listen.filter(|a| !a.is_empty()) {
+            self.daemon_listen = Some(v);
+        }
+        if let Some(v) = file.control_token.filter(|t| !t.is_empty()) {
+            self.control_token = Some(expand_vars(&v, None));
+        }
+        if let Some(v) = file.grpc_listen.filter(|a| !a.is_empty()) {
+            self.grpc_listen = Some(v);
+        }
+        if let Some(v) = file.remote.filter(|r| !r.is_empty()) {
+            self.remote = Some(expand_vars(&v, None));
+        }
+        if let Some(v) = file.autostart {
+            self.autostart = v;
+        }
+        if let Some(v) = file.restart_on_crash {
+            self.restart_on_crash = v;
+        }
+        if let Some(v) = file.scan_depth {
+            self.scan_depth = v.min(MAX_SCAN_DEPTH);
+        }
+        if let Some(v) = file.scan_ignore {
+            self.scan_ignore = v;
+        }
+        if let Some(v) = file.ignore {
+            self.ignore = v;
+        }
+        if let Some(ms) = file.tick_rate_ms {
+            self.tick_rate = clamp_millis(ms, 50, 2_000);
+        }
+        if let Some(ms) = file.status_poll_ms {
+            self.status_poll_interval = clamp_millis(ms, 250, 60_000);
+        }
+        if let Some(ms) = file.connect_timeout_ms {
+            self.connect_timeout = clamp_millis(ms, 50, 5_000);
+        }
+        if let Some(v) = file.connect_host.filter(|h| !h.is_empty()) {
+            self.connect_host = v.trim_start_matches('[').trim_end_matches(']').to_string();
+        }
+        if let Some(ms) = file.start_wait_ms {
+            self.start_wait = clamp_millis(ms, 0, 30_000);
+        }
+        if let Some(ms) = file.stop_timeout_ms {
+            self.stop_timeout = clamp_millis(ms, 0, 600_000);
+        }
+        if let Some(ms) = file.viewer_timeout_ms {
+            self.viewer_timeout = if ms == 0 { Duration::ZERO } else { clamp_millis(ms, 1_000, 120_000) };
+        }
+        if let Some(v) = file.suspend_on_stop {
+            self.suspend_on_stop = v;
+        }
+        if let Some(ms) = file.start_stagger_ms {
+            self.start_stagger = clamp_millis(ms, 0, 600_000);
+        }
+        if let Some(v) = file.start_concurrency {
+            self.start_concurrency = v;
+        }
+        if let Some(ms) = file.paste_key_delay_ms {
+            self.paste_key_delay = clamp_millis(ms, 0, 1000);
+        }
+        if let Some(v) = file.git_sync {
+            self.git_sync = v;
+        }
+        if let Some(v) = file.read_only {
+            self.read_only = v;
+        }
+        if let Some(v) = file.verbose {
+            self.verbose = v;
+        }
+        if let Some(v) = file.ram_commit_percent {
+            self.ram_commit_percent = v.min(1_000);
+        }
+        if let Some(v) = file.backup_dir {
+            self.backup_dir = expand_path(&v);
+        }
+        if let Some(v) = file.cloud_init {
+            self.cloud_init = v;
+        }
+        if let Some(v) = file.multiplexer {
+            self.multiplexer = v;
+        }
+        if let Some(v) = file.image_protocol {
+            self.image_protocol = v;
+        }
+        if let Some(v) = file.layout.filter(|l| !l.is_empty()) {
+            self.layout = Some(v);
+        }
+        if let Some(v) = file.macos_rdp_app.filter(|a| !a.is_empty()) {
+            self.macos_rdp_app = Some(expand_vars(&v, None));
+        }
+        if let Some(v) = file.macos_vnc_app.filter(|a| !a.is_empty()) {
+            self.macos_vnc_app = Some(expand_vars(&v, None));
+        }
+        if let Some(v) = file.macos_spice_app.filter(|a| !a.is_empty()) {
+            self.macos_spice_app = Some(expand_vars(&v, None));
+        }
+        if let Some(v) = file.ssh_user.filter(|u| !u.is_empty()) {
+            self.ssh_user = Some(v);
+        }
+        if let Some(v) = file.webhooks {
+            self.webhooks = v.iter().map(|url| expand_vars(url, None)).collect();
+        }
+        if let Some(v) = file.mqtt_host.filter(|h| !h.is_empty()) {
+            self.mqtt_host = Some(v);
+        }
+        if let Some(v) = file.mqtt_prefix.filter(|p| !p.is_empty()) {
+            self.mqtt_prefix = v.trim_end_matches('/').to_string();
+        }
+        if let Some(v) = file.mqtt_user.filter(|u| !u.is_empty()) {
+            self.mqtt_user = Some(v);
+        }
+        if let Some(v) = file.mqtt_password {
+            self.mqtt_password = Some(expand_vars(&v, None));
+        }
+        if let Some(v) = file.mdns {
+            self.mdns = v;
+        }
+        if let Some(v) = file.log_sink {
+            self.log_sink = v;
+        }
+        if let Some(v) = file.notify {
+            self.notify = v.iter().map(|url| expand_vars(url, None)).collect();
+        }
+        if let Some(v) = file.backends {
+            self.backends = v;
+        }
+        if let Some(v) = file.low_disk_gib {
+            self.low_disk_gib = v;
+        }
+        if let Some(v) = file.alert_cpu_percent {
+            self.alert_cpu_percent = v;
+        }
+        if let Some(ms) = file.alert_cpu_duration_ms {
+            self.alert_cpu_duration = clamp_millis(ms, 0, 86_400_000);
+        }
+        if let Some(v) = file.alert_free_ram_gib {
+            self.alert_free_ram_gib = v;
+        }
+        for (vm, profile) in file.overrides {
+            self.remmina_overrides.insert(vm.to_lowercase(), profile);
+        }
+        for (vm, host) in file.hosts {
+            self.vm_hosts.insert(vm.to_lowercase(), host.trim_start_matches('[').trim_end_matches(']').to_string());
+        }
+        for (vm, destination) in file.ssh_tunnel {
+            self.ssh_tunnels.insert(vm.to_lowercase(), destination);
+        }
+        for (vm, order) in file.protocols {
+            self.protocols.insert(vm.to_lowercase(), order.iter().map(|p| p.to_lowercase()).collect());
+        }
+        self.iso_checksums.extend(file.iso_checksums);
+        for (vm, display) in file.spice_display {
+            self.spice_display.insert(vm.to_lowercase(), display);
+        }
+        for (vm, env) in file.env {
+            self.vm_env.insert(vm.to_lowercase(), env);
+        }
+        for (vm, streaming) in file.streaming {
+            self.streaming.insert(vm.to_lowercase(), streaming);
+        }
+        for (vm, policy) in file.backup {
+            self.backups.insert(vm.to_lowercase(), policy);
+        }
+        self.groups.extend(file.group);
+        for (vm, gateway) in file.rdp_gateway {
+            self.rdp_gateways.insert(vm.to_lowercase(), gateway);
+        }
+        for (client, command) in file.clients {
+            let words: Vec<String> = command.split_whitespace().map(String::from).collect();
+            if !words.is_empty() {
+                self.client_commands.insert(client, words);
+            }
+        }
+    }
+
+    /// Probe which preferred clients are installed and drop the missing ones from each
+    /// chain. A chain with no installed client is left as-is so launch errors still surface.
+    /// Returns a summary for the log.
+    pub fn detect_viewers(&mut self) -> Vec<String> {
+        let mut summary = Vec::new();
+        let client_commands = &self.client_commands;
+        for (label, chain) in [
+            ("RDP", &mut self.rdp_clients),
+            ("VNC", &mut self.vnc_clients),
+            ("SPICE", &mut self.spice_clients),
+            ("Moonlight", &mut self.moonlight_clients),
+        ] {
+            let installed: Vec<String> = chain
+                .iter()
+                .filter(|c| client_commands.contains_key(*c) || resolve_viewer(c).is_some())
+                .cloned()
+                .collect();
+            if installed.is_empty() {
+                summary.push(format!("No {} client found (tried {}).", label, chain.join(", ")));
+            } else {
+                summary.push(format!("{} clients: {}", label, installed.join(" -> ")));
+                *chain = installed;
+            }
+        }
+        summary
+    }
+}
+
+/// Expand `${VM}` (when a VM is given), `${HOME}` and other `${NAME}` environment
+/// variables in a config value. Unknown variables are left as written.
+pub fn expand_vars(value: &str, vm: Option<&str>) -> String {
+    let mut out = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${")
+        && let Some(len) = rest[start..].find('}')
+    {
+        out.push_str(&rest[..start]);
+        let name = &rest[start + 2..start + len];
+        let expanded = match name {
+            "VM" => vm.map(str::to_string),
+            "HOME" => dirs::home_dir().map(|h| h.to_string_lossy().to_string()),
+            _ => std::env::var(name).ok(),
+        };
+        out.push_str(&expanded.unwrap_or_else(|| rest[start..=start + len].to_string()));
+        rest = &rest[start + len + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// `expand_vars` for paths, plus a leading `~` for the home directory (the user
+/// profile on Windows, where `%NAME%` variables are expanded too).
+pub fn expand_path(path: &Path) -> PathBuf {
+    let mut expanded = expand_vars(&path.to_string_lossy(), None);
+    if cfg!(windows) {
+        expanded = expand_windows_vars(&expanded);
+    }
+    match expanded.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with(['/', '\\']) => match dirs::home_dir() {
+            Some(home) => home.join(rest.trim_start_matches(['/', '\\'])),
+            None => PathBuf::from(expanded),
+        },
+        _ => PathBuf::from(expanded),
+    }
+}
+
+/// Expand `%NAME%` environment variables, leaving unknown ones as they are.
+fn expand_windows_vars(value: &str) -> String {
+    let mut out = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find('%')
+        && let Some(len) = rest[start + 1..].find('%')
+    {
+        out.push_str(&rest[..start]);
+        let name = &rest[start + 1..start + 1 + len];
+        match std::env::var(name) {
+            Ok(expanded) if !name.is_empty() => out.push_str(&expanded),
+            _ => out.push_str(&rest[start..start + len + 2]),
+        }
+        rest = &rest[start + len + 2..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Split a comma-separated config value into trimmed, non-empty items.
+pub fn split_list(value: &str) -> Vec<String> {
+    value.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect()
+}
+
+/// Shell-style glob match supporting `*` and `?`, case-insensitive.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.to_lowercase().chars().collect();
+    let t: Vec<char> = text.to_lowercase().chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            backtrack = Some((pi, ti));
+            pi += 1;
+        } else if let Some((star, matched)) = backtrack {
+            pi = star + 1;
+            ti = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+/// $XDG_CONFIG_HOME/quick-cli (or the platform equivalent).
+pub fn config_dir() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(|| dirs::home_dir().expect("Unable to get home directory").join(".config"))
+        .join("quick-cli")
+}
+
+/// $XDG_STATE_HOME/quick-cli, falling back to the local data directory on platforms without one.
+pub fn state_dir() -> PathBuf {
+    dirs::state_dir()
+        .or_else(dirs::data_local_dir)
+        .unwrap_or_else(|| dirs::home_dir().expect("Unable to get home directory").join(".local/state"))
+        .join("quick-cli")
+}
+
+fn legacy_config_path() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".quick-cli.conf"))
+}
+
+/// Parse the legacy ~/.quick-cli.conf key=value format.
+/// Lines starting with "override=" are interpreted as:
+///     override=vm_stem, /path/to/remmina_profile.remmina
+/// and "host=" lines as:
+///     host=vm_stem, 10.8.0.5
+/// "quickemu_dir=" may be repeated to add several VM directories (workspaces).
+/// Returns the parsed file and the lines that could not be mapped, as "<line number>: <line>".
+fn parse_legacy_config(contents: &str) -> (ConfigFile, Vec<String>) {
+    let mut file = ConfigFile::default();
+    let mut unmapped = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let Some((key, value)) = trimmed.split_once('=') else {
+            unmapped.push(format!("{}: {}", number + 1, line));
+            continue;
+        };
+        let value = value.trim();
+        let mapped = match key.trim() {
+            "remote_app" => {
+                file.remote_app = Some(value.to_string());
+                true
+            }
+            "quickemu_dir" => {
+                file.quickemu_dirs.get_or_insert_with(Vec::new).push(PathBuf::from(value));
+                true
+            }
+            "default_spice_port" => value.parse().map(|p| file.default_spice_port = Some(p)).is_ok(),
+            "os_type" => {
+                file.os_type = Some(value.to_string());
+                true
+            }
+            "control_socket" => {
+                file.control_socket = Some(PathBuf::from(value));
+                true
+            }
+            "autostart" => {
+                file.autostart = Some(split_list(value));
+                true
+            }
+            "restart_on_crash" => {
+                file.restart_on_crash = Some(split_list(value));
+                true
+            }
+            "scan_depth" => value.parse().map(|d| file.scan_depth = Some(d)).is_ok(),
+            "scan_ignore" => {
+                file.scan_ignore = Some(split_list(value));
+                true
+            }
+            "ignore" => {
+                file.ignore = Some(split_list(value));
+                true
+            }
+            "tick_rate_ms" => value.parse().map(|ms| file.tick_rate_ms = Some(ms)).is_ok(),
+            "status_poll_ms" => value.parse().map(|ms| file.status_poll_ms = Some(ms)).is_ok(),
+            "connect_timeout_ms" => value.parse().map(|ms| file.connect_timeout_ms = Some(ms)).is_ok(),
+            "start_wait_ms" => value.parse().map(|ms| file.start_wait_ms = Some(ms)).is_ok(),
+            "stop_timeout_ms" => value.parse().map(|ms| file.stop_timeout_ms = Some(ms)).is_ok(),
+            "start_stagger_ms" => value.parse().map(|ms| file.start_stagger_ms = Some(ms)).is_ok(),
+            "start_concurrency" => value.parse().map(|n| file.start_concurrency = Some(n)).is_ok(),
+            "paste_key_delay_ms" => value.parse().map(|ms| file.paste_key_delay_ms = Some(ms)).is_ok(),
+            "alert_cpu_duration_ms" => value.parse().map(|ms| file.alert_cpu_duration_ms = Some(ms)).is_ok(),
+            "override" => {
+                // Expected format: override=vm_stem, /path/to/remmina_profile.remmina
+                let parts: Vec<&str> = value.split(',').map(|s| s.trim()).collect();
+                if parts.len() == 2 {
+                    file.overrides.insert(parts[0].to_lowercase(), parts[1].to_string());
+                }
+                parts.len() == 2
+            }
+            "host" => {
+                let parts: Vec<&str> = value.split(',').map(|s| s.trim()).collect();
+                if parts.len() == 2 {
+                    file.hosts.insert(parts[0].to_lowercase(), parts[1].to_string());
+                }
+                parts.len() == 2
+            }
+            _ => false,
+        };
+        if !mapped {
+            unmapped.push(format!("{}: {}", number + 1, line));
+        }
+    }
+    (file, unmapped)
+}
+
+fn write_config_file(path: &Path, file: &ConfigFile) -> Result<(), Box<dyn Error>> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, toml::to_string(file)?)?;
+    Ok(())
+}
+
+/// The config file `load_config` reads: the `--config` path, $QUICK_CLI_CONFIG, or
+/// $XDG_CONFIG_HOME/quick-cli/config.toml.
+fn config_path(explicit_path: Option<&Path>) -> PathBuf {
+    explicit_path
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("QUICK_CLI_CONFIG").map(PathBuf::from))
+        .unwrap_or_else(|| config_dir().join("config.toml"))
+}
+
+/// `quick-cli migrate-config`: convert the legacy ~/.quick-cli.conf (or `from`) into the
+/// config file `load_config` reads. An existing file is only replaced with `force`, and
+/// nothing is written in a dry run. Returns the target path, the TOML and the legacy
+/// lines that could not be mapped.
+pub fn migrate_config(
+    from: Option<&Path>,
+    explicit_path: Option<&Path>,
+    force: bool,
+    dry_run: bool,
+) -> Result<(PathBuf, String, Vec<String>), Box<dyn Error>> {
+    let from = from.map(PathBuf::from).or_else(legacy_config_path).ok_or("no home directory")?;
+    let contents = fs::read_to_string(&from).map_err(|e| format!("cannot read {}: {}", from.display(), e))?;
+    let (file, unmapped) = parse_legacy_config(&contents);
+    let path = config_path(explicit_path);
+    let toml = toml::to_string(&file)?;
+    if path.exists() && !force && !dry_run {
+        return Err(format!("{} already exists; use --force to replace it", path.display()).into());
+    }
+    if !dry_run {
+        write_config_file(&path, &file)?;
+    }
+    Ok((path, toml, unmapped))
+}
+
+/// Loads configuration from, in order of precedence, the `--config` path, $QUICK_CLI_CONFIG,
+/// or $XDG_CONFIG_HOME/quick-cli/config.toml. On first run the default location is
+/// created, migrating the legacy ~/.quick-cli.conf when one exists.
+pub fn load_config(explicit_path: Option<&Path>) -> Result<Config, Box<dyn Error>> {
+    let mut config = Config::default();
+    let path = explicit_path
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("QUICK_CLI_CONFIG").map(PathBuf::from));
+    let path = match path {
+        Some(path) => path,
+        None => {
+            let path = config_dir().join("config.toml");
+            if !path.exists() {
+                let legacy = legacy_config_path().and_then(|p| fs::read_to_string(p).ok());
+                let file = match legacy {
+                    Some(contents) => parse_legacy_config(&contents).0,
+                    None => ConfigFile {
+                        remote_app: Some(config.remote_app.clone()),
+                        quickemu_dirs: Some(config.quickemu_dirs.clone()),
+                        default_spice_port: Some(config.default_spice_port),
+                        os_type: Some(config.os_type.clone()),
+                        ..ConfigFile::default()
+                    },
+                };
+                let _ = write_config_file(&path, &file);
+                config.apply_file(file);
+                config.source = Some(path);
+                return Ok(config);
+            }
+            path
+        }
+    };
+    let contents = fs::read_to_string(&path)
+        .map_err(|e| format!("cannot read config {}: {}", path.display(), e))?;
+    let file: ConfigFile = toml::from_str(&contents)
+        .map_err(|e| format!("invalid config {}: {}", path.display(), e))?;
+    config.apply_file(file);
+    config.source = Some(path);
+    Ok(config)
+}
+
+/// List all VM configuration files across every configured quickemu directory.
+pub fn list_vms(config: &Config) -> Vec<PathBuf> {
+    config.quickemu_dirs.iter().flat_map(|dir| list_vms_in_dir(dir, config)).collect()
+}
+
+/// List all VM configuration files (ending with ".conf") in a single quickemu directory,
+/// descending up to `scan_depth` levels into subdirectories.
+pub fn list_vms_in_dir(dir: &Path, config: &Config) -> Vec<PathBuf> {
+    let mut vms = Vec::new();
+    scan_dir(dir, config.scan_depth, config, &mut vms);
+    vms
+}
+
+/// Whether the VM matches one of the `ignore=` patterns and is hidden by default.
+pub fn is_hidden(vm_conf: &Path, config: &Config) -> bool {
+    let stem = vm_conf.file_stem().unwrap().to_string_lossy();
+    config.ignore.iter().any(|p| glob_match(p, &stem))
+}
+
+/// Free-form notes about a VM, kept next to its config as `{vm}.notes`.
+pub fn vm_notes_file(vm_conf: &Path) -> PathBuf {
+    vm_conf.with_extension("notes")
+}
+
+/// The VM's notes, if the sidecar file exists and is not blank.
+pub fn read_vm_notes(vm_conf: &Path) -> Option<String> {
+    let notes = fs::read_to_string(vm_notes_file(vm_conf)).ok()?;
+    let notes = notes.trim();
+    (!notes.is_empty()).then(|| notes.to_string())
+}
+
+fn scan_dir(dir: &Path, depth: usize, config: &Config, vms: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else { return };
+    let mut subdirs = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_file() {
+            if let Some(ext) = path.extension()
+                && ext == "conf"
+            {
+                vms.push(path);
+            }
+        } else if depth > 0 && path.is_dir() {
+            subdirs.push(path);
+        }
+    }
+    for subdir in subdirs {
+        let name = subdir.file_name().unwrap().to_string_lossy().to_string();
+        if name.starts_with('.') || config.scan_ignore.iter().any(|p| glob_match(p, &name)) {
+            continue;
+        }
+        // Skip the VM directories quickemu creates next to each .conf (disks, sockets).
+        if vms.iter().any(|vm| vm.with_extension("") == subdir) {
+            continue;
+        }
+        scan_dir(&subdir, depth - 1, config, vms);
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// Protocol Parsing and Running Detection
+///////////////////////////////////////////////////////////////////////////////
+
+/// How a VM's display is reached, with the host port to connect to.
+#[derive(Clone)]
+pub enum RemoteProtocol {
+    Rdp(u16),
+    Vnc(u16),
+    Spice(u16),
+    SpiceUnix(PathBuf), // SPICE on a unix socket on this host, see spice_socket
+    Moonlight(u16),     // Game streaming from Sunshine's HTTP API port, see Streaming
+}
+
+impl RemoteProtocol {
+    /// "RDP", "VNC", "SPICE" or "Moonlight".
+    pub fn label(&self) -> &'static str {
+        match self {
+            RemoteProtocol::Rdp(_) => "RDP",
+            RemoteProtocol::Vnc(_) => "VNC",
+            RemoteProtocol::Spice(_) | RemoteProtocol::SpiceUnix(_) => "SPICE",
+            RemoteProtocol::Moonlight(_) => "Moonlight",
+        }
+    }
+
+    /// TCP port, or None for a unix socket.
+    pub fn port(&self) -> Option<u16> {
+        match self {
+            RemoteProtocol::Rdp(p) | RemoteProtocol::Vnc(p) | RemoteProtocol::Spice(p) => Some(*p),
+            RemoteProtocol::Moonlight(p) => Some(*p),
+            RemoteProtocol::SpiceUnix(_) => None,
+        }
+    }
+
+    /// The same protocol on another TCP port, e.g. the local end of a tunnel.
+    pub fn with_port(&self, port: u16) -> RemoteProtocol {
+        match self {
+            RemoteProtocol::Rdp(_) => RemoteProtocol::Rdp(port),
+            RemoteProtocol::Vnc(_) => RemoteProtocol::Vnc(port),
+            RemoteProtocol::Spice(_) | RemoteProtocol::SpiceUnix(_) => RemoteProtocol::Spice(port),
+            RemoteProtocol::Moonlight(_) => RemoteProtocol::Moonlight(port),
+        }
+    }
+
+    /// Where the display is reached: "host:port", or the socket path.
+    pub fn endpoint(&self, host: &str) -> String {
+        match self {
+            RemoteProtocol::SpiceUnix(socket) => socket.display().to_string(),
+            _ => format_addr(host, self.port().unwrap_or_default()),
+        }
+    }
+
+    /// URL for clients that take one, e.g. "spice://127.0.0.1:5930" or "spice+unix:///path".
+    pub fn url(&self, host: &str) -> String {
+        match self {
+            RemoteProtocol::SpiceUnix(socket) => format!("spice+unix://{}", socket.display()),
+            _ => format!("{}://{}", self.label().to_lowercase(), self.endpoint(host)),
+        }
+    }
+
+    /// The viewer fallback chain for the protocol.
+    pub fn clients<'a>(&self, config: &'a Config) -> &'a Vec<String> {
+        match self {
+            RemoteProtocol::Rdp(_) => &config.rdp_clients,
+            RemoteProtocol::Vnc(_) => &config.vnc_clients,
+            RemoteProtocol::Spice(_) | RemoteProtocol::SpiceUnix(_) => &config.spice_clients,
+            RemoteProtocol::Moonlight(_) => &config.moonlight_clients,
+        }
+    }
+}
+
+/// Value of a `key="value"` line in a quickemu .conf file, with quotes stripped.
+pub fn vm_conf_value(vm_conf: &Path, key: &str) -> Option<String> {
+    let contents = fs::read_to_string(vm_conf).ok()?;
+    contents.lines().find_map(|line| {
+        let (k, v) = line.trim().split_once('=')?;
+        (k.trim() == key).then(|| v.trim().trim_matches('"').to_string())
+    })
+}
+
+/// Guest family from the .conf's `guest_os=` setting.
+#[derive(Clone, Copy, PartialEq)]
+pub enum GuestOs {
+    Windows,
+    MacOs,
+    Linux,
+    Bsd,
+    Other,
+}
+
+impl GuestOs {
+    /// Short tag shown next to the VM name.
+    pub fn tag(self) -> &'static str {
+        match self {
+            GuestOs::Windows => "win",
+            GuestOs::MacOs => "mac",
+            GuestOs::Linux => "lnx",
+            GuestOs::Bsd => "bsd",
+            GuestOs::Other => "---",
+        }
+    }
+}
+
+/// quickemu writes `guest_os="windows"`, "windows-server", "macos", "linux",
+/// "linux_old", "freebsd", ...; quickget omits it for plain Linux guests.
+pub fn guest_os(vm_conf: &Path) -> GuestOs {
+    match vm_conf_value(vm_conf, "guest_os").as_deref() {
+        Some(os) if os.starts_with("windows") => GuestOs::Windows,
+        Some("macos") => GuestOs::MacOs,
+        Some(os) if os.starts_with("linux") => GuestOs::Linux,
+        None => GuestOs::Linux,
+        Some(os) if os.ends_with("bsd") => GuestOs::Bsd,
+        Some(_) => GuestOs::Other,
+    }
+}
+
+/// `(host port, guest port)` pairs from the .conf's `port_forwards=("2222:22" ...)` line.
+pub fn port_forwards(vm_conf: &Path) -> Vec<(u16, u16)> {
+    let mut forwards = Vec::new();
+    if let Ok(contents) = fs::read_to_string(vm_conf) {
+        for line in contents.lines() {
+            if line.contains("port_forwards")
+                && let (Some(start), Some(end)) = (line.find('('), line.rfind(')'))
+            {
+                let forwards_str = &line[start + 1..end];
+                let parts: Vec<&str> = forwards_str.split('"')
+                    .filter(|s| !s.trim().is_empty())
+                    .collect();
+                for mapping in parts {
+                    let split: Vec<&str> = mapping.split(':').collect();
+                    if split.len() == 2
+                        && let Ok(guest_port) = split[1].parse::<u16>()
+                        && let Ok(host_port) = split[0].parse::<u16>()
+                    {
+                        forwards.push((host_port, guest_port));
+                    }
+                }
+            }
+        }
+    }
+    forwards
+}
+
+/// Host port forwarded to a guest port, if any.
+pub fn forwarded_port(vm_conf: &Path, guest_port: u16) -> Option<u16> {
+    port_forwards(vm_conf).into_iter().find(|&(_, guest)| guest == guest_port).map(|(host, _)| host)
+}
+
+/// Guest port of the VM's Sunshine HTTP API: its `[streaming.<vm>]` port or SUNSHINE_PORT.
+pub fn sunshine_port(vm_conf: &Path, config: &Config) -> u16 {
+    let stem = vm_conf.file_stem().unwrap_or_default().to_string_lossy().to_lowercase();
+    config.streaming.get(&stem).and_then(|s| s.port).unwrap_or(SUNSHINE_PORT)
+}
+
+/// Parse the VM configuration.
+/// A forwarded Sunshine port (see sunshine_port) means Moonlight streaming.
+/// If a "port_forwards" line is found for guest port 3389 or 5900, return Rdp or Vnc.
+/// With both forwarded, Windows guests prefer RDP and others take the first listed;
+/// macOS guests never use RDP. Otherwise, assume SPICE, on its unix socket if it has one.
+pub fn parse_vm_config(vm_conf: &Path, config: &Config) -> RemoteProtocol {
+    let os = guest_os(vm_conf);
+    let forwards = port_forwards(vm_conf);
+    let sunshine = sunshine_port(vm_conf, config);
+    if let Some(&(host_port, _)) = forwards.iter().find(|&&(_, guest)| guest == sunshine) {
+        return RemoteProtocol::Moonlight(host_port);
+    }
+    let mut vnc = None;
+    for (host_port, guest_port) in forwards {
+        if guest_port == 3389 && os != GuestOs::MacOs {
+            return RemoteProtocol::Rdp(host_port);
+        } else if guest_port == 5900 && os != GuestOs::Windows {
+            return RemoteProtocol::Vnc(host_port);
+        } else if guest_port == 5900 {
+            vnc.get_or_insert(host_port);
+        }
+    }
+    match vnc {
+        Some(port) => RemoteProtocol::Vnc(port),
+        None => spice_protocol(vm_conf, config),
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// quickemu Runtime Files
+///////////////////////////////////////////////////////////////////////////////
+
+/// What quickemu records about a running VM in its VM directory.
+#[derive(Default)]
+pub struct VmRuntime {
+    pub pid: Option<u32>,              // From {vm}.pid (written by qemu's -pidfile)
+    pub ports: HashMap<String, u16>,   // From {vm}.ports, e.g. "spice" -> 5930, "ssh" -> 22220
+    pub sockets: HashMap<String, PathBuf>, // {vm}.ports entries holding a socket path instead of a port
+}
+
+/// quickemu keeps the VM directory (disks, sockets, runtime files) next to its .conf file.
+pub fn vm_dir(vm_conf: &Path) -> PathBuf {
+    vm_conf.with_file_name(vm_conf.file_stem().unwrap())
+}
+
+/// `{vm dir}/{vm}{suffix}`, e.g. suffix "-monitor.socket".
+pub fn vm_runtime_file(vm_conf: &Path, suffix: &str) -> PathBuf {
+    let vm_stem = vm_conf.file_stem().unwrap().to_string_lossy();
+    vm_dir(vm_conf).join(format!("{}{}", vm_stem, suffix))
+}
+
+/// Read the pid and ports files; missing files leave the fields empty.
+pub fn read_runtime(vm_conf: &Path) -> VmRuntime {
+    let mut runtime = VmRuntime::default();
+    if let Ok(contents) = fs::read_to_string(vm_runtime_file(vm_conf, ".pid")) {
+        runtime.pid = contents.trim().parse().ok();
+    }
+    // Format: one "service,port" pair per line; a unix socket is given as "service,unix:/path"
+    // or a plain path, relative ones in the VM directory.
+    if let Ok(contents) = fs::read_to_string(vm_runtime_file(vm_conf, ".ports")) {
+        for line in contents.lines() {
+            let Some((service, value)) = line.split_once(',') else { continue };
+            let (service, value) = (service.trim().to_string(), value.trim());
+            if let Ok(port) = value.parse::<u16>() {
+                runtime.ports.insert(service, port);
+            } else if !value.is_empty() {
+                let path = value.strip_prefix("unix:").unwrap_or(value);
+                runtime.sockets.insert(service, vm_dir(vm_conf).join(path));
+            }
+        }
+    }
+    runtime
+}
+
+/// SPICE port for the VM: the one quickemu recorded in its .ports file, else the one
+/// quick-cli last passed to quickemu (see assign_spice_port), else the configured default.
+pub fn spice_port(vm_conf: &Path, config: &Config) -> u16 {
+    let stem = vm_conf.file_stem().unwrap_or_default().to_string_lossy().to_lowercase();
+    read_runtime(vm_conf)
+        .ports
+        .get("spice")
+        .copied()
+        .or_else(|| assigned_spice_ports().remove(&stem))
+        .unwrap_or(config.default_spice_port)
+}
+
+fn spice_ports_path() -> PathBuf {
+    state_dir().join("spice-ports")
+}
+
+/// SPICE ports passed to quickemu with --spice-port, by lowercase VM stem, from
+/// $XDG_STATE_HOME/quick-cli/spice-ports (`vm=port` lines).
+fn assigned_spice_ports() -> HashMap<String, u16> {
+    let contents = fs::read_to_string(spice_ports_path()).unwrap_or_default();
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (vm, port) = line.split_once('=')?;
+            Some((vm.trim().to_string(), port.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+fn save_spice_ports(ports: &HashMap<String, u16>) -> io::Result<()> {
+    let mut lines: Vec<String> = ports.iter().map(|(vm, port)| format!("{}={}\n", vm, port)).collect();
+    lines.sort();
+    fs::create_dir_all(state_dir())?;
+    fs::write(spice_ports_path(), lines.concat())
+}
+
+/// Whether nothing listens on the local TCP port, so qemu can bind it.
+fn port_free(port: u16) -> bool {
+    TcpListener::bind(("127.0.0.1", port)).is_ok()
+}
+
+/// SPICE port to pass quickemu for a VM about to start, or None to leave the default.
+/// When the default port is busy (another VM has it), the first free port above it
+/// that is not assigned to another VM is picked and recorded, so VMs started together
+/// get distinct ports instead of racing quickemu's own probing, and connecting later
+/// targets the right one even if the .ports file is missing.
+fn assign_spice_port(vm_conf: &Path, config: &Config, logs: &Arc<Mutex<Vec<String>>>) -> Option<u16> {
+    let stem = vm_conf.file_stem()?.to_string_lossy().to_lowercase();
+    let mut assigned = assigned_spice_ports();
+    let default = config.default_spice_port;
+    let port = if port_free(default) {
+        None
+    } else {
+        let taken: Vec<u16> = assigned.iter().filter(|(vm, _)| **vm != stem).map(|(_, port)| *port).collect();
+        let port = (default.saturating_add(1)..=default.saturating_add(100))
+            .find(|port| !taken.contains(port) && port_free(*port));
+        match port {
+            Some(port) => logs.lock().unwrap().push(format!(
+                "SPICE port {} is busy; starting {} on port {}.",
+                default,
+                vm_conf.display(),
+                port
+            )),
+            None => logs.lock().unwrap().push(format!("No free SPICE port above {}; quickemu picks one.", default)),
+        }
+        port
+    };
+    if config.dry_run {
+        return port;
+    }
+    let changed = match port {
+        Some(port) => assigned.insert(stem, port) != Some(port),
+        None => assigned.remove(&stem).is_some(),
+    };
+    if changed && let Err(e) = save_spice_ports(&assigned) {
+        logs.lock().unwrap().push(format!("Failed to record the SPICE port of {}: {}", vm_conf.display(), e));
+    }
+    port
+}
+
+/// Unix socket quickemu serves SPICE on instead of a TCP port: the path in the .ports
+/// file's "spice" entry, else `spice_socket=` in the .conf, else `{vm}-spice.socket` in
+/// the VM directory if it exists. VMs on another host (`[hosts]`) are always reached over TCP.
+pub fn spice_socket(vm_conf: &Path, config: &Config) -> Option<PathBuf> {
+    let stem = vm_conf.file_stem()?.to_string_lossy().to_lowercase();
+    if !cfg!(unix) || config.vm_hosts.contains_key(&stem) {
+        return None;
+    }
+    if let Some(socket) = read_runtime(vm_conf).sockets.remove("spice") {
+        return Some(socket);
+    }
+    if let Some(path) = vm_conf_value(vm_conf, "spice_socket").filter(|p| !p.is_empty()) {
+        return Some(vm_dir(vm_conf).join(expand_path(Path::new(&path))));
+    }
+    Some(vm_runtime_file(vm_conf, "-spice.socket")).filter(|socket| socket.exists())
+}
+
+/// The VM's SPICE display: its unix socket if it has one, else its TCP port.
+pub fn spice_protocol(vm_conf: &Path, config: &Config) -> RemoteProtocol {
+    match spice_socket(vm_conf, config) {
+        Some(socket) => RemoteProtocol::SpiceUnix(socket),
+        None => RemoteProtocol::Spice(spice_port(vm_conf, config)),
+    }
+}
+
+/// Whether a process with this PID exists.
+#[cfg(target_os = "linux")]
+pub fn pid_alive(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+pub fn pid_alive(pid: u32) -> bool {
+    Command::new("kill")
+        .arg("-0")
+        .arg(pid.to_string())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|s| s.success())
+}
+
+#[cfg(not(unix))]
+pub fn pid_alive(pid: u32) -> bool {
+    Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {}", pid), "/NH"])
+        .output()
+        .is_ok_and(|o| String::from_utf8_lossy(&o.stdout).contains(&pid.to_string()))
+}
+
+/// Pid and command line of every running qemu-system process.
+#[cfg(target_os = "linux")]
+pub(crate) fn qemu_processes() -> Vec<(u32, String)> {
+    let Ok(entries) = fs::read_dir("/proc") else { return Vec::new() };
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let pid: u32 = entry.file_name().to_str()?.parse().ok()?;
+            let cmdline = fs::read(entry.path().join("cmdline")).ok()?;
+            let cmdline = String::from_utf8_lossy(&cmdline).replace('\0', " ");
+            cmdline.contains("qemu-system").then_some((pid, cmdline))
+        })
+        .collect()
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+pub(crate) fn qemu_processes() -> Vec<(u32, String)> {
+    let Ok(output) = Command::new("ps").args(["-axww", "-o", "pid=,command="]).output() else { return Vec::new() };
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| line.contains("qemu-system"))
+        .filter_map(|line| {
+            let (pid, cmdline) = line.trim_start().split_once(' ')?;
+            Some((pid.parse().ok()?, cmdline.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(not(unix))]
+pub(crate) fn qemu_processes() -> Vec<(u32, String)> {
+    let script = "Get-CimInstance Win32_Process -Filter \"Name like 'qemu-system%'\" | \
+                  ForEach-Object { \"$($_.ProcessId) $($_.CommandLine)\" }";
+    let Ok(output) = Command::new("powershell").args(["-NoProfile", "-NonInteractive", "-Command", script]).output()
+    else {
+        return Vec::new();
+    };
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let (pid, cmdline) = line.trim().split_once(' ')?;
+            Some((pid.parse().ok()?, cmdline.to_string()))
+        })
+        .collect()
+}
+
+/// Find the qemu process of a VM by its command line, which quickemu fills with the
+/// VM directory (disk, pid file, sockets) and `-name <vm>,process=<vm>`. This also
+/// finds VMs started outside quick-cli and VMs still booting.
+pub fn find_qemu_process(vm_conf: &Path) -> Option<u32> {
+    let vm_name = vm_conf.file_stem()?.to_string_lossy().to_string();
+    let dir = vm_dir(vm_conf).to_string_lossy().to_string();
+    let process = format!("process={}", vm_name);
+    qemu_processes().into_iter().find_map(|(pid, cmdline)| {
+        let matches = cmdline.contains(&format!("{}/", dir))
+            || cmdline.contains(&format!("{}\\", dir))
+            || cmdline.split([' ', ',']).any(|arg| arg == process);
+        matches.then_some(pid)
+    })
+}
+
+/// Sockets qemu creates for a quickemu VM, by file suffix.
+const RUNTIME_SOCKETS: [&str; 4] = ["-monitor.socket", "-serial.socket", "-qmp.socket", "-qga.socket"];
+
+/// Whether anything still listens on a unix socket file.
+#[cfg(unix)]
+pub fn socket_listening(path: &Path) -> bool {
+    UnixStream::connect(path).is_ok()
+}
+
+#[cfg(not(unix))]
+pub fn socket_listening(_path: &Path) -> bool {
+    true
+}
+
+/// Runtime files left behind by a crashed session: a pid file naming a dead
+/// process and sockets nothing listens on. Empty while the VM's qemu is alive.
+pub fn stale_runtime_files(vm_conf: &Path) -> Vec<PathBuf> {
+    let pid_file = vm_runtime_file(vm_conf, ".pid");
+    let pid_dead = match read_runtime(vm_conf).pid {
+        Some(pid) if pid_alive(pid) => return Vec::new(),
+        Some(_) => true,
+        None => false,
+    };
+    if find_qemu_process(vm_conf).is_some() {
+        return Vec::new();
+    }
+    let mut stale = Vec::new();
+    if pid_dead {
+        stale.push(pid_file);
+    }
+    for suffix in RUNTIME_SOCKETS {
+        let socket = vm_runtime_file(vm_conf, suffix);
+        if socket.exists() && (pid_dead || !socket_listening(&socket)) {
+            stale.push(socket);
+        }
+    }
+    stale
+}
+
+/// Remove the VM's stale runtime files (see `stale_runtime_files`), logging each one.
+/// Returns how many were removed (or would be, in dry-run mode).
+pub fn remove_stale_runtime_files(vm_conf: &Path, config: &Config, logs: &Arc<Mutex<Vec<String>>>) -> usize {
+    let mut removed = 0;
+    for path in stale_runtime_files(vm_conf) {
+        let mut l = logs.lock().unwrap();
+        if config.dry_run {
+            l.push(format!("[dry-run] would remove stale {}", path.display()));
+            removed += 1;
+            continue;
+        }
+        match fs::remove_file(&path) {
+            Ok(()) => {
+                l.push(format!("Removed stale {}", path.display()));
+                removed += 1;
+            }
+            Err(e) => l.push(format!("Failed to remove {}: {}", path.display(), e)),
+        }
+    }
+    removed
+}
+
+/// VMs whose qemu runs without a live pid file, with the qemu pid: left over from a
+/// crashed session, or started by hand from a shell. Stop falls back to SIGKILL for
+/// these, since quickemu --kill needs the pid file.
+pub fn orphaned_vms(vms: &[PathBuf], config: &Config) -> Vec<(PathBuf, u32)> {
+    // Pids of qemu inside WSL mean nothing to the Windows host.
+    if is_wsl_command(&config.quickemu) || config.demo {
+        return Vec::new();
+    }
+    vms.iter()
+        .filter(|vm_conf| !read_runtime(vm_conf).pid.is_some_and(pid_alive))
+        .filter_map(|vm_conf| Some((vm_conf.clone(), find_qemu_process(vm_conf)?)))
+        .collect()
+}
+
+/// Take over an orphaned VM by writing its pid file, so that it stops in stages
+/// like a VM quickemu started.
+pub fn adopt_vm(vm_conf: &Path, pid: u32, config: &Config, logs: &Arc<Mutex<Vec<String>>>) {
+    let name = vm_conf.file_stem().unwrap().to_string_lossy();
+    let pid_file = vm_runtime_file(vm_conf, ".pid");
+    if config.dry_run {
+        logs.lock().unwrap().push(format!("[dry-run] would write {} to {}", pid, pid_file.display()));
+        return;
+    }
+    match fs::write(&pid_file, format!("{}\n", pid)) {
+        Ok(()) => logs.lock().unwrap().push(format!("Adopted {} (qemu process {}).", name, pid)),
+        Err(e) => logs.lock().unwrap().push(format!("Failed to adopt {}: {}: {}", name, pid_file.display(), e)),
+    }
+}
+
+/// Check if a given host:port is open. The host may be a name, an IPv4 address or
+/// an IPv6 address with or without brackets.
+pub fn is_port_open(host: &str, port: u16, timeout: Duration) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let addrs: Vec<SocketAddr> = match host.parse::<IpAddr>() {
+        Ok(ip) => vec![SocketAddr::new(ip, port)],
+        Err(_) => (host, port).to_socket_addrs().map(|a| a.collect()).unwrap_or_default(),
+    };
+    addrs.iter().any(|addr| TcpStream::connect_timeout(addr, timeout).is_ok())
+}
+
+/// Host as written in URLs and `host:port` strings: IPv6 literals get brackets.
+pub fn url_host(host: &str) -> String {
+    if host.contains(':') && !host.starts_with('[') { format!("[{}]", host) } else { host.to_string() }
+}
+
+/// `host:port` with IPv6 hosts bracketed.
+pub fn format_addr(host: &str, port: u16) -> String {
+    format!("{}:{}", url_host(host), port)
+}
+
+/// Address where the VM's ports are reached: its `[hosts]` entry, else connect_host.
+pub fn vm_host<'a>(vm_conf: &Path, config: &'a Config) -> &'a str {
+    let stem = vm_conf.file_stem().unwrap_or_default().to_string_lossy().to_lowercase();
+    config.vm_hosts.get(&stem).unwrap_or(&config.connect_host)
+}
+
+/// A SPICE VM is running when qemu accepts connections on its monitor socket.
+/// VMs on another host have no local socket; their SPICE port is probed instead.
+#[cfg(unix)]
+fn is_spice_vm_running(vm_conf: &Path, config: &Config) -> bool {
+    let stem = vm_conf.file_stem().unwrap().to_string_lossy().to_lowercase();
+    if config.vm_hosts.contains_key(&stem) {
+        return is_port_open(vm_host(vm_conf, config), spice_port(vm_conf, config), config.connect_timeout);
+    }
+    UnixStream::connect(vm_runtime_file(vm_conf, "-monitor.socket")).is_ok()
+}
+
+#[cfg(not(unix))]
+fn is_spice_vm_running(vm_conf: &Path, config: &Config) -> bool {
+    is_port_open(vm_host(vm_conf, config), spice_port(vm_conf, config), config.connect_timeout)
+}
+
+/// Determine if the VM is running: the qemu PID quickemu records is alive, or a qemu
+/// process references the VM on its command line. Without either a pid file or a
+/// process, fall back to probing the forwarded port or monitor socket.
+pub fn is_vm_running(vm_conf: &Path, config: &Config) -> bool {
+    if config.demo {
+        return demo::is_running(vm_conf);
+    }
+    // Pids of qemu inside WSL mean nothing to the Windows host; fall back to the ports.
+    let pid = read_runtime(vm_conf).pid.filter(|_| !is_wsl_command(&config.quickemu));
+    if pid.is_some_and(pid_alive) || find_qemu_process(vm_conf).is_some() {
+        return true;
+    }
+    if pid.is_some() {
+        return false;
+    }
+    match parse_vm_config(vm_conf, config) {
+        RemoteProtocol::Rdp(port) | RemoteProtocol::Vnc(port) | RemoteProtocol::Moonlight(port) => {
+            is_port_open(vm_host(vm_conf, config), port, config.connect_timeout)
+        }
+        RemoteProtocol::Spice(_) => is_spice_vm_running(vm_conf, config),
+        // qemu accepts on its SPICE socket exactly while it runs; a crashed VM leaves a dead file.
+        RemoteProtocol::SpiceUnix(socket) => socket_listening(&socket),
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// VM Launching and Connection
+///////////////////////////////////////////////////////////////////////////////
+
+/// Shown in logged command lines in place of a secret.
+const REDACTED: &str = "***";
+/// Options whose value is a secret: in the same argument, or in the next one.
+const SECRET_PREFIXES: &[&str] = &["/p:", "/gp:", "/pth:", "--password="];
+const SECRET_FLAGS: &[&str] = &["-P", "--password", "-pw"];
+
+/// Render a command as a shell-like line, environment overrides first, for the log.
+/// Known password options and secret-looking environment variables are redacted.
+pub fn describe_command(cmd: &Command) -> String {
+    let quote = |s: &std::ffi::OsStr| {
+        let s = s.to_string_lossy();
+        if s.is_empty() || s.contains(|c: char| c.is_whitespace() || "'\"$\\".contains(c)) {
+            format!("'{}'", s.replace('\'', "'\\''"))
+        } else {
+            s.to_string()
+        }
+    };
+    let envs = cmd.get_envs().filter_map(|(k, v)| {
+        let k = k.to_string_lossy();
+        let secret = ["PASS", "SECRET", "TOKEN"].iter().any(|word| k.to_uppercase().contains(word));
+        Some(format!("{}={}", k, if secret { REDACTED.to_string() } else { quote(v?) }))
+    });
+    let mut secret_next = false;
+    let args = cmd.get_args().map(|arg| {
+        let word = arg.to_string_lossy();
+        if std::mem::replace(&mut secret_next, SECRET_FLAGS.contains(&word.as_ref())) {
+            return REDACTED.to_string();
+        }
+        match SECRET_PREFIXES.iter().find(|prefix| word.starts_with(*prefix)) {
+            Some(prefix) => format!("{}{}", prefix, REDACTED),
+            None => quote(arg),
+        }
+    });
+    let words = std::iter::once(quote(cmd.get_program())).chain(args);
+    envs.chain(words).collect::<Vec<_>>().join(" ")
+}
+
+/// Spawn a detached external program with its stdio discarded.
+/// With --dry-run the command line is logged instead and reported as launched;
+/// with `verbose` it is logged along with the spawned PID or the error. The child is
+/// reaped once it exits (see procs::reap).
+pub fn spawn_detached(cmd: &mut Command, config: &Config, logs: &Arc<Mutex<Vec<String>>>) -> io::Result<()> {
+    if let Some(child) = spawn_child(cmd, config, logs)? {
+        procs::adopt(child);
+    }
+    Ok(())
+}
+
+/// Spawn like spawn_detached but hand back the child; None in a dry run.
+pub fn spawn_child(cmd: &mut Command, config: &Config, logs: &Arc<Mutex<Vec<String>>>) -> io::Result<Option<Child>> {
+    if config.dry_run {
+        logs.lock().unwrap().push(format!("[dry-run] {}", describe_command(cmd)));
+        return Ok(None);
+    }
+    let result = cmd.stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null()).spawn();
+    if let Ok(child) = &result {
+        procs::record(child.id(), describe_command(cmd));
+    }
+    if config.verbose {
+        let outcome = match &result {
+            Ok(child) => format!("pid {}", child.id()),
+            Err(e) => e.to_string(),
+        };
+        logs.lock().unwrap().push(format!("$ {} ({})", describe_command(cmd), outcome));
+    }
+    result.map(Some)
+}
+
+/// The flag the configured quickemu uses for one of quickemu's own flags.
+fn quickemu_flag<'a>(config: &'a Config, flag: &'a str) -> &'a str {
+    config.quickemu_flags.get(flag).map_or(flag, String::as_str)
+}
+
+/// A quickemu invocation with `args`, flags renamed through `quickemu_flags`.
+pub fn quickemu_command(config: &Config, args: &[&OsStr]) -> Command {
+    let mut command = Command::new(&config.quickemu[0]);
+    command.args(&config.quickemu[1..]);
+    let wsl = is_wsl_command(&config.quickemu);
+    for arg in args {
+        match arg.to_str() {
+            Some(flag) if flag.starts_with("--") => command.arg(quickemu_flag(config, flag)),
+            Some(path) if wsl && let Some(path) = wsl_path(path) => command.arg(path),
+            _ => command.arg(arg),
+        };
+    }
+    command
+}
+
+/// Whether the command runs inside WSL (`wsl.exe ...`), where Windows paths need translating.
+fn is_wsl_command(words: &[String]) -> bool {
+    words.first().and_then(|w| Path::new(w).file_stem()).is_some_and(|stem| stem.eq_ignore_ascii_case("wsl"))
+}
+
+/// A Windows path as WSL sees it: `C:\VMs\win.conf` is `/mnt/c/VMs/win.conf`, and
+/// `\\wsl$\<distro>\home\me\win.conf` (or `\\wsl.localhost\...`) is `/home/me/win.conf`.
+fn wsl_path(path: &str) -> Option<String> {
+    if let Some(rest) = path.strip_prefix(r"\\wsl$\").or_else(|| path.strip_prefix(r"\\wsl.localhost\")) {
+        let (_distro, inside) = rest.split_once('\\')?;
+        return Some(format!("/{}", inside.replace('\\', "/")));
+    }
+    let drive = path.chars().next().filter(char::is_ascii_alphabetic)?;
+    let rest = path[1..].strip_prefix(':')?;
+    Some(format!("/mnt/{}{}", drive.to_ascii_lowercase(), rest.replace('\\', "/")))
+}
+
+/// A quickget invocation with `args`.
+pub fn quickget_command(config: &Config, args: &[&str]) -> Command {
+    let mut command = Command::new(&config.quickget[0]);
+    command.args(&config.quickget[1..]).args(args);
+    command
+}
+
+/// Why start_vm did not start a VM.
+#[derive(Debug)]
+pub enum StartError {
+    Refused(String),        // Over a group quota with `refuse = true`
+    OverQuota(Vec<String>), // Over group quotas the caller asked to confirm first
+    Failed(String),         // quickemu or the disk secret failed
+}
+
+impl std::fmt::Display for StartError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            StartError::Refused(message) | StartError::Failed(message) => f.write_str(message),
+            StartError::OverQuota(warnings) => f.write_str(&warnings.join(" ")),
+        }
+    }
+}
+
+impl From<StartError> for String {
+    fn from(e: StartError) -> Self {
+        e.to_string()
+    }
+}
+
+/// Launch the VM with quickemu. `passphrase` unlocks an encrypted disk_img (see disk.rs).
+/// Group quotas (see quota.rs) are checked here: over a refusing one the VM is not
+/// started; over others it is, with the excess logged, unless `confirm_quota` asks for
+/// OverQuota instead. Returns a summary once quickemu is launched, or why it was not.
+pub fn start_vm(
+    vm_conf: &Path,
+    config: &Config,
+    logs: &Arc<Mutex<Vec<String>>>,
+    passphrase: Option<&str>,
+    confirm_quota: bool,
+) -> Result<String, StartError> {
+    let excess = quota::exceeded(vm_conf, config);
+    if let Some(refused) = excess.iter().find(|e| e.refuse) {
+        let message = format!("Refusing to start {}: {}", vm_conf.display(), refused.message);
+        logs.lock().unwrap().push(message.clone());
+        return Err(StartError::Refused(message));
+    }
+    if confirm_quota && !excess.is_empty() {
+        return Err(StartError::OverQuota(excess.into_iter().map(|e| e.message).collect()));
+    }
+    for excess in excess {
+        logs.lock().unwrap().push(format!("Over quota: {}", excess.message));
+    }
+    let launched = format!("Launched {}", vm_conf.file_stem().unwrap_or_default().to_string_lossy());
+    if config.demo {
+        demo::start(vm_conf, logs);
+        return Ok(launched);
+    }
+    let vm_arg = vm_conf.as_os_str();
+    let extra_args = match (passphrase, disk::disk_encryption(vm_conf)) {
+        (Some(passphrase), Some(encryption)) => {
+            let args = if config.dry_run {
+                disk::secret_qemu_args(vm_conf, encryption)
+            } else {
+                disk::secret_args(vm_conf, encryption, passphrase, logs)
+            };
+            match args {
+                Ok(args) => Some(args),
+                Err(e) => {
+                    let message = format!("Error preparing disk secret for {}: {}", vm_conf.display(), e);
+                    logs.lock().unwrap().push(message.clone());
+                    notify::send(config, "VM start failed", &message, logs);
+                    return Err(StartError::Failed(message));
+                }
+            }
+        }
+        _ => None,
+    };
+    let protocol = parse_vm_config(vm_conf, config);
+    let mut cmd = match protocol {
+        RemoteProtocol::Rdp(_) | RemoteProtocol::Vnc(_) | RemoteProtocol::Moonlight(_) => {
+            let mut l = logs.lock().unwrap();
+            l.push(format!("Launching VM {} headless...", vm_conf.display()));
+            drop(l);
+            quickemu_command(config, &["--vm".as_ref(), vm_arg, "--display".as_ref(), "none".as_ref()])
+        },
+        _ => {
+            let mut l = logs.lock().unwrap();
+            l.push(format!("Launching VM {} normally...", vm_conf.display()));
+            drop(l);
+            quickemu_command(config, &["--vm".as_ref(), vm_arg])
+        }
+    };
+    let stem = vm_conf.file_stem().unwrap_or_default().to_string_lossy().to_lowercase();
+    apply_vm_env(&mut cmd, &vm_conf.file_stem().unwrap_or_default().to_string_lossy(), config);
+    if matches!(protocol, RemoteProtocol::Spice(_))
+        && !config.vm_hosts.contains_key(&stem)
+        && let Some(port) = assign_spice_port(vm_conf, config, logs)
+    {
+        cmd.arg(quickemu_flag(config, "--spice-port")).arg(port.to_string());
+    }
+    let resume = suspend::resume_args(vm_conf);
+    if resume.is_some() {
+        logs.lock().unwrap().push(format!("Resuming {} from its saved state.", vm_conf.display()));
+    }
+    let extra_args: Vec<String> = extra_args.into_iter().chain(resume.clone()).collect();
+    if !extra_args.is_empty() {
+        cmd.arg(quickemu_flag(config, "--extra_args")).arg(extra_args.join(" "));
+    }
+    match spawn_detached(&mut cmd, config, logs) {
+        Ok(()) if !config.dry_run => {
+            if resume.is_some() {
+                suspend::clear(vm_conf);
+            }
+            snapshot_config(vm_conf, logs);
+            thread::sleep(config.start_wait);
+            Ok(launched)
+        }
+        Ok(()) => Ok(launched),
+        Err(e) => {
+            let message = format!("Error launching VM {}: {}", vm_conf.display(), e);
+            logs.lock().unwrap().push(message.clone());
+            notify::send(config, "VM start failed", &message, logs);
+            Err(StartError::Failed(message))
+        }
+    }
+}
+
+/// Keep a copy of the config for the `D` diff view (see confdiff.rs).
+fn snapshot_config(vm_conf: &Path, logs: &Arc<Mutex<Vec<String>>>) {
+    if let Err(e) = confdiff::snapshot(vm_conf) {
+        logs.lock().unwrap().push(format!("Failed to snapshot {}: {}", vm_conf.display(), e));
+    }
+}
+
+/// Open a directory in the platform file manager.
+pub fn open_in_file_manager(dir: &Path, config: &Config, logs: &Arc<Mutex<Vec<String>>>) -> io::Result<()> {
+    let opener = match config.os_type.as_str() {
+        "windows" => "explorer.exe",
+        "macos" => "open",
+        _ => "xdg-open",
+    };
+    spawn_detached(Command::new(opener).arg(dir), config, logs)
+}
+
+/// Text on the host clipboard, read with the platform's clipboard tool.
+pub fn read_clipboard(config: &Config) -> Result<String, String> {
+    let mut candidates: Vec<Vec<&str>> = match config.os_type.as_str() {
+        "windows" => vec![vec!["powershell", "-NoProfile", "-NonInteractive", "-Command", "Get-Clipboard -Raw"]],
+        "macos" => vec![vec!["pbpaste"]],
+        _ => vec![vec!["xclip", "-o", "-selection", "clipboard"], vec!["xsel", "--clipboard", "--output"]],
+    };
+    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        candidates.insert(0, vec!["wl-paste", "--no-newline"]);
+    }
+    for words in &candidates {
+        if let Ok(output) = Command::new(words[0]).args(&words[1..]).output()
+            && output.status.success()
+        {
+            return Ok(String::from_utf8_lossy(&output.stdout).to_string());
+        }
+    }
+    let tools: Vec<&str> = candidates.iter().map(|w| w[0]).collect();
+    Err(format!("no clipboard tool worked (tried {})", tools.join(", ")))
+}
+
+/// Force a SPICE connection regardless of protocol.
+pub fn force_spice_connect(vm_conf: &Path, config: &Config, logs: &Arc<Mutex<Vec<String>>>) -> Result<String, String> {
+    let vm_name = vm_conf.file_stem().unwrap().to_string_lossy();
+    if connect_protocol(&spice_protocol(vm_conf, config), vm_host(vm_conf, config), vm_conf, config, logs) {
+        Ok(format!("Connected to {} via SPICE", vm_name))
+    } else {
+        Err(format!("SPICE connection to {} failed.", vm_name))
+    }
+}
+
+/// Host and protocol to connect to. Bridged VMs have no localhost forwards:
+/// their Sunshine (with a `[streaming.<vm>]` table), RDP or VNC port is probed on the
+/// discovered guest IP, and SPICE (served by qemu on the host) is the fallback.
+fn connect_target(vm_conf: &Path, config: &Config, logs: &Arc<Mutex<Vec<String>>>) -> (String, RemoteProtocol) {
+    let local = || (vm_host(vm_conf, config).to_string(), parse_vm_config(vm_conf, config));
+    if !guestip::is_bridged(vm_conf) {
+        return local();
+    }
+    let ip = match guestip::discover(vm_conf) {
+        Ok((ip, source)) => {
+            logs.lock().unwrap().push(format!("Guest IP {} (from {}).", ip, source));
+            ip
+        }
+        Err(e) => {
+            logs.lock().unwrap().push(format!("Bridged VM, guest IP unknown: {}.", e));
+            return local();
+        }
+    };
+    let stem = vm_conf.file_stem().unwrap().to_string_lossy().to_lowercase();
+    let sunshine = sunshine_port(vm_conf, config);
+    if config.streaming.contains_key(&stem) && is_port_open(&ip, sunshine, config.connect_timeout) {
+        (ip, RemoteProtocol::Moonlight(sunshine))
+    } else if guest_os(vm_conf) != GuestOs::MacOs && is_port_open(&ip, 3389, config.connect_timeout) {
+        (ip, RemoteProtocol::Rdp(3389))
+    } else if is_port_open(&ip, 5900, config.connect_timeout) {
+        (ip, RemoteProtocol::Vnc(5900))
+    } else {
+        logs.lock().unwrap().push(format!("No RDP or VNC on {}; using SPICE.", ip));
+        local()
+    }
+}
+
+/// Connect to the VM.
+/// First, if an override or auto-detected Remmina profile exists, launch Remmina with it
+/// (using the "-c" flag) and return once it connects.
+/// Otherwise, or when it fails or hangs, use protocol-specific connection.
+/// Returns how it connected, or why it could not.
+pub fn connect_vm(vm_conf: &Path, config: &Config, logs: &Arc<Mutex<Vec<String>>>) -> Result<String, String> {
+    let vm_name = vm_conf.file_stem().unwrap().to_string_lossy();
+    if let Some(profile_path) = remmina::profile_for_vm(vm_conf, config) {
+        let mut l = logs.lock().unwrap();
+        l.push(format!(
+            "Profile found for {}. Launching Remmina with profile: {}",
+            vm_conf.display(),
+            profile_path.display()
+        ));
+        drop(l);
+        let result = viewer::launch(
+            viewer_command(&config.remote_app, &vm_name, config).arg("-c").arg(&profile_path),
+            remmina::server_port(&profile_path),
+            config,
+            logs,
+        );
+        match result {
+            Ok(()) => return Ok(format!("Connected to {} with its Remmina profile", vm_name)),
+            Err(e) => logs.lock().unwrap().push(format!(
+                "Remmina with the profile failed ({}); falling back to normal connection.",
+                e
+            )),
+        }
+    }
+    let chain = connect_chain(vm_conf, config, logs);
+    if chain.is_empty() {
+        let message = format!("{}: none of the protocols in [protocols] is available.", vm_name);
+        logs.lock().unwrap().push(message.clone());
+        return Err(message);
+    }
+    for (i, (host, protocol)) in chain.iter().enumerate() {
+        if connect_protocol(protocol, host, vm_conf, config, logs) {
+            return Ok(format!("Connected to {} via {}", vm_name, protocol.label()));
+        }
+        if let Some((_, next)) = chain.get(i + 1) {
+            logs.lock().unwrap().push(format!("{} failed; trying {}.", protocol.label(), next.label()));
+        }
+    }
+    let message = format!("Could not connect to {}.", vm_name);
+    logs.lock().unwrap().push(message.clone());
+    Err(message)
+}
+
+/// Launch the platform's viewer for one protocol, through the VM's SSH tunnel if it has one.
+fn connect_protocol(
+    protocol: &RemoteProtocol,
+    host: &str,
+    vm_conf: &Path,
+    config: &Config,
+    logs: &Arc<Mutex<Vec<String>>>,
+) -> bool {
+    let vm_name = vm_conf.file_stem().unwrap().to_string_lossy();
+    let tunnelled = match tunnel::open(vm_conf, host, protocol, config, logs) {
+        Ok(tunnelled) => tunnelled,
+        Err(e) => {
+            logs.lock().unwrap().push(format!("{}: {}", vm_name, e));
+            return false;
+        }
+    };
+    let (host, protocol) = match &tunnelled {
+        Some((host, protocol)) => (host.as_str(), protocol),
+        None => (host, protocol),
+    };
+    match (protocol, config.os_type.as_str()) {
+        (RemoteProtocol::Rdp(port), "macos") => connect_rdp_macos(host, *port, &vm_name, config, logs),
+        (RemoteProtocol::Rdp(port), _) => connect_rdp_linux(host, *port, vm_conf, config, logs),
+        (RemoteProtocol::Vnc(port), "macos") => connect_vnc_macos(host, *port, &vm_name, config, logs),
+        (RemoteProtocol::Vnc(port), _) => connect_vnc_linux(host, *port, vm_conf, config, logs),
+        // Moonlight is the same program everywhere, and Windows viewers run like Linux ones;
+        // [clients] points at them where they are not on PATH.
+        (RemoteProtocol::Moonlight(_), _) | (_, "windows") => {
+            launch_viewer_chain(protocol, host, vm_conf, config, logs)
+        }
+        (_, "macos") => connect_spice_macos(protocol, host, vm_conf, config, logs),
+        _ => connect_spice_linux(protocol, host, vm_conf, config, logs),
+    }
+}
+
+/// Protocols connect_vm tries in order, each with the host it is reached on. By default
+/// the detected protocol, then SPICE; a `[protocols]` entry lists them explicitly, and
+/// those the VM does not forward are skipped.
+fn connect_chain(vm_conf: &Path, config: &Config, logs: &Arc<Mutex<Vec<String>>>) -> Vec<(String, RemoteProtocol)> {
+    let (host, detected) = connect_target(vm_conf, config, logs);
+    let local = vm_host(vm_conf, config).to_string();
+    let stem = vm_conf.file_stem().unwrap().to_string_lossy().to_lowercase();
+    let Some(order) = config.protocols.get(&stem) else {
+        let spice = matches!(detected, RemoteProtocol::Spice(_) | RemoteProtocol::SpiceUnix(_));
+        let mut chain = vec![(host, detected)];
+        if !spice {
+            chain.push((local, spice_protocol(vm_conf, config)));
+        }
+        return chain;
+    };
+    let mut chain = Vec::new();
+    for name in order {
+        let entry = match (name.as_str(), &detected) {
+            ("rdp", RemoteProtocol::Rdp(port)) => Some((host.clone(), RemoteProtocol::Rdp(*port))),
+            ("rdp", _) => forwarded_port(vm_conf, 3389).map(|port| (local.clone(), RemoteProtocol::Rdp(port))),
+            ("vnc", RemoteProtocol::Vnc(port)) => Some((host.clone(), RemoteProtocol::Vnc(*port))),
+            ("vnc", _) => forwarded_port(vm_conf, 5900).map(|port| (local.clone(), RemoteProtocol::Vnc(port))),
+            ("moonlight", RemoteProtocol::Moonlight(port)) => Some((host.clone(), RemoteProtocol::Moonlight(*port))),
+            ("moonlight", _) => forwarded_port(vm_conf, sunshine_port(vm_conf, config))
+                .map(|port| (local.clone(), RemoteProtocol::Moonlight(port))),
+            ("spice", _) => Some((local.clone(), spice_protocol(vm_conf, config))),
+            _ => {
+                logs.lock().unwrap().push(format!("[protocols] {}: unknown protocol \"{}\".", stem, name));
+                None
+            }
+        };
+        match entry {
+            Some(entry) => chain.push(entry),
+            None if matches!(name.as_str(), "rdp" | "vnc" | "moonlight") => {
+                let message = format!("{} has no {} port forward; skipping it.", stem, name);
+                logs.lock().unwrap().push(message);
+            }
+            None => {}
+        }
+    }
+    chain
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// Viewer Resolution (PATH, Flatpak, Snap)
+///////////////////////////////////////////////////////////////////////////////
+
+/// Flatpak application providing a client, and the command to run inside it if not the default.
+fn flatpak_app(client: &str) -> Option<(&'static str, Option<&'static str>)> {
+    match client {
+        "remmina" => Some(("org.remmina.Remmina", None)),
+        "remote-viewer" => Some(("org.virt_manager.virt-viewer", Some("remote-viewer"))),
+        "virt-viewer" => Some(("org.virt_manager.virt-viewer", Some("virt-viewer"))),
+        "xfreerdp" => Some(("com.freerdp.FreeRDP", None)),
+        _ => None,
+    }
+}
+
+/// Find an executable by name on $PATH.
+pub(crate) fn find_in_path(name: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(name))
+        .find(|candidate| candidate.is_file())
+}
+
+fn flatpak_installed(app_id: &str) -> bool {
+    let mut roots = vec![PathBuf::from("/var/lib/flatpak/app")];
+    if let Some(data) = dirs::data_dir() {
+        roots.push(data.join("flatpak/app"));
+    }
+    roots.iter().any(|root| root.join(app_id).is_dir())
+}
+
+/// Standard install location of a Windows client whose installer does not add it to PATH:
+/// System32 for mstsc, else Program Files (both) and %LOCALAPPDATA%\Programs.
+fn windows_install(client: &str) -> Option<PathBuf> {
+    let env_dir = |name: &str| std::env::var_os(name).map(PathBuf::from);
+    let client = client.trim_end_matches(".exe");
+    if client == "mstsc" {
+        return env_dir("SystemRoot").map(|root| root.join(r"System32\mstsc.exe")).filter(|p| p.is_file());
+    }
+    let roots: Vec<PathBuf> = [env_dir("ProgramFiles"), env_dir("ProgramFiles(x86)")]
+        .into_iter()
+        .chain([env_dir("LOCALAPPDATA").map(|d| d.join("Programs"))])
+        .flatten()
+        .collect();
+    let subdirs: &[&str] = match client {
+        "tvnviewer" => &["TightVNC"],
+        "vncviewer" => &[r"RealVNC\VNC Viewer", "TigerVNC", r"uvnc bvba\UltraVNC"],
+        // virt-viewer installs into a versioned directory, e.g. "VirtViewer v11.0-256".
+        "remote-viewer" | "virt-viewer" => {
+            return roots.iter().find_map(|root| {
+                fs::read_dir(root)
+                    .ok()?
+                    .flatten()
+                    .filter(|e| e.file_name().to_string_lossy().starts_with("VirtViewer"))
+                    .map(|e| e.path().join("bin").join(format!("{}.exe", client)))
+                    .find(|p| p.is_file())
+            });
+        }
+        _ => return None,
+    };
+    roots
+        .iter()
+        .flat_map(|root| subdirs.iter().map(move |sub| root.join(sub).join(format!("{}.exe", client))))
+        .find(|p| p.is_file())
+}
+
+/// Locate an installed client: the binary on $PATH, a Flatpak install (`flatpak run ...`),
+/// a Snap install (/snap/bin), or a standard install directory on Windows.
+/// Returns the command words needed to run it.
+fn resolve_viewer(client: &str) -> Option<Vec<String>> {
+    if find_in_path(client).is_some() || (cfg!(windows) && find_in_path(&format!("{}.exe", client)).is_some()) {
+        return Some(vec![client.to_string()]);
+    }
+    if cfg!(windows) {
+        return windows_install(client).map(|path| vec![path.to_string_lossy().to_string()]);
+    }
+    if !cfg!(target_os = "linux") {
+        return None;
+    }
+    if let Some((app_id, inner)) = flatpak_app(client)
+        && flatpak_installed(app_id)
+    {
+        let mut words = vec!["flatpak".to_string(), "run".to_string()];
+        if let Some(inner) = inner {
+            words.push(format!("--command={}", inner));
+        }
+        words.push(app_id.to_string());
+        return Some(words);
+    }
+    let snap = Path::new("/snap/bin").join(client);
+    if snap.is_file() {
+        return Some(vec![snap.to_string_lossy().to_string()]);
+    }
+    None
+}
+
+/// Build the command that launches a viewer client: an explicit `[clients]` entry from
+/// the config (with `${VM}` expanded), else whatever resolve_viewer finds, else the bare name.
+pub fn viewer_command(client: &str, vm_name: &str, config: &Config) -> Command {
+    let words = config
+        .client_commands
+        .get(client)
+        .map(|words| words.iter().map(|w| expand_vars(w, Some(vm_name))).collect())
+        .or_else(|| resolve_viewer(client))
+        .unwrap_or_else(|| vec![client.to_string()]);
+    let mut command = Command::new(&words[0]);
+    command.args(&words[1..]);
+    // Viewers started from a console or SSH session still belong on the local X display.
+    let no_display = std::env::var_os("DISPLAY").is_none() && std::env::var_os("WAYLAND_DISPLAY").is_none();
+    if config.os_type == "linux" && no_display {
+        command.env("DISPLAY", ":0");
+    }
+    apply_vm_env(&mut command, vm_name, config);
+    command
+}
+
+/// Set the VM's `[env.<vm>]` variables on a command spawned for it.
+pub fn apply_vm_env(command: &mut Command, vm_name: &str, config: &Config) {
+    if let Some(env) = config.vm_env.get(&vm_name.to_lowercase()) {
+        for (key, value) in env {
+            command.env(key, expand_vars(value, Some(vm_name)));
+        }
+    }
+}
+
+/// Arguments that make a client connect to the given protocol and port.
+/// Known clients get their native syntax; anything else receives a URL.
+pub fn viewer_args(client: &str, protocol: &RemoteProtocol, vm_name: &str, host: &str) -> Vec<String> {
+    let scheme = protocol.label().to_lowercase();
+    let url = protocol.url(host);
+    let addr = protocol.endpoint(host);
+    let name = Path::new(client).file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    match (name.as_str(), protocol) {
+        ("remmina", _) => vec!["--quiet".into(), "-p".into(), scheme, url],
+        ("xfreerdp" | "xfreerdp3" | "wlfreerdp" | "sdl-freerdp", RemoteProtocol::Rdp(_)) => vec![
+            format!("/v:{}", addr),
+            "/f".into(),
+            "/dynamic-resolution".into(),
+        ],
+        ("mstsc", RemoteProtocol::Rdp(_)) => vec![format!("/v:{}", addr)],
+        ("vncviewer" | "tvnviewer", RemoteProtocol::Vnc(_)) => vec![addr],
+        // moonlight-qt; the app name follows, see moonlight_app.
+        ("moonlight" | "Moonlight", RemoteProtocol::Moonlight(_)) => vec!["stream".into(), addr],
+        ("spicy", RemoteProtocol::Spice(port)) => vec![
+            "--title".into(),
+            vm_name.into(),
+            "-h".into(),
+            host.into(),
+            "-p".into(),
+            port.to_string(),
+        ],
+        ("spicy", RemoteProtocol::SpiceUnix(_)) => vec!["--title".into(), vm_name.into(), format!("--uri={}", url)],
+        _ => vec![url],
+    }
+}
+
+/// App a Moonlight stream launches: the VM's `[streaming.<vm>]` app, else Sunshine's "Desktop".
+pub fn moonlight_app(vm_name: &str, config: &Config) -> String {
+    let streaming = config.streaming.get(&vm_name.to_lowercase());
+    streaming.and_then(|s| s.app.clone()).unwrap_or_else(|| "Desktop".to_string())
+}
+
+/// Display flags for a SPICE viewer from the VM's `[spice_display.<vm>]` options.
+/// remote-viewer and virt-viewer map one guest display per client monitor in full
+/// screen mode, so `monitors` above one implies `--full-screen` for them; spicy has
+/// no monitor or resize options and only gets full screen.
+pub fn spice_display_args(client: &str, vm_name: &str, config: &Config) -> Vec<String> {
+    let Some(display) = config.spice_display.get(&vm_name.to_lowercase()) else { return Vec::new() };
+    let name = Path::new(client).file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let mut args = Vec::new();
+    match name.as_str() {
+        "remote-viewer" | "virt-viewer" => {
+            if display.fullscreen || display.monitors.is_some_and(|n| n > 1) {
+                args.push("--full-screen".to_string());
+            }
+            if let Some(resize) = display.auto_resize {
+                args.push(format!("--auto-resize={}", if resize { "always" } else { "never" }));
+            }
+        }
+        "spicy" if display.fullscreen => args.push("--full-screen".to_string()),
+        _ => {}
+    }
+    args
+}
+
+/// RD Gateway flags for a FreeRDP client. FreeRDP 3 (xfreerdp3, sdl-freerdp) takes
+/// a single /gateway option; FreeRDP 2 takes /g, /gu and /gd. The password is never
+/// put on the command line, where `ps` shows it; FreeRDP asks for it. FreeRDP 3
+/// splits /gateway at commas, so a user or domain containing one is left for it to ask.
+pub fn rdp_gateway_args(client: &str, vm_name: &str, config: &Config) -> Vec<String> {
+    let Some(gateway) = config.rdp_gateways.get(&vm_name.to_lowercase()) else { return Vec::new() };
+    let name = Path::new(client).file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let credentials = [("u", &gateway.user), ("d", &gateway.domain)];
+    match name.as_str() {
+        "xfreerdp3" | "sdl-freerdp" => {
+            let mut option = format!("/gateway:g:{}", gateway.host);
+            for (key, value) in credentials {
+                if let Some(value) = value.as_ref().filter(|v| !v.contains(',')) {
+                    option.push_str(&format!(",{}:{}", key, value));
+                }
+            }
+            vec![option]
+        }
+        "xfreerdp" | "wlfreerdp" => std::iter::once(format!("/g:{}", gateway.host))
+            .chain(credentials.iter().filter_map(|(key, value)| Some(format!("/g{}:{}", key, value.as_ref()?))))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Write an .rdp file for mstsc, which only reads gateway settings from files.
+/// The gateway password cannot be stored in plain text; mstsc asks for it.
+fn write_rdp_file(addr: &str, vm_name: &str, gateway: &RdpGateway) -> io::Result<PathBuf> {
+    let dir = state_dir().join("rdp");
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("{}.rdp", vm_name));
+    let mut contents = format!(
+        "full address:s:{}\r\ngatewayhostname:s:{}\r\ngatewayusagemethod:i:1\r\ngatewayprofileusagemethod:i:1\r\n\
+         gatewaycredentialssource:i:0\r\n",
+        addr, gateway.host
+    );
+    if let Some(user) = &gateway.user {
+        let user = match &gateway.domain {
+            Some(domain) => format!("{}\\{}", domain, user),
+            None => user.clone(),
+        };
+        contents.push_str(&format!("gatewayusername:s:{}\r\n", user));
+    }
+    fs::write(&path, contents)?;
+    Ok(path)
+}
+
+/// Try each client of the protocol's fallback chain in order until one launches.
+fn launch_viewer_chain(
+    protocol: &RemoteProtocol,
+    host: &str,
+    vm_conf: &Path,
+    config: &Config,
+    logs: &Arc<Mutex<Vec<String>>>,
+) -> bool {
+    let chain = protocol.clients(config);
+    let vm_name = vm_conf.file_stem().unwrap().to_string_lossy();
+    for (i, client) in chain.iter().enumerate() {
+        {
+            let mut l = logs.lock().unwrap();
+            l.push(format!("Connecting with {}...", client));
+        }
+        let result = chain_args(client, protocol, &vm_name, host, config, logs).and_then(|args| {
+            viewer::launch(viewer_command(client, &vm_name, config).args(args), protocol.port(), config, logs)
+        });
+        match result {
+            Ok(_) => {
+                let via = format!("{} {}", protocol.label(), protocol.endpoint(host));
+                logs.lock().unwrap().push(format!("Connected to {} via {} with {}.", vm_name, via, client));
+                return true;
+            }
+            Err(e) => {
+                let mut l = logs.lock().unwrap();
+                match chain.get(i + 1) {
+                    Some(next) => l.push(format!("{} launch failed ({}), trying {}...", client, e, next)),
+                    None => l.push(format!("{} launch failed ({}).", client, e)),
+                }
+            }
+        }
+    }
+    false
+}
+
+/// Arguments for one client of a viewer chain. mstsc only reads gateway settings from
+/// files, so with an RD Gateway it gets an .rdp file instead.
+fn chain_args(
+    client: &str,
+    protocol: &RemoteProtocol,
+    vm_name: &str,
+    host: &str,
+    config: &Config,
+    logs: &Arc<Mutex<Vec<String>>>,
+) -> Result<Vec<String>, String> {
+    let mstsc = Path::new(client).file_stem().is_some_and(|s| s.eq_ignore_ascii_case("mstsc"));
+    if let (RemoteProtocol::Rdp(port), Some(gateway)) = (protocol, config.rdp_gateways.get(&vm_name.to_lowercase()))
+        && mstsc
+    {
+        let path = write_rdp_file(&format_addr(host, *port), vm_name, gateway)
+            .map_err(|e| format!("cannot write .rdp file for the gateway: {}", e))?;
+        logs.lock().unwrap().push(format!("Using RD Gateway {} ({}).", gateway.host, path.display()));
+        return Ok(vec![path.to_string_lossy().to_string()]);
+    }
+    let extra = match protocol {
+        RemoteProtocol::Spice(_) | RemoteProtocol::SpiceUnix(_) => spice_display_args(client, vm_name, config),
+        RemoteProtocol::Rdp(_) => rdp_gateway_args(client, vm_name, config),
+        RemoteProtocol::Vnc(_) => Vec::new(),
+        RemoteProtocol::Moonlight(_) => vec![moonlight_app(vm_name, config)],
+    };
+    Ok(viewer_args(client, protocol, vm_name, host).into_iter().chain(extra).collect())
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// Platform-Specific Connection Functions
+///////////////////////////////////////////////////////////////////////////////
+
+/// Default macOS apps per protocol, most preferred first. Windows App is the renamed
+/// Microsoft Remote Desktop; both take the same rdp:// URLs.
+const MACOS_RDP_APPS: [&str; 2] = ["Windows App", "Microsoft Remote Desktop"];
+const MACOS_VNC_APPS: [&str; 2] = ["Screen Sharing", "VNC Viewer"];
+
+/// Locate a macOS application bundle given by name ("Windows App") or path.
+fn macos_app(name: &str) -> Option<PathBuf> {
+    let path = Path::new(name);
+    if path.is_absolute() {
+        return path.is_dir().then(|| path.to_path_buf());
+    }
+    let bundle = if name.ends_with(".app") { name.to_string() } else { format!("{}.app", name) };
+    let mut roots: Vec<PathBuf> = [
+        "/Applications",
+        "/System/Applications",
+        "/System/Applications/Utilities",
+        "/System/Library/CoreServices/Applications", // Screen Sharing before macOS 14
+    ]
+    .iter()
+    .map(PathBuf::from)
+    .collect();
+    roots.extend(dirs::home_dir().map(|home| home.join("Applications")));
+    roots.into_iter().map(|root| root.join(&bundle)).find(|p| p.is_dir())
+}
+
+/// `open -a <app> <url>` with the configured app, else the first installed default.
+/// Opening a URL without naming the app fails with a bare "no application" error.
+fn macos_open(configured: Option<&str>, defaults: &[&str], url: &str) -> Result<Command, String> {
+    let app = match configured {
+        Some(name) => macos_app(name).ok_or_else(|| format!("{} is not installed", name))?,
+        None => defaults
+            .iter()
+            .find_map(|name| macos_app(name))
+            .ok_or_else(|| format!("none of {} is installed", defaults.join(", ")))?,
+    };
+    let mut command = Command::new("open");
+    command.arg("-a").arg(app).arg(url);
+    Ok(command)
+}
+
+fn connect_rdp_macos(host: &str, host_port: u16, vm_name: &str, config: &Config, logs: &Arc<Mutex<Vec<String>>>) -> bool {
+    // Windows App and Microsoft Remote Desktop accept .rdp settings as URL attributes.
+    let url = match config.rdp_gateways.get(&vm_name.to_lowercase()) {
+        Some(gateway) => format!(
+            "rdp://full%20address=s:{}&gatewayhostname=s:{}&gatewayusagemethod=i:1",
+            format_addr(host, host_port),
+            gateway.host
+        ),
+        None => format!("rdp://{}", format_addr(host, host_port)),
+    };
+    match macos_open(config.macos_rdp_app.as_deref(), &MACOS_RDP_APPS, &url) {
+        Ok(mut command) => {
+            logs.lock().unwrap().push(format!("Connecting via macOS RDP to port {}", host_port));
+            spawn_detached(&mut command, config, logs).is_ok()
+        }
+        Err(e) => {
+            logs.lock().unwrap().push(format!("No macOS RDP client: {} (see macos_rdp_app).", e));
+            false
+        }
+    }
+}
+
+fn connect_rdp_linux(host: &str, host_port: u16, vm_conf: &Path, config: &Config, logs: &Arc<Mutex<Vec<String>>>) -> bool {
+    launch_viewer_chain(&RemoteProtocol::Rdp(host_port), host, vm_conf, config, logs)
+}
+
+fn connect_vnc_macos(host: &str, host_port: u16, _vm_name: &str, config: &Config, logs: &Arc<Mutex<Vec<String>>>) -> bool {
+    let url = format!("vnc://{}", format_addr(host, host_port));
+    match macos_open(config.macos_vnc_app.as_deref(), &MACOS_VNC_APPS, &url) {
+        Ok(mut command) => {
+            logs.lock().unwrap().push(format!("Connecting via macOS VNC to port {}", host_port));
+            spawn_detached(&mut command, config, logs).is_ok()
+        }
+        Err(e) => {
+            logs.lock().unwrap().push(format!("No macOS VNC client: {} (see macos_vnc_app).", e));
+            false
+        }
+    }
+}
+
+fn connect_vnc_linux(host: &str, host_port: u16, vm_conf: &Path, config: &Config, logs: &Arc<Mutex<Vec<String>>>) -> bool {
+    launch_viewer_chain(&RemoteProtocol::Vnc(host_port), host, vm_conf, config, logs)
+}
+
+fn connect_spice_macos(
+    protocol: &RemoteProtocol,
+    host: &str,
+    vm_conf: &Path,
+    config: &Config,
+    logs: &Arc<Mutex<Vec<String>>>,
+) -> bool {
+    let mut l = logs.lock().unwrap();
+    l.push(format!("Connecting via SPICE on macOS to {}", protocol.endpoint(host)));
+    drop(l);
+    let url = protocol.url(host);
+    let vm_name = vm_conf.file_stem().unwrap().to_string_lossy();
+    // remote-viewer from Homebrew's virt-viewer is a plain binary, not an app bundle.
+    if config.macos_spice_app.is_none() && resolve_viewer("remote-viewer").is_some() {
+        let mut command = viewer_command("remote-viewer", &vm_name, config);
+        command.args(spice_display_args("remote-viewer", &vm_name, config)).arg(url);
+        return viewer::launch(&mut command, protocol.port(), config, logs).is_ok();
+    }
+    match macos_open(config.macos_spice_app.as_deref(), &[], &url) {
+        Ok(mut command) => spawn_detached(&mut command, config, logs).is_ok(),
+        Err(_) => {
+            let message = "No macOS SPICE client: install virt-viewer (brew) or set macos_spice_app.";
+            logs.lock().unwrap().push(message.into());
+            false
+        }
+    }
+}
+
+fn connect_spice_linux(
+    protocol: &RemoteProtocol,
+    host: &str,
+    vm_conf: &Path,
+    config: &Config,
+    logs: &Arc<Mutex<Vec<String>>>,
+) -> bool {
+    launch_viewer_chain(protocol, host, vm_conf, config, logs)
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// Connection Diagnostics
+///////////////////////////////////////////////////////////////////////////////
+
+/// Check everything a connection depends on without launching anything, returning
+/// one "[PASS]"/"[FAIL]"/"[INFO]" line per check followed by a summary.
+pub fn diagnose_vm(vm_conf: &Path, config: &Config) -> Vec<String> {
+    let vm_name = vm_conf.file_stem().unwrap().to_string_lossy().to_string();
+    let mut report = Vec::new();
+    let mut check = |ok: bool, msg: String| report.push(format!("[{}] {}", if ok { "PASS" } else { "FAIL" }, msg));
+    check(fs::read_to_string(vm_conf).is_ok(), format!("config {} is readable", vm_conf.display()));
+    let protocol = parse_vm_config(vm_conf, config);
+    let (label, endpoint) = (protocol.label(), protocol.endpoint(vm_host(vm_conf, config)));
+    let runtime = read_runtime(vm_conf);
+    match runtime.pid {
+        Some(pid) => check(pid_alive(pid), format!("qemu process {} from {}.pid is alive", pid, vm_name)),
+        None => check(false, format!("no {}.pid file (VM not started by quickemu?)", vm_name)),
+    }
+    match find_qemu_process(vm_conf) {
+        Some(pid) => check(true, format!("qemu process {} references the VM on its command line", pid)),
+        None => check(false, "no qemu process references the VM on its command line".into()),
+    }
+    match &protocol {
+        RemoteProtocol::SpiceUnix(socket) => {
+            check(socket_listening(socket), format!("SPICE socket {} accepts connections", socket.display()))
+        }
+        _ => check(
+            is_port_open(vm_host(vm_conf, config), protocol.port().unwrap_or_default(), config.connect_timeout),
+            format!("{} port {} accepts connections", label, endpoint),
+        ),
+    }
+    let monitor = vm_runtime_file(vm_conf, "-monitor.socket");
+    #[cfg(unix)]
+    check(UnixStream::connect(&monitor).is_ok(), format!("monitor socket {} accepts connections", monitor.display()));
+    #[cfg(not(unix))]
+    check(monitor.exists(), format!("monitor socket {} exists", monitor.display()));
+    if config.os_type == "linux" {
+        for client in protocol.clients(config) {
+            match config.client_commands.get(client).cloned().or_else(|| resolve_viewer(client)) {
+                Some(words) => check(true, format!("viewer {} found: {}", client, words.join(" "))),
+                None => check(false, format!("viewer {} not found", client)),
+            }
+        }
+    }
+    let failed = report.iter().filter(|l| l.starts_with("[FAIL]")).count();
+    let passed = report.len() - failed;
+    report.insert(0, format!("Diagnostics for {}: protocol {} on {}", vm_name, label, endpoint));
+    if let Some(profile) = remmina::profile_for_vm(vm_conf, config) {
+        report.push(format!("[INFO] Remmina profile {} is used first", profile.display()));
+    }
+    report.push(format!("Diagnostics for {}: {} passed, {} failed.", vm_name, passed, failed));
+    report
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// Stopping VMs
+///////////////////////////////////////////////////////////////////////////////
+
+/// Stages of a stop, shown in the VM list while it is in progress.
+#[derive(Clone, Copy, PartialEq)]
+pub enum StopStage {
+    Suspend,   // Saving RAM and device state (suspend_on_stop)
+    Powerdown, // ACPI powerdown sent, waiting for the guest to shut down
+    Kill,      // quickemu --kill issued
+    SigKill,   // qemu killed with SIGKILL / taskkill /F
+}
+
+impl StopStage {
+    pub fn label(self) -> &'static str {
+        match self {
+            StopStage::Suspend => "saving state",
+            StopStage::Powerdown => "ACPI powerdown",
+            StopStage::Kill => "quickemu --kill",
+            StopStage::SigKill => "SIGKILL",
+        }
+    }
+}
+
+/// How long to wait for the VM to go away after quickemu --kill before SIGKILL.
+const KILL_WAIT: Duration = Duration::from_secs(10);
+
+/// VMs being stopped in this process and their current stage.
+static STOPPING: Mutex<Vec<(PathBuf, StopStage)>> = Mutex::new(Vec::new());
+
+/// The stage a VM's stop has reached, if quick-cli is stopping it.
+pub fn stop_stage(vm_conf: &Path) -> Option<StopStage> {
+    STOPPING.lock().unwrap().iter().find(|(vm, _)| vm == vm_conf).map(|(_, stage)| *stage)
+}
+
+fn set_stop_stage(vm_conf: &Path, stage: Option<StopStage>) {
+    let mut stopping = STOPPING.lock().unwrap();
+    stopping.retain(|(vm, _)| vm != vm_conf);
+    if let Some(stage) = stage {
+        stopping.push((vm_conf.to_path_buf(), stage));
+    }
+}
+
+/// Ask the guest to shut down: `system_powerdown` over QMP, or over the HMP monitor socket.
+fn send_powerdown(vm_conf: &Path) -> Result<(), String> {
+    match qmp::QmpClient::connect(vm_conf) {
+        Ok(mut client) => client.execute("system_powerdown", None).map(|_| ()),
+        #[cfg(unix)]
+        Err(_) => {
+            use std::io::Write;
+            let monitor = vm_runtime_file(vm_conf, "-monitor.socket");
+            let mut stream = UnixStream::connect(&monitor).map_err(|e| format!("{}: {}", monitor.display(), e))?;
+            stream.write_all(b"system_powerdown\n").map_err(|e| e.to_string())
+        }
+        #[cfg(not(unix))]
+        Err(e) => Err(e),
+    }
+}
+
+/// Poll once a second until the VM has stopped or the timeout passes.
+fn wait_stopped(vm_conf: &Path, config: &Config, timeout: Duration) -> bool {
+    let deadline = std::time::Instant::now() + timeout;
+    loop {
+        if !is_vm_running(vm_conf, config) {
+            return true;
+        }
+        if std::time::Instant::now() >= deadline {
+            return false;
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+}
+
+pub(crate) fn kill_command(pid: u32) -> Command {
+    let mut command;
+    if cfg!(windows) {
+        command = Command::new("taskkill");
+        command.args(["/F", "/PID", &pid.to_string()]);
+    } else {
+        command = Command::new("kill");
+        command.args(["-9", &pid.to_string()]);
+    }
+    command
+}
+
+/// Stop the VM in stages, in the background: ACPI powerdown, then after
+/// `stop_timeout` quickemu --kill, then SIGKILL of the qemu process. VMs matched
+/// by `suspend_on_stop` first try to save their state (see suspend.rs). Returns once
+/// the stop is under way; stop_stage shows its progress until it ends.
+pub fn stop_vm(vm_conf: &Path, config: &Config, logs: &Arc<Mutex<Vec<String>>>) -> Result<String, String> {
+    if stop_stage(vm_conf).is_some() {
+        let message = format!("{} is already being stopped.", vm_conf.display());
+        logs.lock().unwrap().push(message.clone());
+        return Err(message);
+    }
+    let stopping = format!("Stopping {}", vm_conf.file_stem().unwrap_or_default().to_string_lossy());
+    if config.demo {
+        demo::stop(vm_conf, logs);
+        return Ok(stopping);
+    }
+    logs.lock().unwrap().push(format!("Stopping VM {}...", vm_conf.display()));
+    let mut kill = quickemu_command(config, &["--kill".as_ref(), "--vm".as_ref(), vm_conf.as_os_str()]);
+    let save_state = suspend::enabled(vm_conf, config);
+    if config.dry_run {
+        if save_state {
+            logs.lock().unwrap().push(format!("[dry-run] would savevm {} and quit", suspend::SNAPSHOT_TAG));
+        }
+        logs.lock().unwrap().push(format!(
+            "[dry-run] would send ACPI powerdown and wait {}s, then run: {}, then SIGKILL qemu",
+            config.stop_timeout.as_secs(),
+            describe_command(&kill)
+        ));
+        return Ok(stopping);
+    }
+    snapshot_config(vm_conf, logs);
+    watchdog::expect_stop(vm_conf);
+    set_stop_stage(vm_conf, Some(if save_state { StopStage::Suspend } else { StopStage::Powerdown }));
+    let (vm_conf, config, logs) = (vm_conf.to_path_buf(), config.clone(), Arc::clone(logs));
+    thread::spawn(move || {
+        let log = |message: String| logs.lock().unwrap().push(message);
+        let name = vm_conf.file_stem().unwrap().to_string_lossy().to_string();
+        if save_state {
+            match suspend::suspend(&vm_conf) {
+                Ok(()) if wait_stopped(&vm_conf, &config, KILL_WAIT) => {
+                    log(format!("Saved the state of {}; it resumes on the next start.", name));
+                    return set_stop_stage(&vm_conf, None);
+                }
+                Ok(()) => log(format!("Saved the state of {}, but it is still running.", name)),
+                Err(e) => log(format!("Saving the state of {} failed ({}); shutting it down instead.", name, e)),
+            }
+            set_stop_stage(&vm_conf, Some(StopStage::Powerdown));
+        }
+        let acpi = !config.stop_timeout.is_zero();
+        if acpi {
+            match send_powerdown(&vm_conf) {
+                Ok(()) => log(format!("Sent ACPI powerdown to {}.", name)),
+                Err(e) => log(format!("ACPI powerdown for {} failed: {}", name, e)),
+            }
+        }
+        if acpi && wait_stopped(&vm_conf, &config, config.stop_timeout) {
+            log(format!("{} shut down.", name));
+            return set_stop_stage(&vm_conf, None);
+        }
+        // quickemu --kill needs the pid file; note the pid first for the last stage.
+        let pid = read_runtime(&vm_conf).pid.filter(|pid| pid_alive(*pid)).or_else(|| find_qemu_process(&vm_conf));
+        set_stop_stage(&vm_conf, Some(StopStage::Kill));
+        match spawn_detached(&mut kill, &config, &logs) {
+            Ok(_) => log(format!("Stop command issued for {}.", vm_conf.display())),
+            Err(e) => log(format!("Error stopping VM {}: {}", vm_conf.display(), e)),
+        }
+        if wait_stopped(&vm_conf, &config, KILL_WAIT) {
+            return set_stop_stage(&vm_conf, None);
+        }
+        match pid {
+            Some(pid) => {
+                set_stop_stage(&vm_conf, Some(StopStage::SigKill));
+                log(format!("{} still running; killing qemu process {}.", name, pid));
+                let _ = spawn_detached(&mut kill_command(pid), &config, &logs);
+                if !wait_stopped(&vm_conf, &config, KILL_WAIT) {
+                    log(format!("{} is still running after SIGKILL.", name));
+                }
+            }
+            None => log(format!("{} is still running and its qemu process was not found.", name)),
+        }
+        set_stop_stage(&vm_conf, None);
+    });
+    Ok(stopping)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A fresh directory under the system temp dir, removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            let n = NEXT.fetch_add(1, Ordering::Relaxed);
+            let dir = std::env::temp_dir().join(format!("quick-cli-test-{}-{}", std::process::id(), n));
+            fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// A quickemu .conf in its own TempDir.
+    struct TempVm {
+        _dir: TempDir,
+        conf: PathBuf,
+    }
+
+    impl TempVm {
+        fn new(name: &str, contents: &str) -> Self {
+            let dir = TempDir::new();
+            let conf = dir.0.join(format!("{}.conf", name));
+            fs::write(&conf, contents).unwrap();
+            TempVm { _dir: dir, conf }
+        }
+    }
+
+    #[test]
+    fn read_runtime_parses_pid_and_ports() {
+        let vm = TempVm::new("rt", "");
+        fs::create_dir_all(vm_dir(&vm.conf)).unwrap();
+        fs::write(vm_runtime_file(&vm.conf, ".pid"), "4242\n").unwrap();
+        let ports = "ssh,22220\nspice, 5930\nmonitor,unix:rt-monitor.socket\nserial,/run/rt-serial.socket\nno comma\n";
+        fs::write(vm_runtime_file(&vm.conf, ".ports"), ports).unwrap();
+        let runtime = read_runtime(&vm.conf);
+        assert_eq!(runtime.pid, Some(4242));
+        assert_eq!(runtime.ports.get("ssh"), Some(&22220));
+        assert_eq!(runtime.ports.get("spice"), Some(&5930));
+        assert_eq!(runtime.sockets.get("monitor"), Some(&vm_dir(&vm.conf).join("rt-monitor.socket")));
+        assert_eq!(runtime.sockets.get("serial"), Some(&PathBuf::from("/run/rt-serial.socket")));
+        assert_eq!(runtime.ports.len() + runtime.sockets.len(), 4);
+    }
+
+    #[test]
+    fn read_runtime_without_files_is_empty() {
+        let vm = TempVm::new("rt-none", "");
+        let runtime = read_runtime(&vm.conf);
+        assert!(runtime.pid.is_none() && runtime.ports.is_empty() && runtime.sockets.is_empty());
+    }
+
+    #[test]
+    fn glob_match_cases() {
+        assert!(glob_match("win*", "Windows-11"));
+        assert!(glob_match("?buntu", "ubuntu"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "axxbyyc"));
+        assert!(glob_match("*-test", "debian-test"));
+        assert!(!glob_match("a*b*c", "axxbyy"));
+        assert!(!glob_match("?", ""));
+        assert!(!glob_match("win", "windows"));
+    }
+
+    #[test]
+    fn viewer_args_per_client() {
+        let host = "127.0.0.1";
+        assert_eq!(
+            viewer_args("/usr/bin/xfreerdp3", &RemoteProtocol::Rdp(3389), "win", host),
+            ["/v:127.0.0.1:3389", "/f", "/dynamic-resolution"]
+        );
+        assert_eq!(
+            viewer_args("remmina", &RemoteProtocol::Vnc(5901), "lnx", host),
+            ["--quiet", "-p", "vnc", "vnc://127.0.0.1:5901"]
+        );
+        assert_eq!(viewer_args("vncviewer", &RemoteProtocol::Vnc(5901), "lnx", host), ["127.0.0.1:5901"]);
+        assert_eq!(viewer_args("mstsc.exe", &RemoteProtocol::Rdp(3389), "win", host), ["/v:127.0.0.1:3389"]);
+        assert_eq!(viewer_args("vncviewer", &RemoteProtocol::Rdp(3389), "win", host), ["rdp://127.0.0.1:3389"]);
+        assert_eq!(
+            viewer_args("spicy", &RemoteProtocol::Spice(5930), "lnx", host),
+            ["--title", "lnx", "-h", "127.0.0.1", "-p", "5930"]
+        );
+        let socket = RemoteProtocol::SpiceUnix(PathBuf::from("/vms/lnx/lnx-spice.socket"));
+        assert_eq!(
+            viewer_args("spicy", &socket, "lnx", host),
+            ["--title", "lnx", "--uri=spice+unix:///vms/lnx/lnx-spice.socket"]
+        );
+        assert_eq!(viewer_args("remote-viewer", &socket, "lnx", host), ["spice+unix:///vms/lnx/lnx-spice.socket"]);
+        assert_eq!(
+            viewer_args("moonlight", &RemoteProtocol::Moonlight(47989), "game", host),
+            ["stream", "127.0.0.1:47989"]
+        );
+    }
+
+    #[test]
+    fn expand_vars_substitutes_known_names() {
+        assert_eq!(expand_vars("/vms/${VM}.remmina", Some("win11")), "/vms/win11.remmina");
+        assert_eq!(expand_vars("${VM}", None), "${VM}");
+        assert_eq!(expand_vars("${QUICK_CLI_TEST_UNSET_VARIABLE}/x", None), "${QUICK_CLI_TEST_UNSET_VARIABLE}/x");
+        assert_eq!(expand_vars("a ${unclosed", Some("vm")), "a ${unclosed");
+        let path = std::env::var("PATH").unwrap();
+        assert_eq!(expand_vars("${PATH}:${VM}", Some("vm")), format!("{}:vm", path));
+        if let Some(home) = dirs::home_dir() {
+            assert_eq!(expand_vars("${HOME}/vms", None), format!("{}/vms", home.display()));
+        }
+    }
+
+    #[test]
+    fn is_port_open_on_ipv6_loopback() {
+        // Hosts without IPv6 cannot bind ::1; there is nothing to check there.
+        let Ok(listener) = std::net::TcpListener::bind("[::1]:0") else { return };
+        let port = listener.local_addr().unwrap().port();
+        let timeout = Duration::from_secs(1);
+        assert!(is_port_open("::1", port, timeout));
+        assert!(is_port_open("[::1]", port, timeout));
+        drop(listener);
+        assert!(!is_port_open("::1", port, timeout));
+    }
+
+    #[test]
+    fn url_host_brackets_only_bare_ipv6() {
+        assert_eq!(url_host("127.0.0.1"), "127.0.0.1");
+        assert_eq!(url_host("vmbox.lan"), "vmbox.lan");
+        assert_eq!(url_host("::1"), "[::1]");
+        assert_eq!(url_host("[fe80::1]"), "[fe80::1]");
+        assert_eq!(format_addr("fd00::5", 3389), "[fd00::5]:3389");
+        assert_eq!(format_addr("[fd00::5]", 3389), "[fd00::5]:3389");
+        assert_eq!(format_addr("10.0.0.2", 5900), "10.0.0.2:5900");
+    }
+
+    #[test]
+    fn parse_vm_config_picks_protocol() {
+        let config = Config::default();
+        let windows = TempVm::new("pvc-win", "guest_os=\"windows\"\nport_forwards=(\"5901:5900\" \"3390:3389\")\n");
+        assert!(matches!(parse_vm_config(&windows.conf, &config), RemoteProtocol::Rdp(3390)));
+        let linux = TempVm::new("pvc-lnx", "guest_os=\"linux\"\nport_forwards=(\"5901:5900\" \"3390:3389\")\n");
+        assert!(matches!(parse_vm_config(&linux.conf, &config), RemoteProtocol::Vnc(5901)));
+        let mac = TempVm::new("pvc-mac", "guest_os=\"macos\"\nport_forwards=(\"3390:3389\")\n");
+        let spice = config.default_spice_port;
+        assert!(matches!(parse_vm_config(&mac.conf, &config), RemoteProtocol::Spice(port) if port == spice));
+        let sunshine = TempVm::new("pvc-sun", &format!("port_forwards=(\"3390:3389\" \"48000:{}\")\n", SUNSHINE_PORT));
+        assert!(matches!(parse_vm_config(&sunshine.conf, &config), RemoteProtocol::Moonlight(48000)));
+    }
+
+    #[test]
+    fn guest_os_from_conf() {
+        let cases = [
+            ("guest_os=\"windows\"\n", GuestOs::Windows),
+            ("guest_os=\"windows-server\"\n", GuestOs::Windows),
+            ("guest_os=\"macos\"\n", GuestOs::MacOs),
+            ("guest_os=\"linux_old\"\n", GuestOs::Linux),
+            ("ram=\"4G\"\n", GuestOs::Linux),
+            ("guest_os=\"freebsd\"\n", GuestOs::Bsd),
+            ("guest_os=\"haiku\"\n", GuestOs::Other),
+        ];
+        for (contents, expected) in cases {
+            let vm = TempVm::new("os", contents);
+            assert!(guest_os(&vm.conf) == expected, "{}", contents.trim());
+        }
+    }
+
+    #[test]
+    fn rdp_gateway_args_per_freerdp_version() {
+        let mut config = Config::default();
+        let gateway = RdpGateway { host: "gw.example.com".into(), user: Some("alice".into()), domain: None };
+        config.rdp_gateways.insert("win11".into(), gateway);
+        assert_eq!(rdp_gateway_args("/usr/bin/xfreerdp3", "Win11", &config), ["/gateway:g:gw.example.com,u:alice"]);
+        assert_eq!(rdp_gateway_args("xfreerdp", "win11", &config), ["/g:gw.example.com", "/gu:alice"]);
+        let gateway = RdpGateway { host: "gw".into(), user: Some("alice".into()), domain: Some("CORP,EU".into()) };
+        config.rdp_gateways.insert("win11".into(), gateway);
+        assert_eq!(rdp_gateway_args("sdl-freerdp", "win11", &config), ["/gateway:g:gw,u:alice"]);
+        assert!(rdp_gateway_args("remmina", "win11", &config).is_empty());
+        assert!(rdp_gateway_args("xfreerdp", "other", &config).is_empty());
+    }
+
+    #[test]
+    fn default_chains_follow_remote_app() {
+        let mut config = Config::default();
+        config.apply_file(ConfigFile { remote_app: Some("xfreerdp".into()), ..ConfigFile::default() });
+        assert_eq!(config.rdp_clients, ["xfreerdp"]);
+        assert_eq!(config.vnc_clients, ["vncviewer"]);
+        assert_eq!(config.spice_clients, ["spicy", "remote-viewer"]);
+        config.apply_file(ConfigFile { remote_app: Some("remote-viewer".into()), ..ConfigFile::default() });
+        assert_eq!(config.rdp_clients, ["xfreerdp"]);
+        assert_eq!(config.vnc_clients, ["vncviewer"]);
+        assert_eq!(config.spice_clients, ["remote-viewer", "spicy"]);
+        config.apply_file(ConfigFile { remote_app: Some("/opt/bin/remmina".into()), ..ConfigFile::default() });
+        assert_eq!(config.vnc_clients, ["/opt/bin/remmina", "vncviewer"]);
+        assert_eq!(default_clients("mstsc.exe", "windows", "rdp"), ["mstsc.exe"]);
+        assert_eq!(default_clients("mstsc.exe", "windows", "vnc"), ["tvnviewer", "vncviewer"]);
+        assert_eq!(default_clients("mstsc.exe", "windows", "spice"), ["remote-viewer"]);
+    }
+
+    #[test]
+    fn describe_command_redacts_secrets() {
+        let mut cmd = Command::new("xfreerdp");
+        cmd.env("RDGW_PASSWORD", "hunter2").env("LANG", "C").args(["/v:host", "/p:hunter2", "/u:alice"]);
+        assert_eq!(describe_command(&cmd), "LANG=C RDGW_PASSWORD=*** xfreerdp /v:host /p:*** /u:alice");
+        let mut cmd = Command::new("mosquitto_sub");
+        cmd.args(["-u", "ha", "-P", "hunter2", "-t", "a b"]);
+        assert_eq!(describe_command(&cmd), "mosquitto_sub -u ha -P *** -t 'a b'");
+    }
+
+    #[test]
+    fn ipv6_hosts_entry_in_viewer_urls() {
+        let mut config = Config::default();
+        config.apply_file(toml::from_str("[hosts]\nV6vm = \"[fd00::5]\"\n").unwrap());
+        let vm = TempVm::new("v6vm", "guest_os=\"windows\"\nport_forwards=(\"3390:3389\")\n");
+        let host = vm_host(&vm.conf, &config);
+        assert_eq!(host, "fd00::5");
+        assert_eq!(parse_vm_config(&vm.conf, &config).url(host), "rdp://[fd00::5]:3390");
+        let spice = spice_protocol(&vm.conf, &config);
+        assert_eq!(spice.url(host), format!("spice://[fd00::5]:{}", config.default_spice_port));
+        let args = viewer_args("xfreerdp", &RemoteProtocol::Rdp(3390), "v6vm", host);
+        assert_eq!(args, ["/v:[fd00::5]:3390", "/f", "/dynamic-resolution"]);
+    }
+
+    #[test]
+    fn wsl_path_translation() {
+        assert_eq!(wsl_path(r"C:\VMs\win.conf").as_deref(), Some("/mnt/c/VMs/win.conf"));
+        assert_eq!(wsl_path(r"\\wsl$\Ubuntu\home\me\win.conf").as_deref(), Some("/home/me/win.conf"));
+        assert_eq!(wsl_path(r"\\wsl.localhost\Debian\vms\lnx.conf").as_deref(), Some("/vms/lnx.conf"));
+        assert_eq!(wsl_path(r"\\wsl$\Ubuntu").as_deref(), None);
+        assert_eq!(wsl_path("/home/me/win.conf"), None);
+        assert_eq!(wsl_path(r"VMs\win.conf"), None);
+    }
+
+    #[test]
+    fn port_forwards_skip_malformed_entries() {
+        let vm = TempVm::new("pf", "guest_os=\"linux\"\nport_forwards=(\"2222:22\" \"bad\" \"1:2:3\" \"5930:5930\")\n");
+        assert_eq!(port_forwards(&vm.conf), vec![(2222, 22), (5930, 5930)]);
+        assert_eq!(forwarded_port(&vm.conf, 22), Some(2222));
+        assert_eq!(forwarded_port(&vm.conf, 3389), None);
+    }
+
+    #[test]
+    fn legacy_override_and_host_lines() {
+        let contents = "override=Win11, /home/me/win11.remmina\nhost=Lab, fd00::5\noverride=no-path\nhost=a, b, c\n";
+        let (file, unmapped) = parse_legacy_config(contents);
+        assert_eq!(file.overrides.get("win11").map(String::as_str), Some("/home/me/win11.remmina"));
+        assert_eq!(file.hosts.get("lab").map(String::as_str), Some("fd00::5"));
+        assert_eq!(unmapped, ["3: override=no-path", "4: host=a, b, c"]);
+    }
+
+    #[test]
+    fn legacy_quickemu_dirs_accumulate() {
+        let (file, unmapped) = parse_legacy_config("quickemu_dir=/vms\nquickemu_dir = ~/more-vms\n");
+        assert_eq!(file.quickemu_dirs, Some(vec![PathBuf::from("/vms"), PathBuf::from("~/more-vms")]));
+        assert!(unmapped.is_empty());
+    }
+
+    #[test]
+    fn legacy_unmapped_lines_keep_their_numbers() {
+        let contents = "# comment\n\nremote_app=remmina\nnot a setting\nscan_depth=deep\nfavourite_color=blue\n";
+        let (file, unmapped) = parse_legacy_config(contents);
+        assert_eq!(file.remote_app.as_deref(), Some("remmina"));
+        assert_eq!(file.scan_depth, None);
+        assert_eq!(unmapped, ["4: not a setting", "5: scan_depth=deep", "6: favourite_color=blue"]);
+    }
+
+    #[test]
+    fn migrate_config_keeps_an_existing_file() {
+        let dir = TempDir::new();
+        let legacy = dir.0.join("quick-cli.conf");
+        fs::write(&legacy, "quickemu_dir=/vms\noverride=win11, /p/win11.remmina\nbogus\n").unwrap();
+        let target = dir.0.join("config.toml");
+        fs::write(&target, "remote_app = \"mine\"\n").unwrap();
+        assert!(migrate_config(Some(&legacy), Some(&target), false, false).is_err());
+        let (path, toml, unmapped) = migrate_config(Some(&legacy), Some(&target), false, true).unwrap();
+        assert_eq!(path, target);
+        assert!(toml.contains("/p/win11.remmina"));
+        assert_eq!(unmapped, ["3: bogus"]);
+        assert_eq!(fs::read_to_string(&target).unwrap(), "remote_app = \"mine\"\n");
+
+        migrate_config(Some(&legacy), Some(&target), true, false).unwrap();
+        let config = load_config(Some(&target)).unwrap();
+        assert_eq!(config.quickemu_dirs, [PathBuf::from("/vms")]);
+        assert_eq!(config.remmina_overrides.get("win11").map(String::as_str), Some("/p/win11.remmina"));
+    }
+
+    #[test]
+    fn migrate_config_creates_the_target_directory() {
+        let dir = TempDir::new();
+        let legacy = dir.0.join("quick-cli.conf");
+        fs::write(&legacy, "host=lab, [::1]\n").unwrap();
+        let target = dir.0.join("quick-cli").join("config.toml");
+        migrate_config(Some(&legacy), Some(&target), false, false).unwrap();
+        let config = load_config(Some(&target)).unwrap();
+        assert_eq!(config.vm_hosts.get("lab").map(String::as_str), Some("::1"));
+    }
+}
@@ -0,0 +1,111 @@
+///////////////////////////////////////////////////////////////////////////////
+// Daemon Log Sink
+///////////////////////////////////////////////////////////////////////////////
+//
+// Where `quick-cli daemon` writes its log, chosen by `log_sink`:
+//     stderr    one line per entry (default; the journal captures it as plain
+//               text when the daemon runs as a systemd service)
+//     journald  journald's native protocol, so VM events carry fields that
+//               journalctl can filter on, e.g.
+//               `journalctl -t quick-cli QUICK_CLI_EVENT=vm_stopped QUICK_CLI_VM=win11`
+//     syslog    RFC 3164 messages to the local syslog socket (/dev/log, or
+//               /var/run/syslog on macOS), facility daemon
+// Every field of an event's JSON form (events.rs) becomes QUICK_CLI_<FIELD>.
+
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+
+use crate::{events::Event, Config};
+
+const IDENTIFIER: &str = "quick-cli";
+
+#[cfg(unix)]
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+#[cfg(target_os = "macos")]
+const SYSLOG_SOCKET: &str = "/var/run/syslog";
+#[cfg(all(unix, not(target_os = "macos")))]
+const SYSLOG_SOCKET: &str = "/dev/log";
+
+// Syslog severities, also journald's PRIORITY field.
+const NOTICE: u8 = 5;
+const INFO: u8 = 6;
+
+pub enum LogSink {
+    Stderr,
+    #[cfg(unix)]
+    Journald(UnixDatagram),
+    #[cfg(unix)]
+    Syslog(UnixDatagram),
+}
+
+impl LogSink {
+    /// The configured sink. Falls back to stderr, with the reason, when its socket
+    /// cannot be reached.
+    pub fn open(config: &Config) -> (LogSink, Option<String>) {
+        let socket = match config.log_sink.as_str() {
+            "stderr" => return (LogSink::Stderr, None),
+            #[cfg(unix)]
+            sink @ ("journald" | "syslog") => {
+                let path = if sink == "journald" { JOURNAL_SOCKET } else { SYSLOG_SOCKET };
+                let socket = UnixDatagram::unbound().and_then(|s| s.connect(path).map(|_| s));
+                socket.map_err(|e| format!("{}: {}", path, e))
+            }
+            other => Err(format!("unsupported log_sink {:?}", other)),
+        };
+        match socket {
+            #[cfg(unix)]
+            Ok(socket) if config.log_sink == "journald" => (LogSink::Journald(socket), None),
+            #[cfg(unix)]
+            Ok(socket) => (LogSink::Syslog(socket), None),
+            Err(e) => (LogSink::Stderr, Some(format!("Logging to stderr instead of {}: {}", config.log_sink, e))),
+        }
+    }
+
+    /// Write a plain log line.
+    pub fn line(&self, text: &str) {
+        self.send(text, INFO, &[]);
+    }
+
+    /// Write a VM or job event with its fields.
+    pub fn event(&self, event: &Event) {
+        let fields: Vec<(String, String)> = match serde_json::to_value(event) {
+            Ok(serde_json::Value::Object(map)) => map
+                .into_iter()
+                .map(|(key, value)| {
+                    let value = value.as_str().map(str::to_string).unwrap_or_else(|| value.to_string());
+                    (format!("QUICK_CLI_{}", key.to_uppercase()), value)
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        self.send(&event.describe(), if event.is_change() { NOTICE } else { INFO }, &fields);
+    }
+
+    #[cfg_attr(not(unix), allow(unused_variables))]
+    fn send(&self, text: &str, priority: u8, fields: &[(String, String)]) {
+        // Both socket formats are line based; a stray newline would split the entry.
+        let text = text.replace('\n', " ");
+        match self {
+            LogSink::Stderr => eprintln!("{}", text),
+            #[cfg(unix)]
+            LogSink::Journald(socket) => {
+                let mut entry = format!("MESSAGE={}\nPRIORITY={}\nSYSLOG_IDENTIFIER={}\n", text, priority, IDENTIFIER);
+                for (key, value) in fields {
+                    entry.push_str(&format!("{}={}\n", key, value.replace('\n', " ")));
+                }
+                if socket.send(entry.as_bytes()).is_err() {
+                    eprintln!("{}", text);
+                }
+            }
+            #[cfg(unix)]
+            LogSink::Syslog(socket) => {
+                const DAEMON_FACILITY: u8 = 3;
+                let pri = DAEMON_FACILITY * 8 + priority;
+                let message = format!("<{}>{}[{}]: {}", pri, IDENTIFIER, std::process::id(), text);
+                if socket.send(message.as_bytes()).is_err() {
+                    eprintln!("{}", text);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,200 @@
+///////////////////////////////////////////////////////////////////////////////
+// Keyboard Macros
+///////////////////////////////////////////////////////////////////////////////
+//
+// Like vim's `q` registers, but on `M` since `q` quits: `M a` records every
+// following key into register `a` until the next `M`, and `@a` replays them
+// as if typed (`@@` repeats the last replayed register). Registers are kept
+// in $XDG_STATE_HOME/quick-cli/macros as `a=/deb<Enter>r` lines, in the same
+// `<Enter>`/`<C-p>` notation vim uses, so they can also be written by hand.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fs,
+    path::PathBuf,
+};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use quick_cli::state_dir;
+
+// Replayed keys (including nested `@x`) are capped so a macro that replays
+// itself cannot loop forever.
+const MAX_QUEUED: usize = 10_000;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Pending {
+    Record, // `M` pressed, waiting for the register
+    Replay, // `@` pressed, waiting for the register
+}
+
+#[derive(Default)]
+pub struct Macros {
+    registers: BTreeMap<char, Vec<KeyEvent>>,
+    pub recording: Option<(char, Vec<KeyEvent>)>,
+    pub pending: Option<Pending>,
+    queue: VecDeque<KeyEvent>, // Keys of the macro being replayed, fed to the UI one per frame
+    last: Option<char>,        // Register replayed last, for `@@`
+}
+
+fn macros_path() -> PathBuf {
+    state_dir().join("macros")
+}
+
+impl Macros {
+    pub fn load() -> Self {
+        let mut macros = Self::default();
+        let contents = fs::read_to_string(macros_path()).unwrap_or_default();
+        for line in contents.lines() {
+            let mut chars = line.chars();
+            if let (Some(register), Some('=')) = (chars.next(), chars.next())
+                && is_register(register)
+            {
+                macros.registers.insert(register, decode(chars.as_str()));
+            }
+        }
+        macros
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let contents: String = self.registers.iter().map(|(r, keys)| format!("{}={}\n", r, encode(keys))).collect();
+        let path = macros_path();
+        fs::create_dir_all(state_dir())
+            .and_then(|_| fs::write(&path, contents))
+            .map_err(|e| format!("cannot write {}: {}", path.display(), e))
+    }
+
+    /// Next key of the macro being replayed.
+    pub fn next_key(&mut self) -> Option<KeyEvent> {
+        self.queue.pop_front()
+    }
+
+    /// Remember a key typed while recording.
+    pub fn record(&mut self, key: KeyEvent) {
+        if let Some((_, keys)) = &mut self.recording {
+            keys.push(key);
+        }
+    }
+
+    pub fn start_recording(&mut self, register: char) {
+        self.recording = Some((register, Vec::new()));
+    }
+
+    /// Store the recording in its register. Returns the register and key count.
+    pub fn stop_recording(&mut self) -> Option<(char, usize, Result<(), String>)> {
+        let (register, mut keys) = self.recording.take()?;
+        if keys.last().is_some_and(|k| k.code == KeyCode::Char('M')) {
+            keys.pop(); // The `M` that stopped the recording
+        }
+        let count = keys.len();
+        self.registers.insert(register, keys);
+        Some((register, count, self.save()))
+    }
+
+    /// Queue a register's keys ahead of any remaining ones, so nested macros run in place.
+    pub fn replay(&mut self, register: char) -> Result<usize, String> {
+        let register = if register == '@' { self.last.ok_or("no macro replayed yet")? } else { register };
+        let keys = self.registers.get(&register).ok_or_else(|| format!("register '{}' is empty", register))?;
+        if self.queue.len() + keys.len() > MAX_QUEUED {
+            self.queue.clear();
+            return Err(format!("macro '{}' replays too many keys; stopped", register));
+        }
+        for key in keys.iter().rev() {
+            self.queue.push_front(*key);
+        }
+        self.last = Some(register);
+        Ok(keys.len())
+    }
+}
+
+pub fn is_register(c: char) -> bool {
+    c.is_ascii_alphanumeric()
+}
+
+fn key_name(code: KeyCode) -> Option<String> {
+    let name = match code {
+        KeyCode::Enter => "Enter",
+        KeyCode::Esc => "Esc",
+        KeyCode::Tab => "Tab",
+        KeyCode::BackTab => "S-Tab",
+        KeyCode::Backspace => "BS",
+        KeyCode::Delete => "Del",
+        KeyCode::Up => "Up",
+        KeyCode::Down => "Down",
+        KeyCode::Left => "Left",
+        KeyCode::Right => "Right",
+        KeyCode::Home => "Home",
+        KeyCode::End => "End",
+        KeyCode::PageUp => "PageUp",
+        KeyCode::PageDown => "PageDown",
+        KeyCode::Char(' ') => "Space",
+        KeyCode::Char('<') => "lt",
+        KeyCode::F(n) => return Some(format!("F{}", n)),
+        _ => return None,
+    };
+    Some(name.to_string())
+}
+
+fn named_key(name: &str) -> Option<KeyCode> {
+    let code = match name.to_ascii_lowercase().as_str() {
+        "enter" | "cr" => KeyCode::Enter,
+        "esc" => KeyCode::Esc,
+        "tab" => KeyCode::Tab,
+        "s-tab" => KeyCode::BackTab,
+        "bs" => KeyCode::Backspace,
+        "del" => KeyCode::Delete,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        "space" => KeyCode::Char(' '),
+        "lt" => KeyCode::Char('<'),
+        f if f.starts_with('f') && f.len() > 1 => KeyCode::F(f[1..].parse().ok()?),
+        _ => return None,
+    };
+    Some(code)
+}
+
+/// Write keys in vim notation: plain characters as-is, others as `<Name>` or `<C-x>`.
+pub fn encode(keys: &[KeyEvent]) -> String {
+    let mut out = String::new();
+    for key in keys {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match (key_name(key.code), key.code) {
+            (_, KeyCode::Char(c)) if ctrl => out.push_str(&format!("<C-{}>", c)),
+            (Some(name), _) => out.push_str(&format!("<{}>", name)),
+            (None, KeyCode::Char(c)) => out.push(c),
+            _ => {} // Keys the UI ignores anyway
+        }
+    }
+    out
+}
+
+pub fn decode(text: &str) -> Vec<KeyEvent> {
+    let mut keys = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if c == '<'
+            && let Some(end) = rest.find('>')
+        {
+            let name = &rest[1..end];
+            let key = match name.strip_prefix("C-").or_else(|| name.strip_prefix("c-")) {
+                Some(ch) if ch.chars().count() == 1 => {
+                    Some(KeyEvent::new(KeyCode::Char(ch.chars().next().unwrap()), KeyModifiers::CONTROL))
+                }
+                _ => named_key(name).map(|code| KeyEvent::new(code, KeyModifiers::NONE)),
+            };
+            if let Some(key) = key {
+                keys.push(key);
+                rest = &rest[end + 1..];
+                continue;
+            }
+        }
+        keys.push(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE));
+        rest = &rest[c.len_utf8()..];
+    }
+    keys
+}
@@ -1,795 +1,2267 @@
 use std::{
-
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     error::Error,
     fs,
     io,
-    net::{SocketAddr, TcpStream},
     path::{Path, PathBuf},
-    process::{Command, Stdio},
-    sync::{Arc, Mutex},
+    process::Command,
+    sync::{atomic::Ordering, Arc, Mutex},
     thread,
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use tui::{
+use ratatui::{
     backend::CrosstermBackend,
-    layout::{Constraint, Direction, Layout},
+    layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
-    text::{Span, Spans},
-    widgets::{Block, Borders, List, ListItem, Paragraph},
-    Terminal,
+    text::{Line, Span},
+    widgets::{
+        Block, Borders, Clear, List, ListItem, ListState, Paragraph, Scrollbar, ScrollbarOrientation,
+        ScrollbarState, Wrap,
+    },
+    Frame, Terminal,
 };
 use crossterm::{
-    event::{self, Event, KeyCode},
+    cursor::MoveTo,
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 
-#[cfg(unix)]
-use std::os::unix::fs::MetadataExt;
+mod chords;
+mod cli;
+mod crash;
+mod command;
+mod confview;
+mod macros;
+mod palette;
+
+use chords::ChordAction;
+use command::{Command as CliCommand, Target};
+use confview::ConfigView;
+use macros::{Macros, Pending};
+use palette::{Palette, PaletteAction};
+use quick_cli::{
+    backends::{self, ExternalVm},
+    backup, cloudinit, confdiff, console,
+    daemon::{self, DaemonClient},
+    demo,
+    disk,
+    events::{self, VmState, Watcher},
+    filebrowser::{FileBrowser, Pane},
+    firewall,
+    host::{self, HostResources},
+    iso::{self, IsoStatuses},
+    jobs::{JobState, Jobs},
+    launchers, manifest,
+    mdns::Advertiser,
+    metrics::{self, DiskCounters, SharedMetrics},
+    notify::LowDiskAlarm,
+    procs,
+    qmp::{self, QmpConsole},
+    remmina,
+    screenshots::{self, ImageProtocol},
+    snapshot, sync,
+    term::{paint, Tone},
+    watch, webhook,
+    adopt_vm, connect_vm, describe_command, diagnose_vm, force_spice_connect, guest_os, is_hidden, is_vm_running,
+    list_vms, list_vms_in_dir, load_config, migrate_config, open_in_file_manager, orphaned_vms, parse_vm_config,
+    read_clipboard, read_vm_notes, remove_stale_runtime_files, spawn_detached, split_list, start_vm, state_dir,
+    stop_stage, stop_vm, vm_dir, vm_host, vm_notes_file, Config, GuestOs, StartError,
+};
 
 ///////////////////////////////////////////////////////////////////////////////
-// Configuration and VM Listing
+// Persistent UI State
 ///////////////////////////////////////////////////////////////////////////////
 
-struct Config {
-    remote_app: String,      // e.g. "remmina" (or native client on Windows/macOS)
-    quickemu_dir: PathBuf,   // Directory with VM config files
-    default_spice_port: u16, // Default SPICE port if not specified in VM config
-    os_type: String,         // "windows", "macos", or "linux"
-    // Override mapping: key = VM config file stem (lowercase), value = path to Remmina profile.
-    remmina_overrides: HashMap<String, String>,
+/// UI state that survives restarts, stored in $XDG_STATE_HOME/quick-cli/state as key=value lines.
+/// Unlike the config file this is rewritten by the application.
+struct UiState {
+    list_percent: u16, // Height of the VM list pane, as a percentage of the screen
+    show_logs: bool,
+    pinned: Vec<String>, // VM stems pinned to the top of the list, in pin order
+    sort_by_recent: bool, // Most-recently-used first instead of by name
+    last_used: HashMap<String, u64>, // VM stem -> last start/connect (Unix seconds)
+    layout: LayoutPreset,
+}
+
+/// Arrangement of the panes between the host line and the footer.
+#[derive(Clone, Copy, PartialEq)]
+enum LayoutPreset {
+    ListLogs,    // VM list (with a short details pane) over the logs
+    ListDetails, // VM list over a full details pane
+    LogsOnly,
+    Dashboard, // VMs as a grid of cards over the logs
+}
+
+/// `Tab` cycles through the presets in this order.
+const LAYOUT_PRESETS: [LayoutPreset; 4] =
+    [LayoutPreset::ListLogs, LayoutPreset::ListDetails, LayoutPreset::LogsOnly, LayoutPreset::Dashboard];
+
+impl LayoutPreset {
+    /// Name used by the `layout` config key and the state file.
+    fn name(self) -> &'static str {
+        match self {
+            LayoutPreset::ListLogs => "list+logs",
+            LayoutPreset::ListDetails => "list+details",
+            LayoutPreset::LogsOnly => "logs-only",
+            LayoutPreset::Dashboard => "dashboard",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        LAYOUT_PRESETS.into_iter().find(|preset| preset.name() == name.trim())
+    }
 }
 
-impl Default for Config {
+impl Default for UiState {
     fn default() -> Self {
-        let home = dirs::home_dir().expect("Unable to get home directory");
-        let os_type = if cfg!(target_os = "windows") {
-            "windows".to_string()
-        } else if cfg!(target_os = "macos") {
-            "macos".to_string()
-        } else {
-            "linux".to_string()
-        };
-        let remote_app = match os_type.as_str() {
-            "windows" => "mstsc.exe".to_string(),
-            "macos" => "open".to_string(),
-            _ => "remmina".to_string(),
-        };
         Self {
-            remote_app,
-            quickemu_dir: home.join(".quickemu"),
-            default_spice_port: 5930,
-            os_type,
-            remmina_overrides: HashMap::new(),
+            list_percent: 60,
+            show_logs: true,
+            pinned: Vec::new(),
+            sort_by_recent: false,
+            last_used: HashMap::new(),
+            layout: LayoutPreset::ListLogs,
         }
     }
 }
 
-/// Loads configuration from ~/.quick-cli.conf.
-/// Lines starting with "override=" are interpreted as:
-///     override=vm_stem, /path/to/remmina_profile.remmina
-fn load_config() -> Config {
-    let home = dirs::home_dir().expect("Unable to get home directory");
-    let config_path = home.join(".quick-cli.conf");
-    let os_type = if cfg!(target_os = "windows") {
-        "windows".to_string()
-    } else if cfg!(target_os = "macos") {
-        "macos".to_string()
-    } else {
-        "linux".to_string()
-    };
-    let default_remote_app = match os_type.as_str() {
-        "windows" => "mstsc.exe".to_string(),
-        "macos" => "open".to_string(),
-        _ => "remmina".to_string(),
-    };
-    let mut config = Config {
-        remote_app: default_remote_app.clone(),
-        quickemu_dir: home.join(".quickemu"),
-        default_spice_port: 5930,
-        os_type: os_type.clone(),
-        remmina_overrides: HashMap::new(),
-    };
-    if !config_path.exists() {
-        let default_config = format!(
-            "remote_app={}\nquickemu_dir={}\ndefault_spice_port=5930\nos_type={}\n",
-            default_remote_app,
-            home.join(".quickemu").to_string_lossy(),
-            os_type
-        );
-        let _ = fs::write(&config_path, default_config);
-        return config;
-    }
-    let contents = fs::read_to_string(&config_path).unwrap_or_default();
+const MIN_LIST_PERCENT: u16 = 20;
+const MAX_LIST_PERCENT: u16 = 80;
+/// Samples shown in the details pane sparklines: three minutes of history.
+const SPARKLINE_WIDTH: usize = 36;
+/// Minimum size of a dashboard card, borders included.
+const CARD_WIDTH: u16 = 30;
+const CARD_HEIGHT: u16 = 6;
+/// Log lines kept for the logs pane; older ones are dropped each tick.
+const MAX_LOG_LINES: usize = 5000;
+
+fn state_path() -> PathBuf {
+    state_dir().join("state")
+}
+
+fn load_state() -> UiState {
+    let mut state = UiState::default();
+    let path = state_path();
+    // Carry over state written by versions that kept it in ~/.quick-cli.state.
+    if !path.exists()
+        && let Some(legacy) = dirs::home_dir().map(|home| home.join(".quick-cli.state"))
+        && legacy.exists()
+    {
+        let _ = fs::create_dir_all(state_dir());
+        let _ = fs::rename(legacy, &path);
+    }
+    let contents = fs::read_to_string(path).unwrap_or_default();
     for line in contents.lines() {
         if let Some((key, value)) = line.split_once('=') {
             match key.trim() {
-                "remote_app" => config.remote_app = value.trim().to_string(),
-                "quickemu_dir" => config.quickemu_dir = PathBuf::from(value.trim()),
-                "default_spice_port" => {
+                "list_percent" => {
                     if let Ok(p) = value.trim().parse::<u16>() {
-                        config.default_spice_port = p;
+                        state.list_percent = p.clamp(MIN_LIST_PERCENT, MAX_LIST_PERCENT);
+                    }
+                }
+                "show_logs" => state.show_logs = value.trim() != "false",
+                "pinned" => state.pinned = split_list(value),
+                "sort" => state.sort_by_recent = value.trim() == "recent",
+                "layout" => state.layout = LayoutPreset::parse(value).unwrap_or(LayoutPreset::ListLogs),
+                "last_used" => {
+                    // Format: last_used=vm_stem:unix_seconds, other_vm:unix_seconds
+                    for item in split_list(value) {
+                        if let Some((name, secs)) = item.rsplit_once(':')
+                            && let Ok(secs) = secs.trim().parse::<u64>()
+                        {
+                            state.last_used.insert(name.trim().to_string(), secs);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    state
+}
+
+fn save_state(state: &UiState) {
+    let mut last_used: Vec<String> = state.last_used.iter().map(|(k, v)| format!("{}:{}", k, v)).collect();
+    last_used.sort();
+    let contents = format!(
+        "list_percent={}\nshow_logs={}\npinned={}\nsort={}\nlayout={}\nlast_used={}\n",
+        state.list_percent,
+        state.show_logs,
+        state.pinned.join(", "),
+        if state.sort_by_recent { "recent" } else { "name" },
+        state.layout.name(),
+        last_used.join(", ")
+    );
+    let _ = fs::create_dir_all(state_dir());
+    let _ = fs::write(state_path(), contents);
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// App UI
+///////////////////////////////////////////////////////////////////////////////
+
+/// The `D` view: the selected VM's config diffed against its last snapshot.
+struct DiffView {
+    vm_name: String,
+    lines: Option<Vec<confdiff::DiffLine>>, // None when no snapshot exists yet
+    scroll: u16,
+}
+
+/// The `I` view: every ISO referenced by a listed VM.
+struct IsoView {
+    entries: Vec<(String, PathBuf)>, // (VM stem, ISO path)
+    selected: usize,
+}
+
+/// `P` process inspector: qemu and spawned processes, refreshed with `r`.
+struct ProcessView {
+    processes: Vec<procs::ProcessInfo>,
+    selected: usize,
+}
+
+/// `G` screenshot gallery of one VM, newest first.
+struct Gallery {
+    vm_conf: PathBuf,
+    shots: Vec<PathBuf>,
+    selected: usize,
+}
+
+impl Gallery {
+    fn selected_shot(&self) -> Option<&PathBuf> {
+        self.shots.get(self.selected)
+    }
+}
+
+/// `B` backup browser of one VM, newest first.
+struct RestoreView {
+    vm_conf: PathBuf,
+    backups: Vec<backup::Backup>,
+    selected: usize,
+    step: RestoreStep,
+}
+
+enum RestoreStep {
+    Browse,
+    ConfirmInPlace,    // `r`: waiting for y/n before overwriting the disk
+    CloneName(String), // `n`: typing the new VM's name
+    CloneSeed(String), // Linux clones: waiting for y/n on a cloud-init seed with the SSH keys
+}
+
+/// Pending start of a VM that would oversubscribe host memory or go over a group quota,
+/// waiting for y/n.
+struct ConfirmStart {
+    vm_conf: PathBuf,
+    message: String,
+    connect: bool,
+}
+
+/// Host ports waiting for confirmation before `:firewall` opens them.
+struct ConfirmFirewall {
+    vm_conf: PathBuf,
+    ports: Vec<u16>,
+    tool: firewall::Tool,
+}
+
+/// qemu processes of known VMs found without a pid file at startup, see `orphaned_vms`.
+struct OrphanView {
+    orphans: Vec<(PathBuf, u32)>,
+    selected: usize,
+}
+
+/// Pending start of a VM with an encrypted disk, waiting for its passphrase.
+struct PassphrasePrompt {
+    vm_conf: PathBuf,
+    input: String,
+    connect: bool,   // Connect once started (Enter rather than `r`)
+    confirmed: bool, // Quota warnings were already confirmed
+}
+
+/// An action's outcome (a summary, or why it failed) and when it was recorded. Shared
+/// with the threads that connect in the background.
+type LastResult = Arc<Mutex<Option<(Result<String, String>, Instant)>>>;
+
+fn record_result(last_result: &LastResult, result: Result<String, String>) {
+    *last_result.lock().unwrap() = Some((result, Instant::now()));
+}
+
+struct App {
+    vm_list: Vec<PathBuf>,
+    external: Arc<Mutex<Vec<ExternalVm>>>, // VMs of other backends, listed after vm_list
+    metrics: SharedMetrics,                 // Sampled in the background, see metrics.rs
+    list_state: ListState,
+    logs: Arc<Mutex<Vec<String>>>,
+    spinner_index: usize,
+    // Set when a `quick-cli daemon` is listening; start/stop and status go through it.
+    daemon: Option<DaemonClient>,
+    remote_dir: Option<PathBuf>, // Mirror of a remote daemon's VMs (--remote), refreshed with the status
+    status: HashMap<String, bool>, // VM stem -> running, refreshed every status_poll_interval
+    last_status_poll: Option<Instant>,
+    watcher: Watcher, // VM transitions for webhooks and mDNS when no daemon handles them
+    advertiser: Advertiser,
+    low_disk: LowDiskAlarm,
+    host: HostResources, // Refreshed with the status
+    last_sync: Instant, // Last config sync commit check, see sync::SYNC_INTERVAL
+    last_result: LastResult, // Outcome of the last start, stop or connect, for the status bar
+    start_queue: VecDeque<PathBuf>,         // VMs waiting to be launched by `:start all`
+    booting: Vec<(PathBuf, Instant)>,       // VMs launched from the queue that are still booting
+    next_start: Instant,                    // Earliest launch of the next queued VM (start_stagger)
+    pending_stops: Vec<PathBuf>,            // VMs stopping in the background, reported in last_result when done
+    ui_state: UiState,
+    dashboard_columns: usize, // Cards per dashboard row as last drawn
+    command_line: Option<String>,         // Some while the `:` command line is open
+    palette: Option<Palette>,             // Some while the Ctrl+P palette is open
+    qmp_console: Option<QmpConsole>,      // Some while the `Q` QMP console is open
+    key_menu: Option<usize>,              // Selected entry of qmp::KEY_COMBOS while the `K` menu is open
+    passphrase: Option<PassphrasePrompt>, // Some while asking for an encrypted disk's passphrase
+    confirm_start: Option<ConfirmStart>,  // Some while confirming a start over RAM or a group quota
+    confirm_firewall: Option<ConfirmFirewall>, // Some while confirming `:firewall`
+    orphan_view: Option<OrphanView>,      // Some while offering to adopt or kill orphaned VMs
+    iso_view: Option<IsoView>,            // Some while the `I` ISO view is open
+    process_view: Option<ProcessView>,    // Some while the `P` process inspector is open
+    jobs: Jobs,                           // Backups and other tracked background work, see jobs.rs
+    jobs_view: Option<usize>,             // Selected job while the `J` jobs view is open
+    restore_view: Option<RestoreView>,    // Some while the `B` backup browser is open
+    last_backup_check: Option<Instant>,   // Scheduled backups are checked every backup::SCHEDULE_CHECK
+    gallery: Option<Gallery>,             // Some while the `G` screenshot gallery is open
+    diff_view: Option<DiffView>,          // Some while the `D` config diff view is open
+    config_view: Option<ConfigView>,      // Some while the `C` config viewer is open
+    foreground: Option<Vec<String>>,      // Console to run in the terminal, suspending the TUI
+    file_browser: Option<FileBrowser>,    // Some while the `F` sftp browser is open
+    iso_statuses: IsoStatuses,            // Checksum results, kept while quick-cli runs
+    workspace: Option<usize>,             // Index into config.quickemu_dirs, or None for all
+    show_hidden: bool,                    // Temporarily include VMs matched by `ignore=`
+    notes: HashMap<PathBuf, String>,      // From {vm}.notes sidecar files
+    guest_os: HashMap<PathBuf, GuestOs>,  // From guest_os= in each .conf, for the list tags
+    filter: String,                       // `/` filter on VM names and notes
+    editing_filter: bool,                 // Whether keys go to the filter
+    macros: Macros,                       // `M` recording and `@` replay, see macros.rs
+    chord: bool,                          // Leader key pressed, waiting for the chord's second key
+    should_quit: bool,
+}
+
+impl App {
+    fn new(config: &Config, daemon: Option<DaemonClient>) -> Self {
+        let mut logs = vec!["Application started.".into()];
+        if daemon.is_some() {
+            logs.push("Connected to quick-cli daemon.".into());
+        }
+        let logs = Arc::new(Mutex::new(logs));
+        // Like the disk check, alerts are raised by the daemon when one runs.
+        let alerts = daemon.is_none();
+        let mut app = Self {
+            vm_list: Vec::new(),
+            external: backends::spawn_poller(config, &logs),
+            metrics: metrics::spawn_poller(config, &logs, alerts),
+            list_state: ListState::default(),
+            logs,
+            spinner_index: 0,
+            daemon,
+            remote_dir: None,
+            status: HashMap::new(),
+            last_status_poll: None,
+            watcher: Watcher::default(),
+            advertiser: Advertiser::default(),
+            low_disk: LowDiskAlarm::default(),
+            host: HostResources::default(),
+            last_sync: Instant::now(),
+            last_result: Arc::new(Mutex::new(None)),
+            start_queue: VecDeque::new(),
+            booting: Vec::new(),
+            next_start: Instant::now(),
+            pending_stops: Vec::new(),
+            ui_state: load_state(),
+            dashboard_columns: 1,
+            command_line: None,
+            palette: None,
+            qmp_console: None,
+            key_menu: None,
+            passphrase: None,
+            confirm_start: None,
+            confirm_firewall: None,
+            orphan_view: None,
+            iso_view: None,
+            process_view: None,
+            jobs: Jobs::default(),
+            jobs_view: None,
+            restore_view: None,
+            last_backup_check: None,
+            gallery: None,
+            diff_view: None,
+            config_view: None,
+            foreground: None,
+            file_browser: None,
+            iso_statuses: Default::default(),
+            workspace: None,
+            show_hidden: false,
+            notes: HashMap::new(),
+            guest_os: HashMap::new(),
+            filter: String::new(),
+            editing_filter: false,
+            macros: Macros::load(),
+            chord: false,
+            should_quit: false,
+        };
+        app.reload_vms(config);
+        app
+    }
+    /// Rebuild the VM list for the current workspace and hidden-VM setting.
+    fn reload_vms(&mut self, config: &Config) {
+        let vms = match self.workspace {
+            Some(i) => list_vms_in_dir(&config.quickemu_dirs[i], config),
+            None => list_vms(config),
+        };
+        self.vm_list = vms.into_iter().filter(|vm| self.show_hidden || !is_hidden(vm, config)).collect();
+        self.reload_notes();
+        self.guest_os = self.vm_list.iter().map(|vm| (vm.clone(), guest_os(vm))).collect();
+        if !self.filter.is_empty() {
+            let query = self.filter.to_lowercase();
+            let notes = &self.notes;
+            self.vm_list.retain(|vm| {
+                vm.file_stem().unwrap().to_string_lossy().to_lowercase().contains(&query)
+                    || notes.get(vm).is_some_and(|n| n.to_lowercase().contains(&query))
+            });
+        }
+        self.sort_vms();
+        self.list_state.select(if self.list_len() == 0 { None } else { Some(0) });
+        self.invalidate_status();
+    }
+    fn reload_notes(&mut self) {
+        self.notes = self.vm_list.iter().filter_map(|vm| Some((vm.clone(), read_vm_notes(vm)?))).collect();
+    }
+    /// Handle a key press while typing the `/` filter; the list narrows as you type.
+    fn filter_key(&mut self, code: KeyCode, config: &Config) {
+        match code {
+            KeyCode::Enter => self.editing_filter = false,
+            KeyCode::Esc => {
+                self.editing_filter = false;
+                self.filter.clear();
+            }
+            KeyCode::Backspace => {
+                self.filter.pop();
+            }
+            KeyCode::Char(c) => self.filter.push(c),
+            _ => return,
+        }
+        self.reload_vms(config);
+    }
+    /// Edit the selected VM's notes file in $VISUAL / $EDITOR.
+    fn edit_notes(&mut self, config: &Config) {
+        if self.read_only_denied(config, "editing notes") {
+            return;
+        }
+        let Some(vm_conf) = self.list_state.selected().and_then(|i| self.vm_list.get(i)) else { return };
+        let editor = std::env::var("VISUAL")
+            .or_else(|_| std::env::var("EDITOR"))
+            .unwrap_or_else(|_| if cfg!(windows) { "notepad".into() } else { "vi".into() });
+        let mut words: Vec<String> = editor.split_whitespace().map(String::from).collect();
+        words.push(vm_notes_file(vm_conf).to_string_lossy().to_string());
+        self.foreground = Some(words);
+    }
+    /// Entries in the list: quickemu VMs, then those of other backends.
+    fn list_len(&self) -> usize {
+        self.vm_list.len() + self.external.lock().unwrap().len()
+    }
+    fn selected_external(&self) -> Option<ExternalVm> {
+        let i = self.list_state.selected()?.checked_sub(self.vm_list.len())?;
+        self.external.lock().unwrap().get(i).cloned()
+    }
+    /// Position of the VM among the pinned entries, if pinned.
+    fn pin_index(&self, vm_conf: &Path) -> Option<usize> {
+        let name = vm_conf.file_stem().unwrap().to_string_lossy();
+        self.ui_state.pinned.iter().position(|p| *p == name)
+    }
+    fn last_used(&self, vm_conf: &Path) -> u64 {
+        let name = vm_conf.file_stem().unwrap().to_string_lossy();
+        self.ui_state.last_used.get(name.as_ref()).copied().unwrap_or(0)
+    }
+    /// Pinned VMs first in pin order, then by name or most recent use.
+    fn sort_vms(&mut self) {
+        let mut vms = std::mem::take(&mut self.vm_list);
+        vms.sort_by_key(|vm| vm.file_stem().unwrap().to_string_lossy().to_lowercase());
+        if self.ui_state.sort_by_recent {
+            vms.sort_by_key(|vm| std::cmp::Reverse(self.last_used(vm)));
+        }
+        vms.sort_by_key(|vm| self.pin_index(vm).unwrap_or(usize::MAX));
+        self.vm_list = vms;
+    }
+    /// Re-sort while keeping the same VM selected.
+    fn resort_keep_selection(&mut self) {
+        let selected = self.list_state.selected().and_then(|i| self.vm_list.get(i)).cloned();
+        self.sort_vms();
+        if let Some(vm_conf) = selected {
+            self.list_state.select(self.vm_list.iter().position(|vm| *vm == vm_conf));
+        }
+    }
+    fn toggle_sort(&mut self) {
+        self.ui_state.sort_by_recent = !self.ui_state.sort_by_recent;
+        save_state(&self.ui_state);
+        self.resort_keep_selection();
+        self.log(format!("Sorting by {}.", if self.ui_state.sort_by_recent { "most recently used" } else { "name" }));
+    }
+    /// Record a start/connect for MRU ordering.
+    fn mark_used(&mut self, vm_conf: &Path) {
+        let name = vm_conf.file_stem().unwrap().to_string_lossy().to_string();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        self.ui_state.last_used.insert(name, now);
+        save_state(&self.ui_state);
+        if self.ui_state.sort_by_recent {
+            self.resort_keep_selection();
+        }
+    }
+    fn toggle_pin(&mut self) {
+        let Some(vm_conf) = self.list_state.selected().and_then(|i| self.vm_list.get(i)).cloned() else { return };
+        let name = vm_conf.file_stem().unwrap().to_string_lossy().to_string();
+        match self.pin_index(&vm_conf) {
+            Some(p) => {
+                self.ui_state.pinned.remove(p);
+                self.log(format!("Unpinned {}.", name));
+            }
+            None => {
+                self.ui_state.pinned.push(name.clone());
+                self.log(format!("Pinned {}.", name));
+            }
+        }
+        save_state(&self.ui_state);
+        self.resort_keep_selection();
+    }
+    /// Select the n-th pinned VM (0-based) if it is in the current list.
+    fn jump_to_pin(&mut self, n: usize) {
+        if let Some(i) = self.vm_list.iter().position(|vm| self.pin_index(vm) == Some(n)) {
+            self.list_state.select(Some(i));
+        }
+    }
+    fn toggle_hidden(&mut self, config: &Config) {
+        self.show_hidden = !self.show_hidden;
+        self.reload_vms(config);
+        self.log(format!("Hidden VMs {}.", if self.show_hidden { "shown" } else { "hidden" }));
+    }
+    fn update_spinner(&mut self) {
+        self.spinner_index = (self.spinner_index + 1) % SPINNER_FRAMES.len();
+    }
+    /// Lines of the details pane for a VM: metrics while it runs, then its notes.
+    fn details(&self, vm_conf: &Path, config: &Config) -> Vec<Line<'static>> {
+        let name = vm_conf.file_stem().unwrap().to_string_lossy().to_string();
+        let mut lines = Vec::new();
+        let label = |text: &str| Span::styled(format!("{:<9}", text), Style::default().fg(Color::Cyan));
+        let spark_style = Style::default().fg(Color::Green);
+        {
+            let metrics = self.metrics.lock().unwrap();
+            let spark = |values: &[f64]| {
+                Span::styled(format!("{} ", metrics::sparkline(values, SPARKLINE_WIDTH)), spark_style)
+            };
+            // Percent of one core: CPU milliseconds per second / 10.
+            let cpu: Vec<f64> = metrics.rates(&name, |s| s.cpu_ms).iter().map(|ms| ms / 10.0).collect();
+            if let Some(last) = cpu.last() {
+                lines.push(Line::from(vec![label("CPU"), spark(&cpu), Span::raw(format!("{:.0}%", last))]));
+            }
+            let memory = metrics.values(&name, |s| s.rss_kib);
+            if let Some(last) = memory.last() {
+                lines.push(Line::from(vec![
+                    label("Memory"),
+                    spark(&memory),
+                    Span::raw(metrics::format_kib(*last as u64)),
+                ]));
+            }
+            let network = metrics.rates(&name, |s| Some(s.net_rx? + s.net_tx?));
+            if let (Some(rx), Some(tx)) = (metrics.rate(&name, |s| s.net_rx), metrics.rate(&name, |s| s.net_tx)) {
+                lines.push(Line::from(vec![
+                    label("Network"),
+                    spark(&network),
+                    Span::raw(format!("↓ {}  ↑ {}", metrics::format_rate(rx), metrics::format_rate(tx))),
+                ]));
+            }
+            let disk = |counter: fn(&DiskCounters) -> u64| metrics.rate(&name, |s| s.disk.as_ref().map(counter));
+            if let (Some(rd), Some(wr), Some(rd_ops), Some(wr_ops)) =
+                (disk(|d| d.rd_bytes), disk(|d| d.wr_bytes), disk(|d| d.rd_ops), disk(|d| d.wr_ops))
+            {
+                lines.push(Line::from(vec![
+                    label("Disk"),
+                    Span::raw(format!(
+                        "R {} ({:.0} IOPS)  W {} ({:.0} IOPS)",
+                        metrics::format_rate(rd),
+                        rd_ops,
+                        metrics::format_rate(wr),
+                        wr_ops
+                    )),
+                ]));
+            }
+        }
+        // The Remmina profile a connect would use, so a wrong auto-detected match shows before connecting.
+        if let Some(profile) = remmina::profile_for_vm(vm_conf, config) {
+            let file = profile.file_name().unwrap_or_default().to_string_lossy().to_string();
+            match remmina::preview(&profile) {
+                Some(preview) => {
+                    let mut settings: Vec<String> =
+                        [preview.protocol, preview.server, preview.resolution].into_iter().flatten().collect();
+                    if let Some(user) = preview.username {
+                        settings.push(format!("user {}", user));
+                    }
+                    lines.push(Line::from(vec![label("Remmina"), Span::raw(file)]));
+                    if !settings.is_empty() {
+                        lines.push(Line::from(vec![label(""), Span::raw(settings.join(", "))]));
+                    }
+                }
+                None => lines.push(Line::from(vec![
+                    label("Remmina"),
+                    Span::styled(format!("{} (cannot be read)", profile.display()), Style::default().fg(Color::Red)),
+                ])),
+            }
+        }
+        if let Some(notes) = self.notes.get(vm_conf) {
+            let style = Style::default().fg(Color::Gray);
+            lines.extend(notes.lines().take(4).map(|l| Line::from(Span::styled(l.to_string(), style))));
+        }
+        lines
+    }
+    fn record_result(&self, result: Result<String, String>) {
+        record_result(&self.last_result, result);
+    }
+    /// Report the stops started from this TUI that have ended: stopped, or still running.
+    fn check_pending_stops(&mut self, config: &Config) {
+        let (done, pending): (Vec<_>, Vec<_>) = self.pending_stops.drain(..).partition(|vm| stop_stage(vm).is_none());
+        self.pending_stops = pending;
+        for vm_conf in done {
+            let name = vm_conf.file_stem().unwrap().to_string_lossy().to_string();
+            self.record_result(if is_vm_running(&vm_conf, config) {
+                Err(format!("{} is still running.", name))
+            } else {
+                Ok(format!("Stopped {}", name))
+            });
+        }
+    }
+    /// One-line status bar: the last action's result and how long ago it was, red for failures.
+    fn status_bar(&self) -> Line<'static> {
+        let Some((result, when)) = self.last_result.lock().unwrap().clone() else { return Line::default() };
+        let (message, style) = match result {
+            Ok(message) => (message, Style::default().fg(Color::Green)),
+            Err(message) => (message, Style::default().fg(Color::Red)),
+        };
+        let secs = when.elapsed().as_secs();
+        let age = if secs < 60 { format!("{}s ago", secs) } else { format!("{}m ago", secs / 60) };
+        Line::from(vec![
+            Span::styled(message, style),
+            Span::styled(format!("  ({})", age), Style::default().fg(Color::DarkGray)),
+        ])
+    }
+    /// Pane constraints for the current layout: the main pane, a second pane below
+    /// it (logs or details) when the preset has one, and the footer.
+    fn layout_constraints(&self) -> Vec<Constraint> {
+        let second_pane = match self.ui_state.layout {
+            LayoutPreset::ListLogs | LayoutPreset::Dashboard => self.ui_state.show_logs,
+            LayoutPreset::ListDetails => true,
+            LayoutPreset::LogsOnly => false,
+        };
+        if second_pane {
+            vec![
+                Constraint::Percentage(self.ui_state.list_percent),
+                Constraint::Percentage(90 - self.ui_state.list_percent),
+                Constraint::Percentage(10),
+            ]
+        } else {
+            vec![Constraint::Percentage(90), Constraint::Percentage(10)]
+        }
+    }
+    fn resize_list(&mut self, delta: i16) {
+        let percent = (self.ui_state.list_percent as i16 + delta) as u16;
+        self.ui_state.list_percent = percent.clamp(MIN_LIST_PERCENT, MAX_LIST_PERCENT);
+        save_state(&self.ui_state);
+    }
+    fn toggle_logs(&mut self) {
+        self.ui_state.show_logs = !self.ui_state.show_logs;
+        save_state(&self.ui_state);
+    }
+    fn set_layout(&mut self, layout: LayoutPreset) {
+        self.ui_state.layout = layout;
+        save_state(&self.ui_state);
+        self.log(format!("Layout: {}.", layout.name()));
+    }
+    fn next_layout(&mut self) {
+        let i = LAYOUT_PRESETS.iter().position(|l| *l == self.ui_state.layout).unwrap_or(0);
+        self.set_layout(LAYOUT_PRESETS[(i + 1) % LAYOUT_PRESETS.len()]);
+    }
+    fn toggle_dashboard(&mut self) {
+        let dashboard = self.ui_state.layout == LayoutPreset::Dashboard;
+        self.set_layout(if dashboard { LayoutPreset::ListLogs } else { LayoutPreset::Dashboard });
+    }
+    /// Handle `M` (record) and `@` (replay) and the register key after them.
+    /// Returns whether the key was consumed.
+    fn macro_key(&mut self, code: KeyCode) -> bool {
+        if let Some(pending) = self.macros.pending.take() {
+            match (pending, code) {
+                (Pending::Record, KeyCode::Char(r)) if macros::is_register(r) => {
+                    self.macros.start_recording(r);
+                    self.log(format!("Recording macro '{}'; press M to stop.", r));
+                }
+                (Pending::Replay, KeyCode::Char(r)) if r == '@' || macros::is_register(r) => {
+                    match self.macros.replay(r) {
+                        Ok(count) => self.log(format!("Replaying {} keys.", count)),
+                        Err(e) => self.log(format!("Cannot replay macro: {}.", e)),
+                    }
+                }
+                _ => self.log("Macro cancelled.".into()),
+            }
+            return true;
+        }
+        match code {
+            KeyCode::Char('M') => match self.macros.stop_recording() {
+                Some((register, count, saved)) => {
+                    self.log(format!("Recorded macro '{}' ({} keys); replay it with @{}.", register, count, register));
+                    if let Err(e) = saved {
+                        self.log(format!("Macro not saved: {}.", e));
+                    }
+                }
+                None => self.macros.pending = Some(Pending::Record),
+            },
+            KeyCode::Char('@') => self.macros.pending = Some(Pending::Replay),
+            _ => return false,
+        }
+        true
+    }
+    /// Move the selection by `delta` entries, wrapping around; the dashboard moves
+    /// by whole rows with `delta` of one row's columns.
+    fn move_selection(&mut self, delta: isize) {
+        let count = self.list_len() as isize;
+        if count > 0 {
+            let i = self.list_state.selected().unwrap_or(0) as isize;
+            self.list_state.select(Some((i + delta).rem_euclid(count) as usize));
+        }
+    }
+    /// Title and body of the dashboard card of list entry `i`; the flag is whether it runs.
+    fn dashboard_card(&self, i: usize, config: &Config) -> Option<(String, Vec<Line<'static>>, bool)> {
+        let dim = Style::default().fg(Color::DarkGray);
+        let Some(vm_conf) = self.vm_list.get(i) else {
+            let vm = self.external.lock().unwrap().get(i - self.vm_list.len())?.clone();
+            let state = if vm.running { "running" } else { "stopped" };
+            let lines = vec![
+                Line::from(Span::raw(format!("{} ({})", state, vm.backend))),
+                Line::default(),
+                Line::default(),
+                Line::from(Span::styled("[Enter] connect [r] start [s] stop", dim)),
+            ];
+            return Some((vm.name, lines, vm.running));
+        };
+        let name = vm_conf.file_stem().unwrap().to_string_lossy().to_string();
+        let running = self.is_running(vm_conf, config);
+        let os = self.guest_os.get(vm_conf).copied().unwrap_or(GuestOs::Other);
+        let state = match stop_stage(vm_conf) {
+            Some(stage) => format!("stopping… {}", stage.label()),
+            None if running => format!("{} running", SPINNER_FRAMES[self.spinner_index]),
+            None => "stopped".to_string(),
+        };
+        let protocol = parse_vm_config(vm_conf, config);
+        let protocol = match protocol.port() {
+            Some(port) => format!("{} :{}", protocol.label(), port),
+            None => format!("{} socket", protocol.label()),
+        };
+        let uptime = if running {
+            let metrics = self.metrics.lock().unwrap();
+            let secs = metrics.history(&name).and_then(|h| h.back()).and_then(|s| s.uptime);
+            secs.map_or_else(|| "up".to_string(), |secs| format!("up {}", format_uptime(secs)))
+        } else {
+            String::new()
+        };
+        let actions = if running { "[c] connect [s] stop" } else { "[Enter] start & connect [r] start" };
+        let lines = vec![
+            Line::from(vec![
+                Span::styled(format!("{} ", os.tag()), Style::default().fg(os_color(os))),
+                Span::raw(state),
+            ]),
+            Line::from(protocol),
+            Line::from(uptime),
+            Line::from(Span::styled(actions, dim)),
+        ];
+        Some((name, lines, running))
+    }
+    /// Cycle through All -> each quickemu directory -> All, reloading the VM list.
+    fn next_workspace(&mut self, config: &Config) {
+        if config.quickemu_dirs.len() < 2 {
+            return;
+        }
+        self.workspace = match self.workspace {
+            None => Some(0),
+            Some(i) if i + 1 < config.quickemu_dirs.len() => Some(i + 1),
+            Some(_) => None,
+        };
+        self.reload_vms(config);
+        self.log(format!("Workspace: {}", workspace_name(config, self.workspace)));
+    }
+    fn log(&self, message: String) {
+        self.logs.lock().unwrap().push(message);
+    }
+    fn trim_logs(&self) {
+        let mut logs = self.logs.lock().unwrap();
+        let excess = logs.len().saturating_sub(MAX_LOG_LINES);
+        logs.drain(..excess);
+    }
+    fn vm_names(&self) -> Vec<String> {
+        self.vm_list.iter().map(|vm| vm.file_stem().unwrap().to_string_lossy().to_string()).collect()
+    }
+    fn find_vm(&self, name: &str) -> Option<PathBuf> {
+        let name = name.to_lowercase();
+        self.vm_list
+            .iter()
+            .find(|vm| vm.file_stem().unwrap().to_string_lossy().to_lowercase() == name)
+            .cloned()
+    }
+    fn targets(&self, target: &Target) -> Vec<PathBuf> {
+        match target {
+            Target::All => self.vm_list.clone(),
+            Target::Vm(name) => match self.find_vm(name) {
+                Some(vm) => vec![vm],
+                None => {
+                    self.log(format!("No VM named {}.", name));
+                    Vec::new()
+                }
+            },
+        }
+    }
+    /// Parse and run a line entered on the `:` command line.
+    fn execute_command(&mut self, line: &str, config: &Config) {
+        match command::parse_command(line) {
+            Ok(CliCommand::Start(Target::All)) => self.queue_start_all(config),
+            Ok(CliCommand::Start(target)) => {
+                for vm_conf in self.targets(&target) {
+                    self.start(&vm_conf, config);
+                }
+            }
+            Ok(CliCommand::Stop(target)) => {
+                if matches!(target, Target::All) && !self.start_queue.is_empty() {
+                    self.log(format!("Cancelled {} queued starts.", self.start_queue.len()));
+                    self.start_queue.clear();
+                }
+                for vm_conf in self.targets(&target) {
+                    if matches!(target, Target::All) && !self.is_running(&vm_conf, config) {
+                        continue;
+                    }
+                    self.stop(&vm_conf, config);
+                }
+            }
+            Ok(CliCommand::Connect { vm, spice }) => {
+                for vm_conf in self.targets(&Target::Vm(vm)) {
+                    if spice && self.is_running(&vm_conf, config) {
+                        self.force_spice(&vm_conf, config);
+                    } else {
+                        self.connect(&vm_conf, config);
+                    }
+                }
+            }
+            Ok(CliCommand::Hmp { .. }) if self.read_only_denied(config, "HMP commands") => {}
+            Ok(CliCommand::Hmp { vm, command }) => {
+                for vm_conf in self.targets(&Target::Vm(vm)) {
+                    qmp::run_hmp(&vm_conf, &command, &self.logs);
+                }
+            }
+            Ok(CliCommand::Snapshot { .. }) if self.read_only_denied(config, "snapshots") => {}
+            Ok(CliCommand::Snapshot { vm, name }) => {
+                for vm_conf in self.targets(&Target::Vm(vm)) {
+                    if !self.is_running(&vm_conf, config) {
+                        self.log(format!("{} is not running; live snapshots need a running VM.", vm_conf.display()));
+                        continue;
+                    }
+                    let name = name.clone().unwrap_or_else(|| format!("quick-cli-{}", events::now()));
+                    snapshot::take(&vm_conf, &name, &self.logs);
+                }
+            }
+            Ok(CliCommand::Backup { .. }) if self.read_only_denied(config, "backups") => {}
+            Ok(CliCommand::Backup { target, full }) => {
+                for vm_conf in self.targets(&target) {
+                    backup::start(&vm_conf, full, config, &self.jobs, &self.logs);
+                }
+            }
+            Ok(CliCommand::Create { .. }) if self.read_only_denied(config, "creating VMs") => {}
+            Ok(CliCommand::Create { args, cloud_init }) => {
+                let dir = self.workspace.map_or(&config.quickemu_dirs[0], |w| &config.quickemu_dirs[w]);
+                cloudinit::create_vm(dir, &args, cloud_init, config, &self.jobs, &self.logs);
+            }
+            Ok(CliCommand::SshKey { .. }) if self.read_only_denied(config, "adding SSH keys") => {}
+            Ok(CliCommand::SshKey { vm, user }) => {
+                let user = user.unwrap_or_else(|| cloudinit::user(config));
+                for vm_conf in self.targets(&Target::Vm(vm)) {
+                    if self.is_running(&vm_conf, config) {
+                        cloudinit::inject_keys(&vm_conf, &user, config, &self.logs);
+                    } else {
+                        self.log(format!("{} is not running; its guest agent adds the keys.", vm_conf.display()));
+                    }
+                }
+            }
+            Ok(CliCommand::Firewall { .. }) if self.read_only_denied(config, "firewall changes") => {}
+            Ok(CliCommand::Firewall { vm, close }) => {
+                for vm_conf in self.targets(&Target::Vm(vm)) {
+                    let name = vm_conf.file_stem().unwrap().to_string_lossy().to_string();
+                    if close {
+                        firewall::close(&name, config, &self.logs);
+                        continue;
+                    }
+                    let ports = firewall::vm_ports(&vm_conf);
+                    let tool = firewall::Tool::detect();
+                    if !self.is_running(&vm_conf, config) {
+                        self.log(format!("{} is not running; its ports would close again right away.", name));
+                    } else if ports.is_empty() {
+                        self.log(format!("{} forwards no host ports.", name));
+                    } else if let Some(tool) = tool {
+                        self.confirm_firewall = Some(ConfirmFirewall { vm_conf, ports, tool });
+                    } else {
+                        self.log("Firewall: neither ufw nor firewall-cmd found.".into());
                     }
                 }
-                "os_type" => config.os_type = value.trim().to_string(),
-                "override" => {
-                    // Expected format: override=vm_stem, /path/to/remmina_profile.remmina
-                    let parts: Vec<&str> = value.split(',').map(|s| s.trim()).collect();
-                    if parts.len() == 2 {
-                        config.remmina_overrides.insert(parts[0].to_lowercase(), parts[1].to_string());
+            }
+            Ok(CliCommand::Export(target)) => launchers::export(&self.targets(&target), &self.logs),
+            Ok(CliCommand::Clean(_)) if self.read_only_denied(config, "cleanup") => {}
+            Ok(CliCommand::Clean(target)) => {
+                let removed: usize = self
+                    .targets(&target)
+                    .iter()
+                    .map(|vm_conf| remove_stale_runtime_files(vm_conf, config, &self.logs))
+                    .sum();
+                if removed == 0 {
+                    self.log("No stale runtime files found.".into());
+                }
+                self.refresh_status(config);
+            }
+            Ok(CliCommand::Metrics { json, path }) => {
+                let extension = if json { "json" } else { "csv" };
+                let path = path.map(PathBuf::from).unwrap_or_else(|| {
+                    state_dir().join(format!("metrics-{}.{}", events::now(), extension))
+                });
+                match metrics::export(&self.metrics.lock().unwrap(), json, &path) {
+                    Ok(rows) => self.log(format!("Exported {} metrics samples to {}.", rows, path.display())),
+                    Err(e) => self.log(format!("Metrics export to {} failed: {}", path.display(), e)),
+                }
+            }
+            Ok(CliCommand::Quit) => self.should_quit = true,
+            Err(e) => self.log(format!("Command error: {}", e)),
+        }
+    }
+    /// Handle a key press while the command line is open.
+    fn command_key(&mut self, code: KeyCode, config: &Config) {
+        let vm_names = self.vm_names();
+        let Some(input) = self.command_line.as_mut() else { return };
+        match code {
+            KeyCode::Esc => self.command_line = None,
+            KeyCode::Enter => {
+                let line = self.command_line.take().unwrap_or_default();
+                if !line.trim().is_empty() {
+                    self.execute_command(&line, config);
+                }
+            }
+            KeyCode::Backspace if input.pop().is_none() => self.command_line = None,
+            KeyCode::Tab => {
+                if let Some(completed) = command::complete(input, &vm_names) {
+                    *input = completed;
+                }
+            }
+            KeyCode::Char(c) => input.push(c),
+            _ => {}
+        }
+    }
+    fn connect(&mut self, vm_conf: &Path, config: &Config) {
+        if self.is_running(vm_conf, config) {
+            self.connect_in_background(vm_conf, config);
+            self.mark_used(vm_conf);
+        } else {
+            let message = format!("VM {} is not running; cannot connect.", vm_conf.display());
+            self.log(message.clone());
+            self.record_result(Err(message));
+        }
+    }
+    /// Run connect_vm off the UI thread: each viewer it tries may take up to viewer_timeout.
+    fn connect_in_background(&self, vm_conf: &Path, config: &Config) {
+        let (vm_conf, config, logs) = (vm_conf.to_path_buf(), config.clone(), Arc::clone(&self.logs));
+        let last_result = Arc::clone(&self.last_result);
+        thread::spawn(move || record_result(&last_result, connect_vm(&vm_conf, &config, &logs)));
+    }
+    fn start_and_connect(&mut self, vm_conf: &Path, config: &Config) {
+        if config.read_only {
+            self.connect(vm_conf, config);
+            return;
+        }
+        self.request_start(vm_conf, config, true, false);
+    }
+    fn force_spice(&mut self, vm_conf: &Path, config: &Config) {
+        self.log(format!("Force SPICE connect for {}.", vm_conf.display()));
+        self.mark_used(vm_conf);
+        // Off the UI thread, like connect_in_background.
+        let (vm_conf, config, logs) = (vm_conf.to_path_buf(), config.clone(), Arc::clone(&self.logs));
+        let last_result = Arc::clone(&self.last_result);
+        thread::spawn(move || record_result(&last_result, force_spice_connect(&vm_conf, &config, &logs)));
+    }
+    fn run_palette_action(&mut self, action: PaletteAction, vm_index: usize, config: &Config) {
+        let Some(vm_conf) = self.vm_list.get(vm_index).cloned() else { return };
+        self.list_state.select(Some(vm_index));
+        match action {
+            PaletteAction::StartConnect => self.start_and_connect(&vm_conf, config),
+            PaletteAction::Start => self.start(&vm_conf, config),
+            PaletteAction::Connect => self.connect(&vm_conf, config),
+            PaletteAction::ForceSpice => self.force_spice(&vm_conf, config),
+            PaletteAction::Stop => self.stop(&vm_conf, config),
+        }
+    }
+    /// Handle a key press while the palette is open.
+    fn palette_key(&mut self, code: KeyCode, config: &Config) {
+        let Some(palette) = self.palette.as_mut() else { return };
+        match code {
+            KeyCode::Esc => self.palette = None,
+            KeyCode::Enter => {
+                let selected = palette.selected_entry().map(|e| (e.action, e.vm_index));
+                self.palette = None;
+                if let Some((action, vm_index)) = selected {
+                    self.run_palette_action(action, vm_index, config);
+                }
+            }
+            KeyCode::Down => palette.move_selection(1),
+            KeyCode::Up => palette.move_selection(-1),
+            KeyCode::Backspace => {
+                palette.query.pop();
+                palette.selected = 0;
+            }
+            KeyCode::Char(c) => {
+                palette.query.push(c);
+                palette.selected = 0;
+            }
+            _ => {}
+        }
+    }
+    /// Run the chord whose second key this is (see chords.rs); other keys cancel.
+    fn chord_key(&mut self, code: KeyCode, config: &Config) {
+        self.chord = false;
+        let KeyCode::Char(key) = code else { return };
+        let Some(action) = chords::find(key) else { return };
+        let selected = self.list_state.selected().and_then(|i| self.vm_list.get(i));
+        let vm = selected.map(|vm_conf| vm_conf.file_stem().unwrap().to_string_lossy().to_string());
+        match action {
+            ChordAction::Jobs => self.jobs_view = Some(0),
+            ChordAction::Isos => self.open_iso_view(config),
+            ChordAction::First if self.list_len() > 0 => self.list_state.select(Some(0)),
+            ChordAction::Last if self.list_len() > 0 => self.list_state.select(Some(self.list_len() - 1)),
+            _ => {
+                if let Some(line) = vm.and_then(|vm| action.command(&vm)) {
+                    self.execute_command(&line, config);
+                }
+            }
+        }
+    }
+    /// Log and return true when `action` is refused because read-only mode is on.
+    fn read_only_denied(&mut self, config: &Config, action: &str) -> bool {
+        if config.read_only {
+            let message = format!("Read-only mode: {} disabled.", action);
+            self.log(message.clone());
+            self.record_result(Err(message));
+        }
+        config.read_only
+    }
+    fn open_qmp_console(&mut self, config: &Config) {
+        if self.read_only_denied(config, "QMP console") {
+            return;
+        }
+        let Some(vm_conf) = self.list_state.selected().and_then(|i| self.vm_list.get(i)) else { return };
+        self.qmp_console = Some(QmpConsole::open(vm_conf));
+    }
+    /// Handle a key press while the QMP console is open.
+    fn qmp_key(&mut self, code: KeyCode) {
+        let Some(console) = self.qmp_console.as_mut() else { return };
+        match code {
+            KeyCode::Esc => self.qmp_console = None,
+            KeyCode::Enter => console.submit(),
+            KeyCode::Backspace => {
+                console.input.pop();
+            }
+            KeyCode::Char(c) => console.input.push(c),
+            _ => {}
+        }
+    }
+    /// Type the host clipboard into the selected VM, for guests without clipboard sharing.
+    fn paste_clipboard(&mut self, config: &Config) {
+        if self.read_only_denied(config, "sending keys") {
+            return;
+        }
+        let Some(vm_conf) = self.list_state.selected().and_then(|i| self.vm_list.get(i)).cloned() else { return };
+        if !self.is_running(&vm_conf, config) {
+            self.log(format!("VM {} is not running; cannot paste.", vm_conf.display()));
+            return;
+        }
+        match read_clipboard(config) {
+            Ok(text) if text.is_empty() => self.log("The clipboard is empty.".into()),
+            Ok(text) => qmp::type_text(&vm_conf, &text, config.paste_key_delay, &self.logs),
+            Err(e) => self.log(format!("Cannot read the clipboard: {}", e)),
+        }
+    }
+    fn open_key_menu(&mut self, config: &Config) {
+        if self.read_only_denied(config, "sending keys") {
+            return;
+        }
+        let Some(vm_conf) = self.list_state.selected().and_then(|i| self.vm_list.get(i)).cloned() else { return };
+        if self.is_running(&vm_conf, config) {
+            self.key_menu = Some(0);
+        } else {
+            self.log(format!("VM {} is not running; cannot send keys.", vm_conf.display()));
+        }
+    }
+    /// Handle a key press while the send-keys menu is open.
+    fn key_menu_key(&mut self, code: KeyCode) {
+        let Some(selected) = self.key_menu else { return };
+        let count = qmp::KEY_COMBOS.len();
+        match code {
+            KeyCode::Esc => self.key_menu = None,
+            KeyCode::Enter => {
+                self.key_menu = None;
+                let Some(vm_conf) = self.list_state.selected().and_then(|i| self.vm_list.get(i)) else { return };
+                let (label, keys) = qmp::KEY_COMBOS[selected];
+                qmp::send_keys(vm_conf, label, keys, &self.logs);
+            }
+            KeyCode::Down | KeyCode::Char('j') => self.key_menu = Some((selected + 1) % count),
+            KeyCode::Up | KeyCode::Char('k') => self.key_menu = Some((selected + count - 1) % count),
+            _ => {}
+        }
+    }
+    /// Refresh the cached running status once the poll interval has elapsed, from the
+    /// daemon when connected or by probing each VM locally.
+    fn refresh_status(&mut self, config: &Config) {
+        if self.last_status_poll.is_some_and(|t| t.elapsed() < config.status_poll_interval) {
+            return;
+        }
+        if let (Some(dir), Some(daemon)) = (&self.remote_dir, &self.daemon)
+            && daemon.mirror(dir) == Ok(true)
+        {
+            self.reload_vms(config);
+        }
+        self.status = match &self.daemon {
+            Some(daemon) => daemon.status().unwrap_or_default(),
+            None => self
+                .vm_list
+                .iter()
+                .map(|vm| (vm.file_stem().unwrap().to_string_lossy().to_string(), is_vm_running(vm, config)))
+                .collect(),
+        };
+        // The daemon posts webhooks and advertises VMs itself; without one the TUI does while it runs.
+        if self.daemon.is_none() && (!config.webhooks.is_empty() || config.mdns) && !config.dry_run {
+            for event in self.watcher.poll(config).iter().filter(|e| e.is_change()) {
+                webhook::post(&config.webhooks, event, &self.logs);
+            }
+            self.advertiser.sync(self.watcher.states(), config, &self.logs);
+        }
+        if self.daemon.is_none() {
+            firewall::close_stopped(&self.status, config, &self.logs);
+        }
+        procs::reap();
+        let dir = self.workspace.map_or(&config.quickemu_dirs[0], |w| &config.quickemu_dirs[w]);
+        self.host = HostResources::read(dir);
+        if self.daemon.is_none() {
+            self.low_disk.check(dir, self.host.disk_free, config, &self.logs);
+        }
+        self.last_status_poll = Some(Instant::now());
+    }
+    /// VMs in the current list that the last status poll saw running.
+    fn running_vms(&self) -> Vec<&PathBuf> {
+        self.vm_list
+            .iter()
+            .filter(|vm| self.status.get(vm.file_stem().unwrap().to_string_lossy().as_ref()) == Some(&true))
+            .collect()
+    }
+    /// Header line: host load, free memory and disk, and what running VMs have allocated.
+    fn host_summary(&self) -> String {
+        let unknown = || "?".to_string();
+        let mem = match (self.host.mem_available, self.host.mem_total) {
+            (Some(avail), Some(total)) => format!("{} free of {}", host::format_gib(avail), host::format_gib(total)),
+            (None, Some(total)) => format!("{} total", host::format_gib(total)),
+            _ => unknown(),
+        };
+        let running = self.running_vms();
+        let ram: u64 = running.iter().filter_map(|vm| host::vm_ram(vm)).sum();
+        let cores: u32 = running.iter().filter_map(|vm| host::vm_cores(vm)).sum();
+        // RAM/cores left unset in the .conf are sized by quickemu at start.
+        let auto = running.iter().filter(|vm| host::vm_ram(vm).is_none()).count();
+        let mut allocated = format!("{} running: {} RAM, {} cores", running.len(), host::format_gib(ram), cores);
+        if auto > 0 {
+            allocated.push_str(&format!(" (+{} auto-sized)", auto));
+        }
+        format!(
+            "Load {} | RAM {} | Disk {} free | {}",
+            self.host.load.map_or_else(unknown, |l| format!("{:.2}", l)),
+            mem,
+            self.host.disk_free.map_or_else(unknown, host::format_gib),
+            allocated,
+        )
+    }
+    /// Commit and push changed VM configs every sync::SYNC_INTERVAL when git_sync is on.
+    fn sync_configs(&mut self, config: &Config) {
+        if !config.git_sync || config.read_only || config.dry_run || self.last_sync.elapsed() < sync::SYNC_INTERVAL {
+            return;
+        }
+        sync::commit_changes(&config.quickemu_dirs, &self.logs);
+        self.last_sync = Instant::now();
+    }
+    /// Force a status refresh on the next tick, e.g. after starting or stopping a VM.
+    fn invalidate_status(&mut self) {
+        self.last_status_poll = None;
+    }
+    /// Running state for a VM from the status cache, probing directly on a miss.
+    fn is_running(&self, vm_conf: &Path, config: &Config) -> bool {
+        let name = vm_conf.file_stem().unwrap().to_string_lossy();
+        match self.status.get(name.as_ref()) {
+            Some(running) => *running,
+            None => is_vm_running(vm_conf, config),
+        }
+    }
+    /// Open the passphrase prompt if the VM's disk is encrypted. Returns true when the
+    /// start is deferred until the passphrase is entered.
+    fn prompt_passphrase(&mut self, vm_conf: &Path, connect: bool, confirmed: bool) -> bool {
+        // A remote daemon refuses encrypted VMs itself; the passphrase cannot be sent.
+        if self.remote_dir.is_some() || disk::disk_encryption(vm_conf).is_none() {
+            return false;
+        }
+        if self.passphrase.is_some() {
+            self.log(format!("Skipping {}: another passphrase prompt is open.", vm_conf.display()));
+        } else {
+            let vm_conf = vm_conf.to_path_buf();
+            self.passphrase = Some(PassphrasePrompt { vm_conf, input: String::new(), connect, confirmed });
+        }
+        true
+    }
+    /// Handle a key press while the passphrase prompt is open.
+    fn passphrase_key(&mut self, code: KeyCode, config: &Config) {
+        let Some(prompt) = self.passphrase.as_mut() else { return };
+        match code {
+            KeyCode::Esc => {
+                self.passphrase = None;
+                self.log("Start cancelled.".into());
+            }
+            KeyCode::Enter => {
+                let Some(prompt) = self.passphrase.take() else { return };
+                // Encrypted VMs are started locally: the daemon has no way to take a passphrase.
+                let result = start_vm(&prompt.vm_conf, config, &self.logs, Some(&prompt.input), !prompt.confirmed);
+                self.finish_start(&prompt.vm_conf, config, result, prompt.connect);
+            }
+            KeyCode::Backspace => {
+                prompt.input.pop();
+            }
+            KeyCode::Char(c) => prompt.input.push(c),
+            _ => {}
+        }
+    }
+    fn start(&mut self, vm_conf: &Path, config: &Config) {
+        self.request_start(vm_conf, config, false, false);
+    }
+    /// Queue every stopped VM for a staggered start, see run_start_queue.
+    fn queue_start_all(&mut self, config: &Config) {
+        if self.read_only_denied(config, "starting VMs") {
+            return;
+        }
+        let stopped: Vec<PathBuf> = self
+            .vm_list
+            .iter()
+            .filter(|vm| !self.is_running(vm, config) && !self.start_queue.contains(vm))
+            .cloned()
+            .collect();
+        self.log(format!(
+            "Starting {} VMs, {}s apart, at most {} booting at once.",
+            stopped.len(),
+            config.start_stagger.as_secs(),
+            if config.start_concurrency == 0 { "all".to_string() } else { config.start_concurrency.to_string() }
+        ));
+        self.start_queue.extend(stopped);
+    }
+    /// Start the scheduled backups that are due, unless a daemon runs them.
+    fn run_backups(&mut self, config: &Config) {
+        if self.daemon.is_some() || self.remote_dir.is_some() || config.read_only {
+            return;
+        }
+        if self.last_backup_check.is_none_or(|t| t.elapsed() >= backup::SCHEDULE_CHECK) {
+            backup::run_due(config, &self.jobs, &self.logs);
+            self.last_backup_check = Some(Instant::now());
+        }
+    }
+    /// Launch the next queued VM once the stagger delay has passed and fewer than
+    /// start_concurrency queued VMs are booting. A VM counts as booting until its
+    /// RDP/VNC/SPICE port accepts connections, or for BOOT_SLOT_TIMEOUT.
+    fn run_start_queue(&mut self, config: &Config) {
+        if self.start_queue.is_empty() || Instant::now() < self.next_start || self.confirm_start.is_some() {
+            return;
+        }
+        self.booting
+            .retain(|(vm, since)| since.elapsed() < BOOT_SLOT_TIMEOUT && events::vm_state(vm, config) != VmState::Ready);
+        if config.start_concurrency > 0 && self.booting.len() >= config.start_concurrency {
+            return;
+        }
+        let Some(vm_conf) = self.start_queue.pop_front() else { return };
+        self.start(&vm_conf, config);
+        self.booting.push((vm_conf, Instant::now()));
+        self.next_start = Instant::now() + config.start_stagger;
+        if self.start_queue.is_empty() {
+            self.log("All queued VMs have been launched.".into());
+        }
+    }
+    /// Why starting this VM would oversubscribe host memory, if it would. VMs without an
+    /// explicit `ram` setting are sized by quickemu and cannot be checked.
+    fn oversubscription(&self, vm_conf: &Path, config: &Config) -> Option<String> {
+        if config.ram_commit_percent == 0 {
+            return None;
+        }
+        let ram = host::vm_ram(vm_conf)?;
+        let name = vm_conf.file_stem().unwrap().to_string_lossy();
+        if let Some(available) = self.host.mem_available
+            && ram > available
+        {
+            return Some(format!(
+                "{} needs {} RAM but only {} is available.",
+                name,
+                host::format_gib(ram),
+                host::format_gib(available)
+            ));
+        }
+        let total = self.host.mem_total?;
+        let committed: u64 = self.running_vms().iter().filter_map(|vm| host::vm_ram(vm)).sum::<u64>() + ram;
+        (committed > total / 100 * config.ram_commit_percent).then(|| {
+            format!(
+                "Starting {} commits {} of {} host RAM (limit {}%).",
+                name,
+                host::format_gib(committed),
+                host::format_gib(total),
+                config.ram_commit_percent
+            )
+        })
+    }
+    /// Start a VM once read-only mode, the memory guard and the disk passphrase allow it,
+    /// then connect if asked to. start_vm checks group quotas; unless `confirmed`, warnings
+    /// about them and the memory guard's are confirmed first.
+    fn request_start(&mut self, vm_conf: &Path, config: &Config, connect: bool, confirmed: bool) {
+        if self.read_only_denied(config, "starting VMs") {
+            return;
+        }
+        if !confirmed && let Some(warning) = self.oversubscription(vm_conf, config) {
+            return self.confirm_start(vm_conf, vec![warning], connect);
+        }
+        if self.prompt_passphrase(vm_conf, connect, confirmed) {
+            return;
+        }
+        let result = match &self.daemon {
+            // The daemon checks the quotas itself and only logs warnings.
+            Some(daemon) => {
+                let result = daemon
+                    .start(vm_conf)
+                    .map(|()| format!("Daemon started {}.", vm_conf.display()))
+                    .map_err(|e| format!("Daemon failed to start {}: {}", vm_conf.display(), e));
+                let (Ok(line) | Err(line)) = &result;
+                self.logs.lock().unwrap().push(line.clone());
+                result.map_err(StartError::Failed)
+            }
+            None => start_vm(vm_conf, config, &self.logs, None, !confirmed),
+        };
+        self.finish_start(vm_conf, config, result, connect);
+    }
+    /// Ask before starting a VM despite `warnings`; skipped while another start is being confirmed.
+    fn confirm_start(&mut self, vm_conf: &Path, warnings: Vec<String>, connect: bool) {
+        if self.confirm_start.is_some() {
+            let message = format!("Skipping {}: {}", vm_conf.display(), warnings.join(" "));
+            self.log(message.clone());
+            self.record_result(Err(message));
+        } else {
+            let message = warnings.join("\n");
+            self.confirm_start = Some(ConfirmStart { vm_conf: vm_conf.to_path_buf(), message, connect });
+        }
+    }
+    /// Record a start's outcome and connect if asked to; quota warnings start_vm was
+    /// asked to return go to the confirmation instead.
+    fn finish_start(&mut self, vm_conf: &Path, config: &Config, result: Result<String, StartError>, connect: bool) {
+        if let Err(StartError::OverQuota(warnings)) = result {
+            return self.confirm_start(vm_conf, warnings, connect);
+        }
+        self.record_result(result.map_err(String::from));
+        self.invalidate_status();
+        self.mark_used(vm_conf);
+        if connect {
+            self.connect_in_background(vm_conf, config);
+        }
+    }
+    /// Open an SSH or serial console for the selected VM: in a tmux/zellij pane or
+    /// window when configured, else in this terminal with the TUI suspended.
+    fn open_console(&mut self, kind: console::ConsoleKind, config: &Config) {
+        let Some(vm_conf) = self.list_state.selected().and_then(|i| self.vm_list.get(i)).cloned() else { return };
+        let name = vm_conf.file_stem().unwrap().to_string_lossy().to_string();
+        let words = match console::console_command(kind, &vm_conf, vm_host(&vm_conf, config), config.ssh_user.as_deref()) {
+            Ok(words) => words,
+            Err(e) => {
+                self.log(format!("No console for {}: {}", name, e));
+                return;
+            }
+        };
+        match console::multiplexer_command(&config.multiplexer, &name, &words) {
+            Some(mut command) => {
+                if let Err(e) = spawn_detached(&mut command, config, &self.logs) {
+                    self.log(format!("Failed to open console for {}: {}", name, e));
+                }
+            }
+            None => self.foreground = Some(words),
+        }
+    }
+    /// Show the selected VM's directory (disks, sockets, logs) in the file manager.
+    fn open_vm_dir(&mut self, config: &Config) {
+        let Some(vm_conf) = self.list_state.selected().and_then(|i| self.vm_list.get(i)) else { return };
+        let dir = vm_dir(vm_conf);
+        // Before the first start quickemu has not created the VM directory yet.
+        let dir = if dir.is_dir() { dir } else { vm_conf.parent().unwrap_or(Path::new(".")).to_path_buf() };
+        if let Err(e) = open_in_file_manager(&dir, config, &self.logs) {
+            self.log(format!("Failed to open {}: {}", dir.display(), e));
+        }
+    }
+    fn open_file_browser(&mut self, config: &Config) {
+        let Some(vm_conf) = self.list_state.selected().and_then(|i| self.vm_list.get(i)).cloned() else { return };
+        let name = vm_conf.file_stem().unwrap().to_string_lossy().to_string();
+        match console::ssh_port(&vm_conf) {
+            Ok(port) => self.file_browser = Some(FileBrowser::open(&name, vm_host(&vm_conf, config), port, config.ssh_user.as_deref())),
+            Err(e) => self.log(format!("No file browser for {}: {}", name, e)),
+        }
+    }
+    /// Handle a key press while the file browser is open.
+    fn file_browser_key(&mut self, code: KeyCode, config: &Config) {
+        let Some(browser) = self.file_browser.as_mut() else { return };
+        match code {
+            KeyCode::Esc => self.file_browser = None,
+            KeyCode::Tab | KeyCode::Left | KeyCode::Right => browser.toggle_focus(),
+            KeyCode::Down | KeyCode::Char('j') => browser.move_selection(1),
+            KeyCode::Up | KeyCode::Char('k') => browser.move_selection(-1),
+            KeyCode::Enter => {
+                if let Some(name) = browser.selected_entry().filter(|e| e.is_dir).map(|e| e.name.clone()) {
+                    browser.change_dir(&name);
+                }
+            }
+            KeyCode::Backspace => browser.change_dir(".."),
+            KeyCode::Char('r') => browser.refresh(),
+            KeyCode::Char('c') | KeyCode::F(5) => {
+                // Pulling writes only to the host, pushing changes the guest.
+                if browser.focus == Pane::Local && config.read_only {
+                    self.read_only_denied(config, "pushing files");
+                } else if config.dry_run {
+                    self.log("[dry-run] file copies are not run in a dry run.".into());
+                } else {
+                    let (webhooks, logs) = (config.webhooks.clone(), Arc::clone(&self.logs));
+                    browser.copy_selected(&self.logs, move |description, ok| {
+                        webhook::post(&webhooks, &events::Event::job_finished("copy", description, ok), &logs);
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    fn open_diff_view(&mut self) {
+        let Some(vm_conf) = self.list_state.selected().and_then(|i| self.vm_list.get(i)) else { return };
+        self.diff_view = Some(DiffView {
+            vm_name: vm_conf.file_stem().unwrap().to_string_lossy().to_string(),
+            lines: confdiff::diff_with_snapshot(vm_conf),
+            scroll: 0,
+        });
+    }
+    /// Open the config viewer on the selected VM's .conf, or on quick-cli's config.
+    fn open_config_view(&mut self, vm: bool, config: &Config) {
+        let path = if vm {
+            let Some(vm_conf) = self.list_state.selected().and_then(|i| self.vm_list.get(i)) else { return };
+            vm_conf.clone()
+        } else {
+            match &config.source {
+                Some(path) => path.clone(),
+                None => return self.log("quick-cli is running on built-in defaults; there is no config file.".into()),
+            }
+        };
+        self.config_view = Some(ConfigView::open(path, vm));
+    }
+    /// Handle a key press while the config viewer is open.
+    fn config_view_key(&mut self, code: KeyCode, config: &Config) {
+        let Some(view) = self.config_view.as_mut() else { return };
+        match code {
+            KeyCode::Esc | KeyCode::Char('C') => self.config_view = None,
+            KeyCode::Tab => {
+                let vm = !view.showing_vm;
+                self.open_config_view(vm, config);
+            }
+            KeyCode::Down | KeyCode::Char('j') => view.scroll = view.scroll.saturating_add(1),
+            KeyCode::Up | KeyCode::Char('k') => view.scroll = view.scroll.saturating_sub(1),
+            KeyCode::PageDown => view.scroll = view.scroll.saturating_add(20),
+            KeyCode::PageUp => view.scroll = view.scroll.saturating_sub(20),
+            _ => {}
+        }
+    }
+    /// Handle a key press while the config diff view is open.
+    fn diff_key(&mut self, code: KeyCode) {
+        let Some(view) = self.diff_view.as_mut() else { return };
+        match code {
+            KeyCode::Esc | KeyCode::Char('D') => self.diff_view = None,
+            KeyCode::Down | KeyCode::Char('j') => view.scroll = view.scroll.saturating_add(1),
+            KeyCode::Up | KeyCode::Char('k') => view.scroll = view.scroll.saturating_sub(1),
+            _ => {}
+        }
+    }
+    /// Open the ISO view and start verifying images that have not been checked yet.
+    fn open_iso_view(&mut self, config: &Config) {
+        let entries: Vec<(String, PathBuf)> = self
+            .vm_list
+            .iter()
+            .flat_map(|vm| {
+                let name = vm.file_stem().unwrap().to_string_lossy().to_string();
+                iso::vm_isos(vm).into_iter().map(move |iso| (name.clone(), iso))
+            })
+            .collect();
+        for (_, path) in &entries {
+            if path.exists() && !self.iso_statuses.lock().unwrap().contains_key(path) {
+                iso::verify(path, config, &self.iso_statuses, &self.logs);
+            }
+        }
+        self.iso_view = Some(IsoView { entries, selected: 0 });
+    }
+    /// Handle a key press while the ISO view is open.
+    fn iso_key(&mut self, code: KeyCode, config: &Config) {
+        let Some(view) = self.iso_view.as_mut() else { return };
+        let count = view.entries.len().max(1);
+        match code {
+            KeyCode::Esc | KeyCode::Char('I') => self.iso_view = None,
+            KeyCode::Down | KeyCode::Char('j') => view.selected = (view.selected + 1) % count,
+            KeyCode::Up | KeyCode::Char('k') => view.selected = (view.selected + count - 1) % count,
+            KeyCode::Char('v') => {
+                if let Some((_, path)) = view.entries.get(view.selected) {
+                    iso::verify(path, config, &self.iso_statuses, &self.logs);
+                }
+            }
+            _ => {}
+        }
+    }
+    /// Handle a key press while the jobs view is open.
+    fn jobs_key(&mut self, code: KeyCode) {
+        let count = self.jobs.lock().unwrap().len().max(1);
+        let Some(selected) = self.jobs_view.as_mut() else { return };
+        match code {
+            KeyCode::Esc | KeyCode::Char('J') => self.jobs_view = None,
+            KeyCode::Down | KeyCode::Char('j') => *selected = (*selected + 1) % count,
+            KeyCode::Up | KeyCode::Char('k') => *selected = (*selected + count - 1) % count,
+            _ => {}
+        }
+    }
+    fn open_restore_view(&mut self, config: &Config) {
+        let Some(vm_conf) = self.list_state.selected().and_then(|i| self.vm_list.get(i)).cloned() else { return };
+        let mut backups = backup::list(&vm_conf, config);
+        backups.reverse();
+        self.restore_view = Some(RestoreView { vm_conf, backups, selected: 0, step: RestoreStep::Browse });
+    }
+    /// Handle a key press while the backup browser is open.
+    fn restore_key(&mut self, code: KeyCode, config: &Config) {
+        let Some(view) = self.restore_view.as_mut() else { return };
+        let count = view.backups.len().max(1);
+        match &mut view.step {
+            RestoreStep::Browse => match code {
+                KeyCode::Esc | KeyCode::Char('B') => self.restore_view = None,
+                KeyCode::Down | KeyCode::Char('j') => view.selected = (view.selected + 1) % count,
+                KeyCode::Up | KeyCode::Char('k') => view.selected = (view.selected + count - 1) % count,
+                KeyCode::Char('r') if !view.backups.is_empty() => view.step = RestoreStep::ConfirmInPlace,
+                KeyCode::Char('n') if !view.backups.is_empty() => view.step = RestoreStep::CloneName(String::new()),
+                _ => {}
+            },
+            RestoreStep::ConfirmInPlace => match code {
+                KeyCode::Char('y') | KeyCode::Enter => self.finish_restore(backup::RestoreTarget::InPlace, config),
+                _ => view.step = RestoreStep::Browse,
+            },
+            RestoreStep::CloneName(name) => match code {
+                KeyCode::Esc => view.step = RestoreStep::Browse,
+                KeyCode::Enter if !name.is_empty() && guest_os(&view.vm_conf) == GuestOs::Linux => {
+                    view.step = RestoreStep::CloneSeed(name.clone());
+                }
+                KeyCode::Enter if !name.is_empty() => {
+                    let target = backup::RestoreTarget::Clone { name: name.clone(), seed: false };
+                    self.finish_restore(target, config);
+                }
+                KeyCode::Backspace => {
+                    name.pop();
+                }
+                KeyCode::Char(c) => name.push(c),
+                _ => {}
+            },
+            RestoreStep::CloneSeed(name) => {
+                let name = name.clone();
+                match code {
+                    KeyCode::Char(c @ ('y' | 'n')) => {
+                        self.finish_restore(backup::RestoreTarget::Clone { name, seed: c == 'y' }, config)
                     }
+                    KeyCode::Esc => view.step = RestoreStep::CloneName(name),
+                    _ => {}
+                }
+            }
+        }
+    }
+    /// Restore the selected backup and close the browser.
+    fn finish_restore(&mut self, target: backup::RestoreTarget, config: &Config) {
+        let Some(view) = self.restore_view.take() else { return };
+        if self.read_only_denied(config, "restoring backups") {
+            return;
+        }
+        if let Some(chosen) = view.backups.get(view.selected) {
+            backup::restore(&view.vm_conf, chosen, target, config, &self.jobs, &self.logs);
+        }
+    }
+    fn open_process_view(&mut self) {
+        self.process_view = Some(ProcessView { processes: procs::list(), selected: 0 });
+    }
+    /// Handle a key press while the process inspector is open.
+    fn process_key(&mut self, code: KeyCode, config: &Config) {
+        let Some(view) = self.process_view.as_mut() else { return };
+        let count = view.processes.len().max(1);
+        match code {
+            KeyCode::Esc | KeyCode::Char('P') => self.process_view = None,
+            KeyCode::Down | KeyCode::Char('j') => view.selected = (view.selected + 1) % count,
+            KeyCode::Up | KeyCode::Char('k') => view.selected = (view.selected + count - 1) % count,
+            KeyCode::Char('r') => {
+                view.processes = procs::list();
+                view.selected = view.selected.min(view.processes.len().saturating_sub(1));
+            }
+            KeyCode::Char('x') => {
+                let Some(pid) = view.processes.get(view.selected).map(|p| p.pid) else { return };
+                if self.read_only_denied(config, "killing processes") {
+                    return;
+                }
+                procs::kill(pid, config, &self.logs);
+                self.invalidate_status();
+            }
+            _ => {}
+        }
+    }
+    fn open_gallery(&mut self) {
+        let Some(vm_conf) = self.list_state.selected().and_then(|i| self.vm_list.get(i)).cloned() else { return };
+        self.gallery = Some(Gallery { shots: screenshots::list(&vm_conf), vm_conf, selected: 0 });
+    }
+    /// Pick up screenshots saved in the background, keeping the selection on the same file.
+    fn refresh_gallery(&mut self) {
+        let Some(gallery) = self.gallery.as_mut() else { return };
+        let selected = gallery.selected_shot().cloned();
+        gallery.shots = screenshots::list(&gallery.vm_conf);
+        gallery.selected = selected
+            .and_then(|s| gallery.shots.iter().position(|p| *p == s))
+            .unwrap_or(0);
+    }
+    /// Handle a key press while the screenshot gallery is open.
+    fn gallery_key(&mut self, code: KeyCode, config: &Config) {
+        let Some(gallery) = self.gallery.as_mut() else { return };
+        let count = gallery.shots.len().max(1);
+        match code {
+            KeyCode::Esc | KeyCode::Char('G') => self.gallery = None,
+            KeyCode::Down | KeyCode::Char('j') => gallery.selected = (gallery.selected + 1) % count,
+            KeyCode::Up | KeyCode::Char('k') => gallery.selected = (gallery.selected + count - 1) % count,
+            KeyCode::Char('s') => screenshots::capture(&gallery.vm_conf, &self.logs),
+            KeyCode::Char('o') => {
+                let Some(path) = gallery.selected_shot().cloned() else { return };
+                if let Err(e) = open_in_file_manager(&path, config, &self.logs) {
+                    self.log(format!("Failed to open {}: {}", path.display(), e));
+                }
+            }
+            _ => {}
+        }
+    }
+    /// Handle a key press while the `:firewall` confirmation is open.
+    fn confirm_firewall_key(&mut self, code: KeyCode, config: &Config) {
+        let Some(pending) = self.confirm_firewall.take() else { return };
+        match code {
+            KeyCode::Char('y') | KeyCode::Enter => {
+                firewall::open(&pending.vm_conf, pending.ports, pending.tool, config, &self.logs)
+            }
+            KeyCode::Char('n') | KeyCode::Esc => self.log("Firewall change cancelled.".into()),
+            _ => self.confirm_firewall = Some(pending),
+        }
+    }
+
+    /// Offer to adopt or kill qemu processes of known VMs that run without a pid file.
+    fn find_orphans(&mut self, config: &Config) {
+        let orphans = orphaned_vms(&list_vms(config), config);
+        if orphans.is_empty() {
+            return;
+        }
+        if config.read_only {
+            let names: Vec<String> =
+                orphans.iter().map(|(vm_conf, _)| vm_conf.file_stem().unwrap().to_string_lossy().to_string()).collect();
+            self.log(format!("Running without a pid file: {}.", names.join(", ")));
+            return;
+        }
+        self.orphan_view = Some(OrphanView { orphans, selected: 0 });
+    }
+    /// Handle a key press while the orphaned VM list is open. Each VM leaves the list once
+    /// it is adopted or killed; Esc leaves the rest running as they are.
+    fn orphan_key(&mut self, code: KeyCode, config: &Config) {
+        let Some(view) = self.orphan_view.as_mut() else { return };
+        let count = view.orphans.len().max(1);
+        match code {
+            KeyCode::Esc => self.orphan_view = None,
+            KeyCode::Down | KeyCode::Char('j') => view.selected = (view.selected + 1) % count,
+            KeyCode::Up | KeyCode::Char('k') => view.selected = (view.selected + count - 1) % count,
+            KeyCode::Char('a') | KeyCode::Enter => {
+                let (vm_conf, pid) = view.orphans.remove(view.selected);
+                adopt_vm(&vm_conf, pid, config, &self.logs);
+            }
+            KeyCode::Char('A') => {
+                for (vm_conf, pid) in std::mem::take(&mut view.orphans) {
+                    adopt_vm(&vm_conf, pid, config, &self.logs);
                 }
-                _ => {}
+            }
+            KeyCode::Char('x') => {
+                let (_, pid) = view.orphans.remove(view.selected);
+                procs::kill(pid, config, &self.logs);
+            }
+            _ => return,
+        }
+        if let Some(view) = self.orphan_view.as_mut() {
+            if view.orphans.is_empty() {
+                self.orphan_view = None;
+            } else {
+                view.selected = view.selected.min(view.orphans.len() - 1);
             }
         }
+        self.invalidate_status();
     }
-    config
-}
 
-/// List all VM configuration files (ending with ".conf") in the quickemu directory.
-fn list_vms(config: &Config) -> Vec<PathBuf> {
-    let mut vms = Vec::new();
-    if let Ok(entries) = fs::read_dir(&config.quickemu_dir) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if path.is_file() {
-                if let Some(ext) = path.extension() {
-                    if ext == "conf" {
-                        vms.push(path);
-                    }
-                }
+    /// Handle a key press while the over-budget start confirmation is open.
+    fn confirm_start_key(&mut self, code: KeyCode, config: &Config) {
+        let Some(pending) = self.confirm_start.take() else { return };
+        match code {
+            KeyCode::Char('y') | KeyCode::Enter => self.request_start(&pending.vm_conf, config, pending.connect, true),
+            KeyCode::Char('n') | KeyCode::Esc => self.log("Start cancelled.".into()),
+            _ => self.confirm_start = Some(pending),
+        }
+    }
+    fn stop(&mut self, vm_conf: &Path, config: &Config) {
+        if self.read_only_denied(config, "stopping VMs") {
+            return;
+        }
+        let result = match &self.daemon {
+            Some(daemon) => {
+                let result = daemon
+                    .stop(vm_conf)
+                    .map(|()| format!("Daemon stopped {}.", vm_conf.display()))
+                    .map_err(|e| format!("Daemon failed to stop {}: {}", vm_conf.display(), e));
+                let (Ok(line) | Err(line)) = &result;
+                self.logs.lock().unwrap().push(line.clone());
+                result
             }
+            None => stop_vm(vm_conf, config, &self.logs),
+        };
+        // A local stop goes on in the background; its end is reported by check_pending_stops.
+        if self.daemon.is_none() && result.is_ok() && stop_stage(vm_conf).is_some() {
+            self.pending_stops.push(vm_conf.to_path_buf());
         }
+        self.record_result(result);
+        self.invalidate_status();
     }
-    vms
 }
 
+const SPINNER_FRAMES: [&str; 4] = ["-", "\\", "|", "/"];
+
+/// How long a VM from `:start all` may hold a boot slot without its port opening.
+const BOOT_SLOT_TIMEOUT: Duration = Duration::from_secs(180);
+
 ///////////////////////////////////////////////////////////////////////////////
-// Protocol Parsing and Running Detection
+// External Backend Actions
 ///////////////////////////////////////////////////////////////////////////////
 
-enum RemoteProtocol {
-    Rdp(u16),
-    Vnc(u16),
-    Spice(u16),
-}
-
-/// Parse the VM configuration.
-/// If a "port_forwards" line is found for guest port 3389 or 5900, return Rdp or Vnc.
-/// Otherwise, assume SPICE.
-fn parse_vm_config(vm_conf: &Path, config: &Config) -> RemoteProtocol {
-    if let Ok(contents) = fs::read_to_string(vm_conf) {
-        for line in contents.lines() {
-            if line.contains("port_forwards") {
-                if let (Some(start), Some(end)) = (line.find('('), line.rfind(')')) {
-                    let forwards_str = &line[start + 1..end];
-                    let parts: Vec<&str> = forwards_str.split('"')
-                        .filter(|s| !s.trim().is_empty())
-                        .collect();
-                    for mapping in parts {
-                        let split: Vec<&str> = mapping.split(':').collect();
-                        if split.len() == 2 {
-                            if let Ok(guest_port) = split[1].parse::<u16>() {
-                                if guest_port == 3389 {
-                                    if let Ok(host_port) = split[0].parse::<u16>() {
-                                        return RemoteProtocol::Rdp(host_port);
-                                    }
-                                } else if guest_port == 5900 {
-                                    if let Ok(host_port) = split[0].parse::<u16>() {
-                                        return RemoteProtocol::Vnc(host_port);
-                                    }
-                                }
-                            }
-                        }
-                    }
+impl App {
+    fn start_external(&mut self, vm: &ExternalVm, config: &Config) -> bool {
+        if self.read_only_denied(config, "starting VMs") {
+            return false;
+        }
+        let Some(backend) = backends::backend(vm.backend) else { return false };
+        self.log(format!("Starting {} VM {}...", vm.backend, vm.name));
+        let result = match backend.start_command(vm) {
+            Ok(mut command) => {
+                if backends::run_action(&mut command, config, &self.logs) {
+                    Ok(format!("Started {} VM {}", vm.backend, vm.name))
+                } else {
+                    Err(format!("Starting {} VM {} failed.", vm.backend, vm.name))
                 }
             }
+            Err(e) => {
+                let message = format!("Cannot start {}: {}", vm.name, e);
+                self.log(message.clone());
+                Err(message)
+            }
+        };
+        let started = result.is_ok();
+        self.record_result(result);
+        started
+    }
+    fn stop_external(&mut self, vm: &ExternalVm, config: &Config) {
+        if self.read_only_denied(config, "stopping VMs") {
+            return;
+        }
+        let Some(backend) = backends::backend(vm.backend) else { return };
+        self.log(format!("Stopping {} VM {}...", vm.backend, vm.name));
+        self.record_result(if backend.stop(vm, config, &self.logs) {
+            Ok(format!("Stopped {} VM {}", vm.backend, vm.name))
+        } else {
+            Err(format!("Stopping {} VM {} failed.", vm.backend, vm.name))
+        });
+    }
+    /// Connect to the VM, starting it first (and waiting start_wait) when `start` is set.
+    fn connect_external(&mut self, vm: &ExternalVm, config: &Config, start: bool) {
+        if !vm.running {
+            if !start || config.read_only {
+                let message = format!("VM {} is not running; cannot connect.", vm.name);
+                self.log(message.clone());
+                return self.record_result(Err(message));
+            }
+            if !self.start_external(vm, config) {
+                return;
+            }
+            thread::sleep(config.start_wait);
+        }
+        let Some(backend) = backends::backend(vm.backend) else { return };
+        let result = match backend.connect_command(vm, config) {
+            Ok(mut command) => match spawn_detached(&mut command, config, &self.logs) {
+                Ok(()) => Ok(format!("Connected to {}", vm.name)),
+                Err(e) => Err(format!("Failed to connect to {}: {}", vm.name, e)),
+            },
+            Err(e) => Err(format!("Cannot connect to {}: {}", vm.name, e)),
+        };
+        if let Err(message) = &result {
+            self.log(message.clone());
         }
+        self.record_result(result);
     }
-    RemoteProtocol::Spice(config.default_spice_port)
 }
 
-/// Check if a given host:port is open.
-fn is_port_open(host: &str, port: u16, timeout: Duration) -> bool {
-    let addr = format!("{}:{}", host, port);
-    let socket_addr: SocketAddr = match addr.parse() {
-        Ok(sa) => sa,
-        Err(_) => return false,
-    };
-    TcpStream::connect_timeout(&socket_addr, timeout).is_ok()
+/// Display name of a workspace: the directory name, or "all".
+fn workspace_name(config: &Config, workspace: Option<usize>) -> String {
+    match workspace {
+        Some(i) => dir_label(&config.quickemu_dirs[i]),
+        None => "all".into(),
+    }
 }
 
-#[cfg(unix)]
-fn is_spice_vm_running(vm_conf: &Path, config: &Config) -> bool {
-    let vm_stem = vm_conf.file_stem().unwrap().to_string_lossy();
-    let socket_path = config.quickemu_dir.join(vm_stem.as_ref())
-        .join(format!("{}-monitor.socket", vm_stem));
-    if let Ok(meta) = fs::metadata(&socket_path) {
-        if meta.mode() & 0o170000 == 0o140000 {
-            if let Ok(modified) = meta.modified() {
-                return modified.elapsed().unwrap_or(Duration::from_secs(100)) < Duration::from_secs(10);
-            }
-        }
+/// Color of the guest OS tag in the VM list.
+fn os_color(os: GuestOs) -> Color {
+    match os {
+        GuestOs::Windows => Color::Blue,
+        GuestOs::MacOs => Color::White,
+        GuestOs::Linux => Color::Yellow,
+        GuestOs::Bsd => Color::Red,
+        GuestOs::Other => Color::DarkGray,
     }
-    false
 }
 
-#[cfg(not(unix))]
-fn is_spice_vm_running(vm_conf: &Path, config: &Config) -> bool {
-    is_port_open("127.0.0.1", config.default_spice_port, Duration::from_millis(200))
+fn dir_label(dir: &Path) -> String {
+    dir.file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| dir.display().to_string())
 }
 
-/// Determine if the VM is running.
-fn is_vm_running(vm_conf: &Path, config: &Config) -> bool {
-    match parse_vm_config(vm_conf, config) {
-        RemoteProtocol::Rdp(port) | RemoteProtocol::Vnc(port) => is_port_open("127.0.0.1", port, Duration::from_millis(200)),
-        RemoteProtocol::Spice(_) => is_spice_vm_running(vm_conf, config),
+/// "3d 4h", "2h 5m" or "12m" for an uptime in seconds.
+fn format_uptime(secs: u64) -> String {
+    match secs {
+        s if s >= 86400 => format!("{}d {}h", s / 86400, s % 86400 / 3600),
+        s if s >= 3600 => format!("{}h {}m", s / 3600, s % 3600 / 60),
+        s => format!("{}m", s / 60),
     }
 }
 
-///////////////////////////////////////////////////////////////////////////////
-// Remmina Profile Override and Auto-Detection
-///////////////////////////////////////////////////////////////////////////////
-
-/// Returns a Remmina profile for the given VM.
-/// First checks for an override mapping (exact match on the VM config’s stem, lowercase).
-/// If not found, scans the default Remmina directory for files whose stem contains the VM stem.
-/// If there is exactly one match or an exact match, that is returned.
-fn remmina_profile_for_vm(vm_conf: &Path, config: &Config) -> Option<PathBuf> {
-    let vm_stem = vm_conf.file_stem()?.to_string_lossy().to_lowercase();
-    // Check for explicit override.
-    if let Some(override_path) = config.remmina_overrides.get(&vm_stem) {
-        return Some(PathBuf::from(override_path));
-    }
-    // Auto-detect: scan Remmina directory.
-    let home = dirs::home_dir()?;
-    let remmina_dir = home.join(".local/share/remmina");
-    let mut matches = Vec::new();
-    if let Ok(entries) = fs::read_dir(remmina_dir) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if path.is_file() {
-                if let Some(ext) = path.extension() {
-                    if ext == "remmina" {
-                        if let Some(stem) = path.file_stem() {
-                            let profile_stem = stem.to_string_lossy().to_lowercase();
-                            if profile_stem.contains(&vm_stem) {
-                                matches.push(path);
-                            }
-                        }
-                    }
-                }
-            }
+/// Draw the VMs as a grid of cards in `area`, scrolled to keep the selection in
+/// view. Returns the number of columns, which `j`/`k` move by.
+fn draw_dashboard(f: &mut Frame, area: Rect, app: &App, config: &Config) -> usize {
+    let columns = (area.width / CARD_WIDTH).max(1);
+    let rows = (area.height / CARD_HEIGHT).max(1) as usize;
+    let width = area.width / columns;
+    let selected = app.list_state.selected().unwrap_or(0);
+    let first_row = (selected / columns as usize).saturating_sub(rows - 1);
+    let first = first_row * columns as usize;
+    for (slot, i) in (first..app.list_len().min(first + rows * columns as usize)).enumerate() {
+        let Some((title, lines, running)) = app.dashboard_card(i, config) else { continue };
+        let (row, column) = ((slot / columns as usize) as u16, slot as u16 % columns);
+        let card = Rect::new(area.x + column * width, area.y + row * CARD_HEIGHT, width, CARD_HEIGHT);
+        let mut border = Style::default();
+        let mut title_style = Style::default().add_modifier(Modifier::BOLD);
+        if running {
+            title_style = title_style.fg(Color::Green);
         }
-    }
-    if matches.len() == 1 {
-        return Some(matches.remove(0));
-    }
-    for m in &matches {
-        if let Some(stem) = m.file_stem() {
-            if stem.to_string_lossy().to_lowercase() == vm_stem {
-                return Some(m.clone());
-            }
+        if i == selected {
+            border = border.fg(Color::Yellow);
         }
+        let block = Block::default()
+            .title(Span::styled(title, title_style))
+            .borders(Borders::ALL)
+            .border_style(border);
+        f.render_widget(Paragraph::new(lines).block(block), card);
     }
-    matches.into_iter().next()
+    columns as usize
+}
+
+/// A rectangle of the given percentage size centered in `area`, for popups.
+fn centered_rect(percent_x: u16, percent_y: u16, area: Rect) -> Rect {
+    let vertical = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Percentage((100 - percent_y) / 2),
+            Constraint::Percentage(percent_y),
+            Constraint::Percentage((100 - percent_y) / 2),
+        ].as_ref())
+        .split(area);
+    Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Percentage((100 - percent_x) / 2),
+            Constraint::Percentage(percent_x),
+            Constraint::Percentage((100 - percent_x) / 2),
+        ].as_ref())
+        .split(vertical[1])[1]
 }
 
 ///////////////////////////////////////////////////////////////////////////////
-// VM Launching and Connection
+// Main Function
 ///////////////////////////////////////////////////////////////////////////////
 
-fn get_quickemu_cmd(config: &Config) -> String {
-    if config.os_type == "windows" {
-        "quickemu.exe".to_string()
-    } else {
-        "quickemu".to_string()
-    }
-}
-
-fn start_vm(vm_conf: &Path, config: &Config, logs: &Arc<Mutex<Vec<String>>>) {
-    let vm_arg = vm_conf.as_os_str();
-    let quickemu_cmd = get_quickemu_cmd(config);
-    let mut cmd = match parse_vm_config(vm_conf, config) {
-        RemoteProtocol::Rdp(_) | RemoteProtocol::Vnc(_) => {
-            let mut l = logs.lock().unwrap();
-            l.push(format!("Launching VM {} headless...", vm_conf.display()));
-            drop(l);
-            let mut command = Command::new(&quickemu_cmd);
-            command.arg("--vm").arg(vm_arg).arg("--display").arg("none");
-            command
-        },
-        _ => {
-            let mut l = logs.lock().unwrap();
-            l.push(format!("Launching VM {} normally...", vm_conf.display()));
-            drop(l);
-            let mut command = Command::new(&quickemu_cmd);
-            command.arg("--vm").arg(vm_arg);
-            command
-        }
-    };
-    let _ = cmd
-        .stdin(Stdio::null())
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .spawn()
-        .map(|_| thread::sleep(Duration::from_secs(2)))
-        .map_err(|e| {
-            let mut l = logs.lock().unwrap();
-            l.push(format!("Error launching VM {}: {}", vm_conf.display(), e));
-        });
-}
-
-/// Force a SPICE connection regardless of protocol.
-fn force_spice_connect(vm_conf: &Path, config: &Config, logs: &Arc<Mutex<Vec<String>>>) {
-    let spice_port = config.default_spice_port;
-    if config.os_type == "windows" {
-        connect_spice_windows(spice_port, vm_conf, config, logs);
-    } else if config.os_type == "macos" {
-        connect_spice_macos(spice_port, vm_conf, config, logs);
-    } else {
-        connect_spice_linux(spice_port, vm_conf, config, logs);
+/// Leave the TUI, run a console in the foreground until it exits, then restore the TUI.
+fn run_suspended(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    words: &[String],
+    config: &Config,
+    logs: &Arc<Mutex<Vec<String>>>,
+) -> io::Result<()> {
+    let mut command = Command::new(&words[0]);
+    command.args(&words[1..]);
+    if config.dry_run {
+        logs.lock().unwrap().push(format!("[dry-run] {}", describe_command(&command)));
+        return Ok(());
     }
-}
-
-/// Connect to the VM.
-/// First, if an override or auto-detected Remmina profile exists, launch Remmina with it
-/// (using the "-c" flag) and return immediately.
-/// Otherwise, use protocol-specific connection.
-fn connect_vm(vm_conf: &Path, config: &Config, logs: &Arc<Mutex<Vec<String>>>) {
-    if let Some(profile_path) = remmina_profile_for_vm(vm_conf, config) {
-        let mut l = logs.lock().unwrap();
-        l.push(format!(
-            "Profile found for {}. Launching Remmina with profile: {}",
-            vm_conf.display(),
-            profile_path.display()
-        ));
-        drop(l);
-        let result = Command::new(&config.remote_app)
-            .env("DISPLAY", ":0")
-            .arg("-c")
-            .arg(profile_path.to_str().unwrap())
-            .stdin(Stdio::null())
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .spawn();
-        if result.is_ok() {
-            return;
-        } else {
-            let mut l = logs.lock().unwrap();
-            l.push("Failed to launch Remmina with profile; falling back to normal connection.".into());
-            drop(l);
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+    let status = command.status();
+    enable_raw_mode()?;
+    execute!(terminal.backend_mut(), EnterAlternateScreen)?;
+    terminal.clear()?;
+    // ConPTY hands input the child left unread (often its final Enter) back to us.
+    if cfg!(windows) {
+        while event::poll(Duration::ZERO)? {
+            event::read()?;
         }
     }
-    let _ = std::env::var("DISPLAY").unwrap_or_else(|_| ":0".to_string());
-    let vm_name = vm_conf.file_stem().unwrap().to_string_lossy();
-    match parse_vm_config(vm_conf, config) {
-        RemoteProtocol::Rdp(host_port) => {
-            if config.os_type == "windows" {
-                if !connect_rdp_windows(host_port, &vm_name, logs) {
-                    connect_spice_windows(config.default_spice_port, vm_conf, config, logs);
-                }
-            } else if config.os_type == "macos" {
-                if !connect_rdp_macos(host_port, &vm_name, logs) {
-                    connect_spice_macos(config.default_spice_port, vm_conf, config, logs);
-                }
-            } else {
-                if !connect_rdp_linux(host_port, vm_conf, config, logs) {
-                    connect_spice_linux(config.default_spice_port, vm_conf, config, logs);
-                }
-            }
-        },
-        RemoteProtocol::Vnc(host_port) => {
-            if config.os_type == "windows" {
-                if !connect_vnc_windows(host_port, &vm_name, logs) {
-                    connect_spice_windows(config.default_spice_port, vm_conf, config, logs);
-                }
-            } else if config.os_type == "macos" {
-                if !connect_vnc_macos(host_port, &vm_name, logs) {
-                    connect_spice_macos(config.default_spice_port, vm_conf, config, logs);
-                }
-            } else {
-                if !connect_vnc_linux(host_port, vm_conf, config, logs) {
-                    connect_spice_linux(config.default_spice_port, vm_conf, config, logs);
-                }
-            }
-        },
-        RemoteProtocol::Spice(spice_port) => {
-            if config.os_type == "windows" {
-                connect_spice_windows(spice_port, vm_conf, config, logs);
-            } else if config.os_type == "macos" {
-                connect_spice_macos(spice_port, vm_conf, config, logs);
-            } else {
-                connect_spice_linux(spice_port, vm_conf, config, logs);
-            }
-        },
+    let mut l = logs.lock().unwrap();
+    match status {
+        Ok(status) => l.push(format!("{} exited with {}.", words[0], status)),
+        Err(e) => l.push(format!("Failed to run {}: {}", words[0], e)),
     }
+    Ok(())
 }
 
-///////////////////////////////////////////////////////////////////////////////
-// Platform-Specific Connection Functions
-///////////////////////////////////////////////////////////////////////////////
-
-fn connect_rdp_windows(host_port: u16, _vm_name: &str, logs: &Arc<Mutex<Vec<String>>>) -> bool {
-    let mut l = logs.lock().unwrap();
-    l.push(format!("Connecting via Windows RDP to port {}", host_port));
-    drop(l);
-    let result = Command::new("mstsc.exe")
-        .arg(format!("/v:127.0.0.1:{}", host_port))
-        .stdin(Stdio::null())
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .spawn();
-    result.is_ok()
-}
-
-fn connect_rdp_macos(host_port: u16, _vm_name: &str, logs: &Arc<Mutex<Vec<String>>>) -> bool {
-    let mut l = logs.lock().unwrap();
-    l.push("Connecting via macOS RDP (Microsoft Remote Desktop)".into());
-    drop(l);
-    let url = format!("rdp://127.0.0.1:{}", host_port);
-    let result = Command::new("open")
-        .arg(url)
-        .stdin(Stdio::null())
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .spawn();
-    result.is_ok()
-}
-
-fn connect_rdp_linux(host_port: u16, vm_conf: &Path, config: &Config, logs: &Arc<Mutex<Vec<String>>>) -> bool {
-    let _ = std::env::var("DISPLAY").unwrap_or_else(|_| ":0".to_string());
-    if let Some(profile_path) = remmina_profile_for_vm(vm_conf, config) {
-        let mut l = logs.lock().unwrap();
-        l.push(format!("Connecting using Remmina profile: {}", profile_path.display()));
-        drop(l);
-        let result = Command::new(&config.remote_app)
-            .env("DISPLAY", ":0")
-            .arg("--quiet")
-            .arg("-c")
-            .arg(profile_path.to_str().unwrap())
-            .stdin(Stdio::null())
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .spawn();
-        if result.is_ok() { return true; }
-    }
-    let url = format!("rdp://127.0.0.1:{}", host_port);
-    {
-        let mut l = logs.lock().unwrap();
-        l.push(format!("Connecting via RDP URL: {}", url));
-    }
-    let result = Command::new(&config.remote_app)
-        .env("DISPLAY", ":0")
-        .arg("--quiet")
-        .arg("-p")
-        .arg("rdp")
-        .arg(&url)
-        .stdin(Stdio::null())
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .spawn();
-    if result.is_ok() { return true; }
-    let freerdp_result = Command::new("xfreerdp")
-        .env("DISPLAY", ":0")
-        .arg(format!("/v:127.0.0.1:{}", host_port))
-        .arg("/f")
-        .arg("/dynamic-resolution")
-        .stdin(Stdio::null())
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .spawn();
-    freerdp_result.is_ok()
-}
-
-fn connect_vnc_windows(host_port: u16, _vm_name: &str, logs: &Arc<Mutex<Vec<String>>>) -> bool {
-    let mut l = logs.lock().unwrap();
-    l.push(format!("Connecting via Windows VNC to port {}", host_port));
-    drop(l);
-    let result = Command::new("tvnviewer")
-        .arg(format!("127.0.0.1:{}", host_port))
-        .stdin(Stdio::null())
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .spawn();
-    if result.is_ok() { return true; }
-    let result = Command::new("vncviewer")
-        .arg(format!("127.0.0.1:{}", host_port))
-        .stdin(Stdio::null())
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .spawn();
-    result.is_ok()
-}
-
-fn connect_vnc_macos(host_port: u16, _vm_name: &str, logs: &Arc<Mutex<Vec<String>>>) -> bool {
-    let mut l = logs.lock().unwrap();
-    l.push("Connecting via macOS Screen Sharing (VNC)".into());
-    drop(l);
-    let url = format!("vnc://127.0.0.1:{}", host_port);
-    let result = Command::new("open")
-        .arg(url)
-        .stdin(Stdio::null())
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .spawn();
-    result.is_ok()
-}
-
-fn connect_vnc_linux(host_port: u16, vm_conf: &Path, config: &Config, logs: &Arc<Mutex<Vec<String>>>) -> bool {
-    let _ = std::env::var("DISPLAY").unwrap_or_else(|_| ":0".to_string());
-    if let Some(profile_path) = remmina_profile_for_vm(vm_conf, config) {
-        let mut l = logs.lock().unwrap();
-        l.push(format!("Connecting using Remmina profile: {}", profile_path.display()));
-        drop(l);
-        let result = Command::new(&config.remote_app)
-            .env("DISPLAY", ":0")
-            .arg("--quiet")
-            .arg("-c")
-            .arg(profile_path.to_str().unwrap())
-            .stdin(Stdio::null())
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .spawn();
-        if result.is_ok() { return true; }
-    }
-    let url = format!("vnc://127.0.0.1:{}", host_port);
-    {
-        let mut l = logs.lock().unwrap();
-        l.push(format!("Connecting via VNC URL: {}", url));
-    }
-    let result = Command::new(&config.remote_app)
-        .env("DISPLAY", ":0")
-        .arg("--quiet")
-        .arg("-p")
-        .arg("vnc")
-        .arg(&url)
-        .stdin(Stdio::null())
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .spawn();
-    if result.is_ok() { return true; }
-    let result = Command::new("vncviewer")
-        .env("DISPLAY", ":0")
-        .arg(format!("127.0.0.1:{}", host_port))
-        .stdin(Stdio::null())
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .spawn();
-    result.is_ok()
-}
-
-fn connect_spice_windows(spice_port: u16, vm_conf: &Path, config: &Config, logs: &Arc<Mutex<Vec<String>>>) -> bool {
-    let mut l = logs.lock().unwrap();
-    l.push(format!("Connecting via SPICE on Windows to port {}", spice_port));
-    drop(l);
-    // If an override exists, try Remmina with it.
-    if let Some(profile_path) = remmina_profile_for_vm(vm_conf, config) {
-        let mut l = logs.lock().unwrap();
-        l.push(format!("Using override Remmina profile for SPICE: {}", profile_path.display()));
-        drop(l);
-        let result = Command::new(&config.remote_app)
-            .env("DISPLAY", ":0")
-            .arg("-c")
-            .arg(profile_path.to_str().unwrap())
-            .stdin(Stdio::null())
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .spawn();
-        if result.is_ok() { return true; }
-    }
-    // Otherwise, use virt-viewer.
-    let result = Command::new("virt-viewer")
-        .arg(format!("spice://127.0.0.1:{}", spice_port))
-        .stdin(Stdio::null())
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .spawn();
-    result.is_ok()
-}
-
-fn connect_spice_macos(spice_port: u16, vm_conf: &Path, config: &Config, logs: &Arc<Mutex<Vec<String>>>) -> bool {
-    let mut l = logs.lock().unwrap();
-    l.push("Connecting via SPICE on macOS using Remote Viewer".into());
-    drop(l);
-    // If an override exists, use it.
-    if let Some(profile_path) = remmina_profile_for_vm(vm_conf, config) {
-        let mut l = logs.lock().unwrap();
-        l.push(format!("Using override Remmina profile for SPICE: {}", profile_path.display()));
-        drop(l);
-        let result = Command::new(&config.remote_app)
-            .env("DISPLAY", ":0")
-            .arg("-c")
-            .arg(profile_path.to_str().unwrap())
-            .stdin(Stdio::null())
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .spawn();
-        if result.is_ok() { return true; }
-    }
-    let url = format!("spice://127.0.0.1:{}", spice_port);
-    let result = Command::new("open")
-        .arg(url)
-        .stdin(Stdio::null())
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .spawn();
-    result.is_ok()
-}
-
-fn connect_spice_linux(spice_port: u16, vm_conf: &Path, config: &Config, logs: &Arc<Mutex<Vec<String>>>) -> bool {
-    let _ = std::env::var("DISPLAY").unwrap_or_else(|_| ":0".to_string());
-    if let Ok(result) = Command::new(&config.remote_app)
-        .env("DISPLAY", ":0")
-        .arg("--quiet")
-        .arg("-p")
-        .arg("spice")
-        .arg(format!("spice://127.0.0.1:{}", spice_port))
-        .stdin(Stdio::null())
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .spawn()
-    {
-        if result.id() > 0 {
-            return true;
+/// `quick-cli --connect <vm>`: start the VM if needed, launch its viewer, print the log and exit.
+fn connect_from_cli(config: &Config, name: &str) -> Result<(), Box<dyn Error>> {
+    let vm_conf = list_vms(config)
+        .into_iter()
+        .find(|vm| vm.file_stem().unwrap().to_string_lossy().eq_ignore_ascii_case(name))
+        .ok_or_else(|| format!("unknown VM {}", name))?;
+    let logs = Arc::new(Mutex::new(Vec::new()));
+    let mut started = Ok(String::new());
+    if !is_vm_running(&vm_conf, config) {
+        if config.read_only {
+            return Err(format!("{} is not running and read-only mode is on", name).into());
         }
+        if disk::disk_encryption(&vm_conf).is_some() {
+            return Err(format!("{} has an encrypted disk; start it from the TUI", name).into());
+        }
+        started = start_vm(&vm_conf, config, &logs, None, false).map_err(String::from);
     }
-    {
-        let mut l = logs.lock().unwrap();
-        l.push("Remmina SPICE launch failed, trying spicy...".into());
-    }
-    let result = Command::new("spicy")
-        .env("DISPLAY", ":0")
-        .arg("--title")
-        .arg(vm_conf.file_stem().unwrap().to_string_lossy().as_ref())
-        .arg("-h")
-        .arg("127.0.0.1")
-        .arg("-p")
-        .arg(spice_port.to_string())
-        .stdin(Stdio::null())
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .spawn();
-    if result.is_ok() { return true; }
-    {
-        let mut l = logs.lock().unwrap();
-        l.push("spicy launch failed, trying remote-viewer...".into());
+    let result = started.and_then(|_| connect_vm(&vm_conf, config, &logs));
+    for line in logs.lock().unwrap().iter() {
+        println!("{}", line);
     }
-    let result = Command::new("remote-viewer")
-        .env("DISPLAY", ":0")
-        .arg(format!("spice://127.0.0.1:{}", spice_port))
-        .stdin(Stdio::null())
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .spawn();
-    result.is_ok()
+    result.map(|_| ()).map_err(Into::into)
 }
 
-///////////////////////////////////////////////////////////////////////////////
-// Stop VM and App UI
-///////////////////////////////////////////////////////////////////////////////
-
-fn stop_vm(vm_conf: &Path, _config: &Config, logs: &Arc<Mutex<Vec<String>>>) {
-    {
-        let mut l = logs.lock().unwrap();
-        l.push(format!("Stopping VM {}...", vm_conf.display()));
+/// `quick-cli migrate-config`: write the converted config (print it with --dry-run) and
+/// report the legacy lines left behind.
+fn migrate_config_from_cli(cli: &cli::Cli) -> Result<(), Box<dyn Error>> {
+    let (path, toml, unmapped) =
+        migrate_config(cli.from.as_deref(), cli.config_path.as_deref(), cli.force, cli.dry_run)?;
+    if cli.dry_run {
+        println!("# Would write {}:\n{}", path.display(), toml);
+    } else {
+        println!("{}", paint(&format!("Wrote {}.", path.display()), Tone::Good));
     }
-    let vm_arg = vm_conf.as_os_str();
-    let quickemu_cmd = if cfg!(target_os = "windows") {
-        "quickemu.exe"
+    if unmapped.is_empty() {
+        println!("Every setting was migrated.");
     } else {
-        "quickemu"
-    };
-    let result = Command::new(quickemu_cmd)
-        .arg("--kill")
-        .arg("--vm")
-        .arg(vm_arg)
-        .stdin(Stdio::null())
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .spawn();
-    match result {
-        Ok(_) => {
-            let mut l = logs.lock().unwrap();
-            l.push(format!("Stop command issued for {}.", vm_conf.display()));
-        }
-        Err(e) => {
-            let mut l = logs.lock().unwrap();
-            l.push(format!("Error stopping VM {}: {}", vm_conf.display(), e));
+        println!("{}", paint("Not migrated (unknown key or malformed value):", Tone::Warn));
+        for line in &unmapped {
+            println!("  line {}", line);
         }
     }
+    Ok(())
 }
 
-///////////////////////////////////////////////////////////////////////////////
-// App UI
-///////////////////////////////////////////////////////////////////////////////
-
-use tui::widgets::ListState;
-
-struct App {
-    vm_list: Vec<PathBuf>,
-    list_state: ListState,
-    logs: Arc<Mutex<Vec<String>>>,
-    spinner_index: usize,
-}
-
-impl App {
-    fn new(vm_list: Vec<PathBuf>) -> Self {
-        let mut list_state = ListState::default();
-        if !vm_list.is_empty() {
-            list_state.select(Some(0));
+fn main() -> Result<(), Box<dyn Error>> {
+    let cli = cli::parse_args(std::env::args().skip(1)).map_err(|e| format!("{}\n\n{}", e, cli::USAGE))?;
+    if cli.help {
+        println!("{}", cli::USAGE);
+        return Ok(());
+    }
+    // Before load_config, which would create the config file from the legacy one itself.
+    if cli.command.as_deref() == Some("migrate-config") {
+        return migrate_config_from_cli(&cli);
+    }
+    let mut config = load_config(cli.config_path.as_deref())?;
+    config.read_only |= cli.read_only;
+    config.dry_run = cli.dry_run;
+    config.verbose |= cli.verbose;
+    let _demo_dir = if cli.demo { Some(demo::setup(&mut config)?) } else { None };
+    match cli.command.as_deref() {
+        Some("daemon") => return daemon::run(config, cli.events),
+        Some("events") => return events::run(config),
+        Some("watch") => return watch::run(config, cli.watch_mode, cli.once),
+        Some("apply") => return manifest::run(config, cli.manifest.as_deref().ok_or("apply needs a manifest path")?),
+        _ => {}
+    }
+    let viewers = config.detect_viewers();
+    if let Some(name) = &cli.connect {
+        return connect_from_cli(&config, name);
+    }
+    let remote = cli.remote.or_else(|| config.remote.clone());
+    let mut remote_dir = None;
+    let daemon = match &remote {
+        Some(_) if config.dry_run => return Err("--dry-run cannot be combined with a remote daemon".into()),
+        Some(addr) => {
+            let daemon = DaemonClient::remote(addr, config.control_token.as_deref())
+                .map_err(|e| format!("cannot reach the daemon at {}: {}", addr, e))?;
+            let dir = daemon::remote_dir(addr);
+            daemon.mirror(&dir)?;
+            // Viewers connect to the server itself unless the ports are forwarded to connect_host.
+            if let Some(host) = daemon::remote_host(addr)
+                && config.connect_host == Config::default().connect_host
+            {
+                config.connect_host = host;
+            }
+            config.quickemu_dirs = vec![dir.clone()];
+            remote_dir = Some(dir);
+            Some(daemon)
         }
-        Self {
-            vm_list,
-            list_state,
-            logs: Arc::new(Mutex::new(vec!["Application started.".into()])),
-            spinner_index: 0,
+        // A dry run must not hand real start/stop requests to a running daemon.
+        None if config.dry_run => None,
+        None => DaemonClient::connect(&config.control_socket),
+    };
+    let mut app = App::new(&config, daemon);
+    if let Some(name) = &config.layout {
+        match LayoutPreset::parse(name) {
+            Some(layout) => app.ui_state.layout = layout,
+            None => app.log(format!("Unknown layout {:?}; use list+logs, list+details, logs-only or dashboard.", name)),
         }
     }
-    fn update_spinner(&mut self) {
-        self.spinner_index = (self.spinner_index + 1) % SPINNER_FRAMES.len();
+    if let (Some(addr), Some(dir)) = (&remote, &remote_dir) {
+        app.log(format!("Driving the daemon at {}; its VMs are mirrored in {}.", addr, dir.display()));
     }
-}
-
-const SPINNER_FRAMES: [&str; 4] = ["-", "\\", "|", "/"];
-
-///////////////////////////////////////////////////////////////////////////////
-// Main Function
-///////////////////////////////////////////////////////////////////////////////
-
-fn main() -> Result<(), Box<dyn Error>> {
-    let config = load_config();
-    let vm_list = list_vms(&config);
-    let mut app = App::new(vm_list);
+    app.remote_dir = remote_dir;
+    app.logs.lock().unwrap().extend(viewers);
+    if config.git_sync && !config.dry_run {
+        sync::pull(&config.quickemu_dirs, &app.logs);
+        app.reload_vms(&config);
+    }
+    app.refresh_status(&config);
+    if app.remote_dir.is_none() {
+        app.find_orphans(&config);
+    }
+    crash::install(&app.logs);
+    let terminate = crash::termination_flag();
     enable_raw_mode()?;
+    let _guard = crash::TerminalGuard;
     let mut stdout = io::stdout();
     execute!(stdout, EnterAlternateScreen)?;
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
     let mut last_tick = Instant::now();
-    let tick_rate = Duration::from_millis(200);
+    let image_protocol = screenshots::protocol(&config);
+    let mut previewed: Option<(PathBuf, Rect)> = None; // Screenshot drawn over the gallery's preview area
     loop {
-        if last_tick.elapsed() >= tick_rate {
+        if terminate.load(Ordering::Relaxed) {
+            break;
+        }
+        if let Some(words) = app.foreground.take() {
+            run_suspended(&mut terminal, &words, &config, &app.logs)?;
+            app.reload_notes();
+        }
+        if last_tick.elapsed() >= config.tick_rate {
             app.update_spinner();
+            app.check_pending_stops(&config);
+            app.run_start_queue(&config);
+            app.refresh_status(&config);
+            app.sync_configs(&config);
+            app.run_backups(&config);
+            app.refresh_gallery();
+            app.trim_logs();
             last_tick = Instant::now();
         }
+        if let Some(browser) = app.file_browser.as_mut() {
+            browser.poll();
+        }
+        let preview = match image_protocol {
+            ImageProtocol::None => None,
+            _ => app.gallery.as_ref().and_then(|g| g.selected_shot().cloned()),
+        };
+        if previewed.as_ref().is_some_and(|(shot, _)| preview.as_ref() != Some(shot)) {
+            // Remove the old image before the cells under it are drawn again.
+            io::Write::write_all(terminal.backend_mut(), screenshots::clear(image_protocol))?;
+            terminal.clear()?;
+        }
+        let mut preview_area = None;
         terminal.draw(|f| {
+            let screen = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Length(1), Constraint::Min(0), Constraint::Length(1)].as_ref())
+                .split(f.area());
+            f.render_widget(
+                Paragraph::new(app.host_summary()).style(Style::default().fg(Color::Cyan)),
+                screen[0],
+            );
+            f.render_widget(Paragraph::new(app.status_bar()), screen[2]);
             let chunks = Layout::default()
                 .direction(Direction::Vertical)
-                .constraints([
-                    Constraint::Percentage(60),
-                    Constraint::Percentage(30),
-                    Constraint::Percentage(10),
-                ].as_ref())
-                .split(f.size());
+                .constraints(app.layout_constraints())
+                .split(screen[1]);
+            let footer_chunk = chunks[chunks.len() - 1];
             let items: Vec<ListItem> = app.vm_list.iter().map(|vm_conf| {
                 let name = vm_conf.file_stem().unwrap().to_string_lossy().to_string();
+                let running = app.is_running(vm_conf, &config);
                 let mut display_text = name.clone();
-                if is_vm_running(vm_conf, &config) {
+                if running {
                     let spinner = SPINNER_FRAMES[app.spinner_index];
                     display_text = format!("{} {}", spinner, name);
                 }
-                let span = if is_vm_running(vm_conf, &config) {
+                if let Some(stage) = stop_stage(vm_conf) {
+                    display_text = format!("{} (stopping… {})", display_text, stage.label());
+                }
+                if let Some(p) = app.pin_index(vm_conf) {
+                    display_text = format!("{} [{}]", display_text, p + 1);
+                }
+                // Source column when several directories are merged into one list.
+                if app.workspace.is_none() && config.quickemu_dirs.len() > 1
+                    && let Some(dir) = vm_conf.parent()
+                {
+                    display_text = format!("{:<40} [{}]", display_text, dir_label(dir));
+                }
+                let span = if running {
                     Span::styled(display_text, Style::default().fg(Color::Green).add_modifier(Modifier::BOLD))
+                } else if is_hidden(vm_conf, &config) {
+                    Span::styled(format!("{} (hidden)", display_text), Style::default().fg(Color::DarkGray))
                 } else {
                     Span::raw(display_text)
                 };
-                ListItem::new(Spans::from(span))
+                let os = app.guest_os.get(vm_conf).copied().unwrap_or(GuestOs::Other);
+                ListItem::new(Line::from(vec![
+                    Span::styled(format!("{} ", os.tag()), Style::default().fg(os_color(os))),
+                    span,
+                ]))
             }).collect();
+            let items: Vec<ListItem> = items
+                .into_iter()
+                .chain(app.external.lock().unwrap().iter().map(|vm| {
+                    let label = format!("{:<40} [{}]", vm.name, vm.backend);
+                    if vm.running {
+                        let text = format!("{} {}", SPINNER_FRAMES[app.spinner_index], label);
+                        ListItem::new(Span::styled(text, Style::default().fg(Color::Green).add_modifier(Modifier::BOLD)))
+                    } else {
+                        ListItem::new(label)
+                    }
+                }))
+                .collect();
+            let mut title = if config.quickemu_dirs.len() > 1 {
+                format!("Quick-CLI - VMs ({})", workspace_name(&config, app.workspace))
+            } else {
+                "Quick-CLI - VMs".to_string()
+            };
+            if config.read_only {
+                title.push_str(" [read-only]");
+            }
+            if config.demo {
+                title.push_str(" [demo]");
+            } else if config.dry_run {
+                title.push_str(" [dry-run]");
+            }
+            if !app.filter.is_empty() {
+                title.push_str(&format!(" [/{}]", app.filter));
+            }
+            let dashboard_title = format!("{} ([d] list view)", title);
             let vm_list_widget = List::new(items)
-                .block(Block::default().title("Quick-CLI - VMs").borders(Borders::ALL))
+                .block(Block::default().title(title).borders(Borders::ALL))
                 .highlight_symbol(">> ");
-            f.render_stateful_widget(vm_list_widget, chunks[0], &mut app.list_state);
-            let log_lines: Vec<Spans> = {
-                let logs = app.logs.lock().unwrap();
-                logs.iter().map(|line| Spans::from(Span::raw(line.clone()))).collect()
+            // Details of the selected VM: live metrics, then notes. Below the list, at most
+            // eight lines; list+details gives them the second pane instead.
+            let details = app.list_state.selected().and_then(|i| app.vm_list.get(i)).map(|vm| app.details(vm, &config));
+            let layout = app.ui_state.layout;
+            let details = match layout {
+                LayoutPreset::ListDetails => {
+                    let lines = details.filter(|lines| !lines.is_empty());
+                    let lines = lines.unwrap_or_else(|| vec![Line::from("No details for the selected VM.")]);
+                    let details_widget = Paragraph::new(lines)
+                        .wrap(Wrap { trim: false })
+                        .block(Block::default().title("Details ([N] edit notes)").borders(Borders::ALL));
+                    f.render_widget(details_widget, chunks[1]);
+                    None
+                }
+                LayoutPreset::ListLogs => details,
+                LayoutPreset::LogsOnly | LayoutPreset::Dashboard => None,
+            };
+            let list_area = match details.filter(|lines| !lines.is_empty()) {
+                Some(lines) => {
+                    let height = lines.len().min(8) as u16 + 2;
+                    let split = Layout::default()
+                        .direction(Direction::Vertical)
+                        .constraints([Constraint::Min(3), Constraint::Length(height)])
+                        .split(chunks[0]);
+                    let details_widget = Paragraph::new(lines)
+                        .wrap(Wrap { trim: false })
+                        .block(Block::default().title("Details ([N] edit notes)").borders(Borders::ALL));
+                    f.render_widget(details_widget, split[1]);
+                    split[0]
+                }
+                None => chunks[0],
+            };
+            match layout {
+                LayoutPreset::Dashboard => {
+                    let block = Block::default().title(dashboard_title).borders(Borders::ALL);
+                    let inner = block.inner(chunks[0]);
+                    f.render_widget(block, chunks[0]);
+                    app.dashboard_columns = draw_dashboard(f, inner, &app, &config);
+                }
+                LayoutPreset::ListLogs | LayoutPreset::ListDetails => {
+                    f.render_stateful_widget(vm_list_widget, list_area, &mut app.list_state);
+                }
+                LayoutPreset::LogsOnly => {}
+            }
+            let logs_area = match layout {
+                LayoutPreset::LogsOnly => Some(chunks[0]),
+                LayoutPreset::ListLogs | LayoutPreset::Dashboard if app.ui_state.show_logs => Some(chunks[1]),
+                _ => None,
             };
-            let logs_widget = Paragraph::new(log_lines)
-                .block(Block::default().title("Logs").borders(Borders::ALL));
-            f.render_widget(logs_widget, chunks[1]);
-            let footer_text = Spans::from(vec![
+            if let Some(logs_area) = logs_area {
+                let log_lines: Vec<Line> = {
+                    let logs = app.logs.lock().unwrap();
+                    logs.iter().map(|line| Line::from(line.clone())).collect()
+                };
+                // Keep the newest entries in view, with a scrollbar showing the position.
+                let visible = logs_area.height.saturating_sub(2) as usize;
+                let offset = log_lines.len().saturating_sub(visible);
+                let mut scrollbar_state =
+                    ScrollbarState::new(log_lines.len()).viewport_content_length(visible).position(offset);
+                let logs_widget = Paragraph::new(log_lines)
+                    .block(Block::default().title("Logs").borders(Borders::ALL))
+                    .scroll((u16::try_from(offset).unwrap_or(u16::MAX), 0));
+                f.render_widget(logs_widget, logs_area);
+                f.render_stateful_widget(
+                    Scrollbar::new(ScrollbarOrientation::VerticalRight),
+                    logs_area,
+                    &mut scrollbar_state,
+                );
+            }
+            let footer_text = if app.editing_filter {
+                Line::from(vec![
+                    Span::styled("/", Style::default().fg(Color::Yellow)),
+                    Span::raw(app.filter.clone()),
+                    Span::styled("_", Style::default().add_modifier(Modifier::SLOW_BLINK)),
+                ])
+            } else if let Some(input) = &app.command_line {
+                Line::from(vec![
+                    Span::styled(":", Style::default().fg(Color::Yellow)),
+                    Span::raw(input.clone()),
+                    Span::styled("_", Style::default().add_modifier(Modifier::SLOW_BLINK)),
+                ])
+            } else {
+                Line::from(vec![
                 Span::raw("Keybindings: "),
                 Span::styled("[r] Start", Style::default().fg(Color::Yellow)),
                 Span::raw(" | "),
@@ -801,79 +2273,644 @@ fn main() -> Result<(), Box<dyn Error>> {
                 Span::raw(" | "),
                 Span::styled("[s] Stop", Style::default().fg(Color::Yellow)),
                 Span::raw(" | "),
+                Span::styled("[t] Test", Style::default().fg(Color::Yellow)),
+                Span::raw(" | "),
                 Span::styled("[j/k] Navigate", Style::default().fg(Color::Yellow)),
                 Span::raw(" | "),
+                Span::styled("[L] Logs [+/-] Resize [d] Dashboard", Style::default().fg(Color::Yellow)),
+                Span::raw(" | "),
+                Span::styled("[:] Command [Ctrl+P] Palette [g] More", Style::default().fg(Color::Yellow)),
+                Span::raw(" | "),
                 Span::styled("[q] Quit", Style::default().fg(Color::Yellow)),
-            ]);
+            ])
+            };
+            let footer_title = match &app.macros.recording {
+                Some((register, _)) => format!("Footer - recording @{} ([M] to stop)", register),
+                None => "Footer".to_string(),
+            };
             let footer_widget = Paragraph::new(footer_text)
-                .block(Block::default().title("Footer").borders(Borders::ALL));
-            f.render_widget(footer_widget, chunks[2]);
-        })?;
-        if event::poll(Duration::from_millis(50))? {
-            if let Event::Key(key) = event::read()? {
-                match key.code {
-                    KeyCode::Char('q') => break,
-                    KeyCode::Down | KeyCode::Char('j') => {
-                        let i = match app.list_state.selected() {
-                            Some(i) if i >= app.vm_list.len() - 1 => 0,
-                            Some(i) => i + 1,
-                            None => 0,
-                        };
-                        app.list_state.select(Some(i));
+                .block(Block::default().title(footer_title).borders(Borders::ALL));
+            f.render_widget(footer_widget, footer_chunk);
+            if app.chord {
+                let lines: Vec<Line> = chords::CHORDS
+                    .iter()
+                    .map(|(key, label, _)| {
+                        Line::from(vec![
+                            Span::styled(format!(" {} ", key), Style::default().fg(Color::Yellow)),
+                            Span::raw(*label),
+                        ])
+                    })
+                    .collect();
+                let (width, height) = (44, lines.len() as u16 + 2);
+                let screen = f.area();
+                let area = Rect {
+                    x: screen.width.saturating_sub(width + 1),
+                    y: screen.height.saturating_sub(height + 4),
+                    width: width.min(screen.width),
+                    height: height.min(screen.height),
+                };
+                let hints = Paragraph::new(lines)
+                    .block(Block::default().title(format!("{} ...", chords::LEADER)).borders(Borders::ALL));
+                f.render_widget(Clear, area);
+                f.render_widget(hints, area);
+            }
+            if let Some(palette) = &app.palette {
+                let area = centered_rect(60, 50, f.area());
+                let popup = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints([Constraint::Length(3), Constraint::Min(0)].as_ref())
+                    .split(area);
+                let query = Paragraph::new(Line::from(vec![
+                    Span::styled("> ", Style::default().fg(Color::Yellow)),
+                    Span::raw(palette.query.clone()),
+                ]))
+                .block(Block::default().title("Command Palette").borders(Borders::ALL));
+                let items: Vec<ListItem> = palette
+                    .matches()
+                    .iter()
+                    .map(|e| ListItem::new(e.label.clone()))
+                    .collect();
+                let mut state = ListState::default();
+                state.select(Some(palette.selected));
+                let list = List::new(items)
+                    .block(Block::default().borders(Borders::ALL))
+                    .highlight_style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
+                    .highlight_symbol(">> ");
+                f.render_widget(Clear, area);
+                f.render_widget(query, popup[0]);
+                f.render_stateful_widget(list, popup[1], &mut state);
+            }
+            if let Some(selected) = app.key_menu {
+                let area = centered_rect(40, 50, f.area());
+                let items: Vec<ListItem> = qmp::KEY_COMBOS
+                    .iter()
+                    .map(|(label, _)| ListItem::new(*label))
+                    .collect();
+                let mut state = ListState::default();
+                state.select(Some(selected));
+                let list = List::new(items)
+                    .block(Block::default().title("Send Keys").borders(Borders::ALL))
+                    .highlight_style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
+                    .highlight_symbol(">> ");
+                f.render_widget(Clear, area);
+                f.render_stateful_widget(list, area, &mut state);
+            }
+            if let Some(browser) = &app.file_browser {
+                let area = centered_rect(90, 80, f.area());
+                let rows = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints([Constraint::Min(0), Constraint::Length(3)].as_ref())
+                    .split(area);
+                let panes = Layout::default()
+                    .direction(Direction::Horizontal)
+                    .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+                    .split(rows[0]);
+                f.render_widget(Clear, area);
+                for (pane, entries, title) in [
+                    (Pane::Local, &browser.local, format!("Local: {}", browser.local_dir.display())),
+                    (Pane::Remote, &browser.remote, format!("{}: {}", browser.vm_name, browser.remote_dir)),
+                ] {
+                    let items: Vec<ListItem> = entries
+                        .iter()
+                        .map(|e| ListItem::new(if e.is_dir { format!("{}/", e.name) } else { e.name.clone() }))
+                        .collect();
+                    let mut state = ListState::default();
+                    let focused = browser.focus == pane;
+                    if focused {
+                        state.select(Some(browser.selected[pane as usize]));
+                    }
+                    let border = if focused { Style::default().fg(Color::Yellow) } else { Style::default() };
+                    let list = List::new(items)
+                        .block(Block::default().title(title).borders(Borders::ALL).border_style(border))
+                        .highlight_symbol(">> ");
+                    f.render_stateful_widget(list, panes[pane as usize], &mut state);
+                }
+                let help = Paragraph::new(format!(
+                    "{} | [Tab] switch [Enter] open [Backspace] up [c] copy to other side [r] refresh [Esc] close",
+                    browser.status
+                ))
+                .block(Block::default().borders(Borders::ALL));
+                f.render_widget(help, rows[1]);
+            }
+            if let Some(view) = &app.diff_view {
+                let area = centered_rect(80, 70, f.area());
+                let lines: Vec<Line> = match &view.lines {
+                    None => vec![Line::from("No snapshot yet: one is taken whenever quick-cli starts or stops the VM.")],
+                    Some(lines) if lines.iter().all(|l| matches!(l, confdiff::DiffLine::Same(_))) => {
+                        vec![Line::from("No changes since the last start/stop.")]
                     }
-                    KeyCode::Up | KeyCode::Char('k') => {
-                        let i = match app.list_state.selected() {
-                            Some(0) | None => app.vm_list.len() - 1,
-                            Some(i) => i - 1,
+                    Some(lines) => lines
+                        .iter()
+                        .map(|line| match line {
+                            confdiff::DiffLine::Same(l) => Line::from(format!("  {}", l)),
+                            confdiff::DiffLine::Added(l) => {
+                                Line::from(Span::styled(format!("+ {}", l), Style::default().fg(Color::Green)))
+                            }
+                            confdiff::DiffLine::Removed(l) => {
+                                Line::from(Span::styled(format!("- {}", l), Style::default().fg(Color::Red)))
+                            }
+                        })
+                        .collect(),
+                };
+                let title = format!("{}.conf vs last start/stop (j/k scroll, Esc close)", view.vm_name);
+                let popup = Paragraph::new(lines)
+                    .block(Block::default().title(title).borders(Borders::ALL))
+                    .scroll((view.scroll, 0));
+                f.render_widget(Clear, area);
+                f.render_widget(popup, area);
+            }
+            if let Some(view) = &app.config_view {
+                let area = centered_rect(80, 80, f.area());
+                let title = format!("{} (read-only; Tab other config, j/k scroll, Esc close)", view.path.display());
+                let popup = Paragraph::new(view.lines())
+                    .block(Block::default().title(title).borders(Borders::ALL))
+                    .scroll((view.scroll, 0));
+                f.render_widget(Clear, area);
+                f.render_widget(popup, area);
+            }
+            if let Some(view) = &app.restore_view {
+                let area = centered_rect(80, 60, f.area());
+                let split = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints([Constraint::Min(0), Constraint::Length(3)])
+                    .split(area);
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+                let mut items: Vec<ListItem> = view
+                    .backups
+                    .iter()
+                    .map(|b| {
+                        let size = fs::metadata(&b.path).map(|m| host::format_gib(m.len())).unwrap_or_default();
+                        let age = format!("{} ago", format_uptime(now.saturating_sub(b.time)));
+                        let (kind, color) =
+                            if b.full { ("full", Color::Green) } else { ("  incremental", Color::Cyan) };
+                        ListItem::new(Line::from(vec![
+                            Span::styled(format!("{:<14}", kind), Style::default().fg(color)),
+                            Span::raw(format!("{:<10} {:>10}  ", age, size)),
+                            Span::styled(
+                                b.path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+                                Style::default().fg(Color::DarkGray),
+                            ),
+                        ]))
+                    })
+                    .collect();
+                if items.is_empty() {
+                    items.push(ListItem::new("No backups yet; `:backup <vm>` makes one."));
+                }
+                let mut state = ListState::default();
+                state.select(Some(view.selected));
+                let name = view.vm_conf.file_stem().unwrap().to_string_lossy();
+                let list = List::new(items)
+                    .block(Block::default().title(format!("Backups - {}", name)).borders(Borders::ALL))
+                    .highlight_symbol(">> ");
+                let prompt = match &view.step {
+                    RestoreStep::Browse => Line::from("[r] restore over the disk, [n] restore as a new VM, Esc close"),
+                    RestoreStep::ConfirmInPlace => Line::from(Span::styled(
+                        format!("Overwrite the disk of {} (it must be stopped)? [y/n]", name),
+                        Style::default().fg(Color::Yellow),
+                    )),
+                    RestoreStep::CloneName(input) => Line::from(vec![
+                        Span::styled("New VM name: ", Style::default().fg(Color::Yellow)),
+                        Span::raw(format!("{}_", input)),
+                    ]),
+                    RestoreStep::CloneSeed(input) => Line::from(Span::styled(
+                        format!("Give {} a cloud-init seed with your SSH keys? [y/n]", input),
+                        Style::default().fg(Color::Yellow),
+                    )),
+                };
+                f.render_widget(Clear, area);
+                f.render_stateful_widget(list, split[0], &mut state);
+                f.render_widget(Paragraph::new(prompt).block(Block::default().borders(Borders::ALL)), split[1]);
+            }
+            if let Some(selected) = app.jobs_view {
+                let area = centered_rect(90, 60, f.area());
+                let jobs = app.jobs.lock().unwrap();
+                let mut items: Vec<ListItem> = jobs
+                    .iter()
+                    .map(|job| {
+                        let (state, color) = match &job.state {
+                            JobState::Running => ("running".to_string(), Color::Yellow),
+                            JobState::Done => ("done".to_string(), Color::Green),
+                            JobState::Failed(e) => (format!("failed: {}", e), Color::Red),
+                        };
+                        let percent = match (&job.state, job.progress) {
+                            (JobState::Done, _) => Some(100.0),
+                            (_, progress) => progress,
+                        };
+                        // Jobs whose tool reports no progress (quickget) show an empty bar.
+                        let filled = (percent.unwrap_or(0.0) / 5.0).round().clamp(0.0, 20.0) as usize;
+                        let bar = format!(
+                            "[{}{}] {:>6}",
+                            "#".repeat(filled),
+                            ".".repeat(20 - filled),
+                            percent.map_or("--".to_string(), |p| format!("{:.1}%", p))
+                        );
+                        ListItem::new(vec![
+                            Line::from(vec![
+                                Span::raw(format!("{:<7} {:<20} {} ", job.kind, job.target, bar)),
+                                Span::styled(state, Style::default().fg(color)),
+                            ]),
+                            Line::from(Span::styled(
+                                format!("        {}", job.detail),
+                                Style::default().fg(Color::DarkGray),
+                            )),
+                        ])
+                    })
+                    .collect();
+                if items.is_empty() {
+                    items.push(ListItem::new("No jobs yet; `:backup <vm>` starts one."));
+                }
+                let mut state = ListState::default();
+                state.select(Some(selected.min(items.len() - 1)));
+                let list = List::new(items)
+                    .block(Block::default().title("Jobs (Esc close)").borders(Borders::ALL))
+                    .highlight_symbol(">> ");
+                f.render_widget(Clear, area);
+                f.render_stateful_widget(list, area, &mut state);
+            }
+            if let Some(view) = &app.process_view {
+                let area = centered_rect(90, 70, f.area());
+                let dash = || "-".to_string();
+                let items: Vec<ListItem> = view
+                    .processes
+                    .iter()
+                    .map(|p| {
+                        let cpu = p.cpu.map(|c| format!("{:.1}", c)).unwrap_or_else(dash);
+                        let rss = p.rss_kib.map(|k| format!("{}M", k / 1024)).unwrap_or_else(dash);
+                        let started = p.started.clone().unwrap_or_else(dash);
+                        let color = if p.kind == "qemu" { Color::Cyan } else { Color::Yellow };
+                        ListItem::new(Line::from(vec![
+                            Span::styled(format!("{:>7} {:<8}", p.pid, p.kind), Style::default().fg(color)),
+                            Span::raw(format!("{:>6} {:>7}  {}  {}", cpu, rss, started, p.command)),
+                        ]))
+                    })
+                    .collect();
+                let mut state = ListState::default();
+                state.select(Some(view.selected));
+                let title = "Processes: PID, kind, CPU%, RSS, started, command ([x] kill, [r] refresh, Esc close)";
+                let list = List::new(items)
+                    .block(Block::default().title(title).borders(Borders::ALL))
+                    .highlight_symbol(">> ");
+                f.render_widget(Clear, area);
+                f.render_stateful_widget(list, area, &mut state);
+            }
+            if let Some(gallery) = &app.gallery {
+                let area = centered_rect(90, 80, f.area());
+                let split = Layout::default()
+                    .direction(Direction::Horizontal)
+                    .constraints([Constraint::Length(24), Constraint::Min(0)])
+                    .split(area);
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+                let items: Vec<ListItem> = gallery
+                    .shots
+                    .iter()
+                    .map(|shot| {
+                        let taken: u64 = shot.file_stem().unwrap().to_string_lossy().parse().unwrap_or(0);
+                        let secs = now.saturating_sub(taken);
+                        ListItem::new(match secs {
+                            s if s < 60 => format!("{}s ago", s),
+                            s if s < 3600 => format!("{}m ago", s / 60),
+                            s if s < 86400 => format!("{}h ago", s / 3600),
+                            s => format!("{}d ago", s / 86400),
+                        })
+                    })
+                    .collect();
+                let mut state = ListState::default();
+                state.select(Some(gallery.selected));
+                let name = gallery.vm_conf.file_stem().unwrap().to_string_lossy();
+                let list = List::new(items)
+                    .block(Block::default().title(format!("Screenshots - {}", name)).borders(Borders::ALL))
+                    .highlight_symbol(">> ");
+                let block = Block::default().title("[s] new, [o] open, Esc close").borders(Borders::ALL);
+                let inner = block.inner(split[1]);
+                let text = match gallery.selected_shot() {
+                    None => "No screenshots yet; [s] takes one (needs a QMP socket).".to_string(),
+                    Some(_) if image_protocol != ImageProtocol::None => String::new(),
+                    Some(shot) => format!("{}\n\nNo inline preview here, see image_protocol.", shot.display()),
+                };
+                f.render_widget(Clear, area);
+                f.render_stateful_widget(list, split[0], &mut state);
+                f.render_widget(Paragraph::new(text).wrap(Wrap { trim: false }).block(block), split[1]);
+                preview_area = Some(inner);
+            }
+            if let Some(view) = &app.iso_view {
+                let area = centered_rect(80, 60, f.area());
+                let statuses = app.iso_statuses.lock().unwrap();
+                let items: Vec<ListItem> = view
+                    .entries
+                    .iter()
+                    .map(|(vm, path)| {
+                        let (label, color) = match statuses.get(path) {
+                            _ if !path.exists() => ("missing".to_string(), Color::DarkGray),
+                            Some(status @ iso::IsoStatus::Verified) => (status.label(), Color::Green),
+                            Some(status @ (iso::IsoStatus::Mismatch | iso::IsoStatus::Failed(_))) => {
+                                (status.label(), Color::Red)
+                            }
+                            Some(status) => (status.label(), Color::Yellow),
+                            None => ("not checked".to_string(), Color::Yellow),
                         };
-                        app.list_state.select(Some(i));
+                        ListItem::new(Line::from(vec![
+                            Span::raw(format!("{:<20} {} ", vm, path.display())),
+                            Span::styled(format!("[{}]", label), Style::default().fg(color)),
+                        ]))
+                    })
+                    .collect();
+                let mut state = ListState::default();
+                state.select(Some(view.selected));
+                let list = List::new(items)
+                    .block(Block::default().title("ISO Images ([v] verify, Esc close)").borders(Borders::ALL))
+                    .highlight_symbol(">> ");
+                f.render_widget(Clear, area);
+                f.render_stateful_widget(list, area, &mut state);
+            }
+            if let Some(pending) = &app.confirm_start {
+                let area = centered_rect(60, 20, f.area());
+                let mut text: Vec<Line> = pending.message.lines().map(|l| Line::from(l.to_string())).collect();
+                text.push(Line::from(Span::styled("Start anyway? [y/n]", Style::default().fg(Color::Yellow))));
+                let popup = Paragraph::new(text)
+                    .wrap(Wrap { trim: true })
+                    .block(Block::default().title("Over budget").borders(Borders::ALL));
+                f.render_widget(Clear, area);
+                f.render_widget(popup, area);
+            }
+            if let Some(pending) = &app.confirm_firewall {
+                let area = centered_rect(60, 20, f.area());
+                let name = pending.vm_conf.file_stem().unwrap().to_string_lossy();
+                let ports: Vec<String> = pending.ports.iter().map(u16::to_string).collect();
+                let (ports, tool) = (ports.join(", "), pending.tool.name());
+                let text = vec![
+                    Line::from(format!("Allow TCP {} from the network with {}?", ports, tool)),
+                    Line::from("The rules are removed when the VM stops."),
+                    Line::from(Span::styled("Open the ports? [y/n]", Style::default().fg(Color::Yellow))),
+                ];
+                let popup = Paragraph::new(text)
+                    .block(Block::default().title(format!("Firewall: {}", name)).borders(Borders::ALL));
+                f.render_widget(Clear, area);
+                f.render_widget(popup, area);
+            }
+            if let Some(view) = &app.orphan_view {
+                let area = centered_rect(70, 40, f.area());
+                let items: Vec<ListItem> = view
+                    .orphans
+                    .iter()
+                    .map(|(vm_conf, pid)| {
+                        let name = vm_conf.file_stem().unwrap().to_string_lossy().to_string();
+                        ListItem::new(Line::from(vec![
+                            Span::styled(format!("{:>7} ", pid), Style::default().fg(Color::Cyan)),
+                            Span::raw(name),
+                        ]))
+                    })
+                    .collect();
+                let mut state = ListState::default();
+                state.select(Some(view.selected));
+                let title = "qemu running without a pid file ([a] adopt, [A] adopt all, [x] kill, Esc leave)";
+                let list = List::new(items)
+                    .block(Block::default().title(title).borders(Borders::ALL))
+                    .highlight_symbol(">> ");
+                f.render_widget(Clear, area);
+                f.render_stateful_widget(list, area, &mut state);
+            }
+            if let Some(prompt) = &app.passphrase {
+                let area = centered_rect(50, 20, f.area());
+                let name = prompt.vm_conf.file_stem().unwrap().to_string_lossy();
+                let masked = Paragraph::new(Line::from(vec![
+                    Span::styled("Passphrase: ", Style::default().fg(Color::Yellow)),
+                    Span::raw("*".repeat(prompt.input.chars().count())),
+                ]))
+                .block(Block::default().title(format!("Unlock disk of {} (Esc to cancel)", name)).borders(Borders::ALL));
+                f.render_widget(Clear, area);
+                f.render_widget(masked, area);
+            }
+            if let Some(console) = &app.qmp_console {
+                let area = centered_rect(80, 70, f.area());
+                let popup = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints([Constraint::Min(0), Constraint::Length(3)].as_ref())
+                    .split(area);
+                let visible = popup[0].height.saturating_sub(2) as usize;
+                let offset = console.transcript.len().saturating_sub(visible);
+                let transcript: Vec<Line> = console.transcript.iter().map(|l| Line::from(l.clone())).collect();
+                let transcript = Paragraph::new(transcript)
+                    .block(Block::default().title(format!("QMP - {} (Esc to close)", console.vm_name)).borders(Borders::ALL))
+                    .scroll((u16::try_from(offset).unwrap_or(u16::MAX), 0));
+                let input = Paragraph::new(Line::from(vec![
+                    Span::styled("qmp> ", Style::default().fg(Color::Yellow)),
+                    Span::raw(console.input.clone()),
+                ]))
+                .block(Block::default().borders(Borders::ALL));
+                f.render_widget(Clear, area);
+                f.render_widget(transcript, popup[0]);
+                f.render_widget(input, popup[1]);
+            }
+        })?;
+        // Draw the image after the frame so it is not overwritten; again after a resize.
+        let current = preview.zip(preview_area);
+        if current != previewed {
+            if let Some((shot, area)) = &current {
+                match screenshots::render(shot, image_protocol, area.width, area.height) {
+                    Ok(image) => {
+                        execute!(terminal.backend_mut(), MoveTo(area.x, area.y))?;
+                        io::Write::write_all(terminal.backend_mut(), &image)?;
+                        io::Write::flush(terminal.backend_mut())?;
                     }
-                    KeyCode::Char('r') => {
-                        if let Some(i) = app.list_state.selected() {
-                            let vm_conf = &app.vm_list[i];
-                            start_vm(vm_conf, &config, &app.logs);
+                    Err(e) => app.log(format!("No preview of {}: {}", shot.display(), e)),
+                }
+            }
+            previewed = current;
+        }
+        // Keys of a replayed macro are handled exactly like typed ones, one per frame.
+        let replayed = app.macros.next_key();
+        let key = match replayed {
+            Some(key) => Some(key),
+            None if event::poll(Duration::from_millis(50))? => match event::read()? {
+                Event::Key(key) => Some(key),
+                _ => None,
+            },
+            None => None,
+        };
+        if let Some(key) = key {
+            // Some platforms also report key releases; act on presses only.
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            if replayed.is_none() {
+                app.macros.record(key);
+            }
+            if app.file_browser.is_some() {
+                app.file_browser_key(key.code, &config);
+                continue;
+            }
+            if app.diff_view.is_some() {
+                app.diff_key(key.code);
+                continue;
+            }
+            if app.config_view.is_some() {
+                app.config_view_key(key.code, &config);
+                continue;
+            }
+            if app.iso_view.is_some() {
+                app.iso_key(key.code, &config);
+                continue;
+            }
+            if app.restore_view.is_some() {
+                app.restore_key(key.code, &config);
+                continue;
+            }
+            if app.jobs_view.is_some() {
+                app.jobs_key(key.code);
+                continue;
+            }
+            if app.process_view.is_some() {
+                app.process_key(key.code, &config);
+                continue;
+            }
+            if app.gallery.is_some() {
+                app.gallery_key(key.code, &config);
+                continue;
+            }
+            if app.confirm_start.is_some() {
+                app.confirm_start_key(key.code, &config);
+                continue;
+            }
+            if app.confirm_firewall.is_some() {
+                app.confirm_firewall_key(key.code, &config);
+                continue;
+            }
+            if app.orphan_view.is_some() {
+                app.orphan_key(key.code, &config);
+                continue;
+            }
+            if app.passphrase.is_some() {
+                app.passphrase_key(key.code, &config);
+                continue;
+            }
+            if app.qmp_console.is_some() {
+                app.qmp_key(key.code);
+                continue;
+            }
+            if app.key_menu.is_some() {
+                app.key_menu_key(key.code);
+                continue;
+            }
+            if app.palette.is_some() {
+                app.palette_key(key.code, &config);
+                continue;
+            }
+            if app.editing_filter {
+                app.filter_key(key.code, &config);
+                continue;
+            }
+            if app.command_line.is_some() {
+                app.command_key(key.code, &config);
+                if app.should_quit {
+                    break;
+                }
+                continue;
+            }
+            if app.macro_key(key.code) {
+                continue;
+            }
+            if app.chord {
+                app.chord_key(key.code, &config);
+                continue;
+            }
+            let dashboard = app.ui_state.layout == LayoutPreset::Dashboard;
+            match key.code {
+                KeyCode::Char('p') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    app.palette = Some(Palette::new(&app.vm_names()));
+                }
+                KeyCode::Char('q') => break,
+                KeyCode::Char(':') => app.command_line = Some(String::new()),
+                KeyCode::Char('Q') => app.open_qmp_console(&config),
+                KeyCode::Char('K') => app.open_key_menu(&config),
+                KeyCode::Char('V') => app.paste_clipboard(&config),
+                KeyCode::Char('I') => app.open_iso_view(&config),
+                KeyCode::Char('P') => app.open_process_view(),
+                KeyCode::Char('J') => app.jobs_view = Some(0),
+                KeyCode::Char('B') => app.open_restore_view(&config),
+                KeyCode::Char('G') => app.open_gallery(),
+                KeyCode::Char('D') => app.open_diff_view(),
+                KeyCode::Char('C') => app.open_config_view(true, &config),
+                KeyCode::Char('S') => app.open_console(console::ConsoleKind::Ssh, &config),
+                KeyCode::Char('O') => app.open_console(console::ConsoleKind::Serial, &config),
+                KeyCode::Char('F') => app.open_file_browser(&config),
+                KeyCode::Char('o') => app.open_vm_dir(&config),
+                KeyCode::Char('L') => app.toggle_logs(),
+                KeyCode::Char('w') => app.next_workspace(&config),
+                KeyCode::Char('H') => app.toggle_hidden(&config),
+                KeyCode::Char('/') => app.editing_filter = true,
+                KeyCode::Char('N') => app.edit_notes(&config),
+                KeyCode::Char('p') => app.toggle_pin(),
+                KeyCode::Char('m') => app.toggle_sort(),
+                KeyCode::Char(c @ '1'..='9') => app.jump_to_pin(c as usize - '1' as usize),
+                KeyCode::Char('+') | KeyCode::Char('=') => app.resize_list(5),
+                KeyCode::Char('-') => app.resize_list(-5),
+                KeyCode::Char('d') => app.toggle_dashboard(),
+                KeyCode::Char(chords::LEADER) => app.chord = true,
+                KeyCode::Tab => app.next_layout(),
+                KeyCode::Left | KeyCode::Char('h') if dashboard => app.move_selection(-1),
+                KeyCode::Right | KeyCode::Char('l') if dashboard => app.move_selection(1),
+                KeyCode::Down | KeyCode::Char('j') if dashboard => app.move_selection(app.dashboard_columns as isize),
+                KeyCode::Up | KeyCode::Char('k') if dashboard => app.move_selection(-(app.dashboard_columns as isize)),
+                KeyCode::Down | KeyCode::Char('j') if app.list_len() > 0 => {
+                    let i = match app.list_state.selected() {
+                        Some(i) if i >= app.list_len() - 1 => 0,
+                        Some(i) => i + 1,
+                        None => 0,
+                    };
+                    app.list_state.select(Some(i));
+                }
+                KeyCode::Up | KeyCode::Char('k') if app.list_len() > 0 => {
+                    let i = match app.list_state.selected() {
+                        Some(0) | None => app.list_len() - 1,
+                        Some(i) => i - 1,
+                    };
+                    app.list_state.select(Some(i));
+                }
+                KeyCode::Char('r' | 's' | 'c') | KeyCode::Enter if app.selected_external().is_some() => {
+                    let vm = app.selected_external().unwrap();
+                    match key.code {
+                        KeyCode::Char('r') => {
+                            app.start_external(&vm, &config);
                         }
+                        KeyCode::Char('s') => app.stop_external(&vm, &config),
+                        KeyCode::Char('c') => app.connect_external(&vm, &config, false),
+                        _ => app.connect_external(&vm, &config, true),
                     }
-                    KeyCode::Enter => {
-                        if let Some(i) = app.list_state.selected() {
-                            let vm_conf = &app.vm_list[i];
-                            start_vm(vm_conf, &config, &app.logs);
-                            connect_vm(vm_conf, &config, &app.logs);
-                        }
+                }
+                KeyCode::Char('r') => {
+                    if let Some(vm_conf) = app.list_state.selected().and_then(|i| app.vm_list.get(i)).cloned() {
+                        app.start(&vm_conf, &config);
                     }
-                    KeyCode::Char('c') => {
-                        if let Some(i) = app.list_state.selected() {
-                            let vm_conf = &app.vm_list[i];
-                            if is_vm_running(vm_conf, &config) {
-                                connect_vm(vm_conf, &config, &app.logs);
-                            } else {
-                                let mut l = app.logs.lock().unwrap();
-                                l.push(format!("VM {} is not running; cannot connect.", vm_conf.display()));
-                            }
-                        }
+                }
+                KeyCode::Enter => {
+                    if let Some(vm_conf) = app.list_state.selected().and_then(|i| app.vm_list.get(i)).cloned() {
+                        app.start_and_connect(&vm_conf, &config);
                     }
-                    KeyCode::Char('v') => {
-                        if let Some(i) = app.list_state.selected() {
-                            let vm_conf = &app.vm_list[i];
-                            let mut l = app.logs.lock().unwrap();
-                            l.push(format!("Force SPICE connect for {}.", vm_conf.display()));
-                            drop(l);
-                            force_spice_connect(vm_conf, &config, &app.logs);
-                        }
+                }
+                KeyCode::Char('c') => {
+                    if let Some(vm_conf) = app.list_state.selected().and_then(|i| app.vm_list.get(i)).cloned() {
+                        app.connect(&vm_conf, &config);
                     }
-                    KeyCode::Char('s') => {
-                        if let Some(i) = app.list_state.selected() {
-                            let vm_conf = &app.vm_list[i];
-                            stop_vm(vm_conf, &config, &app.logs);
-                        }
+                }
+                KeyCode::Char('v') => {
+                    if let Some(vm_conf) = app.list_state.selected().and_then(|i| app.vm_list.get(i)).cloned() {
+                        app.force_spice(&vm_conf, &config);
                     }
-                    _ => {}
                 }
+                KeyCode::Char('t') => {
+                    if let Some(vm_conf) = app.list_state.selected().and_then(|i| app.vm_list.get(i)) {
+                        let report = diagnose_vm(vm_conf, &config);
+                        app.logs.lock().unwrap().extend(report);
+                    }
+                }
+                KeyCode::Char('s') => {
+                    if let Some(vm_conf) = app.list_state.selected().and_then(|i| app.vm_list.get(i)).cloned() {
+                        app.stop(&vm_conf, &config);
+                    }
+                }
+                _ => {}
             }
         }
     }
-    disable_raw_mode()?;
-    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
-    terminal.show_cursor()?;
     Ok(())
 }
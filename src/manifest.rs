@@ -0,0 +1,273 @@
+///////////////////////////////////////////////////////////////////////////////
+// Declarative Manifests
+///////////////////////////////////////////////////////////////////////////////
+//
+// `quick-cli apply manifest.toml` brings the VMs a manifest lists to the state
+// it describes, for lab setups that have to be reproducible:
+//
+//     dir = "~/vms"                 # where missing VMs are created (default: first quickemu_dirs entry)
+//
+//     [vm."ubuntu-server-24.04"]    # names with dots are quoted
+//     quickget = ["ubuntu-server", "24.04"]   # creates the VM when its .conf is missing
+//     cloud_init = true                       # ...with a cloud-init seed, see cloudinit.rs
+//     ram = "4G"
+//     cpu_cores = 2
+//     disk_size = "64G"
+//     running = true                          # start it; false stops it, unset leaves it alone
+//     settings = { port_forwards = ["2222:22"] }   # any other .conf keys
+//
+// Each run is idempotent: only differences are acted on. VMs missing from the
+// manifest are never touched, and nothing is ever deleted. Resource changes
+// apply from the VM's next start.
+
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    cloudinit, daemon::DaemonClient, download, expand_path, is_vm_running, list_vms, quickget_command, start_vm,
+    stop_stage, stop_vm,
+    term::{paint, Progress, Tone},
+    Config,
+};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    dir: Option<PathBuf>,
+    #[serde(default)]
+    vm: BTreeMap<String, VmSpec>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct VmSpec {
+    quickget: Vec<String>,                   // quickget arguments: <os> <release> [edition]
+    cloud_init: bool,                        // Seed a VM created here with cloud-init
+    running: Option<bool>,                   // Desired state; None leaves it as it is
+    ram: Option<String>,                     // quickemu's ram, e.g. "4G"
+    cpu_cores: Option<u32>,                  // quickemu's cpu_cores
+    disk_size: Option<String>,               // quickemu's disk_size; only used when the disk is created
+    settings: BTreeMap<String, toml::Value>, // Other .conf keys
+}
+
+/// One .conf value in quickemu's bash syntax: `"text"` or `("a" "b")` for lists.
+fn conf_value(value: &toml::Value) -> Result<String, String> {
+    let quote = |v: &toml::Value| match v {
+        toml::Value::String(s) => Ok(format!("\"{}\"", s)),
+        toml::Value::Integer(_) | toml::Value::Float(_) | toml::Value::Boolean(_) => Ok(format!("\"{}\"", v)),
+        _ => Err(format!("unsupported value {}", v)),
+    };
+    match value {
+        toml::Value::Array(items) => {
+            let items: Result<Vec<String>, String> = items.iter().map(quote).collect();
+            Ok(format!("({})", items?.join(" ")))
+        }
+        v => quote(v),
+    }
+}
+
+/// The .conf keys the spec sets, with their values in .conf syntax.
+fn wanted_values(spec: &VmSpec) -> Result<Vec<(String, String)>, String> {
+    let mut wanted = Vec::new();
+    if let Some(ram) = &spec.ram {
+        wanted.push(("ram".to_string(), format!("\"{}\"", ram)));
+    }
+    if let Some(cores) = spec.cpu_cores {
+        wanted.push(("cpu_cores".to_string(), format!("\"{}\"", cores)));
+    }
+    if let Some(size) = &spec.disk_size {
+        wanted.push(("disk_size".to_string(), format!("\"{}\"", size)));
+    }
+    for (key, value) in &spec.settings {
+        wanted.push((key.clone(), conf_value(value).map_err(|e| format!("settings.{}: {}", key, e))?));
+    }
+    Ok(wanted)
+}
+
+/// Set `key=value` in .conf contents, replacing an existing line or appending one.
+/// Returns the previous value when it changed.
+fn set_value(contents: &mut String, key: &str, value: &str) -> Option<Option<String>> {
+    let mut lines: Vec<String> = contents.lines().map(String::from).collect();
+    let existing = lines.iter().position(|line| line.split_once('=').is_some_and(|(k, _)| k.trim() == key));
+    let previous = match existing {
+        Some(i) => {
+            let old = lines[i].split_once('=').map(|(_, v)| v.trim().to_string()).unwrap_or_default();
+            if old == value {
+                return None;
+            }
+            lines[i] = format!("{}={}", key, value);
+            Some(old)
+        }
+        None => {
+            lines.push(format!("{}={}", key, value));
+            None
+        }
+    };
+    *contents = lines.join("\n") + "\n";
+    Some(previous)
+}
+
+/// Create a missing VM with quickget, its output passed through to the terminal.
+fn create(name: &str, dir: &Path, spec: &VmSpec, config: &Config, out: &dyn Fn(String)) -> Result<PathBuf, String> {
+    if spec.quickget.is_empty() {
+        return Err("it does not exist and has no quickget arguments to create it".into());
+    }
+    let vm_conf = dir.join(format!("{}.conf", name));
+    let words: Vec<&str> = spec.quickget.iter().map(String::as_str).collect();
+    let mut command = quickget_command(config, &words);
+    command.current_dir(dir).stdin(Stdio::null());
+    out(format!("creating it with quickget {}", words.join(" ")));
+    if config.dry_run {
+        let _ = download::prefetch(dir, &words, config, out, &|_| {});
+        return Ok(vm_conf);
+    }
+    fs::create_dir_all(dir).map_err(|e| format!("cannot create {}: {}", dir.display(), e))?;
+    let bar = Progress::new(format!("{}: downloading", name));
+    let prefetched = download::prefetch(dir, &words, config, out, &|percent| bar.set(percent));
+    bar.finish();
+    if let Err(e) = prefetched {
+        out(paint(&format!("{}; quickget downloads the image instead", e), Tone::Warn));
+    }
+    let status = command.status().map_err(|e| format!("cannot run quickget: {}", e))?;
+    if !status.success() {
+        return Err(format!("quickget failed ({})", status));
+    }
+    if !vm_conf.exists() {
+        return Err(format!("quickget did not write {}; name the VM after the .conf it writes", vm_conf.display()));
+    }
+    Ok(vm_conf)
+}
+
+/// Wait for stops issued by stop_vm, which escalate in background threads.
+fn wait_for_stops(vms: &[PathBuf], config: &Config) {
+    let deadline = Instant::now() + config.stop_timeout + Duration::from_secs(30);
+    let bar = Progress::new(format!("Waiting for {} VMs to stop", vms.len()));
+    loop {
+        let stopping = vms.iter().filter(|vm| stop_stage(vm).is_some()).count();
+        bar.set((vms.len() - stopping) as f32 * 100.0 / vms.len() as f32);
+        if stopping == 0 || Instant::now() >= deadline {
+            return;
+        }
+        thread::sleep(Duration::from_millis(500));
+    }
+}
+
+/// Reconcile one VM. Stops are only issued; the caller waits for them.
+fn apply_vm(
+    name: &str,
+    spec: &VmSpec,
+    dir: &Path,
+    config: &Config,
+    daemon: Option<&DaemonClient>,
+    logs: &Arc<Mutex<Vec<String>>>,
+    stopping: &mut Vec<PathBuf>,
+) -> Result<(), String> {
+    let out = |line: String| println!("{}: {}", paint(name, Tone::Bold), line);
+    let existing = list_vms(config)
+        .into_iter()
+        .find(|vm| vm.file_stem().unwrap().to_string_lossy().eq_ignore_ascii_case(name));
+    let created = existing.is_none();
+    let vm_conf = match existing {
+        Some(vm_conf) => vm_conf,
+        None => create(name, dir, spec, config, &out)?,
+    };
+    if created && config.dry_run {
+        out(paint("[dry-run] skipping its settings and state until it exists", Tone::Dim));
+        return Ok(());
+    }
+    if created && spec.cloud_init {
+        match cloudinit::write_seed(&vm_conf, config, logs) {
+            Ok(iso) => out(format!("attached cloud-init seed {}", iso.display())),
+            Err(e) => out(format!("no cloud-init seed: {}", e)),
+        }
+    }
+    let mut contents = fs::read_to_string(&vm_conf).map_err(|e| format!("cannot read {}: {}", vm_conf.display(), e))?;
+    let mut changed = false;
+    for (key, value) in wanted_values(spec)? {
+        if let Some(previous) = set_value(&mut contents, &key, &value) {
+            out(paint(&format!("{}={} (was {})", key, value, previous.as_deref().unwrap_or("unset")), Tone::Warn));
+            changed = true;
+        }
+    }
+    let running = is_vm_running(&vm_conf, config);
+    if changed {
+        if config.dry_run {
+            out(paint(&format!("[dry-run] {} not written", vm_conf.display()), Tone::Dim));
+        } else {
+            fs::write(&vm_conf, &contents).map_err(|e| format!("cannot write {}: {}", vm_conf.display(), e))?;
+            if running && spec.running != Some(false) {
+                out("changes apply from its next start".into());
+            }
+        }
+    }
+    match (spec.running, running) {
+        (Some(true), false) => {
+            out(paint("starting", Tone::Warn));
+            match daemon {
+                Some(daemon) => daemon.start(&vm_conf)?,
+                None => start_vm(&vm_conf, config, logs, None, false).map(|_| ())?,
+            }
+        }
+        (Some(false), true) => {
+            out(paint("stopping", Tone::Warn));
+            match daemon {
+                Some(daemon) => daemon.stop(&vm_conf)?,
+                None => {
+                    // Already being stopped is fine: it is waited for all the same.
+                    let _ = stop_vm(&vm_conf, config, logs);
+                    stopping.push(vm_conf);
+                }
+            }
+        }
+        _ if !changed && !created => out(paint("up to date", Tone::Good)),
+        _ => {}
+    }
+    Ok(())
+}
+
+/// `quick-cli apply <manifest>`: reconcile every VM the manifest lists, printing what
+/// changes. Fails when any VM could not be brought to its desired state.
+pub fn run(config: Config, path: &Path) -> Result<(), Box<dyn Error>> {
+    if config.read_only {
+        return Err("apply changes VMs and is refused in read-only mode".into());
+    }
+    let text = fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    let manifest: Manifest = toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let dir = match &manifest.dir {
+        Some(dir) => expand_path(dir),
+        None => config.quickemu_dirs[0].clone(),
+    };
+    // A running daemon owns starts and stops; a dry run must not reach it.
+    let daemon = if config.dry_run { None } else { DaemonClient::connect(&config.control_socket) };
+    let logs = Arc::new(Mutex::new(Vec::new()));
+    let flush = || logs.lock().unwrap().drain(..).for_each(|line| println!("  {}", line));
+    let mut stopping = Vec::new();
+    let mut failed = Vec::new();
+    for (name, spec) in &manifest.vm {
+        if let Err(e) = apply_vm(name, spec, &dir, &config, daemon.as_ref(), &logs, &mut stopping) {
+            println!("{}: {}", paint(name, Tone::Bold), paint(&format!("failed: {}", e), Tone::Error));
+            failed.push(name.as_str());
+        }
+        flush();
+    }
+    if !stopping.is_empty() {
+        println!("Waiting for {} VMs to stop...", stopping.len());
+        wait_for_stops(&stopping, &config);
+        flush();
+    }
+    if !failed.is_empty() {
+        return Err(format!("{} of {} VMs not applied: {}", failed.len(), manifest.vm.len(), failed.join(", ")).into());
+    }
+    println!("{}", paint(&format!("Applied {} ({} VMs).", path.display(), manifest.vm.len()), Tone::Good));
+    Ok(())
+}
@@ -0,0 +1,132 @@
+///////////////////////////////////////////////////////////////////////////////
+// mDNS Advertisement
+///////////////////////////////////////////////////////////////////////////////
+//
+// With `mdns = true` the services each running VM forwards are advertised on the
+// LAN over mDNS/DNS-SD, so other machines can find them in their viewers' and
+// file managers' network browsers:
+//     <vm>-ssh    _ssh._tcp   the "ssh" port in the VM's .ports file
+//     <vm>-rdp    _rdp._tcp   host port forwarded to guest port 3389
+//     <vm>-vnc    _rfb._tcp   host port forwarded to guest port 5900
+// Records are published by avahi-publish (Linux, needs avahi-daemon) or dns-sd
+// (macOS, Windows with Bonjour), one process per service, and withdrawn by
+// killing it when the VM stops. The daemon advertises; the TUI only does while
+// no daemon runs. VMs on other hosts (`[hosts]`) are not advertised.
+
+use std::{
+    collections::HashMap,
+    process::{Child, Command, Stdio},
+    sync::{Arc, Mutex},
+};
+
+use crate::{events::VmState, forwarded_port, list_vms, read_runtime, Config};
+
+/// Services of a running VM: (instance name, service type, host port).
+fn services(vm_conf: &std::path::Path) -> Vec<(String, &'static str, u16)> {
+    let vm = vm_conf.file_stem().unwrap().to_string_lossy();
+    let mut services = Vec::new();
+    if let Some(&port) = read_runtime(vm_conf).ports.get("ssh") {
+        services.push((format!("{}-ssh", vm), "_ssh._tcp", port));
+    }
+    if let Some(port) = forwarded_port(vm_conf, 3389) {
+        services.push((format!("{}-rdp", vm), "_rdp._tcp", port));
+    }
+    if let Some(port) = forwarded_port(vm_conf, 5900) {
+        services.push((format!("{}-vnc", vm), "_rfb._tcp", port));
+    }
+    services
+}
+
+/// The command that publishes one service until it is killed.
+fn publish_command(name: &str, service: &str, port: u16) -> Command {
+    if cfg!(target_os = "linux") {
+        let mut command = Command::new("avahi-publish");
+        command.args(["-s", name, service, &port.to_string()]);
+        command
+    } else {
+        let mut command = Command::new("dns-sd");
+        command.args(["-R", name, service, "local", &port.to_string()]);
+        command
+    }
+}
+
+/// Publisher processes per VM stem, kept in step with the VMs' states by `sync`.
+#[derive(Default)]
+pub struct Advertiser {
+    published: HashMap<String, Vec<(String, Child)>>,
+    unavailable: bool, // The publishing tool could not be run; logged once
+}
+
+impl Advertiser {
+    /// Advertise VMs that started and withdraw VMs that stopped since the last call.
+    pub fn sync(&mut self, states: &HashMap<String, VmState>, config: &Config, logs: &Arc<Mutex<Vec<String>>>) {
+        if !config.mdns || self.unavailable || config.dry_run {
+            return;
+        }
+        for children in self.published.values_mut() {
+            children.retain_mut(|(name, child)| match child.try_wait() {
+                Ok(Some(status)) => {
+                    logs.lock().unwrap().push(format!("mDNS: advertising {} stopped ({}).", name, status));
+                    false
+                }
+                _ => true,
+            });
+        }
+        let stopped: Vec<String> = self
+            .published
+            .keys()
+            .filter(|vm| states.get(*vm).is_none_or(|state| *state == VmState::Stopped))
+            .cloned()
+            .collect();
+        for vm in stopped {
+            for (_, mut child) in self.published.remove(&vm).unwrap_or_default() {
+                let _ = child.kill();
+                let _ = child.wait();
+            }
+            logs.lock().unwrap().push(format!("mDNS: withdrew {}.", vm));
+        }
+        for vm_conf in list_vms(config) {
+            let vm = vm_conf.file_stem().unwrap().to_string_lossy().to_string();
+            let running = states.get(&vm).is_some_and(|state| *state != VmState::Stopped);
+            if !running || self.published.contains_key(&vm) || config.vm_hosts.contains_key(&vm.to_lowercase()) {
+                continue;
+            }
+            let mut children = Vec::new();
+            for (name, service, port) in services(&vm_conf) {
+                let spawned = publish_command(&name, service, port)
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .spawn();
+                match spawned {
+                    Ok(child) => {
+                        let message = format!("mDNS: advertising {}.{}.local on port {}.", name, service, port);
+                        logs.lock().unwrap().push(message);
+                        children.push((name, child));
+                    }
+                    Err(e) => {
+                        let tool = if cfg!(target_os = "linux") { "avahi-publish" } else { "dns-sd" };
+                        logs.lock().unwrap().push(format!("mDNS: cannot run {}: {}; not advertising VMs.", tool, e));
+                        self.unavailable = true;
+                        for (_, mut child) in children {
+                            let _ = child.kill();
+                            let _ = child.wait();
+                        }
+                        return;
+                    }
+                }
+            }
+            self.published.insert(vm, children);
+        }
+    }
+}
+
+impl Drop for Advertiser {
+    /// Withdraw everything when quick-cli exits.
+    fn drop(&mut self) {
+        for (_, mut child) in self.published.drain().flat_map(|(_, children)| children) {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
@@ -0,0 +1,425 @@
+///////////////////////////////////////////////////////////////////////////////
+// Per-VM Metrics
+///////////////////////////////////////////////////////////////////////////////
+//
+// A background thread samples every running quickemu VM each METRICS_INTERVAL
+// and keeps the last HISTORY_LEN samples per VM. Samples hold cumulative
+// counters; throughput is the difference between the last two samples.
+//   cpu/mem  /proc/<pid>/stat and status of the qemu process (Linux), else
+//            `ps` (Get-Process on Windows)
+//   network  tap interfaces held by the qemu process (Linux, bridged or tap
+//            networking), else the guest agent's interface statistics (user
+//            networking has no host-side counters)
+//   disk     QMP query-blockstats summed over the VM's block devices (needs a
+//            QMP socket, see qmp.rs)
+// After each round the thresholds `alert_cpu_percent` (for
+// `alert_cpu_duration`) and `alert_free_ram_gib` are checked; a crossing is
+// logged and sent to the `notify` URLs once, and re-armed after recovery.
+// `:metrics <csv|json> [path]` writes the history to a file.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use serde::Serialize;
+
+use crate::{
+    events::now, find_qemu_process, guestip, host, list_vms, notify, pid_alive, qmp::QmpClient, read_runtime,
+    vm_runtime_file, Config,
+};
+
+pub const METRICS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Samples kept per VM: ten minutes at METRICS_INTERVAL.
+pub const HISTORY_LEN: usize = 120;
+
+/// Cumulative counters of one VM at one point in time. Network bytes are from the
+/// guest's point of view (received by the guest = sent on the host's tap).
+#[derive(Clone, Default)]
+pub struct Sample {
+    pub time: u64,
+    pub uptime: Option<u64>, // Seconds since the VM's pid file was written
+    pub cpu_ms: Option<u64>, // CPU time used by qemu so far
+    pub rss_kib: Option<u64>,
+    pub net_rx: Option<u64>,
+    pub net_tx: Option<u64>,
+    pub disk: Option<DiskCounters>,
+}
+
+/// Block I/O totals over all of a VM's drives.
+#[derive(Clone, Copy, Default)]
+pub struct DiskCounters {
+    pub rd_bytes: u64,
+    pub wr_bytes: u64,
+    pub rd_ops: u64,
+    pub wr_ops: u64,
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    history: HashMap<String, VecDeque<Sample>>,
+}
+
+impl Metrics {
+    /// Samples of a VM, oldest first.
+    pub fn history(&self, vm: &str) -> Option<&VecDeque<Sample>> {
+        self.history.get(vm)
+    }
+
+    /// Per-second rate of a counter between the last two samples.
+    pub fn rate(&self, vm: &str, counter: impl Fn(&Sample) -> Option<u64>) -> Option<f64> {
+        let samples = self.history.get(vm)?;
+        let (prev, last) = (samples.get(samples.len().checked_sub(2)?)?, samples.back()?);
+        rate_between(prev, last, &counter)
+    }
+
+    /// Per-second rates of a counter between consecutive samples, oldest first.
+    pub fn rates(&self, vm: &str, counter: impl Fn(&Sample) -> Option<u64>) -> Vec<f64> {
+        let Some(samples) = self.history.get(vm) else { return Vec::new() };
+        samples.iter().zip(samples.iter().skip(1)).filter_map(|(a, b)| rate_between(a, b, &counter)).collect()
+    }
+
+    /// Values of a gauge such as memory, oldest first.
+    pub fn values(&self, vm: &str, gauge: impl Fn(&Sample) -> Option<u64>) -> Vec<f64> {
+        let Some(samples) = self.history.get(vm) else { return Vec::new() };
+        samples.iter().filter_map(|s| gauge(s).map(|v| v as f64)).collect()
+    }
+
+    fn push(&mut self, vm: String, sample: Sample) {
+        let samples = self.history.entry(vm).or_default();
+        samples.push_back(sample);
+        if samples.len() > HISTORY_LEN {
+            samples.pop_front();
+        }
+    }
+}
+
+fn rate_between(prev: &Sample, last: &Sample, counter: impl Fn(&Sample) -> Option<u64>) -> Option<f64> {
+    let elapsed = last.time.checked_sub(prev.time).filter(|t| *t > 0)?;
+    Some(counter(last)?.saturating_sub(counter(prev)?) as f64 / elapsed as f64)
+}
+
+pub type SharedMetrics = Arc<Mutex<Metrics>>;
+
+/// The last `width` values as a bar sparkline scaled to their maximum.
+pub fn sparkline(values: &[f64], width: usize) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let values = &values[values.len().saturating_sub(width)..];
+    let max = values.iter().copied().fold(0.0, f64::max);
+    values
+        .iter()
+        .map(|v| if max > 0.0 { BARS[((v / max) * 7.0).round() as usize] } else { BARS[0] })
+        .collect()
+}
+
+/// Kibibytes for display, e.g. "2.1 GiB".
+pub fn format_kib(kib: u64) -> String {
+    match kib {
+        k if k >= 1 << 20 => format!("{:.1} GiB", k as f64 / (1 << 20) as f64),
+        k if k >= 1 << 10 => format!("{:.0} MiB", k as f64 / (1 << 10) as f64),
+        k => format!("{} KiB", k),
+    }
+}
+
+/// Byte rate for display, e.g. "1.2 MB/s".
+pub fn format_rate(bytes_per_sec: f64) -> String {
+    match bytes_per_sec {
+        r if r >= 1e9 => format!("{:.1} GB/s", r / 1e9),
+        r if r >= 1e6 => format!("{:.1} MB/s", r / 1e6),
+        r if r >= 1e3 => format!("{:.0} KB/s", r / 1e3),
+        r => format!("{:.0} B/s", r),
+    }
+}
+
+/// Interfaces of the tap devices a process has open, from the `iff:` line the
+/// tun driver adds to /proc/<pid>/fdinfo.
+#[cfg(target_os = "linux")]
+fn tap_interfaces(pid: u32) -> Vec<String> {
+    let Ok(fds) = fs::read_dir(format!("/proc/{}/fd", pid)) else { return Vec::new() };
+    fds.flatten()
+        .filter(|fd| fs::read_link(fd.path()).is_ok_and(|target| target == Path::new("/dev/net/tun")))
+        .filter_map(|fd| {
+            let fdinfo = format!("/proc/{}/fdinfo/{}", pid, fd.file_name().to_string_lossy());
+            let info = fs::read_to_string(fdinfo).ok()?;
+            info.lines().find_map(|l| l.strip_prefix("iff:")).map(|i| i.trim().to_string())
+        })
+        .collect()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn tap_interfaces(_pid: u32) -> Vec<String> {
+    Vec::new()
+}
+
+/// Guest (rx, tx) bytes summed over the VM's tap interfaces or its guest agent interfaces.
+fn net_counters(vm_conf: &Path, pid: u32) -> Option<(u64, u64)> {
+    let taps = tap_interfaces(pid);
+    if !taps.is_empty() {
+        let read = |iface: &str, counter: &str| -> Option<u64> {
+            let path = format!("/sys/class/net/{}/statistics/{}", iface, counter);
+            fs::read_to_string(path).ok()?.trim().parse().ok()
+        };
+        // The host transmits on the tap what the guest receives.
+        let rx = taps.iter().filter_map(|t| read(t, "tx_bytes")).sum();
+        let tx = taps.iter().filter_map(|t| read(t, "rx_bytes")).sum();
+        return Some((rx, tx));
+    }
+    let interfaces = guestip::guest_agent_interfaces(vm_conf)?;
+    let stats: Vec<&serde_json::Value> = interfaces
+        .iter()
+        .filter(|i| i["name"].as_str().is_some_and(|n| n != "lo" && !n.starts_with("Loopback")))
+        .filter_map(|i| i.get("statistics"))
+        .collect();
+    if stats.is_empty() {
+        return None;
+    }
+    let sum = |key: &str| stats.iter().filter_map(|s| s[key].as_u64()).sum();
+    Some((sum("rx-bytes"), sum("tx-bytes")))
+}
+
+/// CPU time in milliseconds and resident memory of a process. The kernel reports
+/// CPU time in USER_HZ ticks, which is 100 on every Linux architecture.
+#[cfg(target_os = "linux")]
+fn cpu_and_memory(pid: u32) -> Option<(u64, u64)> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name in parentheses may contain spaces; fields resume after it.
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    let ticks: u64 = fields.get(11)?.parse::<u64>().ok()? + fields.get(12)?.parse::<u64>().ok()?;
+    let status = fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    let rss = status.lines().find_map(|l| l.strip_prefix("VmRSS:"))?.trim().trim_end_matches("kB").trim().parse().ok()?;
+    Some((ticks * 10, rss))
+}
+
+/// CPU time from `ps -o time=`, [[dd-]hh:]mm:ss, so only whole seconds.
+#[cfg(all(unix, not(target_os = "linux")))]
+fn cpu_and_memory(pid: u32) -> Option<(u64, u64)> {
+    let output = std::process::Command::new("ps").args(["-o", "time=,rss=", "-p", &pid.to_string()]).output().ok()?;
+    let text = String::from_utf8_lossy(&output.stdout);
+    let mut fields = text.split_whitespace();
+    let time = fields.next()?;
+    let (days, clock) = time.split_once('-').unwrap_or(("0", time));
+    let seconds = clock.split(':').try_fold(0u64, |acc, part| Some(acc * 60 + part.parse::<f64>().ok()? as u64))?;
+    let rss = fields.next()?.parse().ok()?;
+    Some(((days.parse::<u64>().ok()? * 86400 + seconds) * 1000, rss))
+}
+
+#[cfg(not(unix))]
+fn cpu_and_memory(pid: u32) -> Option<(u64, u64)> {
+    let script = format!(
+        "$p = Get-Process -Id {} -ErrorAction SilentlyContinue; \
+         \"$([long]($p.CPU * 1000)) $([long]($p.WorkingSet64 / 1KB))\"",
+        pid
+    );
+    let output = std::process::Command::new("powershell")
+        .args(["-NoProfile", "-NonInteractive", "-Command", &script])
+        .output()
+        .ok()?;
+    let text = String::from_utf8_lossy(&output.stdout);
+    let mut fields = text.split_whitespace();
+    Some((fields.next()?.parse().ok()?, fields.next()?.parse().ok()?))
+}
+
+/// Block device counters from QMP query-blockstats.
+fn disk_counters(vm_conf: &Path) -> Option<DiskCounters> {
+    let stats = QmpClient::connect(vm_conf).ok()?.execute("query-blockstats", None).ok()?;
+    let mut counters = DiskCounters::default();
+    for device in stats.as_array()? {
+        let stat = |key: &str| device["stats"][key].as_u64().unwrap_or(0);
+        counters.rd_bytes += stat("rd_bytes");
+        counters.wr_bytes += stat("wr_bytes");
+        counters.rd_ops += stat("rd_operations");
+        counters.wr_ops += stat("wr_operations");
+    }
+    Some(counters)
+}
+
+/// The qemu pid of a running VM.
+fn qemu_pid(vm_conf: &Path) -> Option<u32> {
+    read_runtime(vm_conf).pid.filter(|pid| pid_alive(*pid)).or_else(|| find_qemu_process(vm_conf))
+}
+
+fn sample(vm_conf: &Path) -> Option<Sample> {
+    let pid = qemu_pid(vm_conf)?;
+    let net = net_counters(vm_conf, pid);
+    let usage = cpu_and_memory(pid);
+    let started = fs::metadata(vm_runtime_file(vm_conf, ".pid")).and_then(|m| m.modified()).ok();
+    Some(Sample {
+        time: now(),
+        uptime: started.and_then(|t| t.elapsed().ok()).map(|d| d.as_secs()),
+        cpu_ms: usage.map(|u| u.0),
+        rss_kib: usage.map(|u| u.1),
+        net_rx: net.map(|n| n.0),
+        net_tx: net.map(|n| n.1),
+        disk: disk_counters(vm_conf),
+    })
+}
+
+/// Threshold crossings already reported.
+#[derive(Default)]
+struct Alerts {
+    cpu_high_since: HashMap<String, u64>,
+    cpu_alerted: HashSet<String>,
+    low_memory: bool,
+}
+
+impl Alerts {
+    fn check(&mut self, metrics: &Metrics, config: &Config, logs: &Arc<Mutex<Vec<String>>>) {
+        let warn = |title: &str, message: String| {
+            logs.lock().unwrap().push(message.clone());
+            notify::send(config, title, &message, logs);
+        };
+        self.cpu_high_since.retain(|vm, _| metrics.history.contains_key(vm));
+        self.cpu_alerted.retain(|vm| metrics.history.contains_key(vm));
+        if config.alert_cpu_percent > 0 {
+            let time = now();
+            for vm in metrics.history.keys() {
+                let cpu = metrics.rate(vm, |s| s.cpu_ms).map(|ms| ms / 10.0);
+                if !cpu.is_some_and(|cpu| cpu > config.alert_cpu_percent as f64) {
+                    self.cpu_high_since.remove(vm);
+                    self.cpu_alerted.remove(vm);
+                    continue;
+                }
+                let since = *self.cpu_high_since.entry(vm.clone()).or_insert(time);
+                if time - since >= config.alert_cpu_duration.as_secs() && self.cpu_alerted.insert(vm.clone()) {
+                    let message = format!(
+                        "VM {} has used more than {}% CPU for {} s.",
+                        vm,
+                        config.alert_cpu_percent,
+                        config.alert_cpu_duration.as_secs()
+                    );
+                    warn("High CPU usage", message);
+                }
+            }
+        }
+        let threshold = config.alert_free_ram_gib.saturating_mul(1 << 30);
+        if let (Some(available), true) = (host::read_memory().0, threshold > 0) {
+            let low = available < threshold;
+            if low && !self.low_memory {
+                let message = format!("Only {} of memory available on the host.", host::format_gib(available));
+                warn("Low host memory", message);
+            }
+            self.low_memory = low;
+        }
+    }
+}
+
+/// Start the sampling thread. History of VMs that stop is dropped. With `alerts`
+/// the thresholds are checked after every round.
+pub fn spawn_poller(config: &Config, logs: &Arc<Mutex<Vec<String>>>, alerts: bool) -> SharedMetrics {
+    let metrics = SharedMetrics::default();
+    let (shared, config, logs) = (Arc::clone(&metrics), config.clone(), Arc::clone(logs));
+    let mut alarms = Alerts::default();
+    thread::spawn(move || {
+        loop {
+            let mut samples = Vec::new();
+            for vm_conf in list_vms(&config) {
+                let name = vm_conf.file_stem().unwrap().to_string_lossy().to_string();
+                samples.push((name, sample(&vm_conf)));
+            }
+            {
+                let mut metrics = shared.lock().unwrap();
+                for (name, sample) in samples {
+                    match sample {
+                        Some(sample) => metrics.push(name, sample),
+                        None => {
+                            metrics.history.remove(&name);
+                        }
+                    }
+                }
+                if alerts {
+                    alarms.check(&metrics, &config, &logs);
+                }
+            }
+            thread::sleep(METRICS_INTERVAL);
+        }
+    });
+    metrics
+}
+
+/// One sample as exported: counters as sampled, CPU as a percentage of one core
+/// since the previous sample.
+#[derive(Serialize)]
+struct ExportRow<'a> {
+    vm: &'a str,
+    time: u64,
+    uptime: Option<u64>,
+    cpu_percent: Option<f64>,
+    rss_kib: Option<u64>,
+    net_rx_bytes: Option<u64>,
+    net_tx_bytes: Option<u64>,
+    disk_rd_bytes: Option<u64>,
+    disk_wr_bytes: Option<u64>,
+    disk_rd_ops: Option<u64>,
+    disk_wr_ops: Option<u64>,
+}
+
+const CSV_HEADER: &str = "vm,time,uptime,cpu_percent,rss_kib,net_rx_bytes,net_tx_bytes,\
+                          disk_rd_bytes,disk_wr_bytes,disk_rd_ops,disk_wr_ops";
+
+impl ExportRow<'_> {
+    fn to_csv(&self) -> String {
+        let field = |v: Option<u64>| v.map(|v| v.to_string()).unwrap_or_default();
+        let vm = if self.vm.contains([',', '"']) {
+            format!("\"{}\"", self.vm.replace('"', "\"\""))
+        } else {
+            self.vm.to_string()
+        };
+        [
+            vm,
+            self.time.to_string(),
+            field(self.uptime),
+            self.cpu_percent.map(|c| format!("{:.1}", c)).unwrap_or_default(),
+            field(self.rss_kib),
+            field(self.net_rx_bytes),
+            field(self.net_tx_bytes),
+            field(self.disk_rd_bytes),
+            field(self.disk_wr_bytes),
+            field(self.disk_rd_ops),
+            field(self.disk_wr_ops),
+        ]
+        .join(",")
+    }
+}
+
+/// Write the history of every VM to `path` as CSV or JSON and return the number of samples.
+pub fn export(metrics: &Metrics, json: bool, path: &Path) -> Result<usize, String> {
+    let mut vms: Vec<&String> = metrics.history.keys().collect();
+    vms.sort();
+    let mut rows = Vec::new();
+    for vm in vms {
+        let samples = &metrics.history[vm];
+        for (i, sample) in samples.iter().enumerate() {
+            let previous = i.checked_sub(1).map(|p| &samples[p]);
+            rows.push(ExportRow {
+                vm,
+                time: sample.time,
+                uptime: sample.uptime,
+                cpu_percent: previous.and_then(|p| rate_between(p, sample, |s| s.cpu_ms)).map(|ms| ms / 10.0),
+                rss_kib: sample.rss_kib,
+                net_rx_bytes: sample.net_rx,
+                net_tx_bytes: sample.net_tx,
+                disk_rd_bytes: sample.disk.map(|d| d.rd_bytes),
+                disk_wr_bytes: sample.disk.map(|d| d.wr_bytes),
+                disk_rd_ops: sample.disk.map(|d| d.rd_ops),
+                disk_wr_ops: sample.disk.map(|d| d.wr_ops),
+            });
+        }
+    }
+    let text = if json {
+        serde_json::to_string_pretty(&rows).map_err(|e| e.to_string())?
+    } else {
+        let mut lines = vec![CSV_HEADER.to_string()];
+        lines.extend(rows.iter().map(ExportRow::to_csv));
+        lines.join("\n") + "\n"
+    };
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    fs::write(path, text).map_err(|e| e.to_string())?;
+    Ok(rows.len())
+}
@@ -0,0 +1,165 @@
+///////////////////////////////////////////////////////////////////////////////
+// MQTT State Publishing
+///////////////////////////////////////////////////////////////////////////////
+//
+// With `mqtt_host` set, `quick-cli daemon` publishes each VM's state to the
+// retained topic `<mqtt_prefix>/<vm>/state` ("stopped", "running" or "ready")
+// and listens on `<mqtt_prefix>/<vm>/command` for "start" and "stop", e.g. from
+// Home Assistant. The mosquitto clients (mosquitto_pub/mosquitto_sub) do the
+// MQTT work. `mqtt_password` is kept off their command lines, where `ps` shows
+// it: it goes in a private client config file under the state dir, which the
+// clients read from $XDG_CONFIG_HOME.
+
+use std::{
+    fs,
+    io::{BufRead, BufReader},
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use crate::{disk::write_private, events::Event, state_dir, Config};
+
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(10);
+
+/// Broker connection arguments shared by mosquitto_pub and mosquitto_sub.
+fn broker_args(config: &Config) -> Option<Vec<String>> {
+    let host = config.mqtt_host.as_deref()?;
+    let (host, port) = match host.rsplit_once(':') {
+        Some((h, p)) if p.parse::<u16>().is_ok() => (h, p),
+        _ => (host, "1883"),
+    };
+    let mut args = vec!["-h".to_string(), host.to_string(), "-p".to_string(), port.to_string()];
+    if let Some(user) = &config.mqtt_user {
+        args.extend(["-u".to_string(), user.clone()]);
+    }
+    Some(args)
+}
+
+/// A mosquitto client command with the broker arguments, reading `mqtt_password`
+/// from its config file; None without `mqtt_host`.
+fn client_command(program: &str, config: &Config) -> Option<Result<Command, String>> {
+    let args = broker_args(config)?;
+    let mut command = Command::new(program);
+    command.args(args);
+    if let Some(password) = &config.mqtt_password {
+        match write_client_config(program, password) {
+            Ok(dir) => command.env("XDG_CONFIG_HOME", dir),
+            Err(e) => return Some(Err(e)),
+        };
+    }
+    Some(Ok(command))
+}
+
+/// Write `-P <password>` to the client's config file, one option per line; returns its directory.
+fn write_client_config(program: &str, password: &str) -> Result<PathBuf, String> {
+    if password.contains(['\n', '\r']) {
+        return Err("mqtt_password cannot contain line breaks".into());
+    }
+    let dir = state_dir().join("mqtt");
+    let file = dir.join(program);
+    fs::create_dir_all(&dir)
+        .and_then(|_| write_private(&file, &format!("-P {}\n", password)))
+        .map_err(|e| format!("cannot write {}: {}", file.display(), e))?;
+    Ok(dir)
+}
+
+/// The VM and payload of a `mosquitto_sub -v` line ("<topic> <payload>") on a
+/// `<prefix>/<vm>/command` topic.
+fn parse_command_message<'a>(line: &'a str, prefix: &str) -> Option<(&'a str, &'a str)> {
+    let (topic, payload) = line.split_once(' ')?;
+    let vm = topic.strip_prefix(prefix)?.strip_prefix('/')?.strip_suffix("/command")?;
+    Some((vm, payload.trim()))
+}
+
+/// Publish the new state carried by a VM event as a retained message.
+pub fn publish(config: &Config, event: &Event, logs: &Arc<Mutex<Vec<String>>>) {
+    let Some((vm, state)) = event.vm_state() else { return };
+    let Some(command) = client_command("mosquitto_pub", config) else { return };
+    let mut command = match command {
+        Ok(command) => command,
+        Err(e) => return logs.lock().unwrap().push(format!("MQTT publish failed: {}", e)),
+    };
+    command.args(["-r", "-t", &format!("{}/{}/state", config.mqtt_prefix, vm), "-m", state.as_str()]);
+    let logs = Arc::clone(logs);
+    thread::spawn(move || {
+        let error = match command.output() {
+            Ok(output) if output.status.success() => return,
+            Ok(output) => String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            Err(e) => format!("failed to run mosquitto_pub: {}", e),
+        };
+        logs.lock().unwrap().push(format!("MQTT publish failed: {}", error));
+    });
+}
+
+/// Subscribe to the command topics in a background thread and call `on_command`
+/// with (vm, payload) for each message. Reconnects when mosquitto_sub exits.
+pub fn subscribe<F>(config: &Config, logs: &Arc<Mutex<Vec<String>>>, on_command: F)
+where
+    F: Fn(&str, &str) + Send + 'static,
+{
+    let Some(command) = client_command("mosquitto_sub", config) else { return };
+    let mut command = match command {
+        Ok(command) => command,
+        Err(e) => return logs.lock().unwrap().push(format!("MQTT subscribe failed: {}", e)),
+    };
+    let prefix = config.mqtt_prefix.clone();
+    command.args(["-v", "-t", &format!("{}/+/command", prefix)]).stdout(Stdio::piped()).stderr(Stdio::null());
+    let logs = Arc::clone(logs);
+    thread::spawn(move || {
+        loop {
+            let child = command.spawn();
+            match child {
+                Ok(mut child) => {
+                    logs.lock().unwrap().push(format!("Subscribed to MQTT topic {}/+/command.", prefix));
+                    // -v prints "<topic> <payload>" per message.
+                    for line in BufReader::new(child.stdout.take().unwrap()).lines().map_while(Result::ok) {
+                        if let Some((vm, payload)) = parse_command_message(&line, &prefix) {
+                            on_command(vm, payload);
+                        }
+                    }
+                    let _ = child.wait();
+                    logs.lock().unwrap().push("MQTT subscription ended; reconnecting.".into());
+                }
+                Err(e) => {
+                    logs.lock().unwrap().push(format!("Failed to run mosquitto_sub: {}", e));
+                }
+            }
+            thread::sleep(RESUBSCRIBE_DELAY);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_messages() {
+        let cases = [
+            ("quick-cli/win11/command start", Some(("win11", "start"))),
+            ("quick-cli/win11/command  stop \r", Some(("win11", "stop"))),
+            ("quick-cli/win11/state running", None),
+            ("other/win11/command start", None),
+            ("quick-cliX/win11/command start", None),
+            ("quick-cli/win11/command", None),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(parse_command_message(line, "quick-cli"), expected, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn broker_host_and_port() {
+        let mut config = Config::default();
+        assert_eq!(broker_args(&config), None);
+        config.mqtt_host = Some("broker.lan".into());
+        assert_eq!(broker_args(&config).unwrap(), ["-h", "broker.lan", "-p", "1883"]);
+        config.mqtt_host = Some("10.0.0.2:8883".into());
+        config.mqtt_user = Some("ha".into());
+        config.mqtt_password = Some("hunter2".into());
+        assert_eq!(broker_args(&config).unwrap(), ["-h", "10.0.0.2", "-p", "8883", "-u", "ha"]);
+    }
+}
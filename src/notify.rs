@@ -0,0 +1,77 @@
+///////////////////////////////////////////////////////////////////////////////
+// Chat Notifications
+///////////////////////////////////////////////////////////////////////////////
+//
+// `notify = [...]` URLs get a short message for events that need attention:
+// failed VM starts, low disk space on a VM directory and the alert thresholds
+// of metrics.rs. The message format follows the URL: Slack and Discord webhooks
+// get their JSON payloads, any other URL (e.g. https://ntfy.sh/<topic>) a
+// plain-text POST with a Title header as ntfy expects. Sent with curl in the
+// background.
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use crate::{host, Config};
+
+/// curl arguments (headers and body) for one notification URL.
+fn payload_args(url: &str, title: &str, message: &str) -> Vec<String> {
+    let json = |body: serde_json::Value| {
+        vec!["-H".into(), "Content-Type: application/json".into(), "--data-binary".into(), body.to_string()]
+    };
+    if url.contains("hooks.slack.com") {
+        json(serde_json::json!({ "text": format!("*{}*\n{}", title, message) }))
+    } else if url.contains("discord.com/api/webhooks") || url.contains("discordapp.com/api/webhooks") {
+        json(serde_json::json!({ "content": format!("**{}**\n{}", title, message) }))
+    } else {
+        vec!["-H".into(), format!("Title: {}", title), "--data-binary".into(), message.to_string()]
+    }
+}
+
+/// Send a notification to every configured URL in background threads.
+pub fn send(config: &Config, title: &str, message: &str, logs: &Arc<Mutex<Vec<String>>>) {
+    if config.dry_run {
+        return;
+    }
+    for url in &config.notify {
+        let args = payload_args(url, title, message);
+        let (url, logs) = (url.clone(), Arc::clone(logs));
+        thread::spawn(move || {
+            let result = Command::new("curl").args(["-fsS", "--max-time", "10"]).args(&args).arg(&url).output();
+            let error = match result {
+                Ok(output) if output.status.success() => return,
+                Ok(output) => String::from_utf8_lossy(&output.stderr).trim().to_string(),
+                Err(e) => format!("failed to run curl: {}", e),
+            };
+            logs.lock().unwrap().push(format!("Notification to {} failed: {}", url, error));
+        });
+    }
+}
+
+/// Notifies once when a VM directory's filesystem drops below `low_disk_gib`,
+/// and again only after it has recovered.
+#[derive(Default)]
+pub struct LowDiskAlarm {
+    low: HashSet<PathBuf>,
+}
+
+impl LowDiskAlarm {
+    pub fn check(&mut self, dir: &Path, disk_free: Option<u64>, config: &Config, logs: &Arc<Mutex<Vec<String>>>) {
+        let (Some(free), threshold) = (disk_free, config.low_disk_gib.saturating_mul(1 << 30)) else { return };
+        if threshold == 0 || config.notify.is_empty() {
+            return;
+        }
+        if free >= threshold {
+            self.low.remove(dir);
+        } else if self.low.insert(dir.to_path_buf()) {
+            let message = format!("Only {} free on the filesystem holding {}.", host::format_gib(free), dir.display());
+            logs.lock().unwrap().push(message.clone());
+            send(config, "Low disk space", &message, logs);
+        }
+    }
+}
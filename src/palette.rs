@@ -0,0 +1,123 @@
+///////////////////////////////////////////////////////////////////////////////
+// Fuzzy Command Palette
+///////////////////////////////////////////////////////////////////////////////
+//
+// Ctrl+P opens a palette listing every (action, VM) pair, e.g. "Connect win11".
+// Each whitespace-separated word of the query must match the entry label as a
+// case-insensitive subsequence, so "conn win" finds "Connect windows-11".
+
+#[derive(Clone, Copy)]
+pub enum PaletteAction {
+    StartConnect,
+    Start,
+    Connect,
+    ForceSpice,
+    Stop,
+}
+
+impl PaletteAction {
+    const ALL: [PaletteAction; 5] = [
+        PaletteAction::StartConnect,
+        PaletteAction::Start,
+        PaletteAction::Connect,
+        PaletteAction::ForceSpice,
+        PaletteAction::Stop,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            PaletteAction::StartConnect => "Start & Connect",
+            PaletteAction::Start => "Start",
+            PaletteAction::Connect => "Connect",
+            PaletteAction::ForceSpice => "Force SPICE connect",
+            PaletteAction::Stop => "Stop",
+        }
+    }
+}
+
+pub struct PaletteEntry {
+    pub label: String,
+    pub action: PaletteAction,
+    pub vm_index: usize,
+}
+
+pub struct Palette {
+    pub query: String,
+    pub selected: usize,
+    entries: Vec<PaletteEntry>,
+}
+
+/// Score a single query word against the label, or None if it is not a subsequence.
+/// Consecutive matches and matches at word starts score higher.
+fn word_score(word: &str, label: &str) -> Option<i64> {
+    let label: Vec<char> = label.chars().collect();
+    let mut score = 0;
+    let mut pos = 0;
+    let mut prev: Option<usize> = None;
+    for c in word.chars() {
+        let found = (pos..label.len()).find(|&i| label[i] == c)?;
+        score += 1;
+        if prev == Some(found.wrapping_sub(1)) {
+            score += 5;
+        }
+        if found == 0 || !label[found - 1].is_alphanumeric() {
+            score += 3;
+        }
+        prev = Some(found);
+        pos = found + 1;
+    }
+    Some(score)
+}
+
+pub fn fuzzy_score(query: &str, label: &str) -> Option<i64> {
+    let label = label.to_lowercase();
+    let mut total = 0;
+    for word in query.to_lowercase().split_whitespace() {
+        total += word_score(word, &label)?;
+    }
+    // Prefer shorter labels when scores tie, so "Start win" beats "Start & Connect win".
+    Some(total * 100 - label.len() as i64)
+}
+
+impl Palette {
+    pub fn new(vm_names: &[String]) -> Self {
+        let mut entries = Vec::new();
+        for (vm_index, name) in vm_names.iter().enumerate() {
+            for action in PaletteAction::ALL {
+                entries.push(PaletteEntry {
+                    label: format!("{} {}", action.label(), name),
+                    action,
+                    vm_index,
+                });
+            }
+        }
+        Self { query: String::new(), selected: 0, entries }
+    }
+
+    /// Entries matching the current query, best match first.
+    pub fn matches(&self) -> Vec<&PaletteEntry> {
+        if self.query.trim().is_empty() {
+            return self.entries.iter().collect();
+        }
+        let mut scored: Vec<(i64, &PaletteEntry)> = self
+            .entries
+            .iter()
+            .filter_map(|e| fuzzy_score(&self.query, &e.label).map(|s| (s, e)))
+            .collect();
+        scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+        scored.into_iter().map(|(_, e)| e).collect()
+    }
+
+    pub fn selected_entry(&self) -> Option<&PaletteEntry> {
+        self.matches().into_iter().nth(self.selected)
+    }
+
+    pub fn move_selection(&mut self, delta: isize) {
+        let count = self.matches().len();
+        if count == 0 {
+            self.selected = 0;
+            return;
+        }
+        self.selected = (self.selected as isize + delta).rem_euclid(count as isize) as usize;
+    }
+}
@@ -0,0 +1,154 @@
+///////////////////////////////////////////////////////////////////////////////
+// Process Inspector
+///////////////////////////////////////////////////////////////////////////////
+//
+// `P` lists the processes quick-cli knows about: every qemu-system process and
+// the viewers (and other programs) it launched itself, recorded by
+// spawn_detached, with CPU, RSS and start time from `ps` (PowerShell's
+// Get-Process on Windows). `x` kills the selected one, for untangling a stuck
+// viewer or VM. Spawned children are kept until they exit and then reaped, so
+// closed viewers do not linger as zombies; any that do are skipped.
+
+use std::{
+    process::{Child, Command},
+    sync::{Arc, Mutex},
+};
+
+use crate::{kill_command, pid_alive, qemu_processes, spawn_detached, Config};
+
+/// Viewers and other programs spawned by this process: (pid, command line).
+static SPAWNED: Mutex<Vec<(u32, String)>> = Mutex::new(Vec::new());
+
+/// Spawned children that have not exited yet.
+static CHILDREN: Mutex<Vec<Child>> = Mutex::new(Vec::new());
+
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub kind: &'static str, // "qemu" or "spawned"
+    pub command: String,
+    pub cpu: Option<f32>, // Percent of one core
+    pub rss_kib: Option<u64>,
+    pub started: Option<String>,
+}
+
+/// Remember a spawned program for the inspector.
+pub fn record(pid: u32, command: String) {
+    let mut spawned = SPAWNED.lock().unwrap();
+    spawned.retain(|(pid, _)| pid_alive(*pid));
+    spawned.push((pid, command));
+}
+
+/// Keep a spawned child until it exits, for reap.
+pub fn adopt(child: Child) {
+    CHILDREN.lock().unwrap().push(child);
+    reap();
+}
+
+/// Collect the exit status of adopted children that have exited.
+pub fn reap() {
+    CHILDREN.lock().unwrap().retain_mut(|child| matches!(child.try_wait(), Ok(None)));
+}
+
+struct Stats {
+    pid: u32,
+    zombie: bool,
+    cpu: f32,
+    rss_kib: u64,
+    started: String,
+}
+
+/// State, CPU, RSS and start time of each pid that still exists.
+#[cfg(unix)]
+fn stats(pids: &[u32]) -> Vec<Stats> {
+    if pids.is_empty() {
+        return Vec::new();
+    }
+    let list: Vec<String> = pids.iter().map(u32::to_string).collect();
+    let Ok(output) = Command::new("ps").args(["-o", "pid=,stat=,pcpu=,rss=,lstart=", "-p", &list.join(",")]).output()
+    else {
+        return Vec::new();
+    };
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            Some(Stats {
+                pid: fields.next()?.parse().ok()?,
+                zombie: fields.next()?.starts_with('Z'),
+                cpu: fields.next()?.parse().ok()?,
+                rss_kib: fields.next()?.parse().ok()?,
+                started: fields.collect::<Vec<_>>().join(" "),
+            })
+        })
+        .collect()
+}
+
+#[cfg(not(unix))]
+fn stats(pids: &[u32]) -> Vec<Stats> {
+    if pids.is_empty() {
+        return Vec::new();
+    }
+    let list: Vec<String> = pids.iter().map(u32::to_string).collect();
+    // CPU is total seconds on Windows, not a percentage; report it as is.
+    let script = format!(
+        "Get-Process -Id {} -ErrorAction SilentlyContinue | ForEach-Object {{ \
+         \"$($_.Id) $([int]$_.CPU) $([int]($_.WorkingSet64 / 1KB)) $($_.StartTime.ToString('s'))\" }}",
+        list.join(",")
+    );
+    let Ok(output) = Command::new("powershell").args(["-NoProfile", "-NonInteractive", "-Command", &script]).output()
+    else {
+        return Vec::new();
+    };
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            Some(Stats {
+                pid: fields.next()?.parse().ok()?,
+                zombie: false,
+                cpu: fields.next()?.parse().ok()?,
+                rss_kib: fields.next()?.parse().ok()?,
+                started: fields.next()?.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// qemu processes first, then the live programs quick-cli spawned.
+pub fn list() -> Vec<ProcessInfo> {
+    let mut processes: Vec<ProcessInfo> = qemu_processes()
+        .into_iter()
+        .map(|(pid, command)| ProcessInfo { pid, kind: "qemu", command, cpu: None, rss_kib: None, started: None })
+        .collect();
+    for (pid, command) in SPAWNED.lock().unwrap().iter() {
+        if pid_alive(*pid) && !processes.iter().any(|p| p.pid == *pid) {
+            processes.push(ProcessInfo {
+                pid: *pid,
+                kind: "spawned",
+                command: command.clone(),
+                cpu: None,
+                rss_kib: None,
+                started: None,
+            });
+        }
+    }
+    let pids: Vec<u32> = processes.iter().map(|p| p.pid).collect();
+    for stats in stats(&pids) {
+        if stats.zombie {
+            processes.retain(|p| p.pid != stats.pid);
+        } else if let Some(p) = processes.iter_mut().find(|p| p.pid == stats.pid) {
+            p.cpu = Some(stats.cpu);
+            p.rss_kib = Some(stats.rss_kib);
+            p.started = Some(stats.started);
+        }
+    }
+    processes
+}
+
+/// Kill a process with SIGKILL / taskkill /F.
+pub fn kill(pid: u32, config: &Config, logs: &Arc<Mutex<Vec<String>>>) {
+    match spawn_detached(&mut kill_command(pid), config, logs) {
+        Ok(()) => logs.lock().unwrap().push(format!("Killed process {}.", pid)),
+        Err(e) => logs.lock().unwrap().push(format!("Failed to kill process {}: {}", pid, e)),
+    }
+}
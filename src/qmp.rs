@@ -0,0 +1,330 @@
+///////////////////////////////////////////////////////////////////////////////
+// QMP (QEMU Machine Protocol) Client
+///////////////////////////////////////////////////////////////////////////////
+//
+// quickemu's default monitor socket speaks the human monitor protocol, so QMP
+// needs a socket added through the VM's extra_args, e.g.
+//     extra_args="-qmp unix:win11/win11-qmp.socket,server,nowait"
+// Both {vm}-qmp.socket and {vm}-monitor.socket are probed; the first one that
+// greets with a QMP banner is used.
+
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+#[cfg(unix)]
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+};
+
+use serde_json::{json, Value};
+
+#[cfg(unix)]
+use crate::vm_runtime_file;
+
+const QMP_TIMEOUT_SECS: u64 = 5;
+
+#[cfg(unix)]
+pub struct QmpClient {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+#[cfg(unix)]
+impl QmpClient {
+    /// Connect to the VM's QMP socket and leave capabilities negotiation mode.
+    pub fn connect(vm_conf: &Path) -> Result<Self, String> {
+        let mut last_error = String::from("no QMP socket found");
+        for suffix in ["-qmp.socket", "-monitor.socket"] {
+            let path = vm_runtime_file(vm_conf, suffix);
+            if !path.exists() {
+                continue;
+            }
+            match Self::connect_socket(&path) {
+                Ok(client) => return Ok(client),
+                Err(e) => last_error = format!("{}: {}", path.display(), e),
+            }
+        }
+        Err(last_error)
+    }
+
+    /// Connect to the QMP socket at `path` and leave capabilities negotiation mode.
+    pub fn connect_socket(path: &Path) -> Result<Self, String> {
+        let stream = UnixStream::connect(path).map_err(|e| e.to_string())?;
+        stream
+            .set_read_timeout(Some(Duration::from_secs(QMP_TIMEOUT_SECS)))
+            .map_err(|e| e.to_string())?;
+        let writer = stream.try_clone().map_err(|e| e.to_string())?;
+        let mut client = Self { reader: BufReader::new(stream), writer };
+        let greeting = client.read_message()?;
+        if greeting.get("QMP").is_none() {
+            return Err("not a QMP socket".into());
+        }
+        client.execute("qmp_capabilities", None)?;
+        Ok(client)
+    }
+
+    fn read_line(&mut self) -> Result<String, String> {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) => Err("QMP socket closed".into()),
+            Ok(_) => Ok(line.trim_end().to_string()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    fn read_message(&mut self) -> Result<Value, String> {
+        let line = self.read_line()?;
+        serde_json::from_str(&line).map_err(|_| format!("unexpected reply: {}", line))
+    }
+
+    /// Send a raw JSON command and return every line received up to and including the
+    /// command's reply, so asynchronous events in between are shown too.
+    /// A bare command name such as `query-status` is wrapped as {"execute": ...}.
+    pub fn execute_raw(&mut self, input: &str) -> Result<Vec<String>, String> {
+        let request: Value = if input.trim_start().starts_with('{') {
+            serde_json::from_str(input).map_err(|e| format!("invalid JSON: {}", e))?
+        } else {
+            json!({ "execute": input.trim() })
+        };
+        writeln!(self.writer, "{}", request).map_err(|e| e.to_string())?;
+        let mut lines = Vec::new();
+        loop {
+            let line = self.read_line()?;
+            let is_reply = serde_json::from_str::<Value>(&line)
+                .map(|v| v.get("return").is_some() || v.get("error").is_some())
+                .unwrap_or(false);
+            lines.push(line);
+            if is_reply {
+                return Ok(lines);
+            }
+        }
+    }
+
+    /// Execute a command and return its "return" value, skipping events.
+    pub fn execute(&mut self, command: &str, arguments: Option<Value>) -> Result<Value, String> {
+        let mut request = json!({ "execute": command });
+        if let Some(arguments) = arguments {
+            request["arguments"] = arguments;
+        }
+        writeln!(self.writer, "{}", request).map_err(|e| e.to_string())?;
+        loop {
+            let reply = self.read_message()?;
+            if let Some(value) = reply.get("return") {
+                return Ok(value.clone());
+            }
+            if let Some(error) = reply.get("error") {
+                let desc = error.get("desc").and_then(Value::as_str).unwrap_or("unknown error");
+                return Err(desc.to_string());
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub struct QmpClient;
+
+#[cfg(not(unix))]
+impl QmpClient {
+    pub fn connect(_vm_conf: &Path) -> Result<Self, String> {
+        Err("QMP over Unix sockets is not supported on this platform".into())
+    }
+
+    pub fn connect_socket(_path: &Path) -> Result<Self, String> {
+        Err("QMP over Unix sockets is not supported on this platform".into())
+    }
+
+    pub fn execute_raw(&mut self, _input: &str) -> Result<Vec<String>, String> {
+        Err("QMP is not supported on this platform".into())
+    }
+
+    pub fn execute(&mut self, _command: &str, _arguments: Option<Value>) -> Result<Value, String> {
+        Err("QMP is not supported on this platform".into())
+    }
+}
+
+/// Run a human monitor command (e.g. `info block`, `device_add ...`) through QMP's
+/// human-monitor-command in a background thread, appending its output to the log.
+pub fn run_hmp(vm_conf: &Path, command: &str, logs: &Arc<Mutex<Vec<String>>>) {
+    let vm_conf = vm_conf.to_path_buf();
+    let command = command.to_string();
+    let logs = Arc::clone(logs);
+    thread::spawn(move || {
+        let vm_name = vm_conf.file_stem().unwrap().to_string_lossy().to_string();
+        let result = QmpClient::connect(&vm_conf).and_then(|mut client| {
+            client.execute("human-monitor-command", Some(json!({ "command-line": command })))
+        });
+        let mut l = logs.lock().unwrap();
+        match result {
+            Ok(output) => {
+                l.push(format!("[{}] (qemu) {}", vm_name, command));
+                let output = output.as_str().unwrap_or_default();
+                l.extend(output.lines().map(|line| format!("[{}] {}", vm_name, line.trim_end())));
+            }
+            Err(e) => l.push(format!("HMP command failed for {}: {}", vm_name, e)),
+        }
+    });
+}
+
+/// Key combinations offered by the `K` menu, as QEMU qcodes pressed together.
+pub const KEY_COMBOS: [(&str, &[&str]); 9] = [
+    ("Ctrl+Alt+Del", &["ctrl", "alt", "delete"]),
+    ("Ctrl+Alt+Backspace", &["ctrl", "alt", "backspace"]),
+    ("Ctrl+Alt+F1", &["ctrl", "alt", "f1"]),
+    ("Ctrl+Alt+F2", &["ctrl", "alt", "f2"]),
+    ("Ctrl+Alt+F7", &["ctrl", "alt", "f7"]),
+    ("SysRq h (help)", &["alt", "sysrq", "h"]),
+    ("SysRq s (sync)", &["alt", "sysrq", "s"]),
+    ("SysRq u (remount read-only)", &["alt", "sysrq", "u"]),
+    ("SysRq b (reboot)", &["alt", "sysrq", "b"]),
+];
+
+/// Longest clipboard text typed into a guest; typing is slow and hard to interrupt.
+pub const PASTE_MAX_CHARS: usize = 4096;
+
+/// qcode of the key typing a character on a US keyboard layout, and whether it needs shift.
+fn char_qcode(c: char) -> Option<(bool, &'static str)> {
+    const ALNUM: &str = "abcdefghijklmnopqrstuvwxyz0123456789";
+    const SHIFTED_DIGITS: &str = ")!@#$%^&*(";
+    let key = match c {
+        'a'..='z' | '0'..='9' => {
+            let i = ALNUM.find(c)?;
+            return Some((false, &ALNUM[i..i + 1]));
+        }
+        'A'..='Z' => return char_qcode(c.to_ascii_lowercase()).map(|(_, key)| (true, key)),
+        c if SHIFTED_DIGITS.contains(c) => {
+            let digit = char::from_digit(SHIFTED_DIGITS.find(c)? as u32, 10)?;
+            return char_qcode(digit).map(|(_, key)| (true, key));
+        }
+        ' ' => (false, "spc"),
+        '\n' => (false, "ret"),
+        '\t' => (false, "tab"),
+        '-' => (false, "minus"),
+        '_' => (true, "minus"),
+        '=' => (false, "equal"),
+        '+' => (true, "equal"),
+        '[' => (false, "bracket_left"),
+        '{' => (true, "bracket_left"),
+        ']' => (false, "bracket_right"),
+        '}' => (true, "bracket_right"),
+        '\\' => (false, "backslash"),
+        '|' => (true, "backslash"),
+        ';' => (false, "semicolon"),
+        ':' => (true, "semicolon"),
+        '\'' => (false, "apostrophe"),
+        '"' => (true, "apostrophe"),
+        '`' => (false, "grave_accent"),
+        '~' => (true, "grave_accent"),
+        ',' => (false, "comma"),
+        '<' => (true, "comma"),
+        '.' => (false, "dot"),
+        '>' => (true, "dot"),
+        '/' => (false, "slash"),
+        '?' => (true, "slash"),
+        _ => return None,
+    };
+    Some(key)
+}
+
+/// Type text into the guest as QMP send-key presses, one character every `delay`,
+/// in a background thread. Characters without a key on a US layout are skipped.
+pub fn type_text(vm_conf: &Path, text: &str, delay: Duration, logs: &Arc<Mutex<Vec<String>>>) {
+    let vm_name = vm_conf.file_stem().unwrap().to_string_lossy().to_string();
+    // Windows clipboards end lines with CRLF; the newline alone presses Enter.
+    let chars: Vec<char> = text.chars().filter(|c| *c != '\r').collect();
+    if chars.len() > PASTE_MAX_CHARS {
+        logs.lock().unwrap().push(format!(
+            "Clipboard has {} characters; only the first {} are typed.",
+            chars.len(),
+            PASTE_MAX_CHARS
+        ));
+    }
+    let chars: Vec<char> = chars.into_iter().take(PASTE_MAX_CHARS).collect();
+    let (vm_conf, logs) = (vm_conf.to_path_buf(), Arc::clone(logs));
+    thread::spawn(move || {
+        let result = QmpClient::connect(&vm_conf).and_then(|mut client| {
+            let mut skipped = 0;
+            for c in &chars {
+                let Some((shift, key)) = char_qcode(*c) else {
+                    skipped += 1;
+                    continue;
+                };
+                let mut keys = vec![json!({ "type": "qcode", "data": key })];
+                if shift {
+                    keys.insert(0, json!({ "type": "qcode", "data": "shift" }));
+                }
+                client.execute("send-key", Some(json!({ "keys": keys })))?;
+                thread::sleep(delay);
+            }
+            Ok(skipped)
+        });
+        let mut l = logs.lock().unwrap();
+        match result {
+            Ok(0) => l.push(format!("Typed {} characters into {}.", chars.len(), vm_name)),
+            Ok(skipped) => l.push(format!(
+                "Typed {} characters into {}; skipped {} without a key on a US layout.",
+                chars.len() - skipped,
+                vm_name,
+                skipped
+            )),
+            Err(e) => l.push(format!("Failed to type into {}: {}", vm_name, e)),
+        }
+    });
+}
+
+/// Press a key combination in the guest with QMP send-key.
+pub fn send_keys(vm_conf: &Path, label: &str, keys: &[&str], logs: &Arc<Mutex<Vec<String>>>) {
+    let vm_name = vm_conf.file_stem().unwrap().to_string_lossy().to_string();
+    let keys: Vec<_> = keys.iter().map(|k| json!({ "type": "qcode", "data": k })).collect();
+    let result = QmpClient::connect(vm_conf)
+        .and_then(|mut client| client.execute("send-key", Some(json!({ "keys": keys }))));
+    let mut l = logs.lock().unwrap();
+    match result {
+        Ok(_) => l.push(format!("Sent {} to {}.", label, vm_name)),
+        Err(e) => l.push(format!("Failed to send {} to {}: {}", label, vm_name, e)),
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// Interactive QMP Console
+///////////////////////////////////////////////////////////////////////////////
+
+/// State of the `Q` console pane: one open connection plus the session transcript.
+pub struct QmpConsole {
+    pub vm_name: String,
+    pub input: String,
+    pub transcript: Vec<String>,
+    client: Option<QmpClient>,
+}
+
+impl QmpConsole {
+    pub fn open(vm_conf: &Path) -> Self {
+        let vm_name = vm_conf.file_stem().unwrap().to_string_lossy().to_string();
+        let (client, status) = match QmpClient::connect(vm_conf) {
+            Ok(client) => (Some(client), format!("Connected to QMP for {}.", vm_name)),
+            Err(e) => (None, format!("QMP unavailable for {}: {}", vm_name, e)),
+        };
+        Self { vm_name, input: String::new(), transcript: vec![status], client }
+    }
+
+    /// Send the current input line and append the request and replies to the transcript.
+    pub fn submit(&mut self) {
+        let line = std::mem::take(&mut self.input);
+        if line.trim().is_empty() {
+            return;
+        }
+        self.transcript.push(format!("-> {}", line));
+        let Some(client) = self.client.as_mut() else {
+            self.transcript.push("!! not connected".into());
+            return;
+        };
+        match client.execute_raw(&line) {
+            Ok(replies) => self.transcript.extend(replies.into_iter().map(|r| format!("<- {}", r))),
+            Err(e) => self.transcript.push(format!("!! {}", e)),
+        }
+    }
+}
@@ -0,0 +1,145 @@
+///////////////////////////////////////////////////////////////////////////////
+// Group Quotas
+///////////////////////////////////////////////////////////////////////////////
+//
+// `[group.<name>]` tables give a set of VMs a shared RAM and CPU core budget,
+// e.g. on a lab host where the students' VMs must leave room for the infra ones:
+//     [group.students]
+//     vms = ["lab-*", "student-*"]   glob patterns on VM names
+//     ram = "16G"                    budget for the group's running VMs
+//     cpu_cores = 8
+//     refuse = true                  refuse over-budget starts (default: warn)
+// Usage is the `ram` and `cpu_cores` of the group's running VMs as set in their
+// .conf files; VMs without them are sized by quickemu at start and not counted.
+// start_vm is the one place the budgets are checked. Without `refuse` the TUI
+// asks before an over-budget start it makes itself and other callers (the
+// daemon, manifests, --connect) only log it.
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    glob_match,
+    host::{format_gib, parse_size, vm_cores, vm_ram},
+    is_vm_running, list_vms, Config,
+};
+
+/// A `[group.<name>]` table.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Quota {
+    pub vms: Vec<String>,       // Glob patterns for the group's VM stems
+    pub ram: Option<String>,    // RAM budget, e.g. "16G"
+    pub cpu_cores: Option<u32>, // CPU core budget
+    pub refuse: bool,           // Refuse starts over budget instead of warning
+}
+
+/// A budget that starting a VM would exceed.
+pub struct Excess {
+    pub message: String,
+    pub refuse: bool,
+}
+
+fn is_member(vm_conf: &Path, quota: &Quota) -> bool {
+    let stem = vm_conf.file_stem().unwrap().to_string_lossy();
+    quota.vms.iter().any(|pattern| glob_match(pattern, &stem))
+}
+
+/// The budgets of the VM's groups that starting it would exceed.
+pub fn exceeded(vm_conf: &Path, config: &Config) -> Vec<Excess> {
+    let groups: Vec<_> = config.groups.iter().filter(|(_, quota)| is_member(vm_conf, quota)).collect();
+    if groups.is_empty() {
+        return Vec::new();
+    }
+    let name = vm_conf.file_stem().unwrap().to_string_lossy();
+    let running: Vec<_> = list_vms(config)
+        .into_iter()
+        .filter(|vm| vm != vm_conf && groups.iter().any(|(_, quota)| is_member(vm, quota)))
+        .filter(|vm| is_vm_running(vm, config))
+        .collect();
+    let mut excess = Vec::new();
+    for (group, quota) in groups {
+        let members: Vec<_> = running.iter().filter(|vm| is_member(vm, quota)).collect();
+        if let Some(budget) = quota.ram.as_deref().and_then(parse_size)
+            && let Some(ram) = vm_ram(vm_conf)
+        {
+            let used = members.iter().filter_map(|vm| vm_ram(vm)).sum::<u64>() + ram;
+            if used > budget {
+                excess.push(Excess {
+                    message: format!(
+                        "Starting {} puts group {} at {} of its {} RAM quota.",
+                        name,
+                        group,
+                        format_gib(used),
+                        format_gib(budget)
+                    ),
+                    refuse: quota.refuse,
+                });
+            }
+        }
+        if let Some(budget) = quota.cpu_cores
+            && let Some(cores) = vm_cores(vm_conf)
+        {
+            let used = members.iter().filter_map(|vm| vm_cores(vm)).sum::<u32>() + cores;
+            if used > budget {
+                excess.push(Excess {
+                    message: format!(
+                        "Starting {} puts group {} at {} of its {} CPU core quota.",
+                        name, group, used, budget
+                    ),
+                    refuse: quota.refuse,
+                });
+            }
+        }
+    }
+    excess
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{start_vm, vm_runtime_file, StartError};
+    use std::{
+        fs,
+        sync::{Arc, Mutex},
+    };
+
+    #[test]
+    fn exceeded_counts_running_group_members() {
+        let dir = std::env::temp_dir().join(format!("quick-cli-quota-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let vm = |name: &str, contents: &str| {
+            let conf = dir.join(format!("{}.conf", name));
+            fs::write(&conf, contents).unwrap();
+            conf
+        };
+        let running = vm("lab-a", "ram=\"8G\"\ncpu_cores=\"4\"\n");
+        let stopped = vm("lab-b", "ram=\"10G\"\ncpu_cores=\"4\"\n");
+        let other = vm("infra", "ram=\"32G\"\ncpu_cores=\"16\"\n");
+        // This test process stands in for lab-a's qemu; lab-b's pid file names no process.
+        for (conf, pid) in [(&running, std::process::id()), (&stopped, 99_999_999)] {
+            let pid_file = vm_runtime_file(conf, ".pid");
+            fs::create_dir_all(pid_file.parent().unwrap()).unwrap();
+            fs::write(pid_file, pid.to_string()).unwrap();
+        }
+        let mut config = Config { quickemu_dirs: vec![dir.clone()], ..Config::default() };
+        let quota = Quota { vms: vec!["lab-*".into()], ram: Some("16G".into()), cpu_cores: Some(8), refuse: true };
+        config.groups.insert("lab".into(), quota);
+
+        let excess = exceeded(&stopped, &config);
+        let messages: Vec<&str> = excess.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, ["Starting lab-b puts group lab at 18.0 GiB of its 16.0 GiB RAM quota."]);
+        assert!(excess[0].refuse);
+        assert!(exceeded(&other, &config).is_empty());
+        // start_vm refuses, or hands soft excesses back for confirmation, before launching anything.
+        let logs = Arc::new(Mutex::new(Vec::new()));
+        assert!(matches!(start_vm(&stopped, &config, &logs, None, false), Err(StartError::Refused(_))));
+        config.groups.get_mut("lab").unwrap().refuse = false;
+        let over = start_vm(&stopped, &config, &logs, None, true);
+        assert!(matches!(over, Err(StartError::OverQuota(warnings)) if warnings.len() == 1));
+        config.groups.get_mut("lab").unwrap().ram = Some("18G".into());
+        assert!(exceeded(&stopped, &config).is_empty());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
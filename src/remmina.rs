@@ -0,0 +1,133 @@
+///////////////////////////////////////////////////////////////////////////////
+// Remmina Profile Override and Auto-Detection
+///////////////////////////////////////////////////////////////////////////////
+//
+// A VM connects with a Remmina profile when it has one: its `[overrides]`
+// entry, else a *.remmina file in one of `remmina_dirs` whose name contains
+// the VM's name (an exact match wins, then the first directory). By default
+// these are ~/.local/share/remmina and the Flatpak app's data directory. The
+// directories are indexed on the first lookup and the answer for each VM is
+// cached, since one connection asks several times. Adding, removing or
+// renaming a profile changes its directory's modification time, which is
+// checked on every lookup and rebuilds the index.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use crate::{expand_vars, Config};
+
+struct Index {
+    dirs: Vec<(PathBuf, Option<SystemTime>)>,  // Directories and their modification time when scanned
+    profiles: Vec<(String, PathBuf)>,          // Lowercase file stem -> profile, in directory order
+    matches: HashMap<String, Option<PathBuf>>, // Lowercase VM stem -> its profile, once looked up
+}
+
+static INDEX: Mutex<Option<Index>> = Mutex::new(None);
+
+fn scan(dir: &Path) -> Vec<(String, PathBuf)> {
+    let mut profiles = Vec::new();
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_file()
+                && let Some(ext) = path.extension()
+                && ext == "remmina"
+                && let Some(stem) = path.file_stem()
+            {
+                profiles.push((stem.to_string_lossy().to_lowercase(), path));
+            }
+        }
+    }
+    // read_dir order is arbitrary; keep the pick among several matches stable.
+    profiles.sort();
+    profiles
+}
+
+/// The only profile whose stem contains the VM stem, else the exact match, else the first.
+fn best_match(profiles: &[(String, PathBuf)], vm_stem: &str) -> Option<PathBuf> {
+    let matches: Vec<_> = profiles.iter().filter(|(stem, _)| stem.contains(vm_stem)).collect();
+    matches
+        .iter()
+        .find(|(stem, _)| stem == vm_stem)
+        .or(matches.first())
+        .map(|(_, path)| path.clone())
+}
+
+/// Returns a Remmina profile for the given VM.
+/// First checks for an override mapping (exact match on the VM config’s stem, lowercase).
+/// If not found, looks the VM up in the index of `remmina_dirs`.
+pub fn profile_for_vm(vm_conf: &Path, config: &Config) -> Option<PathBuf> {
+    let vm_name = vm_conf.file_stem()?.to_string_lossy();
+    let vm_stem = vm_name.to_lowercase();
+    if let Some(override_path) = config.remmina_overrides.get(&vm_stem) {
+        return Some(PathBuf::from(expand_vars(override_path, Some(&vm_name))));
+    }
+    let dirs: Vec<_> = config
+        .remmina_dirs
+        .iter()
+        .map(|dir| (dir.clone(), fs::metadata(dir).and_then(|m| m.modified()).ok()))
+        .collect();
+    let mut index = INDEX.lock().unwrap();
+    if index.as_ref().is_none_or(|index| index.dirs != dirs) {
+        let profiles = dirs.iter().flat_map(|(dir, _)| scan(dir)).collect();
+        *index = Some(Index { dirs, profiles, matches: HashMap::new() });
+    }
+    let Index { profiles, matches, .. } = index.as_mut()?;
+    matches.entry(vm_stem).or_insert_with_key(|vm_stem| best_match(profiles, vm_stem)).clone()
+}
+
+/// Settings of a profile shown in the details pane, to check the match before connecting.
+#[derive(Clone, Default)]
+pub struct Preview {
+    pub protocol: Option<String>,
+    pub server: Option<String>,
+    pub resolution: Option<String>, // "1920x1080", when the profile sets a custom one
+    pub username: Option<String>,
+}
+
+/// Profiles read for previews, with their modification time then.
+static PREVIEWS: Mutex<Vec<(PathBuf, SystemTime, Preview)>> = Mutex::new(Vec::new());
+
+fn parse_preview(contents: &str) -> Preview {
+    let mut values = HashMap::new();
+    for line in contents.lines() {
+        if let Some((key, value)) = line.split_once('=')
+            && !value.trim().is_empty()
+        {
+            values.insert(key.trim(), value.trim().to_string());
+        }
+    }
+    let resolution = match (values.get("resolution_width"), values.get("resolution_height")) {
+        (Some(w), Some(h)) if w != "0" && h != "0" => Some(format!("{}x{}", w, h)),
+        _ => None,
+    };
+    Preview {
+        protocol: values.remove("protocol"),
+        server: values.remove("server"),
+        resolution,
+        username: values.remove("username"),
+    }
+}
+
+/// The profile's key settings, re-read when the file changes. None when it cannot be read.
+pub fn preview(profile: &Path) -> Option<Preview> {
+    let modified = fs::metadata(profile).and_then(|m| m.modified()).ok()?;
+    let mut previews = PREVIEWS.lock().unwrap();
+    if let Some((_, _, preview)) = previews.iter().find(|(path, m, _)| path == profile && *m == modified) {
+        return Some(preview.clone());
+    }
+    let preview = parse_preview(&fs::read_to_string(profile).ok()?);
+    previews.retain(|(path, ..)| path != profile);
+    previews.push((profile.to_path_buf(), modified, preview.clone()));
+    Some(preview)
+}
+
+/// The port in the profile's `server` setting, if it names one.
+pub fn server_port(profile: &Path) -> Option<u16> {
+    preview(profile)?.server?.rsplit_once(':')?.1.parse().ok()
+}
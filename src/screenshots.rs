@@ -0,0 +1,154 @@
+///////////////////////////////////////////////////////////////////////////////
+// Screenshot Gallery
+///////////////////////////////////////////////////////////////////////////////
+//
+// `G` opens the gallery of the selected VM: QMP screendumps saved as PNG under
+// $XDG_STATE_HOME/quick-cli/screenshots/<vm>, newest first. `s` takes a new one
+// (needs a QMP socket, see qmp.rs, and QEMU 7.1 or newer for PNG output).
+// The selected screenshot is previewed inline with the terminal's graphics
+// protocol, picked by `image_protocol`:
+//     auto    kitty in kitty/Ghostty, iterm2 in iTerm2/WezTerm, sixel in Windows
+//             Terminal when chafa is installed, else none
+//     kitty   kitty graphics protocol
+//     iterm2  iTerm2 inline images
+//     sixel   rendered by chafa, which must be installed
+//     none    only the file path; `o` opens it in the default image viewer
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use crate::{events::now, find_in_path, qmp::QmpClient, state_dir, Config};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ImageProtocol {
+    Kitty,
+    Iterm2,
+    Sixel,
+    None,
+}
+
+/// Where the screenshots of a VM are kept.
+pub fn dir(vm_conf: &Path) -> PathBuf {
+    state_dir().join("screenshots").join(vm_conf.file_stem().unwrap())
+}
+
+/// Screenshots of a VM, newest first. Names are unix times, so they sort by age.
+pub fn list(vm_conf: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir(vm_conf)) else { return Vec::new() };
+    let mut shots: Vec<PathBuf> =
+        entries.flatten().map(|e| e.path()).filter(|p| p.extension().is_some_and(|e| e == "png")).collect();
+    shots.sort_by(|a, b| b.cmp(a));
+    shots
+}
+
+/// Save a screendump of the VM in the background. qemu writes the file itself.
+pub fn capture(vm_conf: &Path, logs: &Arc<Mutex<Vec<String>>>) {
+    let (vm_conf, logs) = (vm_conf.to_path_buf(), Arc::clone(logs));
+    thread::spawn(move || {
+        let dir = dir(&vm_conf);
+        let path = dir.join(format!("{}.png", now()));
+        let result = fs::create_dir_all(&dir).map_err(|e| e.to_string()).and_then(|_| {
+            let arguments = serde_json::json!({ "filename": path.to_string_lossy(), "format": "png" });
+            QmpClient::connect(&vm_conf)?.execute("screendump", Some(arguments))
+        });
+        logs.lock().unwrap().push(match result {
+            Ok(_) => format!("Saved screenshot {}.", path.display()),
+            Err(e) => format!("Screenshot failed: {}", e),
+        });
+    });
+}
+
+/// The graphics protocol to preview with, from `image_protocol` and the terminal.
+pub fn protocol(config: &Config) -> ImageProtocol {
+    match config.image_protocol.as_str() {
+        "kitty" => ImageProtocol::Kitty,
+        "iterm2" => ImageProtocol::Iterm2,
+        "sixel" => ImageProtocol::Sixel,
+        "auto" => {
+            let term = std::env::var("TERM").unwrap_or_default();
+            let program = std::env::var("TERM_PROGRAM").unwrap_or_default();
+            if std::env::var_os("KITTY_WINDOW_ID").is_some() || term.contains("kitty") || term.contains("ghostty") {
+                ImageProtocol::Kitty
+            } else if program == "iTerm.app" || program == "WezTerm" {
+                ImageProtocol::Iterm2
+            } else if std::env::var_os("WT_SESSION").is_some() && find_in_path("chafa.exe").is_some() {
+                // Windows Terminal 1.22+ draws sixels; conhost draws nothing.
+                ImageProtocol::Sixel
+            } else {
+                ImageProtocol::None
+            }
+        }
+        _ => ImageProtocol::None,
+    }
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Escape sequence drawing the image into a `cols` x `rows` cell box at the cursor.
+pub fn render(path: &Path, protocol: ImageProtocol, cols: u16, rows: u16) -> Result<Vec<u8>, String> {
+    match protocol {
+        ImageProtocol::Kitty => {
+            let data = base64(&fs::read(path).map_err(|e| e.to_string())?);
+            // Payloads are sent in chunks of at most 4096 bytes; m=1 means more follow.
+            let chunks: Vec<&[u8]> = data.as_bytes().chunks(4096).collect();
+            let mut out = Vec::new();
+            for (i, chunk) in chunks.iter().enumerate() {
+                let more = u8::from(i + 1 < chunks.len());
+                let control = if i == 0 {
+                    format!("a=T,f=100,c={},r={},C=1,q=2,m={}", cols, rows, more)
+                } else {
+                    format!("m={}", more)
+                };
+                out.extend(format!("\x1b_G{};", control).as_bytes());
+                out.extend(*chunk);
+                out.extend(b"\x1b\\");
+            }
+            Ok(out)
+        }
+        ImageProtocol::Iterm2 => {
+            let data = base64(&fs::read(path).map_err(|e| e.to_string())?);
+            Ok(format!("\x1b]1337;File=inline=1;width={};height={};preserveAspectRatio=1:{}\x07", cols, rows, data)
+                .into_bytes())
+        }
+        ImageProtocol::Sixel => {
+            if find_in_path("chafa").is_none() && find_in_path("chafa.exe").is_none() {
+                return Err("sixel previews need chafa".into());
+            }
+            let output = Command::new("chafa")
+                .args(["-f", "sixels", "-s", &format!("{}x{}", cols, rows)])
+                .arg(path)
+                .output()
+                .map_err(|e| format!("failed to run chafa: {}", e))?;
+            Ok(output.stdout)
+        }
+        ImageProtocol::None => Err("no terminal graphics protocol".into()),
+    }
+}
+
+/// Escape sequence removing previewed images, for protocols that draw them on a
+/// layer of their own. Others are overwritten by redrawing the screen.
+pub fn clear(protocol: ImageProtocol) -> &'static [u8] {
+    match protocol {
+        ImageProtocol::Kitty => b"\x1b_Ga=d,q=2\x1b\\",
+        _ => b"",
+    }
+}
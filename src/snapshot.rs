@@ -0,0 +1,165 @@
+///////////////////////////////////////////////////////////////////////////////
+// Live Snapshots
+///////////////////////////////////////////////////////////////////////////////
+//
+// `:snapshot <vm> [name]` takes an internal qcow2 snapshot of every writable
+// disk of a running VM without pausing it, with `snapshot_blkdev_internal` on
+// quickemu's monitor socket. A disk-only snapshot of a live guest is only
+// crash-consistent, so when the QEMU guest agent answers on {vm}-qga.socket
+// the guest's filesystems are frozen (guest-fsfreeze-freeze) for the duration
+// and thawed right after. `:hmp <vm> info snapshots` lists the result.
+
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+#[cfg(unix)]
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+};
+
+#[cfg(unix)]
+use serde_json::json;
+use serde_json::Value;
+
+use crate::suspend::hmp;
+#[cfg(unix)]
+use crate::vm_runtime_file;
+
+const MONITOR_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Freezing waits for the guest to flush its dirty pages.
+const FREEZE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Run one guest agent command and return its "return" value. A guest-sync with a
+/// fresh id goes first, so replies left over from an earlier client are skipped and
+/// a socket without an agent behind it fails fast.
+#[cfg(unix)]
+pub(crate) fn guest_agent(
+    vm_conf: &Path,
+    command: &str,
+    arguments: Option<Value>,
+    timeout: Duration,
+) -> Result<Value, String> {
+    let path = vm_runtime_file(vm_conf, "-qga.socket");
+    let stream = UnixStream::connect(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut writer = stream.try_clone().map_err(|e| e.to_string())?;
+    let mut reader = BufReader::new(stream);
+    let mut call = |request: Value, timeout: Duration| -> Result<Value, String> {
+        reader.get_ref().set_read_timeout(Some(timeout)).map_err(|e| e.to_string())?;
+        writeln!(writer, "{}", request).map_err(|e| e.to_string())?;
+        let mut line = String::new();
+        if reader.read_line(&mut line).map_err(|e| format!("no reply from the guest agent ({})", e))? == 0 {
+            return Err("guest agent closed the connection".into());
+        }
+        let reply: Value = serde_json::from_str(line.trim()).map_err(|_| format!("unexpected reply: {}", line.trim()))?;
+        match reply.get("error") {
+            Some(error) => Err(error["desc"].as_str().unwrap_or("unknown error").to_string()),
+            None => Ok(reply["return"].clone()),
+        }
+    };
+    let id = crate::events::now() % 1_000_000_000 + std::process::id() as u64;
+    loop {
+        if call(json!({ "execute": "guest-sync", "arguments": { "id": id } }), Duration::from_secs(3))? == json!(id) {
+            break;
+        }
+    }
+    match arguments {
+        Some(arguments) => call(json!({ "execute": command, "arguments": arguments }), timeout),
+        None => call(json!({ "execute": command }), timeout),
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn guest_agent(
+    _vm_conf: &Path,
+    _command: &str,
+    _arguments: Option<Value>,
+    _timeout: Duration,
+) -> Result<Value, String> {
+    Err("the guest agent needs a unix socket".into())
+}
+
+/// Writable qcow2 disks from `info block`, by device name. Their header lines read
+/// e.g. `SystemDisk (#block143): /vms/win11/disk.qcow2 (qcow2)`.
+fn snapshot_devices(vm_conf: &Path) -> Result<Vec<String>, String> {
+    let output = hmp(vm_conf, "info block", MONITOR_TIMEOUT)?;
+    Ok(output
+        .lines()
+        .filter(|line| !line.starts_with(char::is_whitespace) && line.trim_end().ends_with("(qcow2)"))
+        .filter_map(|line| line.split_once(" (#").map(|(device, _)| device.trim().to_string()))
+        .filter(|device| !device.is_empty() && !device.contains(char::is_whitespace))
+        .collect())
+}
+
+/// Snapshot every writable qcow2 disk, frozen when the guest agent allows it.
+/// Returns the devices snapshotted.
+pub(crate) fn create(vm_conf: &Path, name: &str, logs: &Arc<Mutex<Vec<String>>>) -> Result<Vec<String>, String> {
+    let devices = snapshot_devices(vm_conf)?;
+    if devices.is_empty() {
+        return Err("no writable qcow2 disk found in `info block`".into());
+    }
+    let frozen = match guest_agent(vm_conf, "guest-fsfreeze-freeze", None, FREEZE_TIMEOUT) {
+        Ok(count) => {
+            logs.lock().unwrap().push(format!("Froze {} guest filesystems.", count));
+            true
+        }
+        Err(e) => {
+            let message = format!("Guest filesystems not frozen ({}); the snapshot is crash-consistent.", e);
+            logs.lock().unwrap().push(message);
+            false
+        }
+    };
+    let result = devices.iter().try_for_each(|device| {
+        let output = hmp(vm_conf, &format!("snapshot_blkdev_internal {} {}", device, name), MONITOR_TIMEOUT)?;
+        match output.lines().find(|l| l.to_lowercase().contains("error")) {
+            Some(error) => Err(format!("{}: {}", device, error.trim())),
+            None => Ok(()),
+        }
+    });
+    if frozen {
+        // A guest left frozen hangs on its next write, so try hard to thaw it.
+        let thawed = (0..3).find_map(|_| guest_agent(vm_conf, "guest-fsfreeze-thaw", None, FREEZE_TIMEOUT).ok());
+        let mut l = logs.lock().unwrap();
+        match thawed {
+            Some(count) => l.push(format!("Thawed {} guest filesystems.", count)),
+            None => l.push(format!(
+                "Failed to thaw the guest filesystems of {}; run guest-fsfreeze-thaw through its guest agent.",
+                vm_conf.display()
+            )),
+        }
+    }
+    result.map(|_| devices)
+}
+
+/// Delete the internal snapshot `name` from each device.
+pub(crate) fn delete(vm_conf: &Path, name: &str, devices: &[String]) -> Result<(), String> {
+    devices.iter().try_for_each(|device| {
+        let command = format!("snapshot_delete_blkdev_internal {} {}", device, name);
+        let output = hmp(vm_conf, &command, MONITOR_TIMEOUT)?;
+        match output.lines().find(|l| l.to_lowercase().contains("error")) {
+            Some(error) => Err(format!("{}: {}", device, error.trim())),
+            None => Ok(()),
+        }
+    })
+}
+
+/// Take a live snapshot named `name` in a background thread, logging the outcome.
+pub fn take(vm_conf: &Path, name: &str, logs: &Arc<Mutex<Vec<String>>>) {
+    let vm_conf = vm_conf.to_path_buf();
+    let name = name.to_string();
+    let logs = Arc::clone(logs);
+    thread::spawn(move || {
+        let vm_name = vm_conf.file_stem().unwrap().to_string_lossy().to_string();
+        logs.lock().unwrap().push(format!("Taking snapshot {} of {}...", name, vm_name));
+        let message = match create(&vm_conf, &name, &logs) {
+            Ok(devices) => format!("Snapshot {} of {} taken ({}).", name, vm_name, devices.join(", ")),
+            Err(e) => format!("Snapshot of {} failed: {}", vm_name, e),
+        };
+        logs.lock().unwrap().push(message);
+    });
+}
@@ -0,0 +1,98 @@
+///////////////////////////////////////////////////////////////////////////////
+// Suspend to Disk
+///////////////////////////////////////////////////////////////////////////////
+//
+// VMs matched by `suspend_on_stop` are not shut down by a stop: their RAM and
+// device state is saved into an internal qcow2 snapshot with the monitor's
+// `savevm`, then qemu quits. A `{vm}.suspended` marker in the VM directory makes
+// the next start pass `-loadvm` so the session resumes where it left off. If
+// saving fails (raw disks, devices that cannot migrate) the normal stop runs.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+#[cfg(unix)]
+use std::{
+    io::{Read, Write},
+    os::unix::net::UnixStream,
+};
+
+use crate::{glob_match, vm_runtime_file, Config};
+
+/// Name of the internal snapshot holding the saved state.
+pub const SNAPSHOT_TAG: &str = "quick-cli-suspend";
+
+/// Saving several GiB of RAM can take a while on slow disks.
+const SAVE_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Whether stopping this VM saves its state instead of shutting it down.
+pub fn enabled(vm_conf: &Path, config: &Config) -> bool {
+    let stem = vm_conf.file_stem().unwrap().to_string_lossy();
+    config.suspend_on_stop.iter().any(|p| glob_match(p, &stem))
+}
+
+fn marker(vm_conf: &Path) -> PathBuf {
+    vm_runtime_file(vm_conf, ".suspended")
+}
+
+pub fn is_suspended(vm_conf: &Path) -> bool {
+    marker(vm_conf).exists()
+}
+
+/// Read monitor output up to the next `(qemu) ` prompt.
+#[cfg(unix)]
+fn read_prompt(stream: &mut UnixStream) -> Result<String, String> {
+    let mut output = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = stream.read(&mut buf).map_err(|e| e.to_string())?;
+        if n == 0 {
+            return Err("monitor closed the connection".into());
+        }
+        output.extend_from_slice(&buf[..n]);
+        if output.ends_with(b"(qemu) ") {
+            return Ok(String::from_utf8_lossy(&output).to_string());
+        }
+    }
+}
+
+/// Run one command on quickemu's human monitor socket and return its output.
+#[cfg(unix)]
+pub(crate) fn hmp(vm_conf: &Path, command: &str, timeout: Duration) -> Result<String, String> {
+    let path = vm_runtime_file(vm_conf, "-monitor.socket");
+    let mut stream = UnixStream::connect(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    stream.set_read_timeout(Some(timeout)).map_err(|e| e.to_string())?;
+    read_prompt(&mut stream)?;
+    stream.write_all(format!("{}\n", command).as_bytes()).map_err(|e| e.to_string())?;
+    read_prompt(&mut stream)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn hmp(_vm_conf: &Path, _command: &str, _timeout: Duration) -> Result<String, String> {
+    Err("quickemu's monitor is a unix socket, not available on this platform".into())
+}
+
+/// Save the VM's state and quit qemu. Blocks until the state is written.
+pub fn suspend(vm_conf: &Path) -> Result<(), String> {
+    let output = hmp(vm_conf, &format!("savevm {}", SNAPSHOT_TAG), SAVE_TIMEOUT)?;
+    if let Some(error) = output.lines().find(|l| l.to_lowercase().contains("error")) {
+        return Err(error.trim().to_string());
+    }
+    fs::write(marker(vm_conf), SNAPSHOT_TAG).map_err(|e| e.to_string())?;
+    // qemu exits without answering; the closed connection is expected.
+    let _ = hmp(vm_conf, "quit", Duration::from_secs(5));
+    Ok(())
+}
+
+/// qemu arguments that resume a suspended VM, if it has saved state.
+pub fn resume_args(vm_conf: &Path) -> Option<String> {
+    is_suspended(vm_conf).then(|| format!("-loadvm {}", SNAPSHOT_TAG))
+}
+
+/// Forget the saved state once the VM has been resumed from it.
+pub fn clear(vm_conf: &Path) {
+    let _ = fs::remove_file(marker(vm_conf));
+}
@@ -0,0 +1,104 @@
+///////////////////////////////////////////////////////////////////////////////
+// Git-backed Config Sync
+///////////////////////////////////////////////////////////////////////////////
+//
+// With `git_sync = true`, every quickemu directory that is a git work tree is
+// pulled (rebasing local commits) at startup, and changed VM .conf files are
+// committed and pushed every SYNC_INTERVAL while the TUI runs. Only *.conf
+// paths are ever staged, so disk images and runtime files stay out of git.
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+pub const SYNC_INTERVAL: Duration = Duration::from_secs(30);
+
+const CONF_PATHSPEC: &str = "*.conf";
+
+/// Run git in `dir`, returning stdout on success and stderr on failure.
+fn git(dir: &Path, args: &[&str]) -> Result<String, String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .map_err(|e| format!("failed to run git: {}", e))?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
+
+fn is_work_tree(dir: &Path) -> bool {
+    git(dir, &["rev-parse", "--is-inside-work-tree"]).is_ok_and(|out| out == "true")
+}
+
+/// Pull each synced directory. On conflicts the rebase is aborted, leaving the
+/// local tree untouched, and the conflicting files are reported.
+pub fn pull(dirs: &[PathBuf], logs: &Arc<Mutex<Vec<String>>>) {
+    for dir in dirs {
+        if !is_work_tree(dir) {
+            logs.lock().unwrap().push(format!("Config sync: {} is not a git repository; skipped.", dir.display()));
+            continue;
+        }
+        let message = match git(dir, &["pull", "--rebase", "--autostash"]) {
+            Ok(_) => format!("Config sync: pulled {}.", dir.display()),
+            Err(e) => {
+                let conflicts = git(dir, &["diff", "--name-only", "--diff-filter=U"]).unwrap_or_default();
+                if conflicts.is_empty() {
+                    format!("Config sync: pull failed for {}: {}", dir.display(), e)
+                } else {
+                    let _ = git(dir, &["rebase", "--abort"]);
+                    format!(
+                        "Config sync: conflict in {} ({}); pull aborted, resolve it with git.",
+                        dir.display(),
+                        conflicts.lines().collect::<Vec<_>>().join(", ")
+                    )
+                }
+            }
+        };
+        logs.lock().unwrap().push(message);
+    }
+}
+
+/// Commit and push changed VM configs in one directory.
+fn commit_and_push(dir: &Path, logs: &Arc<Mutex<Vec<String>>>) {
+    let changed = match git(dir, &["status", "--porcelain", "--", CONF_PATHSPEC]) {
+        Ok(out) if !out.is_empty() => out,
+        _ => return,
+    };
+    let files: Vec<&str> = changed.lines().filter_map(|l| l.get(3..)).collect();
+    let message = format!("Update {}", files.join(", "));
+    let result = git(dir, &["add", "--all", "--", CONF_PATHSPEC])
+        .and_then(|_| git(dir, &["commit", "-m", &message, "--", CONF_PATHSPEC]));
+    let mut l = logs.lock().unwrap();
+    if let Err(e) = result {
+        l.push(format!("Config sync: commit failed in {}: {}", dir.display(), e));
+        return;
+    }
+    l.push(format!("Config sync: committed {} in {}.", files.join(", "), dir.display()));
+    drop(l);
+    if let Err(e) = git(dir, &["push"]) {
+        logs.lock().unwrap().push(format!(
+            "Config sync: push failed for {} (restart to pull and rebase): {}",
+            dir.display(),
+            e
+        ));
+    }
+}
+
+/// Commit and push changes in every synced directory on a background thread.
+pub fn commit_changes(dirs: &[PathBuf], logs: &Arc<Mutex<Vec<String>>>) {
+    let dirs = dirs.to_vec();
+    let logs = Arc::clone(logs);
+    thread::spawn(move || {
+        for dir in dirs.iter().filter(|d| is_work_tree(d)) {
+            commit_and_push(dir, &logs);
+        }
+    });
+}
@@ -0,0 +1,91 @@
+///////////////////////////////////////////////////////////////////////////////
+// Plain CLI Output
+///////////////////////////////////////////////////////////////////////////////
+//
+// The subcommands that print for people (apply, watch, migrate-config) color
+// their status words and draw progress bars for long operations, like image
+// downloads and waiting for VMs to stop, when their output is a terminal.
+// Piped output, NO_COLOR and TERM=dumb get plain text with no bars, so scripts
+// and logs see the same lines as before.
+
+use std::{
+    cell::Cell,
+    io::{self, IsTerminal, Write},
+    sync::OnceLock,
+};
+
+const BAR_WIDTH: usize = 30;
+
+#[derive(Clone, Copy)]
+pub enum Tone {
+    Good,  // Green: done, up to date, running
+    Warn,  // Yellow: changes and things to look at
+    Error, // Red: failures
+    Dim,   // Grey: dry runs and stopped VMs
+    Bold,  // VM names
+}
+
+/// Whether stdout is a terminal that wants color.
+pub fn styled() -> bool {
+    static STYLED: OnceLock<bool> = OnceLock::new();
+    *STYLED.get_or_init(|| {
+        io::stdout().is_terminal()
+            && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+            && std::env::var("TERM").as_deref() != Ok("dumb")
+    })
+}
+
+/// The text in the tone's color, or unchanged when output is plain.
+pub fn paint(text: &str, tone: Tone) -> String {
+    if !styled() {
+        return text.to_string();
+    }
+    let code = match tone {
+        Tone::Good => "32",
+        Tone::Warn => "33",
+        Tone::Error => "31",
+        Tone::Dim => "2",
+        Tone::Bold => "1",
+    };
+    format!("\x1b[{}m{}\x1b[0m", code, text)
+}
+
+/// A progress bar redrawn in place on stderr while output is styled; a no-op otherwise.
+/// Finish it before printing anything else.
+pub struct Progress {
+    label: String,
+    drawn: Cell<bool>,
+}
+
+impl Progress {
+    pub fn new(label: String) -> Self {
+        Self { label, drawn: Cell::new(false) }
+    }
+
+    pub fn set(&self, percent: f32) {
+        if !styled() || !io::stderr().is_terminal() {
+            return;
+        }
+        let filled = ((percent.clamp(0.0, 100.0) / 100.0) * BAR_WIDTH as f32) as usize;
+        let bar = format!("{}{}", "#".repeat(filled), ".".repeat(BAR_WIDTH - filled));
+        let mut stderr = io::stderr().lock();
+        let _ = write!(stderr, "\r\x1b[2K{} [{}] {:5.1}%", self.label, paint(&bar, Tone::Good), percent);
+        let _ = stderr.flush();
+        self.drawn.set(true);
+    }
+
+    /// Clear the bar's line.
+    pub fn finish(&self) {
+        if self.drawn.replace(false) {
+            let mut stderr = io::stderr().lock();
+            let _ = write!(stderr, "\r\x1b[2K");
+            let _ = stderr.flush();
+        }
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
@@ -0,0 +1,106 @@
+///////////////////////////////////////////////////////////////////////////////
+// SSH Tunnels (VMs on another host)
+///////////////////////////////////////////////////////////////////////////////
+//
+// VMs with an `[ssh_tunnel]` entry are reached through an SSH local port forward
+// instead of directly: their viewer port is forwarded from a free port on this
+// machine, and the viewer connects to 127.0.0.1 there. SPICE and forwarded ports
+// that only listen on the remote host's loopback become reachable that way, and
+// nothing but SSH has to cross the network.
+//
+// ssh runs `sleep` on the remote side, which keeps the tunnel open long enough
+// for the viewer to connect; after that ssh stays up exactly as long as a
+// forwarded connection does, so the tunnel goes away when the viewer exits.
+
+use std::{
+    io::Read,
+    net::TcpListener,
+    path::Path,
+    process::{Command, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{describe_command, format_addr, is_port_open, procs, vm_host, Config, RemoteProtocol};
+
+/// Seconds an unused tunnel stays open for the viewer to connect.
+const LINGER_SECS: u64 = 30;
+
+/// How long ssh gets to authenticate and open the forward.
+const READY_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// A free TCP port on this machine's loopback.
+fn free_local_port() -> Result<u16, String> {
+    let listener = TcpListener::bind("127.0.0.1:0").map_err(|e| format!("no free local port: {}", e))?;
+    listener.local_addr().map(|addr| addr.port()).map_err(|e| e.to_string())
+}
+
+/// Forward the protocol's port through the VM's `[ssh_tunnel]` destination and return
+/// the local host and protocol for the viewer. None when the VM has no tunnel (or is
+/// reached on a unix socket, which only exists for local VMs).
+pub fn open(
+    vm_conf: &Path,
+    host: &str,
+    protocol: &RemoteProtocol,
+    config: &Config,
+    logs: &Arc<Mutex<Vec<String>>>,
+) -> Result<Option<(String, RemoteProtocol)>, String> {
+    let stem = vm_conf.file_stem().unwrap().to_string_lossy().to_lowercase();
+    let (Some(destination), Some(port)) = (config.ssh_tunnels.get(&stem), protocol.port()) else {
+        return Ok(None);
+    };
+    // The VM's own host address is its loopback on the other end; a guest IP is kept.
+    let target = if host == vm_host(vm_conf, config) { "localhost" } else { host };
+    let local_port = free_local_port()?;
+    let mut command = Command::new("ssh");
+    command
+        .args(["-o", "ExitOnForwardFailure=yes", "-o", "BatchMode=yes", "-L"])
+        .arg(format!("{}:{}", local_port, format_addr(target, port)))
+        .arg(destination)
+        .arg(format!("sleep {}", LINGER_SECS));
+    let tunnelled = Some(("127.0.0.1".to_string(), protocol.with_port(local_port)));
+    if config.dry_run {
+        logs.lock().unwrap().push(format!("[dry-run] {}", describe_command(&command)));
+        return Ok(tunnelled);
+    }
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("cannot run ssh: {}", e))?;
+    procs::record(child.id(), describe_command(&command));
+    if config.verbose {
+        logs.lock().unwrap().push(format!("$ {} (pid {})", describe_command(&command), child.id()));
+    }
+    let deadline = Instant::now() + READY_TIMEOUT;
+    while !is_port_open("127.0.0.1", local_port, config.connect_timeout) {
+        if let Ok(Some(status)) = child.try_wait() {
+            let mut stderr = String::new();
+            if let Some(mut pipe) = child.stderr.take() {
+                let _ = pipe.read_to_string(&mut stderr);
+            }
+            let reason = stderr.lines().last().map(str::to_string).unwrap_or_else(|| status.to_string());
+            return Err(format!("SSH tunnel to {} failed: {}", destination, reason));
+        }
+        if Instant::now() > deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!("SSH tunnel to {} not ready after {}s", destination, READY_TIMEOUT.as_secs()));
+        }
+        thread::sleep(Duration::from_millis(200));
+    }
+    logs.lock().unwrap().push(format!(
+        "SSH tunnel 127.0.0.1:{} -> {} via {}.",
+        local_port,
+        format_addr(target, port),
+        destination
+    ));
+    let (logs, vm) = (logs.clone(), stem);
+    thread::spawn(move || {
+        let _ = child.wait();
+        logs.lock().unwrap().push(format!("SSH tunnel for {} closed.", vm));
+    });
+    Ok(tunnelled)
+}
@@ -0,0 +1,117 @@
+///////////////////////////////////////////////////////////////////////////////
+// Viewer Launch Supervision
+///////////////////////////////////////////////////////////////////////////////
+//
+// A viewer that starts is not yet a connection (a wedged Remmina starts fine),
+// so each launch is watched for up to `viewer_timeout` (default 15s):
+//   - it exits with an error                 failed, the next client is tried
+//   - it exits cleanly                       connected: it handed the connection
+//                                            to a running instance (Remmina)
+//   - a new TCP connection to the display    connected
+//     port appears
+//   - the timeout passes without either      killed, the next client is tried
+// New connections are found in /proc/net/tcp, so the last two only apply on
+// Linux and to TCP displays (for Moonlight, Sunshine's API port; for Remmina
+// profiles, the port in their `server`). Elsewhere, and for SPICE on a unix
+// socket, a viewer still running after a few seconds counts as connected.
+// `viewer_timeout_ms = 0` turns the watching off.
+
+use std::{
+    process::{Child, Command},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{procs, spawn_child, Config};
+
+/// How long a viewer whose connection cannot be seen gets to fail by exiting.
+const EXIT_GRACE: Duration = Duration::from_secs(3);
+
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Local ports of the established TCP connections to `port`; None where they cannot be listed.
+#[cfg(target_os = "linux")]
+fn connections(port: u16) -> Option<Vec<u16>> {
+    let hex_port = |addr: &str| u16::from_str_radix(addr.rsplit_once(':')?.1, 16).ok();
+    let mut locals = Vec::new();
+    let mut listed = false;
+    for table in ["/proc/net/tcp", "/proc/net/tcp6"] {
+        let Ok(contents) = std::fs::read_to_string(table) else { continue };
+        listed = true;
+        // "sl local_address rem_address st ...": hex "ADDR:PORT" pairs, state 01 is ESTABLISHED.
+        for line in contents.lines().skip(1) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.get(3) == Some(&"01")
+                && fields.get(2).and_then(|remote| hex_port(remote)) == Some(port)
+                && let Some(local) = fields.get(1).and_then(|local| hex_port(local))
+            {
+                locals.push(local);
+            }
+        }
+    }
+    listed.then_some(locals)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn connections(_port: u16) -> Option<Vec<u16>> {
+    None
+}
+
+/// Kill a timed-out viewer. On unix it leads its own process group (see launch), which
+/// also takes down what a wrapper like `flatpak run` started.
+fn kill(child: &mut Child) {
+    #[cfg(unix)]
+    {
+        let group = format!("-{}", child.id());
+        let _ = Command::new("kill").args(["-KILL", "--", &group]).stderr(std::process::Stdio::null()).status();
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+/// Launch a viewer for the display on TCP `port` (None: not a TCP display) and wait until
+/// it connects, fails or times out. Errors say why; a timed-out viewer has been killed.
+pub fn launch(
+    command: &mut Command,
+    port: Option<u16>,
+    config: &Config,
+    logs: &Arc<Mutex<Vec<String>>>,
+) -> Result<(), String> {
+    let before = port.filter(|_| !config.viewer_timeout.is_zero()).and_then(|port| Some((port, connections(port)?)));
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(command, 0);
+    let Some(mut child) = spawn_child(command, config, logs).map_err(|e| e.to_string())? else { return Ok(()) };
+    if config.viewer_timeout.is_zero() {
+        procs::adopt(child);
+        return Ok(());
+    }
+    let wait = if before.is_some() { config.viewer_timeout } else { EXIT_GRACE.min(config.viewer_timeout) };
+    let deadline = Instant::now() + wait;
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) if status.success() => return Ok(()),
+            Ok(Some(status)) => return Err(format!("exited ({})", status)),
+            Ok(None) => {}
+            Err(e) => {
+                procs::adopt(child);
+                return Err(e.to_string());
+            }
+        }
+        if let Some((port, before)) = &before
+            && connections(*port).is_some_and(|now| now.iter().any(|local| !before.contains(local)))
+        {
+            procs::adopt(child);
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            if before.is_none() {
+                procs::adopt(child);
+                return Ok(());
+            }
+            kill(&mut child);
+            return Err(format!("no connection after {}s, killed it", wait.as_secs()));
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
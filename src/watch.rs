@@ -0,0 +1,108 @@
+///////////////////////////////////////////////////////////////////////////////
+// Watch Mode
+///////////////////////////////////////////////////////////////////////////////
+//
+// `quick-cli watch` prints the state of every VM without the TUI, re-polled
+// every status_poll_ms:
+//     (default)   a table, redrawn in place on a terminal; when piped, printed
+//                 again each time a state changes
+//     --changes   one line per state change, after one per VM to start with
+//     --line      a single summary line, e.g. for tmux's status-right:
+//                 `#(quick-cli watch --once --line)`
+//     --once      print the current status and exit
+// For machine-readable output see `quick-cli events`.
+
+use std::{
+    error::Error,
+    io::{self, IsTerminal, Write},
+    thread,
+};
+
+use crate::{
+    events::{VmState, Watcher},
+    list_vms, parse_vm_config,
+    term::{paint, Tone},
+    vm_host, Config,
+};
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum WatchMode {
+    #[default]
+    Table,
+    Changes,
+    Line,
+}
+
+fn table(config: &Config, watcher: &Watcher) -> String {
+    let states = watcher.states();
+    let vms: Vec<_> = list_vms(config)
+        .into_iter()
+        .filter_map(|vm_conf| {
+            let name = vm_conf.file_stem()?.to_string_lossy().to_string();
+            let state = *states.get(&name)?;
+            Some((name, state, vm_conf))
+        })
+        .collect();
+    let width = vms.iter().map(|(name, ..)| name.len()).max().unwrap_or(0).max(2);
+    let mut out = format!("{:<width$}  {:<8}  CONNECT\n", "VM", "STATE");
+    for (name, state, vm_conf) in &vms {
+        let protocol = parse_vm_config(vm_conf, config);
+        let endpoint = protocol.endpoint(vm_host(vm_conf, config));
+        let tone = match state {
+            VmState::Stopped => Tone::Dim,
+            VmState::Running => Tone::Warn,
+            VmState::Ready => Tone::Good,
+        };
+        let state = paint(&format!("{:<8}", state.as_str()), tone);
+        out.push_str(&format!("{:<width$}  {}  {} {}\n", name, state, protocol.label(), endpoint));
+    }
+    out
+}
+
+/// "2/5 running: win11 (ready), debian" in list order.
+fn summary(config: &Config, watcher: &Watcher) -> String {
+    let states = watcher.states();
+    let running: Vec<String> = list_vms(config)
+        .into_iter()
+        .filter_map(|vm_conf| {
+            let name = vm_conf.file_stem()?.to_string_lossy().to_string();
+            match states.get(&name)? {
+                VmState::Stopped => None,
+                VmState::Running => Some(name),
+                VmState::Ready => Some(format!("{} (ready)", name)),
+            }
+        })
+        .collect();
+    match running.len() {
+        0 => format!("0/{} running", states.len()),
+        n => format!("{}/{} running: {}", n, states.len(), running.join(", ")),
+    }
+}
+
+pub fn run(config: Config, mode: WatchMode, once: bool) -> Result<(), Box<dyn Error>> {
+    let redraw = mode == WatchMode::Table && !once && io::stdout().is_terminal();
+    let mut watcher = Watcher::default();
+    let mut last = String::new();
+    loop {
+        let events = watcher.poll(&config);
+        let output = match mode {
+            WatchMode::Changes => events.iter().map(|e| e.describe() + "\n").collect(),
+            WatchMode::Table => table(&config, &watcher),
+            WatchMode::Line => summary(&config, &watcher) + "\n",
+        };
+        // Redrawn tables repeat even when unchanged; everything else only prints news.
+        if redraw || output != last {
+            let mut stdout = io::stdout().lock();
+            if redraw {
+                write!(stdout, "\x1b[H\x1b[2J")?;
+            }
+            write!(stdout, "{}", output)?;
+            stdout.flush()?;
+            last = output;
+        }
+        if once {
+            return Ok(());
+        }
+        thread::sleep(config.status_poll_interval);
+    }
+}
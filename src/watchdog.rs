@@ -0,0 +1,142 @@
+///////////////////////////////////////////////////////////////////////////////
+// Daemon Watchdog
+///////////////////////////////////////////////////////////////////////////////
+//
+// `quick-cli daemon` watches the VM events for VMs that die on their own. qemu
+// removes its pid file when it exits normally (a guest shutdown, or quick-cli's
+// ACPI powerdown), so a VM that stops and leaves a pid file naming a dead
+// process has crashed or was killed from outside. Each one is logged and
+// notified (see notify.rs); VMs matched by `restart_on_crash` are started
+// again, at most MAX_RESTARTS times in RESTART_WINDOW so a VM that crashes
+// while booting does not loop. VMs matched by `autostart` are started when the
+// daemon starts. Stops through stop_vm (which may end in a SIGKILL) are never
+// taken for crashes, and a read-only daemon only reports.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    disk::disk_encryption,
+    events::{Event, VmState},
+    glob_match, is_wsl_command, list_vms, notify, stale_runtime_files, start_vm, vm_runtime_file, Config,
+};
+
+/// Restarts of one VM allowed within RESTART_WINDOW before the watchdog gives up on it.
+const MAX_RESTARTS: usize = 3;
+const RESTART_WINDOW: Duration = Duration::from_secs(600);
+
+/// VMs stopped through stop_vm whose stop has not been seen yet.
+static EXPECTED_STOPS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Note that the VM is being stopped on purpose, so its stop is not a crash.
+pub fn expect_stop(vm_conf: &Path) {
+    let mut expected = EXPECTED_STOPS.lock().unwrap();
+    if !expected.iter().any(|vm| vm == vm_conf) {
+        expected.push(vm_conf.to_path_buf());
+    }
+}
+
+/// Forget an expected stop; true if there was one.
+fn take_expected(vm_conf: &Path) -> bool {
+    let mut expected = EXPECTED_STOPS.lock().unwrap();
+    let before = expected.len();
+    expected.retain(|vm| vm != vm_conf);
+    expected.len() != before
+}
+
+/// Whether the stopped VM left its pid file behind, see stale_runtime_files.
+fn crashed(vm_conf: &Path, config: &Config) -> bool {
+    // Pids of qemu inside WSL mean nothing to the Windows host.
+    !is_wsl_command(&config.quickemu) && stale_runtime_files(vm_conf).contains(&vm_runtime_file(vm_conf, ".pid"))
+}
+
+fn matches(patterns: &[String], vm: &str) -> bool {
+    patterns.iter().any(|p| glob_match(p, vm))
+}
+
+#[derive(Default)]
+pub struct Watchdog {
+    restarts: HashMap<String, Vec<Instant>>, // VM stem -> its restarts within RESTART_WINDOW
+}
+
+impl Watchdog {
+    /// Act on the events of one poll: autostart VMs on the first, report and restart crashed ones.
+    pub fn handle(&mut self, events: &[Event], config: &Config, logs: &Arc<Mutex<Vec<String>>>) {
+        let mut vms = None;
+        for event in events {
+            let (vm, first_poll) = match event {
+                Event::State { vm, state: VmState::Stopped, .. } => (vm, true),
+                Event::Stopped { vm, .. } => (vm, false),
+                Event::Started { vm, .. } => {
+                    // Running again, so an expected stop that never came is moot.
+                    if let Some(vm_conf) = find(vms.get_or_insert_with(|| list_vms(config)), vm) {
+                        take_expected(&vm_conf);
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            let Some(vm_conf) = find(vms.get_or_insert_with(|| list_vms(config)), vm) else { continue };
+            if first_poll {
+                if matches(&config.autostart, vm) {
+                    self.start(&vm_conf, format!("Watchdog: starting {} (autostart).", vm), config, logs);
+                }
+                continue;
+            }
+            if take_expected(&vm_conf) || !crashed(&vm_conf, config) {
+                continue;
+            }
+            let message = format!("VM {} stopped unexpectedly (its qemu process died).", vm);
+            logs.lock().unwrap().push(format!("Watchdog: {}", message));
+            notify::send(config, "VM crashed", &message, logs);
+            if !matches(&config.restart_on_crash, vm) {
+                continue;
+            }
+            let restarts = self.restarts.entry(vm.to_lowercase()).or_default();
+            restarts.retain(|t| t.elapsed() < RESTART_WINDOW);
+            if restarts.len() >= MAX_RESTARTS {
+                let message = format!(
+                    "VM {} crashed {} times in {} minutes; not restarting it again.",
+                    vm,
+                    MAX_RESTARTS + 1,
+                    RESTART_WINDOW.as_secs() / 60
+                );
+                logs.lock().unwrap().push(format!("Watchdog: {}", message));
+                notify::send(config, "VM keeps crashing", &message, logs);
+                continue;
+            }
+            restarts.push(Instant::now());
+            let attempt = restarts.len();
+            let message = format!("Watchdog: restarting {} ({} of {}).", vm, attempt, MAX_RESTARTS);
+            self.start(&vm_conf, message, config, logs);
+        }
+    }
+
+    /// Start the VM in the background (start_vm waits start_wait), unless read-only mode or
+    /// an encrypted disk, which needs a passphrase, prevents it.
+    fn start(&self, vm_conf: &Path, message: String, config: &Config, logs: &Arc<Mutex<Vec<String>>>) {
+        let name = vm_conf.file_stem().unwrap().to_string_lossy();
+        if config.read_only {
+            logs.lock().unwrap().push(format!("Watchdog: not starting {} in read-only mode.", name));
+            return;
+        }
+        if disk_encryption(vm_conf).is_some() {
+            logs.lock().unwrap().push(format!("Watchdog: cannot start {}: its disk needs a passphrase.", name));
+            return;
+        }
+        logs.lock().unwrap().push(message);
+        let (vm_conf, config, logs) = (vm_conf.to_path_buf(), config.clone(), Arc::clone(logs));
+        thread::spawn(move || {
+            let _ = start_vm(&vm_conf, &config, &logs, None, false);
+        });
+    }
+}
+
+fn find(vms: &[PathBuf], vm: &str) -> Option<PathBuf> {
+    vms.iter().find(|vm_conf| vm_conf.file_stem().is_some_and(|stem| stem.to_string_lossy() == vm)).cloned()
+}
@@ -0,0 +1,41 @@
+///////////////////////////////////////////////////////////////////////////////
+// Webhooks
+///////////////////////////////////////////////////////////////////////////////
+//
+// Every URL in `webhooks = [...]` receives a JSON POST (the same objects as
+// `quick-cli events`) when a VM starts, becomes ready or stops, and when a
+// background job finishes. Requests are sent with curl in the background;
+// failures only go to the log.
+
+use std::{
+    process::Command,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use crate::events::Event;
+
+const TIMEOUT_SECS: &str = "10";
+
+/// POST the event to each URL in a background thread.
+pub fn post(urls: &[String], event: &Event, logs: &Arc<Mutex<Vec<String>>>) {
+    if urls.is_empty() {
+        return;
+    }
+    let body = event.to_json();
+    for url in urls {
+        let (url, body, logs) = (url.clone(), body.clone(), Arc::clone(logs));
+        thread::spawn(move || {
+            let result = Command::new("curl")
+                .args(["-fsS", "--max-time", TIMEOUT_SECS, "-X", "POST"])
+                .args(["-H", "Content-Type: application/json", "--data-binary", &body, &url])
+                .output();
+            let error = match result {
+                Ok(output) if output.status.success() => return,
+                Ok(output) => String::from_utf8_lossy(&output.stderr).trim().to_string(),
+                Err(e) => format!("failed to run curl: {}", e),
+            };
+            logs.lock().unwrap().push(format!("Webhook {} failed: {}", url, error));
+        });
+    }
+}